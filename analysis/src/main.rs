@@ -68,7 +68,7 @@ fn gather_policy_data(agent: &Net, rng: &mut impl Rng) {
 
         for (node, _) in batched_mcts.nodes_and_envs() {
             line.clear();
-            node.children.iter().for_each(|(a, child)| {
+            node.children().iter().for_each(|(a, child)| {
                 write!(
                     &mut line,
                     "{a}:{}:{}:{}:{},",
@@ -106,7 +106,7 @@ fn main() {
             // Print raw network output.
             let xs = tch::Tensor::concat(
                 &node
-                    .children
+                    .children()
                     .iter()
                     .map(|(a, _)| {
                         let mut clone = env.clone();
@@ -128,7 +128,7 @@ fn main() {
                 .into_iter()
                 .zip(value_out)
                 .zip(ube_out)
-                .zip(node.children.iter())
+                .zip(node.children().iter())
                 .collect::<Vec<_>>();
             network_output.sort_by_key(|(_, (_, n))| n.visit_count);
             network_output.reverse();
@@ -0,0 +1,331 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use player::{Player, RandomPlayer, Searcher};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sprt::{score_and_variance, Decision, Sprt};
+use takzero::{
+    network::{net4_simhash, net5, net6_simhash, Network},
+    search::{
+        agent::Agent,
+        env::{Environment, Terminal},
+        resign::Resignation,
+    },
+};
+use tch::Device;
+
+mod player;
+mod sprt;
+
+/// Games longer than this are adjudicated as draws.
+const MAX_PLIES: usize = 300;
+/// Number of random plies at the start of each pair of games.
+const OPENING_PLIES: usize = 2;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// First model, or `random` for a random-move baseline
+    #[arg(long)]
+    model_a: String,
+    /// Second model, or `random` for a random-move baseline
+    #[arg(long)]
+    model_b: String,
+    /// Number of games, rounded up to an even number so that both models
+    /// play each opening with both colors. With SPRT this is the maximum
+    #[arg(long, default_value_t = 100)]
+    games: usize,
+    /// Visits per move
+    #[arg(long, default_value_t = 256)]
+    visits: u32,
+    /// Board size (4, 5, or 6)
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(4..=6))]
+    size: u8,
+    /// Print the results as JSON
+    #[arg(long)]
+    json: bool,
+    /// Seed for the openings, random if not given
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Elo difference of the SPRT null hypothesis. Setting both `--elo0` and
+    /// `--elo1` stops the match as soon as the test is decided
+    #[arg(long, requires = "elo1", allow_negative_numbers = true)]
+    elo0: Option<f64>,
+    /// Elo difference of the SPRT alternative hypothesis
+    #[arg(long, requires = "elo0", allow_negative_numbers = true)]
+    elo1: Option<f64>,
+    /// SPRT false positive rate
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+    /// SPRT false negative rate
+    #[arg(long, default_value_t = 0.05)]
+    beta: f64,
+    /// Resign when the root value stays below this threshold
+    /// (never resigns by default)
+    #[arg(long, allow_negative_numbers = true)]
+    resign_threshold: Option<f32>,
+    /// Number of moves in a row below the threshold before resigning
+    #[arg(long, default_value_t = 5)]
+    resign_moves: usize,
+}
+
+impl Args {
+    fn resignation(&self) -> Option<Resignation> {
+        Some(Resignation::new(self.resign_threshold?, self.resign_moves))
+    }
+
+    fn sprt(&self) -> Option<Sprt> {
+        Some(Sprt {
+            elo0: self.elo0?,
+            elo1: self.elo1?,
+            alpha: self.alpha,
+            beta: self.beta,
+        })
+    }
+}
+
+/// Results from the perspective of model A.
+#[derive(Debug, Default, Clone, Copy)]
+struct Results {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+    /// Games which reached `MAX_PLIES`, also counted as draws.
+    adjudicated: u32,
+    /// Games which ended by resignation, also counted as wins or losses.
+    resigned: u32,
+}
+
+impl Results {
+    fn games(&self) -> f64 {
+        f64::from(self.wins + self.draws + self.losses)
+    }
+
+    fn score(&self) -> f64 {
+        0.5f64.mul_add(f64::from(self.draws), f64::from(self.wins)) / self.games()
+    }
+
+    fn win_rate(&self) -> f64 {
+        f64::from(self.wins) / self.games()
+    }
+
+    /// 95% confidence interval of the score, using the normal approximation.
+    fn confidence_interval(&self) -> (f64, f64) {
+        let (score, variance) = score_and_variance(
+            f64::from(self.wins),
+            f64::from(self.draws),
+            f64::from(self.losses),
+        );
+        let margin = 1.96 * (variance / self.games()).sqrt();
+        ((score - margin).max(0.0), (score + margin).min(1.0))
+    }
+
+    fn decide(&self, sprt: &Sprt) -> Decision {
+        sprt.decide(self.wins, self.draws, self.losses)
+    }
+
+    fn to_json(self, sprt: Option<&Sprt>) -> String {
+        let (low, high) = self.confidence_interval();
+        let sprt = sprt.map_or_else(String::new, |sprt| {
+            let (lower, upper) = sprt.bounds();
+            format!(
+                ",\"sprt\":{{\"decision\":\"{:?}\",\"llr\":{},\"bounds\":[{lower},{upper}]}}",
+                self.decide(sprt),
+                sprt.llr(self.wins, self.draws, self.losses),
+            )
+        });
+        format!(
+            "{{\"wins\":{},\"draws\":{},\"losses\":{},\"adjudicated\":{},\"resigned\":{},\"score\"\
+             :{},\"win_rate\":{},\"confidence_interval\":[{low},{high}]{sprt}}}",
+            self.wins,
+            self.draws,
+            self.losses,
+            self.adjudicated,
+            self.resigned,
+            self.score(),
+            self.win_rate(),
+        )
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+    let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    log::info!("seed = {seed}");
+    let rng = StdRng::seed_from_u64(seed);
+
+    let results = tch::no_grad(|| match args.size {
+        4 => run::<net4_simhash::Net, net4_simhash::Env>(&args, rng),
+        5 => run::<net5::Net, net5::Env>(&args, rng),
+        6 => run::<net6_simhash::Net, net6_simhash::Env>(&args, rng),
+        _ => unreachable!("the size should be checked when parsing arguments"),
+    });
+
+    let sprt = args.sprt();
+    if args.json {
+        println!("{}", results.to_json(sprt.as_ref()));
+    } else {
+        let (low, high) = results.confidence_interval();
+        println!(
+            "{} vs. {}: +{} ={} -{} ({} adjudicated, {} resigned)\nscore: {:.1}% [{:.1}%, \
+             {:.1}%]\nwin rate: {:.1}%",
+            args.model_a,
+            args.model_b,
+            results.wins,
+            results.draws,
+            results.losses,
+            results.adjudicated,
+            results.resigned,
+            results.score() * 100.0,
+            low * 100.0,
+            high * 100.0,
+            results.win_rate() * 100.0,
+        );
+        if let Some(sprt) = &sprt {
+            let (lower, upper) = sprt.bounds();
+            println!(
+                "SPRT [{}, {}]: {:?}, LLR {:.2} [{lower:.2}, {upper:.2}]",
+                sprt.elo0,
+                sprt.elo1,
+                results.decide(sprt),
+                sprt.llr(results.wins, results.draws, results.losses),
+            );
+        }
+    }
+}
+
+fn load_player<NET, E>(name: &str, visits: u32) -> Box<dyn Player<E>>
+where
+    NET: Network + Agent<E> + 'static,
+    E: Environment + 'static,
+{
+    if name == "random" {
+        return Box::new(RandomPlayer::default());
+    }
+    let net = NET::load(PathBuf::from(name), Device::cuda_if_available())
+        .unwrap_or_else(|err| panic!("could not load {name}: {err}"));
+    Box::new(Searcher::new(net, visits))
+}
+
+fn run<NET, E>(args: &Args, mut rng: StdRng) -> Results
+where
+    NET: Network + Agent<E> + 'static,
+    E: Environment + 'static,
+{
+    let mut a = load_player::<NET, E>(&args.model_a, args.visits);
+    let mut b = load_player::<NET, E>(&args.model_b, args.visits);
+
+    let sprt = args.sprt();
+    let resignation = args.resignation();
+    let mut results = Results::default();
+    let mut actions = Vec::new();
+    for pair in 0..args.games.div_ceil(2) {
+        let opening = E::new_opening_with_random_steps(&mut rng, &mut actions, OPENING_PLIES);
+        for a_moves_first in [true, false] {
+            let (first, second) = if a_moves_first {
+                (&mut a, &mut b)
+            } else {
+                (&mut b, &mut a)
+            };
+            let outcome = play_game(
+                first.as_mut(),
+                second.as_mut(),
+                opening.clone(),
+                resignation.as_ref(),
+                &mut rng,
+            );
+            if let Outcome::Winner { resigned: true, .. } = outcome {
+                results.resigned += 1;
+            }
+            match outcome {
+                Outcome::Winner { first_won, .. } if first_won == a_moves_first => {
+                    results.wins += 1;
+                }
+                Outcome::Winner { .. } => results.losses += 1,
+                Outcome::Draw => results.draws += 1,
+                Outcome::Adjudicated => {
+                    results.draws += 1;
+                    results.adjudicated += 1;
+                }
+            }
+        }
+        log::info!("after {} games: {results:?}", 2 * (pair + 1));
+        // Checking after whole pairs keeps the colors balanced.
+        if sprt.is_some_and(|sprt| results.decide(&sprt) != Decision::Continue) {
+            break;
+        }
+    }
+    results
+}
+
+enum Outcome {
+    Winner {
+        first_won: bool,
+        resigned: bool,
+    },
+    Draw,
+    /// The game was stopped after `MAX_PLIES`.
+    Adjudicated,
+}
+
+/// Play a game between two players, starting from `env`.
+fn play_game<E: Environment>(
+    first: &mut dyn Player<E>,
+    second: &mut dyn Player<E>,
+    mut env: E,
+    resignation: Option<&Resignation>,
+    rng: &mut StdRng,
+) -> Outcome {
+    first.reset();
+    second.reset();
+    let mut resignations = resignation.map(|r| [r.clone(), r.clone()]);
+    let mut first_to_move = true;
+    for _ in 0..MAX_PLIES {
+        let (current, other) = if first_to_move {
+            (&mut *first, &mut *second)
+        } else {
+            (&mut *second, &mut *first)
+        };
+        let action = current.choose(&env, rng);
+        if let (Some(resignations), Some(value)) = (&mut resignations, current.root_value()) {
+            if resignations[usize::from(!first_to_move)].update(value) {
+                return Outcome::Winner {
+                    first_won: !first_to_move,
+                    resigned: true,
+                };
+            }
+        }
+        // A player which chooses an illegal action loses.
+        if !env.is_legal(&action) {
+            let player = if first_to_move { "first" } else { "second" };
+            log::error!("The {player} player chose the illegal action {action:?}");
+            return Outcome::Winner {
+                first_won: !first_to_move,
+                resigned: false,
+            };
+        }
+        current.observe(&action);
+        other.observe(&action);
+        env.step(action);
+        first_to_move = !first_to_move;
+
+        // The terminal is from the perspective of the player to move.
+        match env.terminal() {
+            Some(Terminal::Win) => {
+                return Outcome::Winner {
+                    first_won: first_to_move,
+                    resigned: false,
+                }
+            }
+            Some(Terminal::Loss) => {
+                return Outcome::Winner {
+                    first_won: !first_to_move,
+                    resigned: false,
+                }
+            }
+            Some(Terminal::Draw) => return Outcome::Draw,
+            None => {}
+        }
+    }
+    Outcome::Adjudicated
+}
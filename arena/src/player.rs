@@ -0,0 +1,79 @@
+use rand::{rngs::StdRng, seq::IteratorRandom};
+use takzero::search::{agent::Agent, env::Environment, node::Node};
+
+/// One side of a match.
+pub trait Player<E: Environment> {
+    /// Pick an action in the given position.
+    fn choose(&mut self, env: &E, rng: &mut StdRng) -> E::Action;
+    /// Value of the last position searched by `choose`, from the perspective
+    /// of this player, if the player has one.
+    fn root_value(&self) -> Option<f32> {
+        None
+    }
+    /// Called for every action played, by either side.
+    fn observe(&mut self, _action: &E::Action) {}
+    /// Called before every game.
+    fn reset(&mut self) {}
+}
+
+/// Plays uniformly random legal actions.
+pub struct RandomPlayer<E: Environment> {
+    actions: Vec<E::Action>,
+}
+
+impl<E: Environment> Default for RandomPlayer<E> {
+    fn default() -> Self {
+        Self {
+            actions: Vec::new(),
+        }
+    }
+}
+
+impl<E: Environment> Player<E> for RandomPlayer<E> {
+    fn choose(&mut self, env: &E, rng: &mut StdRng) -> E::Action {
+        env.populate_actions(&mut self.actions);
+        self.actions
+            .drain(..)
+            .choose(rng)
+            .expect("there should be a legal action")
+    }
+}
+
+/// Searches with an agent for a fixed number of visits per move,
+/// keeping the tree between moves.
+pub struct Searcher<A, E: Environment> {
+    agent: A,
+    node: Node<E>,
+    visits: u32,
+}
+
+impl<A, E: Environment> Searcher<A, E> {
+    pub fn new(agent: A, visits: u32) -> Self {
+        Self {
+            agent,
+            node: Node::default(),
+            visits,
+        }
+    }
+}
+
+impl<A: Agent<E>, E: Environment> Player<E> for Searcher<A, E> {
+    fn choose(&mut self, env: &E, _rng: &mut StdRng) -> E::Action {
+        for _ in 0..self.visits {
+            self.node.simulate_simple(&self.agent, env.clone(), 0.0);
+        }
+        self.node.select_best_action()
+    }
+
+    fn root_value(&self) -> Option<f32> {
+        Some(f32::from(self.node.evaluation))
+    }
+
+    fn observe(&mut self, action: &E::Action) {
+        self.node.descend(action);
+    }
+
+    fn reset(&mut self) {
+        self.node.clear();
+    }
+}
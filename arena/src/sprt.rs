@@ -0,0 +1,117 @@
+//! Sequential probability ratio test using the trinomial
+//! normal approximation of the log-likelihood ratio.
+//! <https://www.chessprogramming.org/Sequential_Probability_Ratio_Test>
+
+/// Pseudo-count added to each outcome, so that the variance is never zero
+/// and a handful of games cannot end the test.
+const PSEUDO_COUNT: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The elo difference is at most `elo0`.
+    AcceptH0,
+    /// The elo difference is at least `elo1`.
+    AcceptH1,
+    Continue,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    /// Probability of accepting H1 when H0 is true.
+    pub alpha: f64,
+    /// Probability of accepting H0 when H1 is true.
+    pub beta: f64,
+}
+
+fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Mean score and its variance per game.
+pub fn score_and_variance(wins: f64, draws: f64, losses: f64) -> (f64, f64) {
+    let games = wins + draws + losses;
+    let score = 0.5f64.mul_add(draws, wins) / games;
+    let variance = wins.mul_add(
+        (1.0 - score).powi(2),
+        draws.mul_add((0.5 - score).powi(2), losses * score.powi(2)),
+    ) / games;
+    (score, variance)
+}
+
+impl Sprt {
+    /// Lower and upper bounds for the log-likelihood ratio.
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    /// Log-likelihood ratio of H1 against H0 for the given results.
+    pub fn llr(&self, wins: u32, draws: u32, losses: u32) -> f64 {
+        let wins = f64::from(wins) + PSEUDO_COUNT;
+        let draws = f64::from(draws) + PSEUDO_COUNT;
+        let losses = f64::from(losses) + PSEUDO_COUNT;
+        let (score, variance) = score_and_variance(wins, draws, losses);
+        let games = wins + draws + losses;
+        let s0 = elo_to_score(self.elo0);
+        let s1 = elo_to_score(self.elo1);
+        games * (s1 - s0) * (2.0f64.mul_add(score, -s0) - s1) / (2.0 * variance)
+    }
+
+    pub fn decide(&self, wins: u32, draws: u32, losses: u32) -> Decision {
+        let llr = self.llr(wins, draws, losses);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Decision::AcceptH1
+        } else if llr <= lower {
+            Decision::AcceptH0
+        } else {
+            Decision::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, Sprt};
+
+    const SPRT: Sprt = Sprt {
+        elo0: 0.0,
+        elo1: 10.0,
+        alpha: 0.05,
+        beta: 0.05,
+    };
+
+    #[test]
+    fn bounds_are_symmetric_for_equal_errors() {
+        let (lower, upper) = SPRT.bounds();
+        assert!((lower + upper).abs() < 1e-9);
+        assert!((upper - 19f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn few_games_continue() {
+        assert_eq!(SPRT.decide(0, 0, 0), Decision::Continue);
+        assert_eq!(SPRT.decide(3, 2, 1), Decision::Continue);
+    }
+
+    #[test]
+    fn clear_results_are_accepted() {
+        assert_eq!(SPRT.decide(600, 200, 200), Decision::AcceptH1);
+        assert_eq!(SPRT.decide(200, 200, 600), Decision::AcceptH0);
+        // Equal strength is closer to H0.
+        assert_eq!(SPRT.decide(2000, 2000, 2000), Decision::AcceptH0);
+    }
+
+    #[test]
+    fn llr_crosses_the_upper_bound() {
+        let (_, upper) = SPRT.bounds();
+        // Always winning eventually accepts H1.
+        let games = (1..).find(|&wins| SPRT.llr(wins, 0, 0) >= upper).unwrap();
+        assert_eq!(SPRT.decide(games, 0, 0), Decision::AcceptH1);
+        assert_eq!(SPRT.decide(games - 1, 0, 0), Decision::Continue);
+    }
+}
@@ -0,0 +1,420 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use fast_tak::{Game, Reserves};
+use ordered_float::NotNan;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use takzero::{
+    network::{
+        net4_simhash,
+        net5,
+        net6_simhash,
+        parse_device,
+        repr::{game_to_tensor, games_to_tensor},
+        Network,
+    },
+    search::{
+        agent::{dummy::Dummy, Agent},
+        env::Environment,
+        eval::Eval,
+        node::Node,
+        SearchConfig,
+    },
+    target::{final_ownership, Target},
+};
+use tch::{Device, Tensor};
+
+/// Largest number of random plies played to create a position.
+const MAX_RANDOM_PLIES: usize = 30;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Model to benchmark, a randomly initialized network is used if absent
+    #[arg(long)]
+    model: Option<PathBuf>,
+    /// Board size (4, 5, or 6)
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(4..=6))]
+    size: u8,
+    /// Number of positions which are evaluated together
+    #[arg(long, default_value_t = 128)]
+    batch_size: usize,
+    /// Number of measured batches for each path
+    #[arg(long, default_value_t = 100)]
+    iterations: usize,
+    /// Number of batches which are evaluated before measuring
+    #[arg(long, default_value_t = 10)]
+    warmup: usize,
+    /// Device to benchmark on (`cpu`, `cuda:N`, or `mps`)
+    #[arg(long, default_value = "cuda:0", value_parser = parse_device)]
+    device: Device,
+    /// Seed for the positions and the random network
+    #[arg(long, default_value_t = 123)]
+    seed: u64,
+    /// Also measure `Node::parallel_simulate` with each of these numbers
+    /// of threads, like `--threads 1,2,4`
+    #[arg(long, value_delimiter = ',')]
+    threads: Vec<usize>,
+    /// Number of leaves which `Node::simulate_batch` evaluates together
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(usize).range(1..))]
+    leaf_batch_size: usize,
+    /// Number of positions which are searched to compare
+    /// `Node::simulate_batch` with one leaf per agent call
+    #[arg(long, default_value_t = 4)]
+    search_positions: usize,
+    /// Number of targets in the buffer which batches are sampled from
+    #[arg(long, default_value_t = 10_000)]
+    buffer_size: usize,
+    /// Number of simulations for each position of the parallel search
+    /// and of the search tree measurement
+    #[arg(long, default_value_t = 800)]
+    visits: usize,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+    log::info!("{args:?}");
+
+    tch::no_grad(|| match args.size {
+        4 => run::<net4_simhash::Net, { net4_simhash::N }, { net4_simhash::HALF_KOMI }>(&args),
+        5 => run::<net5::Net, { net5::N }, { net5::HALF_KOMI }>(&args),
+        6 => run::<net6_simhash::Net, { net6_simhash::N }, { net6_simhash::HALF_KOMI }>(&args),
+        _ => unreachable!("the size should be checked when parsing arguments"),
+    });
+}
+
+fn run<NET, const N: usize, const HALF_KOMI: i8>(args: &Args)
+where
+    NET: Network + Agent<Game<N, HALF_KOMI>>,
+    Reserves<N>: Default,
+{
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let net = match &args.model {
+        Some(model) => NET::load(model, args.device).expect("model should be loadable"),
+        None => NET::new(args.device, Some(rng.gen())),
+    };
+    println!(
+        "{N}x{N} with {} parameters on {:?}, batches of {}",
+        net.num_parameters(),
+        args.device,
+        args.batch_size
+    );
+
+    let batches: Vec<_> = (0..args.warmup + args.iterations)
+        .map(|_| random_batch::<N, HALF_KOMI>(args.batch_size, &mut rng))
+        .collect();
+    let (warmup, measured) = batches.split_at(args.warmup);
+
+    // Only the forward pass, including moving the inputs to the device.
+    let forward = |(envs, _): &Batch<N, HALF_KOMI>| {
+        let xs = games_to_tensor(envs, args.device);
+        let (policy, value, ube) = net.forward_t(&xs, false);
+        // Wait for the results so that asynchronous devices are measured too.
+        let _ = (
+            policy.sum(tch::Kind::Float).double_value(&[]),
+            value.sum(tch::Kind::Float).double_value(&[]),
+            ube.sum(tch::Kind::Float).double_value(&[]),
+        );
+    };
+    // Everything search needs: masking the policy to the legal moves,
+    // the values, and the uncertainties.
+    let full = |(envs, actions): &Batch<N, HALF_KOMI>| {
+        net.policy_value_uncertainty(envs, actions).for_each(drop);
+    };
+
+    warmup.iter().for_each(forward);
+    report("forward_t", &measure(measured, forward), args.batch_size);
+    warmup.iter().for_each(full);
+    report(
+        "policy_value_uncertainty",
+        &measure(measured, full),
+        args.batch_size,
+    );
+
+    // Building the inputs of a batch in one tensor,
+    // against one tensor per position which are concatenated.
+    let batched_input = |(envs, _): &Batch<N, HALF_KOMI>| {
+        let _ = games_to_tensor(envs, args.device)
+            .sum(tch::Kind::Float)
+            .double_value(&[]);
+    };
+    let concatenated_input = |(envs, _): &Batch<N, HALF_KOMI>| {
+        let tensors: Vec<_> = envs
+            .iter()
+            .map(|env| game_to_tensor(env, args.device))
+            .collect();
+        let _ = Tensor::cat(&tensors, 0)
+            .sum(tch::Kind::Float)
+            .double_value(&[]);
+    };
+    report(
+        "games_to_tensor",
+        &measure(measured, batched_input),
+        args.batch_size,
+    );
+    report(
+        "game_to_tensor and Tensor::cat",
+        &measure(measured, concatenated_input),
+        args.batch_size,
+    );
+
+    // Reading a batch of targets with every optional field,
+    // from the text format and from the binary format.
+    let targets: Vec<_> = measured
+        .iter()
+        .map(|batch| random_targets(batch, &mut rng))
+        .collect();
+    let texts: Vec<String> = targets
+        .iter()
+        .map(|targets| targets.iter().map(ToString::to_string).collect())
+        .collect();
+    let binaries: Vec<Vec<u8>> = targets
+        .iter()
+        .map(|targets| {
+            let mut bytes = Vec::new();
+            for target in targets {
+                target
+                    .write_bin(&mut bytes)
+                    .expect("writing to a Vec should not fail");
+            }
+            bytes
+        })
+        .collect();
+    let parse_text = |text: &String| {
+        for line in text.lines() {
+            let _: Target<Game<N, HALF_KOMI>> = line.parse().expect("target should parse");
+        }
+    };
+    let read_binary = |bytes: &Vec<u8>| {
+        let mut reader = bytes.as_slice();
+        while !reader.is_empty() {
+            Target::<Game<N, HALF_KOMI>>::read_bin(&mut reader).expect("target should be read");
+        }
+    };
+    report(
+        "Target::from_str",
+        &measure(&texts, parse_text),
+        args.batch_size,
+    );
+    report(
+        "Target::read_bin",
+        &measure(&binaries, read_binary),
+        args.batch_size,
+    );
+
+    // Taking a batch out of the replay buffer of the learner and putting it
+    // back, by shuffling the whole buffer, and by sampling indices and
+    // removing only those targets.
+    let mut buffer = random_targets(
+        &random_batch::<N, HALF_KOMI>(args.buffer_size, &mut rng),
+        &mut rng,
+    );
+    let batch_size = args.batch_size.min(buffer.len());
+    let shuffle: Vec<_> = (0..args.iterations)
+        .map(|_| {
+            let start = Instant::now();
+            buffer.shuffle(&mut rng);
+            let batch: Vec<_> = buffer.drain(..batch_size).collect();
+            buffer.extend(batch);
+            start.elapsed()
+        })
+        .collect();
+    let sample: Vec<_> = (0..args.iterations)
+        .map(|_| {
+            let start = Instant::now();
+            let mut indices =
+                rand::seq::index::sample(&mut rng, buffer.len(), batch_size).into_vec();
+            indices.sort_unstable_by_key(|&i| std::cmp::Reverse(i));
+            let batch: Vec<_> = indices.into_iter().map(|i| buffer.swap_remove(i)).collect();
+            buffer.extend(batch);
+            start.elapsed()
+        })
+        .collect();
+    report(
+        &format!("sampling from {} targets by shuffling", buffer.len()),
+        &shuffle,
+        batch_size,
+    );
+    report(
+        &format!("sampling from {} targets by index", buffer.len()),
+        &sample,
+        batch_size,
+    );
+
+    // Growing and dropping search trees without a network, so that the cost
+    // of the tree itself, mostly growing its arena as nodes are expanded,
+    // is not hidden behind the network.
+    let Some((positions, _)) = measured.first() else {
+        return;
+    };
+    let mut search = Duration::ZERO;
+    let mut drop_tree = Duration::ZERO;
+    for env in positions {
+        let start = Instant::now();
+        let mut root = Node::default();
+        for _ in 0..args.visits {
+            root.simulate_simple(&Dummy, env.clone(), 0.0);
+        }
+        search += start.elapsed();
+        let start = Instant::now();
+        drop(root);
+        drop_tree += start.elapsed();
+    }
+    println!(
+        "search tree with {} visits and no network: {:.0} visits/s, dropping a tree takes {:.3} ms",
+        args.visits,
+        (positions.len() * args.visits) as f64 / search.as_secs_f64(),
+        drop_tree.as_secs_f64() * 1000.0 / positions.len() as f64,
+    );
+
+    // Searching with the network, evaluating one leaf per call
+    // and several leaves per call.
+    let searched = &positions[..args.search_positions.min(positions.len())];
+    let single = search_speed(searched, args.visits, |root, env| {
+        root.simulate_simple(&net, env.clone(), 0.0);
+        1
+    });
+    let config = SearchConfig::default();
+    let batched = search_speed(searched, args.visits, |root, env| {
+        root.simulate_batch(&net, env, &config, args.leaf_batch_size)
+    });
+    println!(
+        "simulate_simple: {single:.0} visits/s, simulate_batch with {} leaves: {batched:.0} \
+         visits/s, {:.2}x the speed",
+        args.leaf_batch_size,
+        batched / single
+    );
+
+    if args.threads.is_empty() {
+        return;
+    }
+    let mut baseline = None;
+    for &threads in &args.threads {
+        let start = Instant::now();
+        for env in positions {
+            let mut root = Node::default();
+            root.parallel_simulate(&net, env, 0.0, threads, args.visits, &mut rng);
+        }
+        let visits_per_second =
+            (positions.len() * args.visits) as f64 / start.elapsed().as_secs_f64();
+        let &mut (baseline_threads, baseline_visits_per_second) =
+            baseline.get_or_insert((threads, visits_per_second));
+        println!(
+            "parallel_simulate with {threads} threads: {visits_per_second:.0} visits/s, {:.2}x \
+             the speed with {baseline_threads} threads",
+            visits_per_second / baseline_visits_per_second
+        );
+    }
+}
+
+type Batch<const N: usize, const HALF_KOMI: i8> = (
+    Vec<Game<N, HALF_KOMI>>,
+    Vec<Vec<<Game<N, HALF_KOMI> as Environment>::Action>>,
+);
+
+/// Positions after a random number of random plies, with their legal moves.
+fn random_batch<const N: usize, const HALF_KOMI: i8>(
+    batch_size: usize,
+    rng: &mut impl Rng,
+) -> Batch<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    let mut envs = Vec::with_capacity(batch_size);
+    let mut actions = Vec::with_capacity(batch_size);
+    while envs.len() < batch_size {
+        let plies = rng.gen_range(0..=MAX_RANDOM_PLIES);
+        let mut env_actions = Vec::new();
+        let env = Game::new_opening_with_random_steps(rng, &mut env_actions, plies);
+        if env.terminal().is_some() {
+            continue;
+        }
+        env_actions.clear();
+        env.populate_actions(&mut env_actions);
+        envs.push(env);
+        actions.push(env_actions);
+    }
+    (envs, actions)
+}
+
+/// Targets of the positions of a batch with random policies and values.
+fn random_targets<const N: usize, const HALF_KOMI: i8>(
+    (envs, actions): &Batch<N, HALF_KOMI>,
+    rng: &mut impl Rng,
+) -> Vec<Target<Game<N, HALF_KOMI>>>
+where
+    Reserves<N>: Default,
+{
+    envs.iter()
+        .zip(actions)
+        .map(|(env, actions)| {
+            let mut env = env.clone();
+            // The text format does not store it.
+            env.reversible_plies = 0;
+            Target {
+                policy: actions
+                    .iter()
+                    .map(|action| (*action, NotNan::new(rng.gen()).expect("should not be NaN")))
+                    .collect(),
+                value: rng.gen_range(-1.0..=1.0),
+                ube: rng.gen(),
+                score: Some(rng.gen_range(-1.0..=1.0)),
+                ownership: Some(final_ownership(&env, env.to_move)),
+                result: Some(Eval::Win(rng.gen_range(0..100))),
+                env,
+            }
+        })
+        .collect()
+}
+
+/// Visits per second of a search of at least `visits` simulations
+/// for each position, where `simulate` returns the number of simulations.
+fn search_speed<E: Environment>(
+    positions: &[E],
+    visits: usize,
+    mut simulate: impl FnMut(&mut Node<E>, &E) -> usize,
+) -> f64 {
+    let start = Instant::now();
+    let mut total = 0;
+    for env in positions {
+        let mut root = Node::default();
+        let mut done = 0;
+        while done < visits {
+            done += simulate(&mut root, env);
+        }
+        total += done;
+    }
+    total as f64 / start.elapsed().as_secs_f64()
+}
+
+fn measure<T>(batches: &[T], f: impl Fn(&T)) -> Vec<Duration> {
+    batches
+        .iter()
+        .map(|batch| {
+            let start = Instant::now();
+            f(batch);
+            start.elapsed()
+        })
+        .collect()
+}
+
+fn report(name: &str, latencies: &[Duration], batch_size: usize) {
+    if latencies.is_empty() {
+        return;
+    }
+    let mut sorted = latencies.to_vec();
+    sorted.sort_unstable();
+    let percentile = |p: usize| sorted[(sorted.len() - 1) * p / 100].as_secs_f64() * 1000.0;
+    let total: Duration = latencies.iter().sum();
+    let positions_per_second = (latencies.len() * batch_size) as f64 / total.as_secs_f64();
+    println!(
+        "{name}: {positions_per_second:.0} positions/s, batch latency p50 {:.2} ms, p90 {:.2} ms, \
+         p99 {:.2} ms",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+    );
+}
@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use takzero::{
+    network::{
+        calibration::Calibration,
+        net5::{Env, Net},
+        parse_device,
+        repr::games_to_tensor,
+        Network,
+    },
+    search::env::{Environment, Terminal},
+    target::get_replays,
+};
+use tch::Device;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Model whose values are calibrated
+    #[arg(long)]
+    model: PathBuf,
+    /// Files with replays of completed games, like the `replays.txt`
+    /// written by `selfplay`
+    #[arg(long, required = true, num_args = 1..)]
+    replays: Vec<PathBuf>,
+    /// Where to save the calibrated model, the model is overwritten if absent
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Number of positions which are evaluated together
+    #[arg(long, default_value_t = 512)]
+    batch_size: usize,
+    /// Device to evaluate on (`cpu`, `cuda:N`, or `mps`)
+    #[arg(long, default_value = "cuda:0", value_parser = parse_device)]
+    device: Device,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+    log::info!("{args:?}");
+
+    let mut net = Net::load(&args.model, args.device).expect("model should be loadable");
+    let (positions, scores) = positions_with_scores(&args.replays);
+    if positions.is_empty() {
+        log::error!("There are no positions from completed games to calibrate with");
+        return;
+    }
+
+    let values: Vec<f32> = tch::no_grad(|| {
+        positions
+            .chunks(args.batch_size)
+            .flat_map(|batch| {
+                let xs = games_to_tensor(batch, args.device);
+                let (_, values, _) = net.forward_t(&xs, false);
+                Vec::<f32>::try_from(values.view([-1])).expect("values should be one-dimensional")
+            })
+            .collect()
+    });
+    let samples: Vec<_> = values.into_iter().zip(scores).collect();
+    let calibration = Calibration::fit(&samples).expect("there should be samples");
+    println!(
+        "Fitted scale {} and bias {} on {} positions, a value of 0 is a {:.1}% chance to win",
+        calibration.scale,
+        calibration.bias,
+        samples.len(),
+        calibration.win_probability(0.0) * 100.0
+    );
+
+    net.set_calibration(calibration);
+    let output = args.output.as_ref().unwrap_or(&args.model);
+    net.save(output).expect("model should be saveable");
+    log::info!("Saved the calibrated model to {}", output.display());
+}
+
+/// Every position before the end of a completed game, with the score which
+/// the player to move got: 1 for a win, 0.5 for a draw, and 0 for a loss.
+/// Games which were stopped early, for example by resignation, are skipped.
+fn positions_with_scores(paths: &[PathBuf]) -> (Vec<Env>, Vec<f32>) {
+    let mut positions = Vec::new();
+    let mut scores = Vec::new();
+    let mut incomplete = 0;
+    for path in paths {
+        let replays = get_replays(path).expect("replays should be readable");
+        for replay in replays {
+            let mut env = replay.env;
+            let mut game = Vec::with_capacity(replay.actions.len());
+            for action in replay.actions {
+                game.push(env.clone());
+                env.step(action);
+            }
+            let Some(terminal) = env.terminal() else {
+                incomplete += 1;
+                continue;
+            };
+            let last_score = match terminal {
+                Terminal::Win => 1.0,
+                Terminal::Loss => 0.0,
+                Terminal::Draw => 0.5,
+            };
+            // Players alternate, so the score flips every ply back from the end.
+            let length = game.len();
+            scores.extend((0..length).map(|i| {
+                if (length - i) % 2 == 0 {
+                    last_score
+                } else {
+                    1.0 - last_score
+                }
+            }));
+            positions.extend(game);
+        }
+    }
+    if incomplete > 0 {
+        log::info!("Skipped {incomplete} games which did not end");
+    }
+    (positions, scores)
+}
@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use fast_tak::{takparse::Tps, Game, Reserves};
+use ordered_float::NotNan;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use takzero::{
+    network::{net4_simhash, net5, net6_simhash, parse_device, Network},
+    search::{agent::Agent, book::OpeningBook, env::Environment},
+};
+use tch::Device;
+
+/// Largest number of random plies played to create a probe position.
+const MAX_RANDOM_PLIES: usize = 30;
+
+/// Compare the policies and values of two checkpoints on the same positions,
+/// and print the differences as JSON.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Earlier checkpoint
+    #[arg(long)]
+    old: PathBuf,
+    /// Later checkpoint
+    #[arg(long)]
+    new: PathBuf,
+    /// Board size (4, 5, or 6)
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(4..=6))]
+    size: u8,
+    /// Probe positions in the format of an opening book, one TPS or list of
+    /// moves per line. Random positions are generated if absent
+    #[arg(long)]
+    positions: Option<PathBuf>,
+    /// Number of random probe positions
+    #[arg(long, default_value_t = 1000)]
+    count: usize,
+    /// Seed for the random probe positions, so that every comparison
+    /// uses the same ones
+    #[arg(long, default_value_t = 123)]
+    seed: u64,
+    /// Number of positions which are evaluated together
+    #[arg(long, default_value_t = 128)]
+    batch_size: usize,
+    /// Device to evaluate on (`cpu`, `cuda:N`, or `mps`)
+    #[arg(long, default_value = "cuda:0", value_parser = parse_device)]
+    device: Device,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+    log::info!("{args:?}");
+
+    let report = tch::no_grad(|| match args.size {
+        4 => run::<net4_simhash::Net, { net4_simhash::N }, { net4_simhash::HALF_KOMI }>(&args),
+        5 => run::<net5::Net, { net5::N }, { net5::HALF_KOMI }>(&args),
+        6 => run::<net6_simhash::Net, { net6_simhash::N }, { net6_simhash::HALF_KOMI }>(&args),
+        _ => unreachable!("the size should be checked when parsing arguments"),
+    });
+    println!("{report}");
+}
+
+/// Difference between the checkpoints on one position.
+struct Drift {
+    tps: String,
+    /// KL-divergence of the new policy from the old one.
+    policy_kl: f64,
+    /// Absolute difference between the values.
+    value_error: f64,
+}
+
+fn run<NET, const N: usize, const HALF_KOMI: i8>(args: &Args) -> String
+where
+    NET: Network + Agent<Game<N, HALF_KOMI>>,
+    Reserves<N>: Default,
+{
+    let old = NET::load(&args.old, args.device).expect("old model should be loadable");
+    let new = NET::load(&args.new, args.device).expect("new model should be loadable");
+    let positions = match &args.positions {
+        Some(path) => OpeningBook::<Game<N, HALF_KOMI>>::load(path)
+            .expect("probe positions should be valid")
+            .positions()
+            .to_vec(),
+        None => random_positions(args.count, &mut StdRng::seed_from_u64(args.seed)),
+    };
+
+    let mut drifts = Vec::with_capacity(positions.len());
+    for envs in positions.chunks(args.batch_size.max(1)) {
+        let actions: Vec<_> = envs
+            .iter()
+            .map(|env| {
+                let mut actions = Vec::new();
+                env.populate_actions(&mut actions);
+                actions
+            })
+            .collect();
+        let old_outputs = old.policy_value_uncertainty(envs, &actions);
+        let new_outputs = new.policy_value_uncertainty(envs, &actions);
+        drifts.extend(envs.iter().zip(old_outputs.zip(new_outputs)).map(
+            |(env, ((old_policy, old_value, _), (new_policy, new_value, _)))| Drift {
+                tps: Tps::from(env.clone()).to_string(),
+                policy_kl: kl_divergence(&logits(&old_policy), &logits(&new_policy)),
+                value_error: f64::from((old_value - new_value).abs()),
+            },
+        ));
+    }
+    to_json(&drifts)
+}
+
+fn logits<A>(policy: &[(A, NotNan<f32>)]) -> Vec<f64> {
+    policy.iter().map(|(_, logit)| f64::from(**logit)).collect()
+}
+
+/// KL-divergence of the softmax of `q` from the softmax of `p`.
+fn kl_divergence(p: &[f64], q: &[f64]) -> f64 {
+    let (log_p, log_q) = (log_softmax(p), log_softmax(q));
+    log_p
+        .iter()
+        .zip(log_q)
+        .map(|(&log_p, log_q)| log_p.exp() * (log_p - log_q))
+        .sum::<f64>()
+        // Rounding can make the divergence of identical policies negative.
+        .max(0.0)
+}
+
+fn log_softmax(logits: &[f64]) -> Vec<f64> {
+    let max = logits.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let log_sum = logits.iter().map(|x| (x - max).exp()).sum::<f64>().ln() + max;
+    logits.iter().map(|x| x - log_sum).collect()
+}
+
+/// Positions after a random number of random plies, which are not over.
+fn random_positions<const N: usize, const HALF_KOMI: i8>(
+    count: usize,
+    rng: &mut impl Rng,
+) -> Vec<Game<N, HALF_KOMI>>
+where
+    Reserves<N>: Default,
+{
+    let mut positions = Vec::with_capacity(count);
+    let mut actions = Vec::new();
+    while positions.len() < count {
+        let plies = rng.gen_range(0..=MAX_RANDOM_PLIES);
+        let env = Game::new_opening_with_random_steps(rng, &mut actions, plies);
+        actions.clear();
+        if env.terminal().is_none() {
+            positions.push(env);
+        }
+    }
+    positions
+}
+
+fn to_json(drifts: &[Drift]) -> String {
+    let count = drifts.len().max(1) as f64;
+    let mean_policy_kl = drifts.iter().map(|drift| drift.policy_kl).sum::<f64>() / count;
+    let max_policy_kl = drifts
+        .iter()
+        .map(|drift| drift.policy_kl)
+        .fold(0.0, f64::max);
+    let value_mae = drifts.iter().map(|drift| drift.value_error).sum::<f64>() / count;
+    let max_value_error = drifts
+        .iter()
+        .map(|drift| drift.value_error)
+        .fold(0.0, f64::max);
+
+    let per_position: Vec<_> = drifts
+        .iter()
+        .map(|drift| {
+            serde_json::json!({
+                "tps": drift.tps,
+                "policy_kl": drift.policy_kl,
+                "value_error": drift.value_error,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "positions": drifts.len(),
+        "mean_policy_kl": mean_policy_kl,
+        "max_policy_kl": max_policy_kl,
+        "value_mae": value_mae,
+        "max_value_error": max_value_error,
+        "per_position": per_position,
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{kl_divergence, to_json, Drift};
+
+    #[test]
+    fn kl_divergence_of_policies() {
+        let policy = [1.0, -0.5, 2.0];
+        assert!(kl_divergence(&policy, &policy).abs() < 1e-12);
+        // Logits which differ by a constant give the same policy.
+        let shifted = policy.map(|logit| logit + 3.0);
+        assert!(kl_divergence(&policy, &shifted).abs() < 1e-12);
+
+        let other = [2.0, 0.0, -1.0];
+        let divergence = kl_divergence(&policy, &other);
+        assert!(divergence > 0.0);
+        assert!((divergence - kl_divergence(&other, &policy)).abs() > 1e-6);
+    }
+
+    #[test]
+    fn report_is_valid_json() {
+        let drifts = [
+            Drift {
+                tps: "x3/x3/x3 1 1".to_string(),
+                policy_kl: 0.5,
+                value_error: 0.25,
+            },
+            Drift {
+                tps: "1,x2/x3/x2,2 1 2".to_string(),
+                policy_kl: 1.5,
+                value_error: 0.75,
+            },
+        ];
+        let report: serde_json::Value = serde_json::from_str(&to_json(&drifts)).unwrap();
+        assert_eq!(report["positions"], 2);
+        assert_eq!(report["mean_policy_kl"], 1.0);
+        assert_eq!(report["max_value_error"], 0.75);
+        assert_eq!(report["per_position"][1]["tps"], "1,x2/x3/x2,2 1 2");
+    }
+}
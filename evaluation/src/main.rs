@@ -12,7 +12,7 @@ use takzero::{
     search::{
         agent::Agent,
         env::{Environment, Terminal},
-        node::{batched::BatchedMCTS, Node},
+        node::batched::BatchedMCTS,
     },
 };
 use tch::Device;
@@ -214,7 +214,7 @@ where
                 .zip(&done)
                 .filter(|(_, done)| **done)
                 .for_each(|(((node, other_env), (_, current_env)), _)| {
-                    *node = Node::default();
+                    node.clear();
                     *other_env = current_env.clone();
                 });
 
@@ -0,0 +1,273 @@
+use std::path::Path;
+
+use tch::{nn::VarStore, TchError, Tensor};
+
+pub const FILE_NAME: &str = "optimizer_latest.ot";
+
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+const EPSILON: f64 = 1e-8;
+/// Name of the saved number of model steps.
+const MODEL_STEPS: &str = "model_steps";
+/// Name of the saved number of optimizer steps.
+const STEPS: &str = "steps";
+
+/// A trainable variable with its Adam moments.
+struct Parameter {
+    name: String,
+    group: usize,
+    tensor: Tensor,
+    exp_avg: Tensor,
+    exp_avg_sq: Tensor,
+}
+
+/// Adam with parameter groups, the same as the one of `tch`, except that its
+/// moments can be saved and loaded, so that training continues where it
+/// stopped. The optimizers of `tch` live in libtorch and cannot be saved.
+pub struct Adam {
+    parameters: Vec<Parameter>,
+    learning_rates: Vec<f64>,
+    steps: i32,
+}
+
+impl Adam {
+    /// Optimize the trainable variables of `vs`, keeping the parameter groups
+    /// which they were given, with the same learning rate for all groups.
+    pub fn new(vs: &VarStore, learning_rate: f64) -> Self {
+        let variables = vs.variables_.lock().unwrap();
+        let names: std::collections::HashMap<_, _> = variables
+            .named_variables
+            .iter()
+            .map(|(name, tensor)| (tensor.data_ptr(), name.clone()))
+            .collect();
+        let parameters: Vec<_> = variables
+            .trainable_variables
+            .iter()
+            .map(|variable| Parameter {
+                name: names[&variable.tensor.data_ptr()].clone(),
+                group: variable.group,
+                tensor: variable.tensor.shallow_clone(),
+                exp_avg: variable.tensor.zeros_like(),
+                exp_avg_sq: variable.tensor.zeros_like(),
+            })
+            .collect();
+        let groups = parameters.iter().map(|p| p.group + 1).max().unwrap_or(1);
+        Self {
+            parameters,
+            learning_rates: vec![learning_rate; groups],
+            steps: 0,
+        }
+    }
+
+    pub fn zero_grad(&mut self) {
+        for parameter in &mut self.parameters {
+            parameter.tensor.zero_grad();
+        }
+    }
+
+    /// Scale the gradients down so that their global norm is at most `max`.
+    pub fn clip_grad_norm(&self, max: f64) {
+        tch::no_grad(|| {
+            let gradients: Vec<_> = self
+                .parameters
+                .iter()
+                .map(|parameter| parameter.tensor.grad())
+                .filter(Tensor::defined)
+                .collect();
+            let Some(norm) = gradients
+                .iter()
+                .map(|gradient| gradient.norm().square())
+                .reduce(|a, b| a + b)
+                .map(|sum| f64::try_from(sum.sqrt()).unwrap_or(f64::INFINITY))
+            else {
+                return;
+            };
+            let scale = max / (norm + 1e-6);
+            if scale < 1.0 {
+                for mut gradient in gradients {
+                    let _ = gradient.g_mul_scalar_(scale);
+                }
+            }
+        });
+    }
+
+    /// Set the learning rate of one parameter group.
+    /// Groups without parameters are ignored.
+    pub fn set_lr_group(&mut self, group: usize, learning_rate: f64) {
+        if let Some(rate) = self.learning_rates.get_mut(group) {
+            *rate = learning_rate;
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.steps += 1;
+        let bias_correction1 = 1.0 - BETA1.powi(self.steps);
+        let bias_correction2 = 1.0 - BETA2.powi(self.steps);
+        tch::no_grad(|| {
+            for parameter in &mut self.parameters {
+                let gradient = parameter.tensor.grad();
+                if !gradient.defined() {
+                    continue;
+                }
+                parameter.exp_avg *= BETA1;
+                parameter.exp_avg += &gradient * (1.0 - BETA1);
+                parameter.exp_avg_sq *= BETA2;
+                parameter.exp_avg_sq += gradient.square() * (1.0 - BETA2);
+                let denominator = parameter.exp_avg_sq.sqrt() / bias_correction2.sqrt() + EPSILON;
+                let step_size = self.learning_rates[parameter.group] / bias_correction1;
+                parameter.tensor -= &parameter.exp_avg / denominator * step_size;
+            }
+        });
+    }
+
+    pub fn backward_step(&mut self, loss: &Tensor) {
+        self.zero_grad();
+        loss.backward();
+        self.step();
+    }
+
+    /// Save the moments to the directory, together with the number of model
+    /// steps which they go with.
+    pub fn save(&self, directory: &Path, model_steps: usize) -> Result<(), TchError> {
+        let mut tensors = vec![
+            (MODEL_STEPS.to_string(), Tensor::from(model_steps as i64)),
+            (STEPS.to_string(), Tensor::from(i64::from(self.steps))),
+        ];
+        for parameter in &self.parameters {
+            tensors.push((
+                format!("exp_avg.{}", parameter.name),
+                parameter.exp_avg.shallow_clone(),
+            ));
+            tensors.push((
+                format!("exp_avg_sq.{}", parameter.name),
+                parameter.exp_avg_sq.shallow_clone(),
+            ));
+        }
+        let path = directory.join(FILE_NAME);
+        let temp_path = path.with_extension("ot.tmp");
+        Tensor::save_multi(&tensors, &temp_path)?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
+    }
+
+    /// Load the moments which were saved at `model_steps`.
+    /// Returns `Ok(false)` without changing anything if there are none.
+    pub fn load(&mut self, directory: &Path, model_steps: usize) -> Result<bool, TchError> {
+        let path = directory.join(FILE_NAME);
+        if !path.exists() {
+            return Ok(false);
+        }
+        let tensors: std::collections::HashMap<_, _> =
+            Tensor::load_multi(&path)?.into_iter().collect();
+        let get = |name: &str| {
+            tensors.get(name).ok_or_else(|| {
+                TchError::TensorNameNotFound(name.to_string(), path.display().to_string())
+            })
+        };
+        let saved_model_steps = get(MODEL_STEPS)?.int64_value(&[]);
+        if saved_model_steps != model_steps as i64 {
+            log::warn!(
+                "Optimizer state was saved at {saved_model_steps} steps but resuming from \
+                 {model_steps} steps, starting a new one"
+            );
+            return Ok(false);
+        }
+        let steps = i32::try_from(get(STEPS)?.int64_value(&[]))
+            .map_err(|err| TchError::Convert(err.to_string()))?;
+        tch::no_grad(|| {
+            for parameter in &mut self.parameters {
+                parameter
+                    .exp_avg
+                    .f_copy_(get(&format!("exp_avg.{}", parameter.name))?)?;
+                parameter
+                    .exp_avg_sq
+                    .f_copy_(get(&format!("exp_avg_sq.{}", parameter.name))?)?;
+            }
+            Ok::<_, TchError>(())
+        })?;
+        self.steps = steps;
+        Ok(true)
+    }
+
+    /// Number of steps that were taken, including those before loading.
+    pub const fn steps(&self) -> i32 {
+        self.steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tch::{
+        nn::{self, Module, OptimizerConfig, VarStore},
+        Device,
+        Kind,
+        Tensor,
+    };
+
+    use super::Adam;
+
+    fn loss(linear: &nn::Linear) -> Tensor {
+        let xs = Tensor::from_slice(&[1.0_f32, -2.0, 3.0, 0.5]).view([1, 4]);
+        (linear.forward(&xs) - 1.0).square().sum(Kind::Float)
+    }
+
+    #[test]
+    fn steps_match_the_adam_of_tch() {
+        let vs = VarStore::new(Device::Cpu);
+        let linear = nn::linear(vs.root() / "linear", 4, 2, nn::LinearConfig::default());
+        let mut other_vs = VarStore::new(Device::Cpu);
+        let other = nn::linear(
+            other_vs.root() / "linear",
+            4,
+            2,
+            nn::LinearConfig::default(),
+        );
+        other_vs.copy(&vs).unwrap();
+
+        let mut adam = Adam::new(&vs, 1e-2);
+        let mut reference = nn::Adam::default().build(&other_vs, 1e-2).unwrap();
+        for _ in 0..10 {
+            adam.backward_step(&loss(&linear));
+            reference.backward_step(&loss(&other));
+        }
+        assert!(linear.ws.allclose(&other.ws, 1e-5, 1e-6, false));
+        assert!(linear.bs.as_ref().unwrap().allclose(
+            other.bs.as_ref().unwrap(),
+            1e-5,
+            1e-6,
+            false
+        ));
+    }
+
+    #[test]
+    fn moments_are_restored_after_loading() {
+        let directory = std::env::temp_dir().join("takzero-adam");
+        std::fs::create_dir_all(&directory).unwrap();
+        let vs = VarStore::new(Device::Cpu);
+        let linear = nn::linear(vs.root() / "linear", 4, 2, nn::LinearConfig::default());
+        let mut adam = Adam::new(&vs, 1e-2);
+        for _ in 0..5 {
+            adam.backward_step(&loss(&linear));
+        }
+        adam.save(&directory, 123).unwrap();
+
+        let mut resumed_vs = VarStore::new(Device::Cpu);
+        let resumed = nn::linear(
+            resumed_vs.root() / "linear",
+            4,
+            2,
+            nn::LinearConfig::default(),
+        );
+        resumed_vs.copy(&vs).unwrap();
+        let mut resumed_adam = Adam::new(&resumed_vs, 1e-2);
+        // Moments of another step are not used.
+        assert!(!resumed_adam.load(&directory, 124).unwrap());
+        assert!(resumed_adam.load(&directory, 123).unwrap());
+        assert_eq!(resumed_adam.steps(), 5);
+
+        adam.backward_step(&loss(&linear));
+        resumed_adam.backward_step(&loss(&resumed));
+        assert!(linear.ws.equal(&resumed.ws));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
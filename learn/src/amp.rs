@@ -0,0 +1,141 @@
+use tch::{nn::VarStore, Device, Kind, Tensor};
+
+const INITIAL_SCALE: f64 = 65536.0;
+const GROWTH_INTERVAL: usize = 2000;
+
+/// Loss scaling for mixed-precision training.
+///
+/// Gradients of an fp16 forward pass easily underflow, so the loss is
+/// multiplied by a large factor before the backward pass and the gradients
+/// are divided by it again before the optimizer step. The master weights
+/// stay in fp32. The factor is halved whenever the gradients overflow,
+/// and doubled after a long enough run without overflow.
+#[derive(Debug)]
+pub struct GradScaler {
+    scale: f64,
+    good_steps: usize,
+}
+
+impl GradScaler {
+    /// Returns `None` if mixed precision is not supported on the device,
+    /// in which case training should stay in fp32.
+    pub fn new(device: Device) -> Option<Self> {
+        if !device.is_cuda() {
+            log::warn!("Mixed precision is only supported on CUDA, training in fp32 on {device:?}");
+            return None;
+        }
+        Some(Self {
+            scale: INITIAL_SCALE,
+            good_steps: 0,
+        })
+    }
+
+    pub fn scale(&self, loss: &Tensor) -> Tensor {
+        loss * self.scale
+    }
+
+    /// Undo the scaling of the gradients and update the scale.
+    /// Returns `false` if the gradients overflowed, in which case
+    /// the optimizer step should be skipped.
+    pub fn unscale(&mut self, vs: &VarStore) -> bool {
+        let gradients: Vec<_> = vs
+            .trainable_variables()
+            .iter()
+            .map(Tensor::grad)
+            .filter(Tensor::defined)
+            .collect();
+        let overflowed = tch::no_grad(|| {
+            let mut non_finite = Tensor::zeros([], (Kind::Int64, vs.device()));
+            for mut gradient in gradients {
+                let _ = gradient.g_mul_scalar_(self.scale.recip());
+                non_finite += gradient.isfinite().logical_not().sum(Kind::Int64);
+            }
+            i64::try_from(non_finite).unwrap_or(1) > 0
+        });
+
+        if overflowed {
+            self.scale /= 2.0;
+            self.good_steps = 0;
+            log::warn!(
+                "Gradients overflowed, lowering loss scale to {}",
+                self.scale
+            );
+            return false;
+        }
+        self.good_steps += 1;
+        if self.good_steps >= GROWTH_INTERVAL {
+            self.scale *= 2.0;
+            self.good_steps = 0;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tch::{
+        nn::{self, Module, OptimizerConfig},
+        Device,
+        Kind,
+        Tensor,
+    };
+
+    use super::GradScaler;
+
+    /// Train a small network on a fixed batch and return the losses.
+    fn losses(amp: bool) -> Vec<f64> {
+        tch::manual_seed(123);
+        let device = Device::Cuda(0);
+        let vs = nn::VarStore::new(device);
+        let net = nn::seq()
+            .add(nn::linear(
+                vs.root() / "hidden",
+                32,
+                64,
+                nn::LinearConfig::default(),
+            ))
+            .add_fn(Tensor::relu)
+            .add(nn::linear(
+                vs.root() / "output",
+                64,
+                1,
+                nn::LinearConfig::default(),
+            ))
+            .add_fn(Tensor::tanh);
+        let mut opt = nn::Adam::default().build(&vs, 1e-3).unwrap();
+        let mut scaler = if amp { GradScaler::new(device) } else { None };
+
+        let xs = Tensor::randn([256, 32], (Kind::Float, device));
+        let ys = xs.sum_dim_intlist(1, true, None).tanh();
+        (0..20)
+            .map(|_| {
+                let output = tch::autocast(amp, || net.forward(&xs)).to_kind(Kind::Float);
+                let loss = (&output - &ys).square().mean(Kind::Float);
+                opt.zero_grad();
+                if let Some(scaler) = &mut scaler {
+                    scaler.scale(&loss).backward();
+                    if scaler.unscale(&vs) {
+                        opt.step();
+                    }
+                } else {
+                    loss.backward();
+                    opt.step();
+                }
+                f64::try_from(loss).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn amp_losses_are_close_to_fp32() {
+        if !tch::Cuda::is_available() {
+            return;
+        }
+        let fp32 = losses(false);
+        let amp = losses(true);
+        assert!(fp32.last() < fp32.first());
+        for (fp32, amp) in fp32.into_iter().zip(amp) {
+            assert!((fp32 - amp).abs() < 0.05 * fp32.max(1e-3), "{fp32} {amp}");
+        }
+    }
+}
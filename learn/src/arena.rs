@@ -0,0 +1,147 @@
+use fast_tak::{takparse::Color, GameResult};
+use rand::Rng;
+use takzero::{
+    network::net6_simhash::{Env, Net},
+    search::{agent::Agent, env::Environment, node::Node, SearchConfig},
+};
+
+/// Games longer than this are counted as draws.
+const MAX_PLIES: usize = 200;
+/// How quickly the Elo estimate follows new results.
+const ELO_K: f64 = 16.0;
+
+/// Win, draw, and loss counts from the perspective of the current net.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Record {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/// Plays the current net against a frozen reference net.
+pub struct Arena {
+    reference: Net,
+    games: usize,
+    visits: u32,
+    config: SearchConfig,
+    total: Record,
+    /// Elo of the current net relative to the reference.
+    elo: f64,
+}
+
+impl Arena {
+    pub const fn new(reference: Net, games: usize, visits: u32, config: SearchConfig) -> Self {
+        Self {
+            reference,
+            games,
+            visits,
+            config,
+            total: Record {
+                wins: 0,
+                draws: 0,
+                losses: 0,
+            },
+            elo: 0.0,
+        }
+    }
+
+    /// Play the games, alternating colors, and log the results.
+    pub fn evaluate(&mut self, net: &Net, model_steps: usize, rng: &mut impl Rng) {
+        let mut record = Record::default();
+        let mut actions = Vec::new();
+        let mut opening = Env::default();
+        tch::no_grad(|| {
+            for game in 0..self.games {
+                // Both colors get to play the same opening.
+                if game % 2 == 0 {
+                    opening = Env::new_opening_with_random_steps(rng, &mut actions, 2);
+                }
+                let color = if game % 2 == 0 {
+                    Color::White
+                } else {
+                    Color::Black
+                };
+                let score = match play_game(
+                    net,
+                    &self.reference,
+                    color,
+                    opening.clone(),
+                    self.visits,
+                    &self.config,
+                ) {
+                    Some(winner) if winner == color => {
+                        record.wins += 1;
+                        1.0
+                    }
+                    Some(_) => {
+                        record.losses += 1;
+                        0.0
+                    }
+                    None => {
+                        record.draws += 1;
+                        0.5
+                    }
+                };
+                let expected = 1.0 / (1.0 + 10f64.powf(-self.elo / 400.0));
+                self.elo = ELO_K.mul_add(score - expected, self.elo);
+            }
+        });
+        self.total.wins += record.wins;
+        self.total.draws += record.draws;
+        self.total.losses += record.losses;
+        #[rustfmt::skip]
+        log::info!(
+            "Evaluation against reference.\n\
+             Training steps: {model_steps}\n\
+             Record (W/D/L): {}/{}/{}\n\
+             Total record (W/D/L): {}/{}/{}\n\
+             Elo: {:+.0}",
+            record.wins, record.draws, record.losses,
+            self.total.wins, self.total.draws, self.total.losses,
+            self.elo
+        );
+    }
+}
+
+/// Play a single game with a fixed number of visits per move.
+/// Returns the winner, or `None` for a draw.
+fn play_game(
+    net: &Net,
+    reference: &Net,
+    color: Color,
+    mut env: Env,
+    visits: u32,
+    config: &SearchConfig,
+) -> Option<Color> {
+    let mut net_node = Node::default();
+    let mut reference_node = Node::default();
+    for _ in 0..MAX_PLIES {
+        match env.result() {
+            GameResult::Winner { color: winner, .. } => return Some(winner),
+            GameResult::Draw { .. } => return None,
+            GameResult::Ongoing => {}
+        }
+        let action = if env.to_move == color {
+            search(net, &mut net_node, &env, visits, config)
+        } else {
+            search(reference, &mut reference_node, &env, visits, config)
+        };
+        net_node.descend(&action);
+        reference_node.descend(&action);
+        env.step(action);
+    }
+    None
+}
+
+fn search<A: Agent<Env>>(
+    agent: &A,
+    node: &mut Node<Env>,
+    env: &Env,
+    visits: u32,
+    config: &SearchConfig,
+) -> <Env as Environment>::Action {
+    for _ in 0..visits {
+        node.simulate_with_config(agent, env.clone(), config);
+    }
+    node.select_best_action()
+}
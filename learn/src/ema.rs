@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use takzero::network::{net6_simhash::Net, Network};
+
+pub const FILE_NAME: &str = "model_latest_ema.ot";
+
+/// Exponential moving average of the weights of the training network.
+///
+/// The averaged network has its own `VarStore`, so it is not touched by
+/// the optimizer. Variables which are not trained, like the running
+/// statistics of batch norm, are copied as they are.
+pub struct Ema {
+    net: Net,
+    decay: f64,
+}
+
+impl Ema {
+    /// Start from the EMA saved in `directory` if there is one,
+    /// and from a copy of `net` otherwise.
+    pub fn new(net: &Net, decay: f64, directory: &Path) -> Self {
+        let device = net.vs().device();
+        let path = directory.join(FILE_NAME);
+        let mut ema = if path.exists() {
+            match Net::load(&path, device) {
+                Ok(ema) => ema,
+                Err(err) => {
+                    log::warn!(
+                        "Could not load {}, starting a new EMA: {err}",
+                        path.display()
+                    );
+                    net.clone(device)
+                }
+            }
+        } else {
+            net.clone(device)
+        };
+        ema.vs_mut().freeze();
+        Self { net: ema, decay }
+    }
+
+    pub const fn net(&self) -> &Net {
+        &self.net
+    }
+
+    /// Move the average towards the weights of `net`.
+    pub fn update(&mut self, net: &Net) {
+        let source = net.vs().variables();
+        tch::no_grad(|| {
+            for (name, mut average) in self.net.vs().variables() {
+                let Some(current) = source.get(&name) else {
+                    continue;
+                };
+                if current.requires_grad() {
+                    average *= self.decay;
+                    average += current * (1.0 - self.decay);
+                } else {
+                    average.copy_(current);
+                }
+            }
+        });
+    }
+
+    pub fn save(&self, directory: &Path) {
+        if let Err(err) = self.net.save(directory.join(FILE_NAME)) {
+            log::error!("Could not save EMA model: {err}");
+        }
+    }
+}
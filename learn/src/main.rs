@@ -1,31 +1,74 @@
 use std::{
     cmp::Reverse,
+    collections::{hash_map::Entry, HashMap},
     fmt,
     fs::{read_dir, OpenOptions},
-    io::{BufRead, BufReader, Read, Seek, Write},
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, Write},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
     time::{Duration, Instant},
 };
 
-use clap::Parser;
+use adam::Adam;
+use amp::GradScaler;
+use arena::Arena;
+use clap::{error::ErrorKind as ClapErrorKind, CommandFactory, Parser, ValueEnum};
+use ema::Ema;
+use fast_tak::takparse::Move;
+use flate2::bufread::GzDecoder;
+use metrics::{Losses, Metrics};
 use ordered_float::NotNan;
 use rand::prelude::*;
+use sum_tree::SumTree;
 use takzero::{
     network::{
-        net6_simhash::{Env, Net, MAXIMUM_VARIANCE, N},
-        repr::{game_to_tensor, move_mask, output_size, policy_tensor},
-        HashNetwork,
+        generic::{
+            ownership_loss,
+            ownership_target,
+            result_wdl_target,
+            score_loss,
+            score_target,
+            short_value_loss,
+            short_value_target,
+            wdl_loss,
+        },
+        net6_simhash::{Env, Net, HALF_KOMI, MAXIMUM_VARIANCE, N},
+        parse_device,
+        repr::{canonicalize, games_to_tensor, move_mask, output_size, policy_tensor},
+        AuxiliaryHeads,
         Network,
+        TrainingNetwork,
+        PARAMETER_GROUPS,
+    },
+    search::{
+        agent::Agent,
+        env::{Environment, Transposable},
+        eval::Eval,
+        parse_discount,
+        SearchConfig,
+        DISCOUNT_FACTOR,
+    },
+    target::{
+        final_ownership,
+        final_score,
+        socket::TargetReceiver,
+        write_targets,
+        Augment,
+        Target,
     },
-    search::{agent::Agent, env::Environment, eval::Eval},
-    target::{Augment, Target},
-};
-use tch::{
-    nn::{Adam, Optimizer, OptimizerConfig},
-    Device,
-    Kind,
-    Tensor,
 };
+use tch::{nn::VarStore, Device, Kind, Tensor};
+use throughput::Throughput;
+use watch::TargetWatcher;
+
+mod adam;
+mod amp;
+mod arena;
+mod ema;
+mod metrics;
+mod sum_tree;
+mod throughput;
+mod watch;
 
 // use crate::rnd_normalization::{reference_games, update_rnd};
 // mod rnd_normalization;
@@ -39,7 +82,6 @@ const _: () = assert_env::<Env>();
 #[rustfmt::skip] #[allow(dead_code)] const fn assert_net<NET: Network + Agent<Env>>() {}
 const _: () = assert_net::<Net>();
 
-const DEVICE: Device = Device::Cuda(0);
 const BATCH_SIZE: usize = 128;
 const STEPS_PER_SAVE: usize = 100;
 const STEPS_PER_CHECKPOINT: usize = 50_000;
@@ -50,29 +92,551 @@ const INITIAL_RANDOM_TARGETS: usize = BATCH_SIZE * 2_000;
 const PRE_TRAINING_STEPS: usize = 1_000;
 const _: () = assert!(INITIAL_RANDOM_TARGETS >= PRE_TRAINING_STEPS * BATCH_SIZE);
 
-// Buffers
+// Buffers (defaults, see `BufferConfig`)
 const STEPS_BEFORE_REANALYZE: usize = 5000;
 const MIN_SELFPLAY_BUFFER_LEN: usize = 10_000;
-const _: () = assert!(MIN_SELFPLAY_BUFFER_LEN >= BATCH_SIZE);
 const MIN_REANALYZE_BUFFER_LEN: usize = 2_000;
-const _: () = assert!(MIN_REANALYZE_BUFFER_LEN >= BATCH_SIZE);
+const REANALYZE_BATCH_FRACTION: f64 = 0.5;
 const SELFPLAY_TARGET_FORCED_USES: u32 = 4;
 const REANALYZE_TARGET_FORCED_USES: u32 = 4;
 const MIN_TIME_BETWEEN_BUFFER_READS: Duration = Duration::from_secs(10);
 const SLEEP_WHEN_NOT_ENOUGH_TARGETS: Duration = Duration::from_secs(30);
 
+// Set when an interrupt is received, so that training can stop gracefully.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
 // Target
 const MINIMUM_UBE_TARGET: f64 = -10.0;
 
+// Prioritized replay
+/// New targets start with the largest possible squared value error.
+const MAX_PRIORITY: f32 = 4.0;
+/// Keep every target possible to sample.
+const MIN_PRIORITY: f32 = 1e-3;
+
 #[derive(Parser, Debug)]
 struct Args {
     /// Directory where to find targets
     /// and also where to save models.
     #[arg(long)]
     directory: PathBuf,
+    /// Device to train on (`cpu`, `cuda:N`, or `mps`).
+    #[arg(long, default_value = "cuda:0", value_parser = parse_device)]
+    device: Device,
+    /// Use deterministic CUDA kernels, which is slower,
+    /// see `takzero::network::set_deterministic`.
+    #[arg(long)]
+    deterministic: bool,
+    /// Seed for sampling, augmentation, and initializing the network,
+    /// random if not given. Together with `--deterministic` and the same
+    /// targets, a seed reproduces a run on the same hardware.
+    #[arg(long)]
+    seed: Option<u64>,
     /// Targets to use for resuming after restart.
     #[arg(long)]
     restart_targets: Option<PathBuf>,
+    /// Number of steps to linearly warm up the learning rate.
+    #[arg(long)]
+    warmup_steps: Option<usize>,
+    /// Peak learning rate (reached after warmup).
+    #[arg(long)]
+    lr_max: Option<f64>,
+    /// Final learning rate at the end of the cosine decay.
+    #[arg(long)]
+    lr_min: Option<f64>,
+    /// Total number of steps over which to decay the learning rate.
+    #[arg(long)]
+    total_steps: Option<usize>,
+    /// Maximum global gradient norm (no clipping by default).
+    #[arg(long, default_value_t = f64::INFINITY)]
+    grad_clip: f64,
+    /// Decoupled weight decay like in `AdamW`, which is applied to the
+    /// weights of convolutions and linear layers, but not to biases and
+    /// normalization parameters (0 disables).
+    #[arg(long, default_value_t = 0.0)]
+    weight_decay: f64,
+    /// Multiplier of the learning rate of the shared core.
+    #[arg(long, default_value_t = 1.0)]
+    lr_mult_core: f64,
+    /// Multiplier of the learning rate of the policy head.
+    #[arg(long, default_value_t = 1.0)]
+    lr_mult_policy: f64,
+    /// Multiplier of the learning rate of the value head.
+    #[arg(long, default_value_t = 1.0)]
+    lr_mult_value: f64,
+    /// Multiplier of the learning rate of the UBE head.
+    #[arg(long, default_value_t = 1.0)]
+    lr_mult_ube: f64,
+    /// Multiplier of the learning rate of the RND networks.
+    #[arg(long, default_value_t = 1.0)]
+    lr_mult_rnd: f64,
+    /// Train with mixed precision on CUDA (stays in fp32 on other devices).
+    #[arg(long)]
+    amp: bool,
+    /// Weight of the UBE loss term (0 disables UBE training).
+    #[arg(long, default_value_t = 1.0)]
+    ube_weight: f64,
+    /// Weight of the loss term of the win/draw/loss value head
+    /// (0 disables it). The network must have the head otherwise.
+    #[arg(long, default_value_t = 0.0)]
+    wdl_weight: f64,
+    /// Weight of the loss term of the short-horizon value head
+    /// (0 disables it). The network must have the head otherwise.
+    #[arg(long, default_value_t = 0.0)]
+    short_value_weight: f64,
+    /// Discount per ply of the target of the short-horizon value head,
+    /// in `(0, 1]`. Only targets which know the result of their game have one.
+    #[arg(long, default_value_t = 0.9, value_parser = parse_discount)]
+    short_value_discount: f32,
+    /// Weight of the score loss term (0 disables it).
+    /// The network must have a score head otherwise.
+    #[arg(long, default_value_t = 0.0)]
+    score_weight: f64,
+    /// Weight of the ownership loss term (0 disables it).
+    /// The network must have an ownership head otherwise.
+    #[arg(long, default_value_t = 0.0)]
+    ownership_weight: f64,
+    /// Loss of the value head.
+    #[arg(long, value_enum, default_value_t = ValueLoss::Mse)]
+    value_loss: ValueLoss,
+    /// Error above which the Huber value loss becomes linear.
+    #[arg(long, default_value_t = 1.0, value_parser = parse_huber_delta)]
+    huber_delta: f64,
+    /// Abort if more than this many steps in a row are skipped
+    /// because of a non-finite loss.
+    #[arg(long, default_value_t = 10)]
+    max_skipped_steps: usize,
+    /// Number of most recent checkpoints to keep (0 keeps all of them).
+    #[arg(long, default_value_t = 0)]
+    keep_checkpoints: usize,
+    /// Sample selfplay targets proportionally to their value loss.
+    #[arg(long)]
+    prioritized_replay: bool,
+    /// Exponent applied to priorities when sampling.
+    #[arg(long, default_value_t = 0.6)]
+    per_alpha: f64,
+    /// Exponent of the importance-sampling weight correction,
+    /// which applies to every term of the loss.
+    #[arg(long, default_value_t = 0.4)]
+    per_beta: f64,
+    /// Mix this much of a uniform distribution over the legal moves
+    /// into the policy target.
+    #[arg(long, default_value_t = 0.0)]
+    policy_label_smoothing: f32,
+    /// Raise the policy target to the power of one over this temperature
+    /// before normalizing it, so that higher temperatures flatten it.
+    #[arg(long, default_value_t = 1.0)]
+    policy_target_temp: f32,
+    /// Add all 8 symmetries of each selfplay target to the buffer.
+    #[arg(long)]
+    augment_expand: bool,
+    /// Merge the targets read at once which are the same position up to
+    /// symmetry into one, averaging their policies and values.
+    #[arg(long, conflicts_with = "augment_expand")]
+    dedup_targets: bool,
+    /// Abort if more than this fraction of the targets read at once
+    /// cannot be parsed (never aborts by default).
+    #[arg(long)]
+    max_corrupt_ratio: Option<f64>,
+    /// Number of training steps before reanalyze targets are used.
+    #[arg(long, default_value_t = STEPS_BEFORE_REANALYZE)]
+    steps_before_reanalyze: usize,
+    /// Minimum number of selfplay targets needed to take a step.
+    #[arg(long, default_value_t = MIN_SELFPLAY_BUFFER_LEN)]
+    min_selfplay_buffer_len: usize,
+    /// Minimum number of reanalyze targets needed to take a step.
+    #[arg(long, default_value_t = MIN_REANALYZE_BUFFER_LEN)]
+    min_reanalyze_buffer_len: usize,
+    /// Keep at most this many selfplay targets, dropping the oldest.
+    #[arg(long)]
+    max_selfplay_buffer_len: Option<usize>,
+    /// Keep at most this many reanalyze targets, dropping the oldest.
+    #[arg(long)]
+    max_reanalyze_buffer_len: Option<usize>,
+    /// Fraction of each batch taken from reanalyze targets, once they are used.
+    #[arg(long, default_value_t = REANALYZE_BATCH_FRACTION)]
+    reanalyze_batch_fraction: f64,
+    /// Weight of the losses of selfplay targets,
+    /// which changes their emphasis without changing the batch fraction.
+    #[arg(long, default_value_t = 1.0)]
+    exploitation_weight: f32,
+    /// Weight of the losses of reanalyze targets.
+    #[arg(long, default_value_t = 1.0)]
+    reanalyze_weight: f32,
+    /// Halve the loss weight of targets for every this many steps since
+    /// they were loaded (0 disables it).
+    #[arg(long, default_value_t = 0)]
+    target_staleness_halflife: usize,
+    /// Accumulate gradients over this many batches before each step.
+    /// Steps, and with them saves and checkpoints, count these larger steps.
+    #[arg(long, default_value_t = 1)]
+    accum_steps: usize,
+    /// Write the losses, learning rate, and buffer sizes of every step
+    /// to `metrics.csv` in the directory.
+    #[arg(long)]
+    metrics: bool,
+    /// Reference model to evaluate the current model against.
+    #[arg(long, requires = "eval_interval")]
+    eval_reference: Option<PathBuf>,
+    /// Play against the reference model every this many steps.
+    #[arg(
+        long,
+        requires = "eval_reference",
+        value_parser = clap::value_parser!(usize).range(1..)
+    )]
+    eval_interval: Option<usize>,
+    /// Number of games to play in each evaluation.
+    #[arg(long, default_value_t = 20)]
+    eval_games: usize,
+    /// Number of visits per move in evaluation games.
+    #[arg(long, default_value_t = 64)]
+    eval_visits: u32,
+    /// Decay of the exponential moving average of the weights, which is
+    /// saved as `model_latest_ema.ot` and used for evaluation (0 disables).
+    #[arg(long, default_value_t = 0.0)]
+    ema_decay: f64,
+    /// Discount per ply of the value, in `(0, 1]`. It is used for the
+    /// value targets of pre-training and for the search in evaluation games.
+    /// Selfplay and reanalyze take the same `--discount`, which should match.
+    #[arg(long, default_value_t = DISCOUNT_FACTOR, value_parser = parse_discount)]
+    discount: f32,
+    /// Where selfplay targets come from. Reanalyze targets are always
+    /// read from `targets-reanalyze.txt`.
+    #[arg(long, value_enum, default_value_t = TargetSource::File)]
+    target_source: TargetSource,
+    /// Address to receive selfplay targets on with `--target-source socket`.
+    #[arg(long, default_value = "0.0.0.0:7070")]
+    target_address: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TargetSource {
+    /// Read `targets-selfplay.txt`, or `targets-selfplay.bin` if it exists,
+    /// and the shards in the directory. Any of them can be gzip compressed,
+    /// with an additional `.gz` extension.
+    File,
+    /// Receive targets from selfplay workers started with
+    /// `--target-address`, see `takzero::target::socket`.
+    Socket,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ValueLoss {
+    /// Squared error.
+    Mse,
+    /// Squared error for small errors and absolute error for large ones,
+    /// which is less sensitive to mislabeled values.
+    Huber,
+}
+
+/// Settings which control how a single training step is taken.
+#[derive(Debug, Clone, Copy)]
+struct StepConfig {
+    grad_clip: f64,
+    weight_decay: f64,
+    /// Learning rate multiplier of every optimizer parameter group, see
+    /// [`assign_lr_groups`], or `None` for groups without any variables,
+    /// which the optimizer does not have.
+    lr_multipliers: [Option<f64>; LR_GROUPS],
+    ube_weight: f64,
+    wdl_weight: f64,
+    short_value_weight: f64,
+    short_value_discount: f32,
+    score_weight: f64,
+    ownership_weight: f64,
+    max_skipped_steps: usize,
+    value_loss: ValueLoss,
+    huber_delta: f64,
+}
+
+impl StepConfig {
+    /// Fails if an auxiliary loss term has a weight but the network does not
+    /// have its head, since the weight would silently do nothing.
+    fn from_args(
+        args: &Args,
+        populated_groups: [bool; LR_GROUPS],
+        heads: AuxiliaryHeads,
+    ) -> Result<Self, String> {
+        for (name, weight, has_head) in [
+            ("wdl", args.wdl_weight, heads.wdl),
+            ("short value", args.short_value_weight, heads.short_value),
+            ("score", args.score_weight, heads.score),
+            ("ownership", args.ownership_weight, heads.ownership),
+        ] {
+            if weight != 0.0 && !has_head {
+                return Err(format!(
+                    "{name} loss weight is {weight}, but the network has no {name} head"
+                ));
+            }
+        }
+        let multipliers = [
+            args.lr_mult_core,
+            args.lr_mult_policy,
+            args.lr_mult_value,
+            args.lr_mult_ube,
+            args.lr_mult_rnd,
+            1.0,
+        ];
+        Ok(Self {
+            grad_clip: args.grad_clip,
+            weight_decay: args.weight_decay,
+            lr_multipliers: std::array::from_fn(|group| {
+                populated_groups[group].then_some(multipliers[group])
+            }),
+            ube_weight: args.ube_weight,
+            wdl_weight: args.wdl_weight,
+            short_value_weight: args.short_value_weight,
+            short_value_discount: args.short_value_discount,
+            score_weight: args.score_weight,
+            ownership_weight: args.ownership_weight,
+            max_skipped_steps: args.max_skipped_steps,
+            value_loss: args.value_loss,
+            huber_delta: args.huber_delta,
+        })
+    }
+
+    /// Learning rate of every parameter group which has variables.
+    fn group_learning_rates(&self, learning_rate: f64) -> impl Iterator<Item = (usize, f64)> + '_ {
+        self.lr_multipliers
+            .iter()
+            .enumerate()
+            .filter_map(move |(group, multiplier)| Some((group, learning_rate * (*multiplier)?)))
+    }
+}
+
+/// Settings for prioritized experience replay.
+#[derive(Debug, Clone, Copy)]
+struct ReplayConfig {
+    prioritized: bool,
+    alpha: f64,
+    beta: f64,
+}
+
+impl ReplayConfig {
+    const fn from_args(args: &Args) -> Self {
+        Self {
+            prioritized: args.prioritized_replay,
+            alpha: args.per_alpha,
+            beta: args.per_beta,
+        }
+    }
+
+    /// Priority of the target raised to `alpha`, which it is sampled by.
+    fn scaled_priority(&self, target: &TargetWithContext) -> f64 {
+        f64::from(target.priority).powf(self.alpha)
+    }
+}
+
+/// How the policy target is changed before training on it.
+#[derive(Debug, Clone, Copy)]
+struct PolicyTargetConfig {
+    label_smoothing: f32,
+    temperature: f32,
+}
+
+impl PolicyTargetConfig {
+    fn from_args(args: &Args) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&args.policy_label_smoothing) {
+            return Err(format!(
+                "policy label smoothing must be between 0 and 1, got {}",
+                args.policy_label_smoothing
+            ));
+        }
+        if args.policy_target_temp <= 0.0 || !args.policy_target_temp.is_finite() {
+            return Err(format!(
+                "policy target temperature must be positive, got {}",
+                args.policy_target_temp
+            ));
+        }
+        Ok(Self {
+            label_smoothing: args.policy_label_smoothing,
+            temperature: args.policy_target_temp,
+        })
+    }
+
+    /// Apply the temperature and then the label smoothing.
+    /// Only the moves in the target, which are the legal moves,
+    /// receive any probability.
+    fn apply(&self, policy: &[(Move, NotNan<f32>)]) -> Vec<(Move, NotNan<f32>)> {
+        if self.label_smoothing == 0.0 && (self.temperature - 1.0).abs() < f32::EPSILON {
+            return policy.to_vec();
+        }
+        let exponent = self.temperature.recip();
+        let powered: Vec<f32> = policy
+            .iter()
+            .map(|(_, p)| p.into_inner().powf(exponent))
+            .collect();
+        let sum: f32 = powered.iter().sum();
+        let uniform = (policy.len() as f32).recip();
+        policy
+            .iter()
+            .zip(powered)
+            .map(|((action, _), p)| {
+                let p = if sum > 0.0 { p / sum } else { uniform };
+                let smoothed = self.label_smoothing.mul_add(uniform - p, p);
+                (
+                    *action,
+                    NotNan::new(smoothed).expect("policy target should not be NaN"),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Sizes of the buffers and how batches are split between them.
+#[derive(Debug, Clone, Copy)]
+struct BufferConfig {
+    steps_before_reanalyze: usize,
+    min_selfplay_len: usize,
+    min_reanalyze_len: usize,
+    max_selfplay_len: Option<usize>,
+    max_reanalyze_len: Option<usize>,
+    /// Number of reanalyze targets in a batch, once they are used.
+    reanalyze_amount: usize,
+    /// Loss weights of the targets from each buffer.
+    exploitation_weight: f32,
+    reanalyze_weight: f32,
+    /// Steps after which the loss weight of a target is halved.
+    staleness_halflife: Option<usize>,
+    /// Number of sub-batches which are sampled for every step.
+    accum_steps: usize,
+}
+
+impl BufferConfig {
+    #[allow(clippy::cast_sign_loss)]
+    fn from_args(args: &Args) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&args.reanalyze_batch_fraction) {
+            return Err(format!(
+                "reanalyze batch fraction must be between 0 and 1, got {}",
+                args.reanalyze_batch_fraction
+            ));
+        }
+        if args.accum_steps == 0 {
+            return Err("the number of accumulation steps must be at least 1".to_string());
+        }
+        for (name, weight) in [
+            ("exploitation", args.exploitation_weight),
+            ("reanalyze", args.reanalyze_weight),
+        ] {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!(
+                    "{name} weight must be finite and not negative, got {weight}"
+                ));
+            }
+        }
+        let config = Self {
+            steps_before_reanalyze: args.steps_before_reanalyze,
+            min_selfplay_len: args.min_selfplay_buffer_len,
+            min_reanalyze_len: args.min_reanalyze_buffer_len,
+            max_selfplay_len: args.max_selfplay_buffer_len,
+            max_reanalyze_len: args.max_reanalyze_buffer_len,
+            reanalyze_amount: (BATCH_SIZE as f64 * args.reanalyze_batch_fraction).round() as usize,
+            exploitation_weight: args.exploitation_weight,
+            reanalyze_weight: args.reanalyze_weight,
+            staleness_halflife: Some(args.target_staleness_halflife).filter(|&steps| steps > 0),
+            accum_steps: args.accum_steps,
+        };
+        // Before reanalyze is used the whole batch comes from selfplay.
+        // All sub-batches of a step are sampled before any is returned.
+        let effective_batch_size = BATCH_SIZE * config.accum_steps;
+        if config.min_selfplay_len < effective_batch_size {
+            return Err(format!(
+                "minimum selfplay buffer length {} is smaller than the effective batch size \
+                 {effective_batch_size}",
+                config.min_selfplay_len
+            ));
+        }
+        if config.min_reanalyze_len < config.reanalyze_amount * config.accum_steps {
+            return Err(format!(
+                "minimum reanalyze buffer length {} is smaller than the {} reanalyze targets in a \
+                 step",
+                config.min_reanalyze_len,
+                config.reanalyze_amount * config.accum_steps
+            ));
+        }
+        for (name, min, max) in [
+            ("selfplay", config.min_selfplay_len, config.max_selfplay_len),
+            (
+                "reanalyze",
+                config.min_reanalyze_len,
+                config.max_reanalyze_len,
+            ),
+        ] {
+            if let Some(max) = max.filter(|&max| max < min) {
+                return Err(format!(
+                    "maximum {name} buffer length {max} is smaller than the minimum {min}"
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Number of selfplay targets in a batch.
+    const fn exploitation_amount(&self, using_reanalyze: bool) -> usize {
+        if using_reanalyze {
+            BATCH_SIZE - self.reanalyze_amount
+        } else {
+            BATCH_SIZE
+        }
+    }
+}
+
+fn parse_huber_delta(s: &str) -> Result<f64, String> {
+    let delta: f64 = s.parse().map_err(|err| format!("{err}"))?;
+    if delta > 0.0 && delta.is_finite() {
+        Ok(delta)
+    } else {
+        Err(format!("the Huber delta should be positive, got {delta}"))
+    }
+}
+
+/// Fall back to whatever is available if the requested CUDA device is missing.
+fn available_device(device: Device) -> Device {
+    match device {
+        Device::Cuda(index) if index as i64 >= tch::Cuda::device_count() => {
+            let fallback = Device::cuda_if_available();
+            log::warn!("{device:?} is not available, falling back to {fallback:?}");
+            fallback
+        }
+        device => device,
+    }
+}
+
+/// Learning rate schedule with linear warmup followed by cosine decay.
+/// Without any of the schedule flags it is a constant `LEARNING_RATE`.
+#[derive(Debug, Clone, Copy)]
+struct LrSchedule {
+    warmup_steps: usize,
+    total_steps: Option<usize>,
+    lr_max: f64,
+    lr_min: f64,
+}
+
+impl LrSchedule {
+    fn from_args(args: &Args) -> Self {
+        let lr_max = args.lr_max.unwrap_or(LEARNING_RATE);
+        Self {
+            warmup_steps: args.warmup_steps.unwrap_or_default(),
+            total_steps: args.total_steps,
+            lr_max,
+            lr_min: args.lr_min.unwrap_or(lr_max),
+        }
+    }
+
+    /// Get the learning rate for the given number of model steps.
+    fn learning_rate(&self, model_steps: usize) -> f64 {
+        if model_steps < self.warmup_steps {
+            return self.lr_max * (model_steps + 1) as f64 / self.warmup_steps as f64;
+        }
+        let Some(total_steps) = self.total_steps else {
+            return self.lr_max;
+        };
+        let decay_steps = total_steps.saturating_sub(self.warmup_steps).max(1);
+        let progress =
+            ((model_steps - self.warmup_steps) as f64 / decay_steps as f64).clamp(0.0, 1.0);
+        let cosine = 0.5 * (1.0 + (std::f64::consts::PI * progress).cos());
+        (self.lr_max - self.lr_min).mul_add(cosine, self.lr_min)
+    }
 }
 
 struct TargetWithContext {
@@ -82,6 +646,12 @@ struct TargetWithContext {
     forced_uses: u32,
     /// The model steps at the time of loading this target.
     model_steps: usize,
+    /// Priority for prioritized replay (the latest value loss).
+    priority: f32,
+    /// Hash of the canonical position, once the target was deduplicated.
+    position_hash: Option<u64>,
+    /// Number of targets which were merged into this one.
+    merged: u32,
 }
 
 impl TargetWithContext {
@@ -95,33 +665,106 @@ impl TargetWithContext {
     }
 }
 
+/// Random number generator for everything in the learner. The seed of
+/// `tch` is derived from it too, so that one seed covers `tch` operations
+/// which do not take a seed.
+fn seeded_rng(seed: Option<u64>) -> StdRng {
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+    log::info!("seed = {seed}");
+    let mut rng = StdRng::seed_from_u64(seed);
+    tch::manual_seed(rng.gen());
+    rng
+}
+
 #[allow(clippy::too_many_lines)]
 fn main() {
     env_logger::init();
     let args = Args::parse();
 
-    let seed: u64 = rand::thread_rng().gen();
-    log::info!("seed = {seed}");
-    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut rng = seeded_rng(args.seed);
+    let device = available_device(args.device);
+    log::info!("device = {device:?}");
+    takzero::network::set_deterministic(args.deterministic);
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            log::warn!("Interrupted again, exiting immediately");
+            std::process::exit(130);
+        }
+        log::info!("Interrupted, stopping after the current step");
+    })
+    .expect("Could not install interrupt handler");
 
+    if let Err(err) = prepare_directory(&args.directory) {
+        Args::command().error(ClapErrorKind::Io, err).exit();
+    }
+
+    let mut progress = None;
     let (mut net, mut starting_steps) =
         if let Some((resume_steps, path)) = get_model_path_with_most_steps(&args.directory) {
             log::info!("Resuming with model at {}", path.display());
+            progress = Progress::load(&args.directory, resume_steps);
             (
-                Net::load(path, DEVICE).expect("Could not load network model"),
+                Net::load(path, device).expect("Could not load network model"),
                 resume_steps,
             )
         } else {
             // Initialize a network.
             log::info!("Initializing a network model");
-            let net = Net::new(DEVICE, Some(rng.gen()));
+            let net = Net::new(device, Some(rng.gen()));
             net.save(args.directory.join("model_0000000.ot")).unwrap();
             (net, 0)
         };
+    log::info!("Network summary:\n{}", net.summary());
 
-    let mut opt = Adam::default().build(net.vs_mut(), LEARNING_RATE).unwrap();
+    let lr_schedule = LrSchedule::from_args(&args);
+    log::info!("{lr_schedule:?}");
+    let populated_groups = assign_lr_groups(net.vs());
+    let step_config = StepConfig::from_args(&args, populated_groups, net.auxiliary_heads())
+        .unwrap_or_else(|err| {
+            Args::command()
+                .error(ClapErrorKind::ValueValidation, err)
+                .exit()
+        });
+    log::info!("{step_config:?}");
+    for (group, learning_rate) in step_config.group_learning_rates(lr_schedule.lr_max) {
+        log::info!(
+            "Peak learning rate of {}: {learning_rate}",
+            PARAMETER_GROUPS.get(group).unwrap_or(&"other")
+        );
+    }
+    let replay_config = ReplayConfig::from_args(&args);
+    log::info!("{replay_config:?}");
+    let buffer_config = BufferConfig::from_args(&args).unwrap_or_else(|err| {
+        Args::command()
+            .error(ClapErrorKind::ValueValidation, err)
+            .exit()
+    });
+    log::info!("{buffer_config:?}");
+    let policy_config = PolicyTargetConfig::from_args(&args).unwrap_or_else(|err| {
+        Args::command()
+            .error(ClapErrorKind::ValueValidation, err)
+            .exit()
+    });
+    log::info!("{policy_config:?}");
+    let mut scaler = if args.amp {
+        GradScaler::new(device)
+    } else {
+        None
+    };
+    let mut skipped_in_a_row = 0;
+    let mut opt = Adam::new(net.vs(), lr_schedule.learning_rate(starting_steps));
+    if starting_steps > 0 {
+        match opt.load(&args.directory, starting_steps) {
+            Ok(true) => log::info!(
+                "Resuming with the optimizer state after {} optimizer steps",
+                opt.steps()
+            ),
+            Ok(false) => log::info!("Starting with a new optimizer state"),
+            Err(err) => log::warn!("Could not load the optimizer state, starting a new one: {err}"),
+        }
+    }
     // Load RND reference games.
-    // let (early_reference, late_reference) = reference_games(DEVICE, &mut rng);
+    // let (early_reference, late_reference) = reference_games(device, &mut rng);
 
     if let Some(target_file) = &args.restart_targets {
         // Resuming after restarting.
@@ -131,13 +774,26 @@ fn main() {
             .collect::<Vec<_>>();
         targets.shuffle(&mut rng);
         for batch in targets.chunks_exact(BATCH_SIZE) {
-            let tensors = create_input_and_target_tensors(batch.iter(), &mut rng);
-            compute_loss_and_take_step(
-                &mut net, &mut opt, tensors,
+            let tensors = create_input_and_target_tensors(
+                batch.iter(),
+                None,
+                &policy_config,
+                step_config.short_value_discount,
+                device,
+                &mut rng,
+            );
+            let step = compute_loss_and_take_step(
+                &mut net,
+                &mut opt,
+                scaler.as_mut(),
+                std::iter::once(tensors),
                 // &early_reference,
                 // &late_reference,
                 false,
+                lr_schedule.learning_rate(starting_steps),
+                &step_config,
             );
+            track_skipped_steps(step.is_some(), &mut skipped_in_a_row, &step_config);
             starting_steps += 1;
         }
         net.save(
@@ -145,17 +801,29 @@ fn main() {
                 .join(format!("model_{starting_steps:0>7}.ot")),
         )
         .unwrap();
-    } else if starting_steps == 0 {
-        // Pre-training.
-        pre_training(
+    } else if starting_steps < PRE_TRAINING_STEPS {
+        // Pre-training, or what is left of it after an interrupt.
+        starting_steps = pre_training(
             &mut net,
             &mut opt,
+            scaler.as_mut(),
+            &lr_schedule,
+            &step_config,
+            &policy_config,
+            args.discount,
+            starting_steps,
             &mut rng,
             &args.directory,
             // &early_reference,
             // &late_reference,
         );
-        starting_steps += PRE_TRAINING_STEPS;
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            save_on_interrupt(&net, &opt, &args.directory, &Progress {
+                model_steps: starting_steps,
+                ..Progress::default()
+            });
+            return;
+        }
         net.save(
             args.directory
                 .join(format!("model_{starting_steps:0>7}.ot")),
@@ -167,29 +835,82 @@ fn main() {
 
     // Initialize buffers.
     let mut exploitation_buffer: Vec<TargetWithContext> =
-        Vec::with_capacity(2 * MIN_SELFPLAY_BUFFER_LEN);
-    let mut exploitation_targets_seek = 0;
+        Vec::with_capacity(2 * buffer_config.min_selfplay_len);
     let mut reanalyze_buffer: Vec<TargetWithContext> = Vec::new();
-    let mut reanalyze_targets_seek = 0;
+    // Only the selfplay targets are sampled by priority.
+    let mut exploitation_priorities = replay_config.prioritized.then(SumTree::default);
+    let mut seeks = progress.unwrap_or_default().seeks;
+
+    // Wait for new targets with a watcher, falling back to polling.
+    let watcher = TargetWatcher::new(&args.directory)
+        .map_err(|err| log::warn!("Could not watch target directory, polling instead: {err}"))
+        .ok();
+
+    let target_receiver = (args.target_source == TargetSource::Socket).then(|| {
+        TargetReceiver::bind(&args.target_address).unwrap_or_else(|err| {
+            Args::command()
+                .error(
+                    ClapErrorKind::Io,
+                    format!("could not listen on {}: {err}", args.target_address),
+                )
+                .exit()
+        })
+    });
+
+    let mut ema = (args.ema_decay > 0.0).then(|| Ema::new(&net, args.ema_decay, &args.directory));
+
+    let mut arena = args.eval_reference.as_ref().map(|path| {
+        let reference = Net::load(path, device).expect("Could not load reference model");
+        let config = SearchConfig {
+            discount: args.discount,
+            ..SearchConfig::default()
+        };
+        Arena::new(reference, args.eval_games, args.eval_visits, config)
+    });
+
+    let mut metrics = args
+        .metrics
+        .then(|| Metrics::open(&args.directory.join("metrics.csv")))
+        .transpose()
+        .expect("Could not open metrics file");
 
     // Main training loop.
     let mut last_loaded = Instant::now();
+    let mut throughput = Throughput::start();
     for model_steps in (starting_steps + 1).. {
         let using_reanalyze =
-            args.restart_targets.is_some() || model_steps >= STEPS_BEFORE_REANALYZE;
+            args.restart_targets.is_some() || model_steps >= buffer_config.steps_before_reanalyze;
 
         // Make sure there are enough targets before sampling a batch.
+        let waiting = Instant::now();
         loop {
             if last_loaded.elapsed() >= MIN_TIME_BETWEEN_BUFFER_READS {
                 fill_buffers(
                     &mut exploitation_buffer,
-                    &mut exploitation_targets_seek,
                     &mut reanalyze_buffer,
-                    &mut reanalyze_targets_seek,
+                    &mut seeks,
+                    target_receiver.as_ref(),
                     &args.directory,
                     model_steps,
                     using_reanalyze,
+                    args.augment_expand,
+                    args.dedup_targets,
+                    args.max_corrupt_ratio,
                 );
+                if let Some(max) = buffer_config.max_selfplay_len {
+                    truncate_buffer_if_needed(&mut exploitation_buffer, max, "selfplay");
+                }
+                if let Some(max) = buffer_config.max_reanalyze_len {
+                    truncate_buffer_if_needed(&mut reanalyze_buffer, max, "reanalyze");
+                }
+                // Filling the buffer changes it all over, so its priorities
+                // are built again from scratch.
+                if let Some(priorities) = &mut exploitation_priorities {
+                    *priorities = exploitation_buffer
+                        .iter()
+                        .map(|target| replay_config.scaled_priority(target))
+                        .collect();
+                }
                 last_loaded = Instant::now();
                 // Write buffer sizes to file for synchronization.
                 if let Ok(mut file) = OpenOptions::new()
@@ -210,38 +931,130 @@ fn main() {
             }
 
             // Create a batch and take a step if there are enough targets.
-            let enough_exploitation_targets = exploitation_buffer.len() >= MIN_SELFPLAY_BUFFER_LEN;
+            let enough_exploitation_targets =
+                exploitation_buffer.len() >= buffer_config.min_selfplay_len;
             let enough_reanalyze_targets =
-                !using_reanalyze || reanalyze_buffer.len() >= MIN_REANALYZE_BUFFER_LEN;
+                !using_reanalyze || reanalyze_buffer.len() >= buffer_config.min_reanalyze_len;
             if enough_exploitation_targets && enough_reanalyze_targets {
                 break;
             }
 
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                save_on_interrupt(&net, &opt, &args.directory, &Progress {
+                    model_steps: model_steps - 1,
+                    seeks,
+                });
+                return;
+            }
+
             #[rustfmt::skip]
             log::info!(
                 "Not enough targets.\n\
-                 Waiting {SLEEP_WHEN_NOT_ENOUGH_TARGETS:?}.\n\
+                 Waiting up to {SLEEP_WHEN_NOT_ENOUGH_TARGETS:?}.\n\
                  Training steps: {model_steps}\n\
                  Exploitation buffer size: {}\n\
                  Reanalyze buffer size: {}",
                 exploitation_buffer.len(),
                 reanalyze_buffer.len()
             );
-            std::thread::sleep(SLEEP_WHEN_NOT_ENOUGH_TARGETS);
+            let changed = watcher
+                .as_ref()
+                .is_some_and(|watcher| watcher.wait(SLEEP_WHEN_NOT_ENOUGH_TARGETS));
+            if changed {
+                // Still avoid reading the buffers too often.
+                std::thread::sleep(
+                    MIN_TIME_BETWEEN_BUFFER_READS.saturating_sub(last_loaded.elapsed()),
+                );
+            } else if watcher.is_none() {
+                std::thread::sleep(SLEEP_WHEN_NOT_ENOUGH_TARGETS);
+            }
         }
+        throughput.waited(waiting.elapsed());
 
-        let tensors = create_batch(
-            using_reanalyze,
-            &mut exploitation_buffer,
-            &mut reanalyze_buffer,
-            &mut rng,
-        );
-        compute_loss_and_take_step(
-            &mut net, &mut opt, tensors,
+        let stepping = Instant::now();
+        let sub_batches: Vec<_> = (0..buffer_config.accum_steps)
+            .map(|_| {
+                // The minimum buffer lengths are validated to cover
+                // all sub-batches of a step.
+                let (batch, mut weights) = sample_batch(
+                    using_reanalyze,
+                    &mut exploitation_buffer,
+                    exploitation_priorities.as_mut(),
+                    &mut reanalyze_buffer,
+                    &replay_config,
+                    &buffer_config,
+                    &mut rng,
+                )
+                .expect("buffers should have enough targets for every sub-batch");
+                let age = weight_by_staleness(
+                    &batch,
+                    &mut weights,
+                    model_steps,
+                    buffer_config.staleness_halflife,
+                );
+                log::debug!("Average target age: {age:.0} steps");
+                (batch, weights)
+            })
+            .collect();
+        let tensors = sub_batches.iter().map(|(batch, weights)| {
+            create_input_and_target_tensors(
+                batch.iter().map(|t| &t.target),
+                Some(weights),
+                &policy_config,
+                step_config.short_value_discount,
+                device,
+                &mut rng,
+            )
+        });
+        let learning_rate = lr_schedule.learning_rate(model_steps);
+        let step = compute_loss_and_take_step(
+            &mut net,
+            &mut opt,
+            scaler.as_mut(),
+            tensors,
             // &early_reference,
             // &late_reference,
             true,
+            learning_rate,
+            &step_config,
         );
+        track_skipped_steps(step.is_some(), &mut skipped_in_a_row, &step_config);
+        if let (Some(ema), Some(_)) = (&mut ema, &step) {
+            ema.update(&net);
+        }
+        if let (Some(metrics), Some(step)) = (&mut metrics, &step) {
+            if let Err(err) = metrics.write(
+                model_steps,
+                &step.losses,
+                learning_rate,
+                exploitation_buffer.len(),
+                reanalyze_buffer.len(),
+            ) {
+                log::error!("Writing metrics: {err}");
+            }
+        }
+        let targets = sub_batches.iter().map(|(batch, _)| batch.len()).sum();
+        for (i, (batch, _)) in sub_batches.into_iter().enumerate() {
+            return_batch(
+                batch,
+                step.as_ref().map(|step| step.value_errors[i].as_slice()),
+                using_reanalyze,
+                &buffer_config,
+                &replay_config,
+                &mut exploitation_buffer,
+                exploitation_priorities.as_mut(),
+                &mut reanalyze_buffer,
+            );
+        }
+        throughput.stepped(targets, stepping.elapsed());
+
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            save_on_interrupt(&net, &opt, &args.directory, &Progress {
+                model_steps,
+                seeks,
+            });
+            return;
+        }
 
         // Save latest model.
         if model_steps % STEPS_PER_SAVE == 0 {
@@ -254,25 +1067,150 @@ fn main() {
                     exploitation_buffer.len(),
                     reanalyze_buffer.len()
                 );
+            log::info!("Throughput: {}", throughput.report());
             net.save(args.directory.join("model_latest.ot")).unwrap();
+            if let Some(ema) = &ema {
+                ema.save(&args.directory);
+            }
+            Progress {
+                model_steps,
+                seeks: seeks.clone(),
+            }
+            .save(&args.directory, false);
+        }
+
+        // Evaluate against the reference model.
+        if let (Some(arena), Some(interval)) = (&mut arena, args.eval_interval) {
+            if model_steps % interval == 0 {
+                arena.evaluate(ema.as_ref().map_or(&net, Ema::net), model_steps, &mut rng);
+            }
         }
 
         // Save checkpoint.
         if model_steps % STEPS_PER_CHECKPOINT == 0 {
             net.save(args.directory.join(format!("model_{model_steps:0>7}.ot")))
                 .unwrap();
+            if let Err(err) = opt.save(&args.directory, model_steps) {
+                log::error!("Could not save the optimizer state: {err}");
+            }
+            // Resuming starts from this checkpoint, so it needs the progress
+            // of this step rather than the latest one.
+            Progress {
+                model_steps,
+                seeks: seeks.clone(),
+            }
+            .save(&args.directory, true);
+            remove_old_checkpoints(&args.directory, args.keep_checkpoints);
             // I don't know if this helps or hurts or does nothing.
             opt.zero_grad();
         }
     }
 }
 
+/// Save the latest model before exiting because of an interrupt.
+///
+/// It is saved as a checkpoint with its number of steps too, because
+/// training resumes from the checkpoint with the most steps, together with
+/// the optimizer state and the progress through the targets.
+fn save_on_interrupt(net: &Net, opt: &Adam, directory: &Path, progress: &Progress) {
+    let steps = progress.model_steps;
+    log::info!("Saving model before exiting.\nTraining steps: {steps}");
+    net.save(directory.join(format!("model_{steps:0>7}.ot")))
+        .unwrap();
+    net.save(directory.join("model_latest.ot")).unwrap();
+    if let Err(err) = opt.save(directory, steps) {
+        log::error!("Could not save the optimizer state: {err}");
+    }
+    progress.save(directory, true);
+    progress.save(directory, false);
+}
+
+/// How far the target files have been read,
+/// so that resuming does not read the same targets again.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Progress {
+    model_steps: usize,
+    /// Seek into each target file, because the plain, binary,
+    /// and compressed files and the shards each have their own.
+    seeks: HashMap<PathBuf, u64>,
+}
+
+impl Progress {
+    const FILE_NAME: &'static str = "progress.txt";
+
+    fn checkpoint_path(directory: &Path, model_steps: usize) -> PathBuf {
+        directory.join(format!("progress_{model_steps:0>7}.txt"))
+    }
+
+    /// Write the progress to file, next to the latest model,
+    /// or next to the checkpoint of its steps if it is `numbered`.
+    fn save(&self, directory: &Path, numbered: bool) {
+        let path = if numbered {
+            Self::checkpoint_path(directory, self.model_steps)
+        } else {
+            directory.join(Self::FILE_NAME)
+        };
+        let mut lines = vec![self.model_steps.to_string()];
+        // One line per file, the path goes last because it may contain commas.
+        lines.extend(
+            self.seeks
+                .iter()
+                .map(|(path, seek)| format!("{seek},{}", path.display())),
+        );
+        if let Err(err) = std::fs::write(path, lines.join("\n")) {
+            log::error!("Writing progress to file: {err}");
+        }
+    }
+
+    /// Read the progress which was saved by a previous run, preferably
+    /// the one of the checkpoint which training resumes from.
+    fn load(directory: &Path, resume_steps: usize) -> Option<Self> {
+        let path = Some(Self::checkpoint_path(directory, resume_steps))
+            .filter(|path| path.exists())
+            .unwrap_or_else(|| directory.join(Self::FILE_NAME));
+        let contents = std::fs::read_to_string(&path).ok()?;
+        let Some(progress) = Self::parse(&contents) else {
+            log::error!("Could not parse {}", path.display());
+            return None;
+        };
+        if progress.model_steps != resume_steps {
+            log::warn!(
+                "Progress was saved at {} steps but resuming from {resume_steps} steps",
+                progress.model_steps
+            );
+        }
+        log::info!("Resuming with {progress:?}");
+        Some(progress)
+    }
+
+    fn parse(contents: &str) -> Option<Self> {
+        let mut lines = contents.trim().lines();
+        Some(Self {
+            model_steps: lines.next()?.parse().ok()?,
+            seeks: lines
+                .map(|line| {
+                    let (seek, path) = line.split_once(',')?;
+                    Some((PathBuf::from(path), seek.parse().ok()?))
+                })
+                .collect::<Option<_>>()?,
+        })
+    }
+}
+
 /// Get the path to the model file (ending with ".ot")
 /// which has the highest number of steps (number after '_')
 /// in the given directory.
 fn get_model_path_with_most_steps(directory: &PathBuf) -> Option<(usize, PathBuf)> {
+    model_paths_with_steps(directory).max_by_key(|(s, _)| *s)
+}
+
+/// Get all model files (ending with ".ot") in the given directory
+/// together with their number of steps (number after '_').
+/// Files without a number of steps, like "model_latest.ot", are skipped.
+fn model_paths_with_steps(directory: &PathBuf) -> impl Iterator<Item = (usize, PathBuf)> {
     read_dir(directory)
-        .unwrap()
+        .into_iter()
+        .flatten()
         .filter_map(|res| res.ok().map(|entry| entry.path()))
         .filter(|p| p.extension().is_some_and(|ext| ext == "ot"))
         .filter_map(|p| {
@@ -286,82 +1224,439 @@ fn get_model_path_with_most_steps(directory: &PathBuf) -> Option<(usize, PathBuf
                 p,
             ))
         })
-        .max_by_key(|(s, _)| *s)
+}
+
+/// Create the directory for models and targets if it does not exist yet,
+/// so that the learner can start fresh in it.
+fn prepare_directory(directory: &Path) -> Result<(), String> {
+    if directory.is_dir() {
+        return Ok(());
+    }
+    if directory.exists() {
+        return Err(format!(
+            "{} is not a directory, pass a directory with --directory",
+            directory.display()
+        ));
+    }
+    std::fs::create_dir_all(directory).map_err(|err| {
+        format!(
+            "could not create directory {}: {err}, create it or pass another one with --directory",
+            directory.display()
+        )
+    })?;
+    log::info!("Created directory {}", directory.display());
+    Ok(())
+}
+
+/// Delete all but the `keep` most recent checkpoints.
+/// The initial model and "model_latest.ot" are never deleted.
+/// Keeping 0 checkpoints means keeping everything.
+fn remove_old_checkpoints(directory: &PathBuf, keep: usize) {
+    if keep == 0 {
+        return;
+    }
+    let mut checkpoints: Vec<_> = model_paths_with_steps(directory)
+        .filter(|(steps, _)| *steps != 0)
+        .collect();
+    checkpoints.sort_unstable_by_key(|(steps, _)| Reverse(*steps));
+    for (steps, path) in checkpoints.into_iter().skip(keep) {
+        log::info!("Deleting old checkpoint {}", path.display());
+        if let Err(err) = std::fs::remove_file(&path) {
+            log::error!("Could not delete {}: {err}", path.display());
+        }
+        let progress = Progress::checkpoint_path(directory, steps);
+        if let Err(err) = std::fs::remove_file(&progress) {
+            if err.kind() != ErrorKind::NotFound {
+                log::error!("Could not delete {}: {err}", progress.display());
+            }
+        }
+    }
+}
+
+/// Number of targets read from a file, and how many of them were corrupt.
+#[derive(Debug, Default, Clone, Copy)]
+struct TargetCounts {
+    parsed: usize,
+    corrupt: usize,
 }
 
 /// Add targets to the buffer from the given file, skipping the targets that
-/// have already been read.
+/// have already been read. Lines which cannot be parsed are logged and
+/// skipped. A last line without a newline is still being written, so it is
+/// left for the next read.
 fn fill_buffer_with_targets(
     buffer: &mut Vec<TargetWithContext>,
     seek: &mut u64,
     file_path: &Path,
     forced_uses: u32,
     model_steps: usize,
-) -> std::io::Result<()> {
+    expand_symmetries: bool,
+) -> std::io::Result<TargetCounts> {
+    if is_gzip(file_path) {
+        return fill_buffer_with_gzip_targets(
+            buffer,
+            seek,
+            file_path,
+            forced_uses,
+            model_steps,
+            expand_symmetries,
+            false,
+        );
+    }
     let mut reader = BufReader::new(OpenOptions::new().read(true).open(file_path)?);
     reader
         .seek(std::io::SeekFrom::Start(*seek))
         .expect("Target file should not get shorter.");
-    buffer.extend(
-        reader
-            .by_ref()
-            .lines()
-            .filter_map(|line| line.unwrap().parse().ok())
-            .map(|target| TargetWithContext {
-                target,
-                forced_uses,
-                model_steps,
-            }),
+    let start = *seek;
+    let mut counts = TargetCounts::default();
+    let mut line = String::new();
+    for line_number in 1.. {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+        *seek += read as u64;
+        match line.parse::<Target<Env>>() {
+            Ok(target) => {
+                counts.parsed += 1;
+                buffer.extend(with_context(
+                    target,
+                    forced_uses,
+                    model_steps,
+                    expand_symmetries,
+                ));
+            }
+            Err(err) => {
+                counts.corrupt += 1;
+                log::warn!(
+                    "Skipping corrupt target on line {line_number} after byte {start} of {}: {err}",
+                    file_path.display()
+                );
+            }
+        }
+    }
+    Ok(counts)
+}
+
+/// Log how many targets were corrupt, and abort if there are too many of
+/// them, which most likely means that the format does not match.
+fn check_corrupt_targets(counts: TargetCounts, max_corrupt_ratio: Option<f64>, name: &str) {
+    if counts.corrupt == 0 {
+        return;
+    }
+    let total = counts.parsed + counts.corrupt;
+    log::warn!(
+        "{} of {total} {name} targets could not be parsed",
+        counts.corrupt
     );
-    *seek = reader
-        .stream_position()
+    let ratio = counts.corrupt as f64 / total as f64;
+    if max_corrupt_ratio.is_some_and(|max| ratio > max) {
+        log::error!("Too many corrupt {name} targets ({ratio:.3}), the format may not match.");
+        std::process::exit(1);
+    }
+}
+
+/// Same as [`fill_buffer_with_targets`], but for targets written with
+/// [`Target::write_bin`]. A target which is still being written is left
+/// for the next read. A target whose bytes cannot be parsed is logged and
+/// skipped, but if even its length cannot be read there is no way to find
+/// the next target, so learning stops instead of reading the same bytes
+/// again on every refill.
+fn fill_buffer_with_binary_targets(
+    buffer: &mut Vec<TargetWithContext>,
+    seek: &mut u64,
+    file_path: &Path,
+    forced_uses: u32,
+    model_steps: usize,
+    expand_symmetries: bool,
+) -> std::io::Result<TargetCounts> {
+    if is_gzip(file_path) {
+        return fill_buffer_with_gzip_targets(
+            buffer,
+            seek,
+            file_path,
+            forced_uses,
+            model_steps,
+            expand_symmetries,
+            true,
+        );
+    }
+    let mut file = OpenOptions::new().read(true).open(file_path)?;
+    file.seek(std::io::SeekFrom::Start(*seek))
         .expect("Target file should not get shorter.");
-    Ok(())
+    let mut reader = CountingReader {
+        inner: BufReader::new(file),
+        count: 0,
+    };
+    let mut counts = TargetCounts::default();
+    loop {
+        let start = *seek;
+        let record = match Target::<Env>::read_bin_record(&mut reader) {
+            Ok(record) => record,
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break Ok(counts),
+            Err(err) => {
+                log::error!(
+                    "Cannot find the end of the target at byte {start} of {}: {err}",
+                    file_path.display()
+                );
+                std::process::exit(1);
+            }
+        };
+        *seek += reader.count;
+        reader.count = 0;
+        match Target::<Env>::from_bin_record(&record) {
+            Ok(target) => {
+                counts.parsed += 1;
+                buffer.extend(with_context(
+                    target,
+                    forced_uses,
+                    model_steps,
+                    expand_symmetries,
+                ));
+            }
+            Err(err) => {
+                counts.corrupt += 1;
+                log::warn!(
+                    "Skipping corrupt target at byte {start} of {}: {err}",
+                    file_path.display()
+                );
+            }
+        }
+    }
 }
 
-struct Tensors {
-    input: Tensor,
-    mask: Tensor,
-    target_value: Tensor,
-    target_policy: Tensor,
-    target_ube: Tensor,
+fn is_gzip(file_path: &Path) -> bool {
+    file_path
+        .extension()
+        .is_some_and(|extension| extension == "gz")
 }
 
-fn create_input_and_target_tensors<'a>(
-    batch: impl Iterator<Item = &'a Target<Env>>,
-    rng: &mut impl Rng,
-) -> Tensors {
-    // Create input tensors.
-    let mut inputs = Vec::with_capacity(BATCH_SIZE);
-    let mut policy_targets = Vec::with_capacity(BATCH_SIZE);
-    let mut masks = Vec::with_capacity(BATCH_SIZE);
-    let mut value_targets = Vec::with_capacity(BATCH_SIZE);
+/// Same as [`fill_buffer_with_targets`] and
+/// [`fill_buffer_with_binary_targets`], but for gzip compressed files.
+///
+/// Selfplay compresses every batch of targets into its own gzip member and
+/// appends it, and concatenated members are a valid gzip file. `seek` is the
+/// offset of the first member which has not been read, so that a refill
+/// only decompresses the new members. A member which is still being written
+/// is left for the next read. A corrupt target is counted and skipped, and
+/// if the end of a binary target cannot be found, so is the rest of its
+/// member.
+fn fill_buffer_with_gzip_targets(
+    buffer: &mut Vec<TargetWithContext>,
+    seek: &mut u64,
+    file_path: &Path,
+    forced_uses: u32,
+    model_steps: usize,
+    expand_symmetries: bool,
+    binary: bool,
+) -> std::io::Result<TargetCounts> {
+    let mut reader = BufReader::new(OpenOptions::new().read(true).open(file_path)?);
+    reader
+        .seek(std::io::SeekFrom::Start(*seek))
+        .expect("Target file should not get shorter.");
+    let mut counts = TargetCounts::default();
+    let mut member = Vec::new();
+    while !reader.fill_buf()?.is_empty() {
+        member.clear();
+        // The decoder only consumes a single member from the reader.
+        match GzDecoder::new(&mut reader).read_to_end(&mut member) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+        let start = *seek;
+        *seek = reader.stream_position()?;
+
+        let targets = if binary {
+            let mut bytes = member.as_slice();
+            let mut targets = Vec::new();
+            while !bytes.is_empty() {
+                let offset = member.len() - bytes.len();
+                // Without the end of a target the rest of the member is lost,
+                // but the next member starts fresh.
+                let record = match Target::<Env>::read_bin_record(&mut bytes) {
+                    Ok(record) => record,
+                    Err(err) => {
+                        counts.corrupt += 1;
+                        log::warn!(
+                            "Skipping the rest of the member at byte {start} of {} after byte \
+                             {offset}: {err}",
+                            file_path.display()
+                        );
+                        break;
+                    }
+                };
+                match Target::<Env>::from_bin_record(&record) {
+                    Ok(target) => targets.push(target),
+                    Err(err) => {
+                        counts.corrupt += 1;
+                        log::warn!(
+                            "Skipping corrupt target at byte {offset} of the member at byte \
+                             {start} of {}: {err}",
+                            file_path.display()
+                        );
+                    }
+                }
+            }
+            targets
+        } else {
+            let text = String::from_utf8_lossy(&member);
+            let mut targets = Vec::new();
+            for (line_number, line) in text.lines().enumerate() {
+                match line.parse::<Target<Env>>() {
+                    Ok(target) => targets.push(target),
+                    Err(err) => {
+                        counts.corrupt += 1;
+                        log::warn!(
+                            "Skipping corrupt target on line {} of the member at byte {start} of \
+                             {}: {err}",
+                            line_number + 1,
+                            file_path.display()
+                        );
+                    }
+                }
+            }
+            targets
+        };
+        counts.parsed += targets.len();
+        for target in targets {
+            buffer.extend(with_context(
+                target,
+                forced_uses,
+                model_steps,
+                expand_symmetries,
+            ));
+        }
+    }
+    Ok(counts)
+}
+
+/// Counts the bytes read, so that the position after the last complete
+/// target is known.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
+fn with_context(
+    target: Target<Env>,
+    forced_uses: u32,
+    model_steps: usize,
+    expand_symmetries: bool,
+) -> Vec<TargetWithContext> {
+    let targets = if expand_symmetries {
+        Vec::from(target.all_symmetries())
+    } else {
+        vec![target]
+    };
+    targets
+        .into_iter()
+        .map(|target| TargetWithContext {
+            target,
+            forced_uses,
+            model_steps,
+            priority: MAX_PRIORITY,
+            position_hash: None,
+            merged: 1,
+        })
+        .collect()
+}
+
+struct Tensors {
+    input: Tensor,
+    mask: Tensor,
+    target_value: Tensor,
+    target_policy: Tensor,
+    target_ube: Tensor,
+    /// Categorical {loss, draw, win} target, see [`result_wdl_target`].
+    target_wdl: Tensor,
+    /// Short-horizon value of each target, and whether the target has one.
+    target_short_value: Tensor,
+    has_short_value: Tensor,
+    /// Final flat margin of each target, and whether the target has one.
+    target_score: Tensor,
+    has_score: Tensor,
+    /// Final owner of each square of each target, and whether the target
+    /// has an ownership.
+    target_ownership: Tensor,
+    has_ownership: Tensor,
+    /// Loss weight of each target, which scales every term of the loss:
+    /// the importance-sampling weight, the weight of its buffer, and its
+    /// staleness weight.
+    weights: Tensor,
+}
+
+/// Create the tensors for a batch.
+/// Without `weights` every target is weighted equally.
+fn create_input_and_target_tensors<'a>(
+    batch: impl Iterator<Item = &'a Target<Env>>,
+    weights: Option<&[f32]>,
+    policy_config: &PolicyTargetConfig,
+    short_value_discount: f32,
+    device: Device,
+    rng: &mut impl Rng,
+) -> Tensors {
+    // Create input tensors.
+    let mut envs = Vec::with_capacity(BATCH_SIZE);
+    let mut policy_targets = Vec::with_capacity(BATCH_SIZE);
+    let mut masks = Vec::with_capacity(BATCH_SIZE);
+    let mut value_targets = Vec::with_capacity(BATCH_SIZE);
     let mut ube_targets = Vec::with_capacity(BATCH_SIZE);
+    let mut results = Vec::with_capacity(BATCH_SIZE);
+    let mut score_targets = Vec::with_capacity(BATCH_SIZE);
+    let mut ownership_targets = Vec::with_capacity(BATCH_SIZE);
     for target in batch {
         let target = target.augment(rng);
-        inputs.push(game_to_tensor(&target.env, DEVICE));
-        policy_targets.push(policy_tensor::<N>(&target.policy, DEVICE));
+        policy_targets.push(policy_tensor::<N>(
+            &policy_config.apply(&target.policy),
+            device,
+        ));
         masks.push(move_mask::<N>(
             &target.policy.iter().map(|(m, _)| *m).collect::<Vec<_>>(),
-            DEVICE,
+            device,
         ));
         value_targets.push(target.value);
         ube_targets.push(target.ube);
+        results.push(target.result);
+        score_targets.push(target.score);
+        ownership_targets.push(target.ownership);
+        envs.push(target.env);
     }
 
     // Get network output.
-    let input = Tensor::cat(&inputs, 0).to(DEVICE);
-    let mask = Tensor::cat(&masks, 0).to(DEVICE);
+    let input = games_to_tensor(&envs, device);
+    let mask = Tensor::cat(&masks, 0).to(device);
     // Get the target.
     let target_policy = Tensor::stack(&policy_targets, 0)
         .view([BATCH_SIZE as i64, output_size::<N>() as i64])
-        .to(DEVICE);
-    let target_value = Tensor::from_slice(&value_targets).unsqueeze(1).to(DEVICE);
+        .to(device);
+    let target_value = Tensor::from_slice(&value_targets).unsqueeze(1).to(device);
     let target_ube = Tensor::from_slice(&ube_targets)
         .unsqueeze(1)
-        .to(DEVICE)
+        .to(device)
+        // Clamp before the log so that zero (or negative) targets do not become NaN.
+        .clamp_min(MINIMUM_UBE_TARGET.exp())
         .log()
-        .clamp(MINIMUM_UBE_TARGET, MAXIMUM_VARIANCE.ln());
+        .clamp_max(MAXIMUM_VARIANCE.ln());
+    let target_wdl = result_wdl_target(&results, &target_value);
+    let (target_short_value, has_short_value) =
+        short_value_target(&results, short_value_discount, device);
+    let (target_score, has_score) = score_target(&score_targets, device);
+    let ownership_targets: Vec<_> = ownership_targets.iter().map(Option::as_deref).collect();
+    let (target_ownership, has_ownership) = ownership_target::<N>(&ownership_targets, device);
+    let weights = weights.map_or_else(
+        || Tensor::ones([BATCH_SIZE as i64, 1], (Kind::Float, device)),
+        |weights| Tensor::from_slice(weights).unsqueeze(1).to(device),
+    );
 
     Tensors {
         input,
@@ -369,62 +1664,370 @@ fn create_input_and_target_tensors<'a>(
         target_value,
         target_policy,
         target_ube,
+        target_wdl,
+        target_short_value,
+        has_short_value,
+        target_score,
+        has_score,
+        target_ownership,
+        has_ownership,
+        weights,
     }
 }
 
-fn compute_loss_and_take_step(
-    net: &mut Net,
-    opt: &mut Optimizer,
-    tensors: Tensors,
+/// The outcome of a training step which was not skipped.
+struct Step {
+    /// Squared value error of each target, for every sub-batch.
+    value_errors: Vec<Vec<f32>>,
+    /// Losses averaged over the sub-batches.
+    losses: Losses,
+}
+
+/// Compute the loss of each sub-batch, accumulating the gradients,
+/// and take a single step with them at the learning rate.
+/// Returns `None` if the step was skipped.
+fn compute_loss_and_take_step<NET: TrainingNetwork>(
+    net: &mut NET,
+    opt: &mut Adam,
+    scaler: Option<&mut GradScaler>,
+    sub_batches: impl ExactSizeIterator<Item = Tensors>,
     // early_reference: &Tensor,
     // late_reference: &Tensor,
     train_ube: bool,
-) {
-    // Get network output.
-    let (policy, network_value, network_ube) = net.forward_t(&tensors.input, true);
+    learning_rate: f64,
+    step_config: &StepConfig,
+) -> Option<Step> {
+    let sub_batch_count = sub_batches.len();
+    let weight = (sub_batch_count as f64).recip();
+    let mut value_errors = Vec::with_capacity(sub_batch_count);
+    let mut losses = Losses {
+        total: 0.0,
+        policy: 0.0,
+        value: 0.0,
+        ube: None,
+        wdl: None,
+        short_value: None,
+        score: None,
+        ownership: None,
+    };
+    opt.zero_grad();
+    for tensors in sub_batches {
+        let (loss, sub_batch_losses, errors) =
+            compute_loss(net, scaler.is_some(), &tensors, train_ube, step_config)?;
+
+        // Update network RND min and max for normalization.
+        // update_rnd(net, early_reference, late_reference);
+
+        // Update the hash counts, or whatever else the network keeps track of.
+        net.observe_batch(&tensors.input);
+
+        // Accumulate gradients of the mean loss over all sub-batches.
+        let loss = loss * weight;
+        match &scaler {
+            Some(scaler) => scaler.scale(&loss).backward(),
+            None => loss.backward(),
+        }
+        losses.total = weight.mul_add(sub_batch_losses.total, losses.total);
+        losses.policy = weight.mul_add(sub_batch_losses.policy, losses.policy);
+        losses.value = weight.mul_add(sub_batch_losses.value, losses.value);
+        losses.ube = sub_batch_losses
+            .ube
+            .map(|ube| weight.mul_add(ube, losses.ube.unwrap_or_default()));
+        losses.wdl = sub_batch_losses
+            .wdl
+            .map(|wdl| weight.mul_add(wdl, losses.wdl.unwrap_or_default()));
+        losses.short_value = sub_batch_losses
+            .short_value
+            .map(|short_value| weight.mul_add(short_value, losses.short_value.unwrap_or_default()));
+        losses.score = sub_batch_losses
+            .score
+            .map(|score| weight.mul_add(score, losses.score.unwrap_or_default()));
+        losses.ownership = sub_batch_losses
+            .ownership
+            .map(|ownership| weight.mul_add(ownership, losses.ownership.unwrap_or_default()));
+        value_errors.push(errors);
+    }
+    #[rustfmt::skip]
+    log::info!(
+        "loss = {}\n\
+         loss_policy = {}\n\
+         loss_value = {}\n\
+         loss_ube = {:?}\n\
+         loss_wdl = {:?}\n\
+         loss_short_value = {:?}\n\
+         loss_score = {:?}\n\
+         loss_ownership = {:?}",
+        losses.total,
+        losses.policy,
+        losses.value,
+        losses.ube,
+        losses.wdl,
+        losses.short_value,
+        losses.score,
+        losses.ownership,
+    );
+
+    // Take step.
+    let overflowed = scaler.is_some_and(|scaler| !scaler.unscale(net.vs()));
+    if log::log_enabled!(log::Level::Debug) {
+        log::debug!("grad_norm = {}", gradient_norm(net));
+    }
+    if step_config.grad_clip.is_finite() {
+        opt.clip_grad_norm(step_config.grad_clip);
+    }
+    // Overflows are expected while the loss scale is still being lowered,
+    // so they only skip the update and do not count as skipped steps.
+    if !overflowed {
+        let mut decay = [0.0; LR_GROUPS];
+        for (group, learning_rate) in step_config.group_learning_rates(learning_rate) {
+            opt.set_lr_group(group, learning_rate);
+            decay[group] = learning_rate * step_config.weight_decay;
+        }
+        if step_config.weight_decay > 0.0 {
+            decay_weights(net.vs(), &decay);
+        }
+        opt.step();
+    }
+    Some(Step {
+        value_errors,
+        losses,
+    })
+}
+
+/// Compute the loss of a single sub-batch.
+/// Returns the loss, its parts, and the squared value error of each target,
+/// or `None` if the loss is not finite.
+fn compute_loss<NET: TrainingNetwork>(
+    net: &NET,
+    amp: bool,
+    tensors: &Tensors,
+    train_ube: bool,
+    step_config: &StepConfig,
+) -> Option<(Tensor, Losses, Vec<f32>)> {
+    // Get network output. With mixed precision only the forward pass runs
+    // in fp16, the outputs are cast back so that the losses, and especially
+    // the squared errors, are computed in fp32 and cannot overflow.
+    let outputs = tch::autocast(amp, || net.forward_training_t(&tensors.input, true));
+    let (policy, network_value, network_ube) = (
+        outputs.policy.to_kind(Kind::Float),
+        outputs.value.to_kind(Kind::Float),
+        outputs.ube.to_kind(Kind::Float),
+    );
     let log_softmax_network_policy = policy
         .masked_fill(&tensors.mask, f64::from(f32::MIN))
         .view([-1, output_size::<N>() as i64])
         .log_softmax(1, Kind::Float);
 
     // Calculate loss.
-    let loss_policy = -(log_softmax_network_policy * &tensors.target_policy).sum(Kind::Float)
+    let loss_policy = -(log_softmax_network_policy * &tensors.target_policy * &tensors.weights)
+        .sum(Kind::Float)
         / i64::try_from(BATCH_SIZE).unwrap();
-    let loss_value = (tensors.target_value - network_value)
-        .square()
-        .mean(Kind::Float);
-    let loss_ube = if train_ube {
-        (tensors.target_ube - network_ube)
-            .square()
-            .mean(Kind::Float)
+    // The squared errors are also used as priorities, whichever loss is used.
+    let value_errors = (&tensors.target_value - &network_value).square();
+    let value_losses = match step_config.value_loss {
+        ValueLoss::Mse => value_errors.shallow_clone(),
+        ValueLoss::Huber => network_value.huber_loss(
+            &tensors.target_value,
+            tch::Reduction::None,
+            step_config.huber_delta,
+        ),
+    };
+    let loss_value = (value_losses * &tensors.weights).mean(Kind::Float);
+    let ube_trained = train_ube && step_config.ube_weight > 0.0;
+    let loss_ube = if ube_trained {
+        ((tensors.target_ube.detach() - network_ube).square() * &tensors.weights).mean(Kind::Float)
     } else {
         // We don't want to train UBE in pre-training.
         Tensor::zeros_like(&loss_value)
     };
+    // Auxiliary losses are only trained if the network has the head.
+    // The scalar value head is still trained next to the WDL head.
+    let loss_wdl = outputs
+        .wdl
+        .filter(|_| step_config.wdl_weight > 0.0)
+        .map(|wdl| {
+            wdl_loss(
+                &wdl.to_kind(Kind::Float),
+                &tensors.target_wdl,
+                &tensors.weights,
+            )
+        });
+    let loss_short_value = outputs
+        .short_value
+        .filter(|_| step_config.short_value_weight > 0.0)
+        .map(|short_value| {
+            short_value_loss(
+                &short_value.to_kind(Kind::Float),
+                &tensors.target_short_value,
+                &tensors.has_short_value,
+                &tensors.weights,
+            )
+        });
+    let loss_score = outputs
+        .score
+        .filter(|_| step_config.score_weight > 0.0)
+        .map(|score| {
+            score_loss(
+                &score.to_kind(Kind::Float),
+                &tensors.target_score,
+                &tensors.has_score,
+                &tensors.weights,
+            )
+        });
+    let loss_ownership = outputs
+        .ownership
+        .filter(|_| step_config.ownership_weight > 0.0)
+        .map(|ownership| {
+            ownership_loss(
+                &ownership.to_kind(Kind::Float),
+                &tensors.target_ownership,
+                &tensors.has_ownership,
+                &tensors.weights,
+            )
+        });
     // let loss_rnd = net.forward_rnd(&tensors.input, true).mean(Kind::Float);
-    let loss = &loss_policy + &loss_value + &loss_ube; // + &loss_rnd;
-    #[rustfmt::skip]
-    log::info!(
-        "loss = {loss:?}\n\
-         loss_policy = {loss_policy:?}\n\
-         loss_value = {loss_value:?}\n\
-         loss_ube = {loss_ube:?}"
-    );
-    // loss_rnd = {loss_rnd:?}"
+    let mut loss = &loss_policy + &loss_value + step_config.ube_weight * &loss_ube; // + &loss_rnd;
+    if let Some(loss_wdl) = &loss_wdl {
+        loss += step_config.wdl_weight * loss_wdl;
+    }
+    if let Some(loss_short_value) = &loss_short_value {
+        loss += step_config.short_value_weight * loss_short_value;
+    }
+    if let Some(loss_score) = &loss_score {
+        loss += step_config.score_weight * loss_score;
+    }
+    if let Some(loss_ownership) = &loss_ownership {
+        loss += step_config.ownership_weight * loss_ownership;
+    }
 
-    // Update network RND min and max for normalization.
-    // update_rnd(net, early_reference, late_reference);
+    // Do not let a NaN or infinite loss poison the weights.
+    if !f64::try_from(&loss).is_ok_and(f64::is_finite) {
+        let non_finite = |t: &Tensor| i64::try_from(t.isfinite().logical_not().sum(Kind::Int64));
+        #[rustfmt::skip]
+        log::error!(
+            "Skipping step because loss is not finite.\n\
+             loss_policy = {loss_policy:?}\n\
+             loss_value = {loss_value:?}\n\
+             loss_ube = {loss_ube:?}\n\
+             loss_wdl = {loss_wdl:?}\n\
+             loss_short_value = {loss_short_value:?}\n\
+             loss_score = {loss_score:?}\n\
+             loss_ownership = {loss_ownership:?}\n\
+             non-finite inputs: {:?}\n\
+             non-finite value targets: {:?}\n\
+             non-finite policy targets: {:?}\n\
+             non-finite UBE targets: {:?}",
+            non_finite(&tensors.input),
+            non_finite(&tensors.target_value),
+            non_finite(&tensors.target_policy),
+            non_finite(&tensors.target_ube),
+        );
+        return None;
+    }
 
-    // Update hash counts
-    net.update_counts(&tensors.input);
+    let scalar = |t: &Tensor| f64::try_from(t).unwrap_or(f64::NAN);
+    let losses = Losses {
+        total: scalar(&loss),
+        policy: scalar(&loss_policy),
+        value: scalar(&loss_value),
+        ube: ube_trained.then(|| scalar(&loss_ube)),
+        wdl: loss_wdl.as_ref().map(scalar),
+        short_value: loss_short_value.as_ref().map(scalar),
+        score: loss_score.as_ref().map(scalar),
+        ownership: loss_ownership.as_ref().map(scalar),
+    };
+    let value_errors = Vec::try_from(value_errors.detach().view(-1).to_kind(Kind::Float))
+        .expect("value errors should be a flat float tensor");
+    Some((loss, losses, value_errors))
+}
 
-    // Take step.
-    opt.backward_step(&loss);
+/// Keep track of how many steps in a row were skipped,
+/// and abort if there were too many.
+fn track_skipped_steps(stepped: bool, skipped_in_a_row: &mut usize, step_config: &StepConfig) {
+    if stepped {
+        *skipped_in_a_row = 0;
+        return;
+    }
+    *skipped_in_a_row += 1;
+    if *skipped_in_a_row > step_config.max_skipped_steps {
+        log::error!("Skipped {skipped_in_a_row} steps in a row because of non-finite loss.");
+        std::process::exit(1);
+    }
+}
+
+/// Number of optimizer parameter groups, one for each of
+/// [`PARAMETER_GROUPS`] and one for all other variables.
+const LR_GROUPS: usize = PARAMETER_GROUPS.len() + 1;
+
+/// Put every trainable variable into the optimizer parameter group of the
+/// prefix of its name in [`PARAMETER_GROUPS`], or into the last group,
+/// so that the groups can have different learning rates.
+/// Returns which groups have any variables.
+///
+/// This has to be done before the optimizer is built,
+/// because the optimizer reads the groups of the variables when it is built.
+fn assign_lr_groups(vs: &VarStore) -> [bool; LR_GROUPS] {
+    let mut variables = vs.variables_.lock().unwrap();
+    let groups: HashMap<_, _> = variables
+        .named_variables
+        .iter()
+        .map(|(name, tensor)| {
+            let group = PARAMETER_GROUPS
+                .iter()
+                .position(|prefix| name.starts_with(prefix))
+                .unwrap_or(LR_GROUPS - 1);
+            (tensor.data_ptr(), group)
+        })
+        .collect();
+    let mut populated = [false; LR_GROUPS];
+    for variable in &mut variables.trainable_variables {
+        variable.group = groups[&variable.tensor.data_ptr()];
+        populated[variable.group] = true;
+    }
+    populated
+}
+
+/// Shrink the weights towards zero by the fraction of their parameter group,
+/// like the decoupled weight decay of `AdamW`. Biases and the parameters of
+/// normalization layers are the only variables with a single dimension,
+/// and are not decayed.
+fn decay_weights(vs: &VarStore, fractions: &[f64]) {
+    let variables = vs.variables_.lock().unwrap();
+    tch::no_grad(|| {
+        for variable in &variables.trainable_variables {
+            if variable.tensor.dim() > 1 {
+                let mut weight = variable.tensor.shallow_clone();
+                weight *= 1.0 - fractions[variable.group];
+            }
+        }
+    });
+}
+
+/// Compute the global norm of all gradients in the network.
+fn gradient_norm(net: &impl Network) -> f64 {
+    net.vs()
+        .trainable_variables()
+        .iter()
+        .map(Tensor::grad)
+        .filter(Tensor::defined)
+        .map(|grad| f64::try_from(grad.norm()).unwrap().powi(2))
+        .sum::<f64>()
+        .sqrt()
 }
 
+/// Train on random games, starting after `starting_steps` steps if a
+/// previous run was interrupted. Stops early when interrupted.
+/// Returns the number of steps after pre-training.
+#[allow(clippy::too_many_arguments)]
 fn pre_training(
     net: &mut Net,
-    opt: &mut Optimizer,
+    opt: &mut Adam,
+    mut scaler: Option<&mut GradScaler>,
+    lr_schedule: &LrSchedule,
+    step_config: &StepConfig,
+    policy_config: &PolicyTargetConfig,
+    discount: f32,
+    starting_steps: usize,
     rng: &mut impl Rng,
     directory: &Path,
     // early_reference: &Tensor,
@@ -452,73 +2055,240 @@ fn pre_training(
                 .expect("there should always be at least one action");
             let policy = actions.drain(..).map(|a| (a, p)).collect();
             // Value is the discounted end of the game.
+            // The discount is applied through the ply count of the `Eval`.
             value = value.negate();
+            let score = Some(final_score(&game, env.to_move));
+            let ownership = Some(final_ownership(&game, env.to_move));
             buffer.push(Target {
                 env,
                 policy,
-                value: f32::from(value),
+                value: value.discounted(discount).into_inner(),
                 ube: MAXIMUM_VARIANCE as f32 - f32::EPSILON,
+                score,
+                ownership,
+                result: Some(value),
             });
         }
     }
     buffer.shuffle(rng);
     // Save initial targets for inspection.
-    let content: String = buffer.iter().map(ToString::to_string).collect();
-    OpenOptions::new()
+    let file = OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
         .open(directory.join("targets-initial.txt"))
-        .unwrap()
-        .write_all(content.as_bytes())
         .unwrap();
+    write_targets(BufWriter::new(file), &buffer).unwrap();
 
-    for batch in buffer.chunks_exact(BATCH_SIZE).take(PRE_TRAINING_STEPS) {
-        let tensors = create_input_and_target_tensors(batch.iter(), rng);
-        compute_loss_and_take_step(
-            net, opt, tensors, // early_reference, late_reference,
+    let mut skipped_in_a_row = 0;
+    for (steps, batch) in buffer
+        .chunks_exact(BATCH_SIZE)
+        .take(PRE_TRAINING_STEPS)
+        .enumerate()
+        .skip(starting_steps)
+    {
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            log::info!("Interrupted pre-training after {steps} steps");
+            return steps;
+        }
+        let tensors = create_input_and_target_tensors(
+            batch.iter(),
+            None,
+            policy_config,
+            step_config.short_value_discount,
+            net.vs().device(),
+            rng,
+        );
+        let step = compute_loss_and_take_step(
+            net,
+            opt,
+            scaler.as_deref_mut(),
+            std::iter::once(tensors), // early_reference, late_reference,
             false,
+            lr_schedule.learning_rate(steps),
+            step_config,
         );
+        track_skipped_steps(step.is_some(), &mut skipped_in_a_row, step_config);
     }
+    PRE_TRAINING_STEPS
 }
 
-fn create_batch(
+/// Remove a batch of targets from the buffers. The selfplay targets are
+/// sampled by priority if there are `exploitation_priorities`.
+/// Returns the targets together with their importance-sampling weights,
+/// or `None` without removing anything if either buffer has fewer targets
+/// than the batch needs.
+fn sample_batch(
     using_reanalyze: bool,
     exploitation_buffer: &mut Vec<TargetWithContext>,
+    exploitation_priorities: Option<&mut SumTree>,
     reanalyze_buffer: &mut Vec<TargetWithContext>,
+    replay_config: &ReplayConfig,
+    buffer_config: &BufferConfig,
     rng: &mut impl Rng,
-) -> Tensors {
-    // TODO: Can we avoid doing an O(n) operation here?
-    // Ideally we would like to sample without replacement,
-    // Then swap_remove those targets which have forced_uses == 0.
-    exploitation_buffer.shuffle(rng);
-    reanalyze_buffer.shuffle(rng);
-
-    if using_reanalyze {
-        let batch: Vec<_> = exploitation_buffer
-            .drain(exploitation_buffer.len() - BATCH_SIZE / 2..)
-            .chain(reanalyze_buffer.drain(reanalyze_buffer.len() - BATCH_SIZE / 2..))
-            .collect();
-        let tensors = create_input_and_target_tensors(batch.iter().map(|t| &t.target), rng);
-        let mut iter = batch.into_iter();
-        exploitation_buffer.extend(
-            iter.by_ref()
-                .take(BATCH_SIZE / 2)
-                .filter_map(TargetWithContext::reuse),
+) -> Option<(Vec<TargetWithContext>, Vec<f32>)> {
+    let exploitation_amount = buffer_config.exploitation_amount(using_reanalyze);
+    let reanalyze_amount = if using_reanalyze {
+        buffer_config.reanalyze_amount
+    } else {
+        0
+    };
+    if exploitation_buffer.len() < exploitation_amount || reanalyze_buffer.len() < reanalyze_amount
+    {
+        log::warn!(
+            "Not enough targets for a batch: {} of {exploitation_amount} selfplay and {} of \
+             {reanalyze_amount} reanalyze targets",
+            exploitation_buffer.len(),
+            reanalyze_buffer.len(),
         );
-        reanalyze_buffer.extend(iter.filter_map(TargetWithContext::reuse));
-        return tensors;
+        return None;
     }
+    let (mut batch, mut weights) = if let Some(priorities) = exploitation_priorities {
+        sample_prioritized_and_remove(
+            exploitation_buffer,
+            priorities,
+            exploitation_amount,
+            replay_config.beta,
+            rng,
+        )
+    } else {
+        (
+            sample_and_remove(exploitation_buffer, exploitation_amount, rng),
+            vec![1.0; exploitation_amount],
+        )
+    };
+    for weight in &mut weights {
+        *weight *= buffer_config.exploitation_weight;
+    }
+    if using_reanalyze {
+        batch.extend(sample_and_remove(reanalyze_buffer, reanalyze_amount, rng));
+        weights.resize(BATCH_SIZE, buffer_config.reanalyze_weight);
+    }
+    Some((batch, weights))
+}
+
+/// Halve the weights of the targets for every `halflife` steps since they
+/// were loaded, if there is a half-life.
+/// Returns the average age of the targets in steps.
+fn weight_by_staleness(
+    batch: &[TargetWithContext],
+    weights: &mut [f32],
+    model_steps: usize,
+    halflife: Option<usize>,
+) -> f64 {
+    let mut total_age = 0;
+    for (target, weight) in batch.iter().zip(weights) {
+        let age = model_steps.saturating_sub(target.model_steps);
+        total_age += age;
+        if let Some(halflife) = halflife {
+            *weight *= 0.5f32.powf(age as f32 / halflife as f32);
+        }
+    }
+    total_age as f64 / batch.len().max(1) as f64
+}
 
-    let batch: Vec<_> = exploitation_buffer
-        .drain(exploitation_buffer.len() - BATCH_SIZE..)
+/// Put the targets which still have uses left back into their buffers,
+/// updating their priorities with the latest value errors.
+#[allow(clippy::too_many_arguments)]
+fn return_batch(
+    batch: Vec<TargetWithContext>,
+    value_errors: Option<&[f32]>,
+    using_reanalyze: bool,
+    buffer_config: &BufferConfig,
+    replay_config: &ReplayConfig,
+    exploitation_buffer: &mut Vec<TargetWithContext>,
+    mut exploitation_priorities: Option<&mut SumTree>,
+    reanalyze_buffer: &mut Vec<TargetWithContext>,
+) {
+    let exploitation_amount = if using_reanalyze {
+        buffer_config.exploitation_amount(true)
+    } else {
+        batch.len()
+    };
+    let mut iter = batch.into_iter().enumerate().map(|(i, mut t)| {
+        if let Some(errors) = value_errors {
+            t.priority = errors[i].max(MIN_PRIORITY);
+        }
+        t
+    });
+    for target in iter
+        .by_ref()
+        .take(exploitation_amount)
+        .filter_map(TargetWithContext::reuse)
+    {
+        if let Some(priorities) = &mut exploitation_priorities {
+            priorities.push(replay_config.scaled_priority(&target));
+        }
+        exploitation_buffer.push(target);
+    }
+    reanalyze_buffer.extend(iter.filter_map(TargetWithContext::reuse));
+}
+
+/// Sample `amount` distinct targets uniformly and remove them from the buffer
+/// without touching the rest of it.
+fn sample_and_remove(
+    buffer: &mut Vec<TargetWithContext>,
+    amount: usize,
+    rng: &mut impl Rng,
+) -> Vec<TargetWithContext> {
+    let indices = rand::seq::index::sample(rng, buffer.len(), amount).into_vec();
+    remove_indices(buffer, indices)
+}
+
+/// Sample `amount` distinct targets proportionally to their priorities
+/// and remove them from the buffer and the priorities.
+/// Returns the targets and their normalized importance-sampling weights.
+fn sample_prioritized_and_remove(
+    buffer: &mut Vec<TargetWithContext>,
+    priorities: &mut SumTree,
+    amount: usize,
+    beta: f64,
+    rng: &mut impl Rng,
+) -> (Vec<TargetWithContext>, Vec<f32>) {
+    debug_assert_eq!(buffer.len(), priorities.len());
+    let len = buffer.len() as f64;
+    let total = priorities.total();
+    let mut sampled: Vec<_> = (0..amount)
+        .map(|_| {
+            let index = priorities.find(rng.gen::<f64>() * priorities.total());
+            let priority = priorities.get(index);
+            // Sampled targets cannot be sampled again.
+            priorities.set(index, 0.0);
+            (index, priority)
+        })
         .collect();
-    let tensors = create_input_and_target_tensors(batch.iter().map(|t| &t.target), rng);
-    exploitation_buffer.extend(batch.into_iter().filter_map(TargetWithContext::reuse));
-    tensors
+    // Removing in descending order guarantees that the element which gets
+    // swapped into place was not sampled.
+    sampled.sort_unstable_by_key(|&(index, _)| Reverse(index));
+    let (batch, weights): (Vec<_>, Vec<_>) = sampled
+        .into_iter()
+        .map(|(index, priority)| {
+            priorities.swap_remove(index);
+            (
+                buffer.swap_remove(index),
+                (len * priority / total).powf(-beta),
+            )
+        })
+        .unzip();
+    let max_weight = weights.iter().copied().fold(f64::MIN_POSITIVE, f64::max);
+    let weights = weights
+        .into_iter()
+        .map(|w| (w / max_weight) as f32)
+        .collect();
+    (batch, weights)
+}
+
+/// Remove the targets at the given (distinct) indices from the buffer.
+fn remove_indices(
+    buffer: &mut Vec<TargetWithContext>,
+    mut indices: Vec<usize>,
+) -> Vec<TargetWithContext> {
+    // Removing in descending order guarantees that the element which gets
+    // swapped into place was not sampled.
+    indices.sort_unstable_by_key(|&i| Reverse(i));
+    indices.into_iter().map(|i| buffer.swap_remove(i)).collect()
 }
 
-#[allow(unused)]
 fn truncate_buffer_if_needed(buffer: &mut Vec<TargetWithContext>, max_length: usize, name: &str) {
     if buffer.len() > max_length {
         log::info!(
@@ -530,38 +2300,1067 @@ fn truncate_buffer_if_needed(buffer: &mut Vec<TargetWithContext>, max_length: us
     }
 }
 
+/// Find the selfplay target shards, `targets-selfplay-*.txt`
+/// or `targets-selfplay-*.txt.gz`.
+fn selfplay_shards(directory: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut shards: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.strip_prefix("targets-selfplay-")
+                        .is_some_and(|rest| rest.ends_with(".txt") || rest.ends_with(".txt.gz"))
+                })
+        })
+        .collect();
+    shards.sort();
+    shards
+}
+
+#[allow(clippy::too_many_arguments)]
 fn fill_buffers(
     exploitation_buffer: &mut Vec<TargetWithContext>,
-    exploitation_targets_seek: &mut u64,
     reanalyze_buffer: &mut Vec<TargetWithContext>,
-    reanalyze_targets_seek: &mut u64,
+    seeks: &mut HashMap<PathBuf, u64>,
+    target_receiver: Option<&TargetReceiver<N, HALF_KOMI>>,
     directory: &Path,
     model_steps: usize,
     using_reanalyze: bool,
+    augment_expand: bool,
+    dedup: bool,
+    max_corrupt_ratio: Option<f64>,
 ) {
     let start = Instant::now();
+    let (exploitation_start, reanalyze_start) = (exploitation_buffer.len(), reanalyze_buffer.len());
 
-    if let Err(error) = fill_buffer_with_targets(
-        exploitation_buffer,
-        exploitation_targets_seek,
-        &directory.join("targets-selfplay.txt"),
-        SELFPLAY_TARGET_FORCED_USES,
-        model_steps,
-    ) {
-        log::error!("Cannot read selfplay targets: {error}");
+    match target_receiver {
+        Some(receiver) => {
+            for target in receiver.try_iter() {
+                exploitation_buffer.extend(with_context(
+                    target,
+                    SELFPLAY_TARGET_FORCED_USES,
+                    model_steps,
+                    augment_expand,
+                ));
+            }
+        }
+        None => fill_selfplay_buffer_from_files(
+            exploitation_buffer,
+            seeks,
+            directory,
+            model_steps,
+            augment_expand,
+            max_corrupt_ratio,
+        ),
     }
 
     if using_reanalyze {
-        if let Err(error) = fill_buffer_with_targets(
+        let path = directory.join("targets-reanalyze.txt");
+        match fill_buffer_with_targets(
             reanalyze_buffer,
-            reanalyze_targets_seek,
-            &directory.join("targets-reanalyze.txt"),
+            seeks.entry(path.clone()).or_insert(0),
+            &path,
             REANALYZE_TARGET_FORCED_USES,
             model_steps,
+            false,
         ) {
-            log::error!("Cannot read reanalyze targets: {error}");
+            Ok(counts) => check_corrupt_targets(counts, max_corrupt_ratio, "reanalyze"),
+            Err(error) => log::error!("Cannot read reanalyze targets: {error}"),
         }
     }
 
+    if dedup {
+        dedup_new_targets(exploitation_buffer, exploitation_start, "selfplay");
+        dedup_new_targets(reanalyze_buffer, reanalyze_start, "reanalyze");
+    }
+
     log::debug!("It took {:?} to add targets to buffer.", start.elapsed());
 }
+
+/// Deduplicate the targets from `start` on with [`dedup_targets`]
+/// and log how many of them were merged.
+fn dedup_new_targets(buffer: &mut Vec<TargetWithContext>, start: usize, name: &str) {
+    let new = buffer.len() - start;
+    if new == 0 {
+        return;
+    }
+    let merged = dedup_targets(buffer, start);
+    log::info!(
+        "Merged {merged} of {new} new {name} targets into others, a dedup ratio of {:.3}",
+        merged as f64 / new as f64
+    );
+}
+
+/// Bring the target into its canonical form and remember its hash,
+/// unless that was already done.
+fn canonicalize_target(context: &mut TargetWithContext) -> u64 {
+    if let Some(hash) = context.position_hash {
+        return hash;
+    }
+    let (canonical, symmetry) = canonicalize(&context.target.env);
+    context.target = context
+        .target
+        .all_symmetries()
+        .into_iter()
+        .nth(symmetry)
+        .expect("there should be 8 symmetries");
+    let hash = canonical.zobrist_hash();
+    context.position_hash = Some(hash);
+    hash
+}
+
+/// Merge the targets from `start` on into the targets of the buffer which are
+/// the same position up to symmetry, and into each other. The first target of
+/// a position is kept in its canonical form, with the running average of the
+/// values, uncertainties, scores, and policies.
+/// Returns the number of new targets which were merged into another one.
+fn dedup_targets(buffer: &mut Vec<TargetWithContext>, start: usize) -> usize {
+    let new: Vec<_> = buffer.drain(start..).collect();
+    // Index in the buffer of each position.
+    let mut positions: HashMap<u64, usize> = HashMap::with_capacity(buffer.len() + new.len());
+    for (index, context) in buffer.iter_mut().enumerate() {
+        positions
+            .entry(canonicalize_target(context))
+            .or_insert(index);
+    }
+    let mut merged = 0;
+    for mut context in new {
+        match positions.entry(canonicalize_target(&mut context)) {
+            Entry::Occupied(entry) => {
+                let existing = &mut buffer[*entry.get()];
+                existing.merged += context.merged;
+                merge_target(
+                    &mut existing.target,
+                    &context.target,
+                    context.merged,
+                    existing.merged,
+                );
+                // The new target gets its uses and is as fresh as the new one.
+                existing.forced_uses = existing.forced_uses.max(context.forced_uses);
+                existing.model_steps = existing.model_steps.max(context.model_steps);
+                existing.priority = existing.priority.max(context.priority);
+                merged += 1;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(buffer.len());
+                buffer.push(context);
+            }
+        }
+    }
+    merged
+}
+
+/// Update the running average in `merged` with `target`, which is the average
+/// of `weight` targets of the same position, for `count` targets in total.
+fn merge_target(merged: &mut Target<Env>, target: &Target<Env>, weight: u32, count: u32) {
+    let average = |old: f32, new: f32| old + (new - old) * weight as f32 / count as f32;
+    merged.value = average(merged.value, target.value);
+    merged.ube = average(merged.ube, target.ube);
+    merged.score = match (merged.score, target.score) {
+        (Some(old), Some(new)) => Some(average(old, new)),
+        (old, new) => old.or(new),
+    };
+    // Results cannot be averaged, so the merged target only keeps its result
+    // if the games agree.
+    merged.result = match (merged.result, target.result) {
+        (Some(old), Some(new)) => (old == new).then_some(old),
+        (old, new) => old.or(new),
+    };
+    // Actions which are missing from a policy have a probability of zero.
+    let probability = |policy: &[(Move, NotNan<f32>)], action: Move| {
+        policy
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map_or(0.0, |(_, p)| p.into_inner())
+    };
+    let mut actions: Vec<_> = merged.policy.iter().map(|(a, _)| *a).collect();
+    actions.extend(
+        target
+            .policy
+            .iter()
+            .map(|(a, _)| *a)
+            .filter(|a| !merged.policy.iter().any(|(b, _)| b == a)),
+    );
+    merged.policy = actions
+        .into_iter()
+        .map(|action| {
+            let p = average(
+                probability(&merged.policy, action),
+                probability(&target.policy, action),
+            );
+            (
+                action,
+                NotNan::new(p).expect("average of probabilities should not be NaN"),
+            )
+        })
+        .collect();
+}
+
+/// Read new selfplay targets from `targets-selfplay.txt`,
+/// or `targets-selfplay.bin` if it exists, and from the shards.
+fn fill_selfplay_buffer_from_files(
+    exploitation_buffer: &mut Vec<TargetWithContext>,
+    seeks: &mut HashMap<PathBuf, u64>,
+    directory: &Path,
+    model_steps: usize,
+    augment_expand: bool,
+    max_corrupt_ratio: Option<f64>,
+) {
+    // Prefer the binary format and compressed files if selfplay writes them.
+    let existing = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| directory.join(name))
+            .find(|path| path.exists())
+    };
+    // Each file has its own seek, so switching formats does not skip targets.
+    let result =
+        if let Some(binary_path) = existing(&["targets-selfplay.bin", "targets-selfplay.bin.gz"]) {
+            fill_buffer_with_binary_targets(
+                exploitation_buffer,
+                seeks.entry(binary_path.clone()).or_insert(0),
+                &binary_path,
+                SELFPLAY_TARGET_FORCED_USES,
+                model_steps,
+                augment_expand,
+            )
+        } else {
+            let path = existing(&["targets-selfplay.txt.gz"])
+                .unwrap_or_else(|| directory.join("targets-selfplay.txt"));
+            fill_buffer_with_targets(
+                exploitation_buffer,
+                seeks.entry(path.clone()).or_insert(0),
+                &path,
+                SELFPLAY_TARGET_FORCED_USES,
+                model_steps,
+                augment_expand,
+            )
+        };
+    let shards = selfplay_shards(directory);
+    match result {
+        Ok(counts) => check_corrupt_targets(counts, max_corrupt_ratio, "selfplay"),
+        // The single file is not needed when every worker writes a shard.
+        Err(error) if error.kind() == ErrorKind::NotFound && !shards.is_empty() => {}
+        Err(error) => log::error!("Cannot read selfplay targets: {error}"),
+    }
+
+    // Several selfplay workers can each write their own shard.
+    // New shards start being read from the beginning.
+    for path in shards {
+        let seek = seeks.entry(path.clone()).or_insert(0);
+        match fill_buffer_with_targets(
+            exploitation_buffer,
+            seek,
+            &path,
+            SELFPLAY_TARGET_FORCED_USES,
+            model_steps,
+            augment_expand,
+        ) {
+            Ok(counts) => check_corrupt_targets(counts, max_corrupt_ratio, "selfplay shard"),
+            Err(error) => log::error!("Cannot read {}: {error}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Write};
+
+    use clap::Parser;
+    use flate2::{write::GzEncoder, Compression};
+    use ordered_float::NotNan;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use takzero::{
+        network::{
+            generic::{self, NetConfig},
+            net6_simhash::{Env, Net, HALF_KOMI, N},
+            AuxiliaryHeads,
+            Network,
+            RndNetwork,
+            TrainingNetwork,
+        },
+        search::{env::Environment, eval::Eval},
+        target::{Target, BIN_VERSION},
+    };
+    use tch::{
+        nn::{self, Module, VarStore},
+        Device,
+        Kind,
+        Tensor,
+    };
+
+    use super::{
+        assign_lr_groups,
+        compute_loss,
+        compute_loss_and_take_step,
+        create_input_and_target_tensors,
+        decay_weights,
+        dedup_targets,
+        fill_buffer_with_binary_targets,
+        fill_buffer_with_targets,
+        get_model_path_with_most_steps,
+        parse_huber_delta,
+        prepare_directory,
+        sample_batch,
+        sample_prioritized_and_remove,
+        seeded_rng,
+        weight_by_staleness,
+        Adam,
+        Args,
+        BufferConfig,
+        PolicyTargetConfig,
+        Progress,
+        ReplayConfig,
+        StepConfig,
+        SumTree,
+        TargetWithContext,
+        Tensors,
+        ValueLoss,
+        BATCH_SIZE,
+        LR_GROUPS,
+    };
+    use crate::metrics::Losses;
+
+    fn buffer(len: usize) -> Vec<TargetWithContext> {
+        (0..len)
+            .map(|model_steps| TargetWithContext {
+                target: Target {
+                    env: Env::default(),
+                    policy: Box::default(),
+                    value: 0.0,
+                    ube: 0.0,
+                    score: None,
+                    ownership: None,
+                    result: None,
+                },
+                forced_uses: 1,
+                model_steps,
+                priority: 1.0,
+                position_hash: None,
+                merged: 1,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sampling_from_a_near_empty_reanalyze_buffer_does_not_panic() {
+        let buffer_config = BufferConfig {
+            steps_before_reanalyze: 0,
+            min_selfplay_len: BATCH_SIZE,
+            min_reanalyze_len: BATCH_SIZE,
+            max_selfplay_len: None,
+            max_reanalyze_len: None,
+            reanalyze_amount: BATCH_SIZE / 2,
+            exploitation_weight: 1.0,
+            reanalyze_weight: 2.0,
+            staleness_halflife: None,
+            accum_steps: 1,
+        };
+        let replay_config = ReplayConfig {
+            prioritized: false,
+            alpha: 0.6,
+            beta: 0.4,
+        };
+        let mut rng = StdRng::seed_from_u64(123);
+        let mut exploitation_buffer = buffer(BATCH_SIZE);
+        let mut reanalyze_buffer = buffer(3);
+
+        let batch = sample_batch(
+            true,
+            &mut exploitation_buffer,
+            None,
+            &mut reanalyze_buffer,
+            &replay_config,
+            &buffer_config,
+            &mut rng,
+        );
+        assert!(batch.is_none());
+        assert_eq!(exploitation_buffer.len(), BATCH_SIZE);
+        assert_eq!(reanalyze_buffer.len(), 3);
+
+        // Without reanalyze the whole batch comes from selfplay.
+        let (batch, weights) = sample_batch(
+            false,
+            &mut exploitation_buffer,
+            None,
+            &mut reanalyze_buffer,
+            &replay_config,
+            &buffer_config,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(batch.len(), BATCH_SIZE);
+        assert_eq!(weights.len(), BATCH_SIZE);
+        assert!(weights.iter().all(|&w| (w - 1.0).abs() < f32::EPSILON));
+        assert!(exploitation_buffer.is_empty());
+
+        // Reanalyze targets are weighted by the reanalyze weight.
+        let mut exploitation_buffer = buffer(BATCH_SIZE);
+        let mut reanalyze_buffer = buffer(BATCH_SIZE);
+        let (_, weights) = sample_batch(
+            true,
+            &mut exploitation_buffer,
+            None,
+            &mut reanalyze_buffer,
+            &replay_config,
+            &buffer_config,
+            &mut rng,
+        )
+        .unwrap();
+        let (exploitation, reanalyze) = weights.split_at(BATCH_SIZE / 2);
+        assert!(exploitation.iter().all(|&w| (w - 1.0).abs() < f32::EPSILON));
+        assert!(reanalyze.iter().all(|&w| (w - 2.0).abs() < f32::EPSILON));
+    }
+
+    #[test]
+    fn prioritized_sampling_follows_the_priorities() {
+        const SAMPLES: usize = 8000;
+        let replay_config = ReplayConfig {
+            prioritized: true,
+            alpha: 1.0,
+            beta: 1.0,
+        };
+        let with_priorities = || {
+            let mut buffer = buffer(4);
+            for (target, priority) in buffer.iter_mut().zip([1.0, 1.0, 1.0, 5.0]) {
+                target.priority = priority;
+            }
+            let priorities: SumTree = buffer
+                .iter()
+                .map(|target| replay_config.scaled_priority(target))
+                .collect();
+            (buffer, priorities)
+        };
+        let mut rng = StdRng::seed_from_u64(456);
+
+        // The targets are told apart by their model steps.
+        let mut counts = [0; 4];
+        let (mut buffer, mut priorities) = with_priorities();
+        for _ in 0..SAMPLES {
+            let (batch, _) = sample_prioritized_and_remove(
+                &mut buffer,
+                &mut priorities,
+                1,
+                replay_config.beta,
+                &mut rng,
+            );
+            assert_eq!(buffer.len(), 3);
+            assert_eq!(priorities.len(), 3);
+            counts[batch[0].model_steps] += 1;
+            priorities.push(replay_config.scaled_priority(&batch[0]));
+            buffer.extend(batch);
+        }
+        let share = |count: usize| count as f64 / SAMPLES as f64;
+        // The last target has five eighths of the total priority.
+        assert!((share(counts[3]) - 0.625).abs() < 0.02, "{counts:?}");
+        assert!(
+            counts[..3]
+                .iter()
+                .all(|&count| (share(count) - 0.125).abs() < 0.02),
+            "{counts:?}"
+        );
+
+        // Importance-sampling weights undo the priorities, relative to the
+        // largest weight.
+        let (mut buffer, mut priorities) = with_priorities();
+        let (batch, weights) = sample_prioritized_and_remove(
+            &mut buffer,
+            &mut priorities,
+            4,
+            replay_config.beta,
+            &mut rng,
+        );
+        assert!(buffer.is_empty());
+        assert_eq!(priorities.len(), 0);
+        for (target, weight) in batch.iter().zip(weights) {
+            let expected = if target.model_steps == 3 { 0.2 } else { 1.0 };
+            assert!((weight - expected).abs() < 1e-6, "{weight} != {expected}");
+        }
+    }
+
+    #[test]
+    fn huber_delta_must_be_positive() {
+        assert!((parse_huber_delta("0.5").unwrap() - 0.5).abs() < f64::EPSILON);
+        assert!(parse_huber_delta("0").is_err());
+        assert!(parse_huber_delta("-1").is_err());
+        assert!(parse_huber_delta("inf").is_err());
+        assert!(parse_huber_delta("delta").is_err());
+    }
+
+    #[test]
+    fn progress_of_the_checkpoint_is_resumed() {
+        let directory = std::env::temp_dir().join("takzero-learn-progress");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let progress = |model_steps, seek| Progress {
+            model_steps,
+            seeks: [
+                (directory.join("targets-selfplay.txt"), seek),
+                (directory.join("targets-selfplay.bin"), 2 * seek),
+                (directory.join("targets-selfplay,1.txt.gz"), 3 * seek),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        progress(100, 10).save(&directory, true);
+        progress(150, 15).save(&directory, false);
+        // Resuming from the checkpoint gets the progress of its steps.
+        assert_eq!(Progress::load(&directory, 100), Some(progress(100, 10)));
+        // Otherwise the latest progress is used.
+        assert_eq!(Progress::load(&directory, 120), Some(progress(150, 15)));
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(Progress::load(&directory, 100), None);
+    }
+
+    #[test]
+    fn missing_directory_is_created() {
+        let directory = std::env::temp_dir().join("takzero-learn-missing/nested");
+        let _ = std::fs::remove_dir_all(directory.parent().unwrap());
+        assert!(get_model_path_with_most_steps(&directory).is_none());
+
+        prepare_directory(&directory).unwrap();
+        assert!(directory.is_dir());
+        assert!(get_model_path_with_most_steps(&directory).is_none());
+        // Preparing it again is fine.
+        prepare_directory(&directory).unwrap();
+
+        // A file cannot be used as the directory.
+        let file = directory.join("file.txt");
+        std::fs::write(&file, "").unwrap();
+        let err = prepare_directory(&file).unwrap_err();
+        assert!(err.contains("--directory"), "{err}");
+        std::fs::remove_dir_all(directory.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn weight_decay_shrinks_weights_but_not_biases() {
+        const LEARNING_RATE: f64 = 1e-2;
+        const WEIGHT_DECAY: f64 = 0.1;
+        let vs = VarStore::new(Device::Cpu);
+        let linear = nn::linear(vs.root() / "linear", 8, 8, nn::LinearConfig::default());
+        let mut opt = Adam::new(&vs, LEARNING_RATE);
+        let bias = linear.bs.as_ref().unwrap().copy();
+        let xs = Tensor::ones([4, 8], (Kind::Float, Device::Cpu));
+        let mut norm = f64::try_from(linear.ws.norm()).unwrap();
+        for _ in 0..10 {
+            // The loss has no gradient, so only the decay changes the weights.
+            let loss = (linear.forward(&xs) * 0.0).sum(Kind::Float);
+            opt.zero_grad();
+            loss.backward();
+            decay_weights(&vs, &[LEARNING_RATE * WEIGHT_DECAY]);
+            opt.step();
+            let new_norm = f64::try_from(linear.ws.norm()).unwrap();
+            assert!(new_norm < norm, "{new_norm} should be less than {norm}");
+            norm = new_norm;
+        }
+        assert!(linear.bs.as_ref().unwrap().equal(&bias));
+    }
+
+    #[test]
+    fn parameter_groups_have_their_own_learning_rate() {
+        let vs = VarStore::new(Device::Cpu);
+        let core = (vs.root() / "core").var("weight", &[4, 4], nn::Init::Const(1.0));
+        let value = (vs.root() / "value").var("weight", &[4, 4], nn::Init::Const(1.0));
+        let other = vs.root().var("min", &[1], nn::Init::Const(1.0));
+        let populated = assign_lr_groups(&vs);
+        assert_eq!(populated, [true, false, true, false, false, true]);
+
+        let mut opt = Adam::new(&vs, 1e-2);
+        // Freeze the value head.
+        opt.set_lr_group(2, 0.0);
+        let loss = (&core + &value).sum(Kind::Float) + other.sum(Kind::Float);
+        opt.backward_step(&loss);
+        tch::no_grad(|| {
+            assert!(core.lt(1.0).all().int64_value(&[]) != 0);
+            assert!(value.eq(1.0).all().int64_value(&[]) != 0);
+            assert!(other.lt(1.0).all().int64_value(&[]) != 0);
+        });
+    }
+
+    #[test]
+    fn gzip_members_are_read_incrementally() {
+        let member = |count: usize| {
+            let mut actions = Vec::new();
+            let env = Env::default();
+            env.populate_actions(&mut actions);
+            let p = NotNan::new(1.0 / actions.len() as f32).unwrap();
+            let target = Target {
+                env,
+                policy: actions.into_iter().map(|a| (a, p)).collect(),
+                value: 0.5,
+                ube: 0.25,
+                score: None,
+                ownership: None,
+                result: None,
+            };
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            for _ in 0..count {
+                write!(encoder, "{target}").unwrap();
+            }
+            encoder.finish().unwrap()
+        };
+        let path = std::env::temp_dir().join("takzero-learn-targets.txt.gz");
+        let (first, second, third) = (member(2), member(1), member(3));
+        let mut file = [first.as_slice(), &second, &third[..third.len() / 2]].concat();
+        std::fs::write(&path, &file).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut seek = 0;
+        let counts = fill_buffer_with_targets(&mut buffer, &mut seek, &path, 1, 0, false).unwrap();
+        assert_eq!((counts.parsed, counts.corrupt), (3, 0));
+        assert_eq!(seek, (first.len() + second.len()) as u64);
+
+        // The rest of the last member is written.
+        file.extend_from_slice(&third[third.len() / 2..]);
+        std::fs::write(&path, &file).unwrap();
+        let counts = fill_buffer_with_targets(&mut buffer, &mut seek, &path, 1, 0, false).unwrap();
+        assert_eq!(counts.parsed, 3);
+        assert_eq!(seek, file.len() as u64);
+        assert_eq!(buffer.len(), 6);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_binary_targets_are_skipped() {
+        let mut actions = Vec::new();
+        let env = Env::default();
+        env.populate_actions(&mut actions);
+        let p = NotNan::new(1.0 / actions.len() as f32).unwrap();
+        let target = Target {
+            env,
+            policy: actions.into_iter().map(|a| (a, p)).collect(),
+            value: 0.5,
+            ube: 0.25,
+            score: None,
+            ownership: None,
+            result: None,
+        };
+        let mut file = Vec::new();
+        target.write_bin(&mut file).unwrap();
+        // The length is right, but the TPS does not fit in it.
+        file.extend_from_slice(&[BIN_VERSION, 3, 0, 0, 0, 0xff, 0xff, 0xff]);
+        target.write_bin(&mut file).unwrap();
+        let path = std::env::temp_dir().join("takzero-learn-corrupt-targets.bin");
+        std::fs::write(&path, &file).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut seek = 0;
+        let counts =
+            fill_buffer_with_binary_targets(&mut buffer, &mut seek, &path, 1, 0, false).unwrap();
+        assert_eq!((counts.parsed, counts.corrupt), (2, 1));
+        assert_eq!(seek, file.len() as u64);
+        assert_eq!(buffer.len(), 2);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_binary_targets_only_lose_the_rest_of_their_member() {
+        let mut actions = Vec::new();
+        let env = Env::default();
+        env.populate_actions(&mut actions);
+        let p = NotNan::new(1.0 / actions.len() as f32).unwrap();
+        let target = Target {
+            env,
+            policy: actions.into_iter().map(|a| (a, p)).collect(),
+            value: 0.5,
+            ube: 0.25,
+            score: None,
+            ownership: None,
+            result: None,
+        };
+        let member = |corrupt: &[u8]| {
+            let mut bytes = Vec::new();
+            target.write_bin(&mut bytes).unwrap();
+            bytes.extend_from_slice(corrupt);
+            target.write_bin(&mut bytes).unwrap();
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&bytes).unwrap();
+            encoder.finish().unwrap()
+        };
+        // A target which does not parse, and one of an unknown version.
+        let file = [
+            member(&[BIN_VERSION, 3, 0, 0, 0, 0xff, 0xff, 0xff]),
+            member(&[BIN_VERSION + 1, 0, 0, 0, 0]),
+            member(&[]),
+        ]
+        .concat();
+        let path = std::env::temp_dir().join("takzero-learn-corrupt-targets.bin.gz");
+        std::fs::write(&path, &file).unwrap();
+
+        let mut buffer = Vec::new();
+        let mut seek = 0;
+        let counts =
+            fill_buffer_with_binary_targets(&mut buffer, &mut seek, &path, 1, 0, false).unwrap();
+        assert_eq!((counts.parsed, counts.corrupt), (5, 2));
+        assert_eq!(seek, file.len() as u64);
+        assert_eq!(buffer.len(), 5);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn seed_reproduces_the_network_and_sampling() {
+        let run = || {
+            let mut rng = seeded_rng(Some(1234));
+            let net = Net::new(Device::Cpu, Some(rng.gen()));
+            let variables: BTreeMap<_, _> = net
+                .vs()
+                .variables()
+                .into_iter()
+                .map(|(name, tensor)| (name, tensor.sum(Kind::Double).double_value(&[])))
+                .collect();
+            let numbers: Vec<u64> = (0..8).map(|_| rng.gen()).collect();
+            (variables, numbers)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn repeated_positions_are_merged() {
+        let target = |moves: &[&str], action: &str, value| {
+            let mut env = Env::default();
+            for action in moves {
+                env.step(action.parse().unwrap());
+            }
+            Target {
+                env,
+                policy: Box::new([(action.parse().unwrap(), NotNan::new(1.0).unwrap())]),
+                value,
+                ube: value,
+                score: None,
+                ownership: None,
+                result: None,
+            }
+        };
+        let first = target(&["a1"], "c3", 1.0);
+        // The same position, mirrored.
+        let [_, _, _, mirrored, ..] = target(&["a1"], "d4", 0.0).all_symmetries();
+        let other = target(&["b2"], "c3", 0.5);
+
+        let mut buffer = buffer(1);
+        buffer[0].target = target(&["a1"], "c3", -1.0);
+        buffer.extend(
+            [first, mirrored, other]
+                .into_iter()
+                .map(|target| TargetWithContext {
+                    target,
+                    forced_uses: 1,
+                    model_steps: 0,
+                    priority: 1.0,
+                    position_hash: None,
+                    merged: 1,
+                }),
+        );
+        assert_eq!(dedup_targets(&mut buffer, 1), 2);
+        // The new targets are merged into the one which was already there.
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer[0].merged, 3);
+        let merged = &buffer[0].target;
+        assert!(merged.value.abs() < 1e-6);
+        assert!(merged.ube.abs() < 1e-6);
+        assert_eq!(merged.policy.len(), 2);
+        let probability = |p: &NotNan<f32>| p.into_inner();
+        assert!(merged
+            .policy
+            .iter()
+            .any(|(_, p)| (probability(p) - 2.0 / 3.0).abs() < 1e-6));
+        assert!(merged
+            .policy
+            .iter()
+            .any(|(_, p)| (probability(p) - 1.0 / 3.0).abs() < 1e-6));
+        assert!((buffer[1].target.value - 0.5).abs() < f32::EPSILON);
+
+        // Later targets are merged with the right weight.
+        buffer.extend(
+            [target(&["a1"], "c3", 1.0)]
+                .into_iter()
+                .map(|target| TargetWithContext {
+                    target,
+                    forced_uses: 1,
+                    model_steps: 5,
+                    priority: 1.0,
+                    position_hash: None,
+                    merged: 1,
+                }),
+        );
+        assert_eq!(dedup_targets(&mut buffer, 2), 1);
+        assert_eq!(buffer.len(), 2);
+        assert!((buffer[0].target.value - 0.25).abs() < 1e-6);
+        assert_eq!(buffer[0].model_steps, 5);
+    }
+
+    #[test]
+    fn stale_targets_are_down_weighted() {
+        const HALFLIFE: usize = 10;
+        // The targets were loaded at steps 0, 1, and so on.
+        let batch = buffer(21);
+        let mut weights = vec![1.0; batch.len()];
+        let age = weight_by_staleness(&batch, &mut weights, 20, None);
+        assert!((age - 10.0).abs() < f64::EPSILON);
+        assert!(weights.iter().all(|&w| (w - 1.0).abs() < f32::EPSILON));
+
+        weight_by_staleness(&batch, &mut weights, 20, Some(HALFLIFE));
+        assert!((weights[20] - 1.0).abs() < f32::EPSILON);
+        assert!((weights[10] - 0.5).abs() < 1e-6);
+        assert!((weights[0] - 0.25).abs() < 1e-6);
+        assert!(weights.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    fn step_config() -> StepConfig {
+        StepConfig {
+            grad_clip: f64::INFINITY,
+            weight_decay: 0.0,
+            lr_multipliers: [Some(1.0); LR_GROUPS],
+            ube_weight: 1.0,
+            wdl_weight: 1.0,
+            short_value_weight: 1.0,
+            short_value_discount: 0.9,
+            score_weight: 1.0,
+            ownership_weight: 1.0,
+            max_skipped_steps: 0,
+            value_loss: ValueLoss::Mse,
+            huber_delta: 1.0,
+        }
+    }
+
+    /// Tensors of a batch of random positions with every auxiliary target.
+    fn random_tensors(rng: &mut StdRng) -> Tensors {
+        let mut actions = Vec::new();
+        let batch: Vec<Target<Env>> = (0..BATCH_SIZE)
+            .map(|_| {
+                let env = Env::new_opening_with_random_steps(rng, &mut actions, 10);
+                env.populate_actions(&mut actions);
+                let p = NotNan::new(1.0 / actions.len() as f32).unwrap();
+                Target {
+                    env,
+                    policy: actions.drain(..).map(|a| (a, p)).collect(),
+                    value: rng.gen_range(-1.0..=1.0),
+                    ube: rng.gen(),
+                    score: Some(rng.gen_range(-1.0..=1.0)),
+                    ownership: Some((0..N * N).map(|_| rng.gen_range(-1..=1)).collect()),
+                    result: Some(Eval::Win(rng.gen_range(0..50))),
+                }
+            })
+            .collect();
+        let policy_config = PolicyTargetConfig {
+            label_smoothing: 0.0,
+            temperature: 1.0,
+        };
+        create_input_and_target_tensors(
+            batch.iter(),
+            None,
+            &policy_config,
+            step_config().short_value_discount,
+            Device::Cpu,
+            rng,
+        )
+    }
+
+    #[test]
+    fn auxiliary_losses_are_added_for_networks_with_the_heads() {
+        const SEED: u64 = 975;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let tensors = random_tensors(&mut rng);
+
+        let net = generic::Net::<N, HALF_KOMI>::with_config(
+            Device::Cpu,
+            Some(rng.gen()),
+            NetConfig::cpu(),
+        );
+        let (_, losses, _) = compute_loss(&net, false, &tensors, true, &step_config()).unwrap();
+        assert!(losses.wdl.is_none());
+        assert!(losses.short_value.is_none());
+        assert!(losses.score.is_none());
+        assert!(losses.ownership.is_none());
+
+        let net =
+            generic::Net::<N, HALF_KOMI>::with_config(Device::Cpu, Some(rng.gen()), NetConfig {
+                wdl_head: true,
+                short_value_head: true,
+                score_head: true,
+                ownership_head: true,
+                ..NetConfig::cpu()
+            });
+        let (loss, losses, _) = compute_loss(&net, false, &tensors, true, &step_config()).unwrap();
+        let wdl = losses.wdl.unwrap();
+        let short_value = losses.short_value.unwrap();
+        let score = losses.score.unwrap();
+        let ownership = losses.ownership.unwrap();
+        assert!(wdl > 0.0);
+        assert!(short_value > 0.0);
+        assert!(score > 0.0);
+        assert!(ownership > 0.0);
+        let parts = losses.policy
+            + losses.value
+            + losses.ube.unwrap()
+            + wdl
+            + short_value
+            + score
+            + ownership;
+        assert!((losses.total - parts).abs() < 1e-4);
+
+        loss.backward();
+        let variables = net.vs().variables();
+        assert!(variables["wdl.linear.weight"].grad().defined());
+        assert!(variables["value_short.linear.weight"].grad().defined());
+        assert!(variables["score.linear.weight"].grad().defined());
+        assert!(variables["ownership.conv2d.weight"].grad().defined());
+    }
+
+    #[test]
+    fn auxiliary_loss_weights_need_the_heads() {
+        let net = Net::new(Device::Cpu, Some(123));
+        let populated = assign_lr_groups(net.vs());
+        let args = Args::parse_from(["learn", "--directory", "."]);
+        assert!(StepConfig::from_args(&args, populated, net.auxiliary_heads()).is_ok());
+        for flag in [
+            "--wdl-weight",
+            "--short-value-weight",
+            "--score-weight",
+            "--ownership-weight",
+        ] {
+            let args = Args::parse_from(["learn", "--directory", ".", flag, "0.5"]);
+            assert!(StepConfig::from_args(&args, populated, net.auxiliary_heads()).is_err());
+            let heads = AuxiliaryHeads {
+                wdl: true,
+                short_value: true,
+                score: true,
+                ownership: true,
+            };
+            assert!(StepConfig::from_args(&args, populated, heads).is_ok());
+        }
+    }
+
+    #[test]
+    fn targets_without_weight_do_not_change_the_loss() {
+        const SEED: u64 = 642;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let tensors = random_tensors(&mut rng);
+        let _ = tensors.weights.get(0).fill_(0.0);
+        let net =
+            generic::Net::<N, HALF_KOMI>::with_config(Device::Cpu, Some(rng.gen()), NetConfig {
+                wdl_head: true,
+                short_value_head: true,
+                score_head: true,
+                ownership_head: true,
+                ..NetConfig::cpu()
+            });
+        let terms = |losses: Losses| {
+            [
+                Some(losses.policy),
+                Some(losses.value),
+                losses.ube,
+                losses.wdl,
+                losses.short_value,
+                losses.score,
+                losses.ownership,
+            ]
+            .map(|term| term.expect("every term should be trained"))
+        };
+        let loss_terms = |tensors: &Tensors| {
+            let (_, losses, _) = compute_loss(&net, false, tensors, true, &step_config()).unwrap();
+            terms(losses)
+        };
+        // Every target of a sample, but not its input.
+        let change_targets = |tensors: &Tensors, sample: i64| {
+            // Scaled instead of filled, so that illegal actions keep a zero
+            // target and the loss stays finite.
+            let mut policy = tensors.target_policy.get(sample);
+            policy *= 0.5;
+            for target in [
+                &tensors.target_value,
+                &tensors.target_ube,
+                &tensors.target_wdl,
+                &tensors.target_short_value,
+                &tensors.target_score,
+                &tensors.target_ownership,
+            ] {
+                let _ = target.get(sample).fill_(0.5);
+            }
+        };
+        let expected = loss_terms(&tensors);
+
+        change_targets(&tensors, 0);
+        let unchanged = loss_terms(&tensors);
+        for (term, expected) in unchanged.into_iter().zip(expected) {
+            assert!((term - expected).abs() < 1e-6, "{term} != {expected}");
+        }
+
+        // The same change to a weighted sample changes every term.
+        change_targets(&tensors, 1);
+        for (term, expected) in loss_terms(&tensors).into_iter().zip(expected) {
+            assert!((term - expected).abs() > 1e-6, "{term} == {expected}");
+        }
+    }
+
+    #[test]
+    fn importance_sampling_weights_scale_every_loss_term() {
+        const SEED: u64 = 753;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let mut tensors = random_tensors(&mut rng);
+        let net =
+            generic::Net::<N, HALF_KOMI>::with_config(Device::Cpu, Some(rng.gen()), NetConfig {
+                wdl_head: true,
+                short_value_head: true,
+                score_head: true,
+                ownership_head: true,
+                ..NetConfig::cpu()
+            });
+        let loss_terms = |tensors: &Tensors| {
+            let (_, losses, _) = compute_loss(&net, false, tensors, true, &step_config()).unwrap();
+            [
+                Some(losses.policy),
+                Some(losses.value),
+                losses.ube,
+                losses.wdl,
+                losses.short_value,
+                losses.score,
+                losses.ownership,
+            ]
+            .map(|term| term.expect("every term should be trained"))
+        };
+        let unweighted = loss_terms(&tensors);
+
+        // Prioritized replay weights targets by at most one.
+        let _ = tensors.weights.fill_(0.5);
+        for (term, unweighted) in loss_terms(&tensors).into_iter().zip(unweighted) {
+            assert!(
+                (term - 0.5 * unweighted).abs() < 1e-5,
+                "{term} != {unweighted} / 2"
+            );
+        }
+    }
+
+    #[test]
+    fn training_steps_update_the_rnd_statistics() {
+        const SEED: u64 = 531;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let mut net = generic::Net::<N, HALF_KOMI>::with_config(
+            Device::Cpu,
+            Some(rng.gen()),
+            NetConfig::cpu(),
+        );
+        let mut opt = Adam::new(net.vs(), 1e-4);
+        let before = net.rnd_statistics();
+        assert!(before.count < 1.0);
+
+        let sub_batches = [random_tensors(&mut rng), random_tensors(&mut rng)];
+        compute_loss_and_take_step(
+            &mut net,
+            &mut opt,
+            None,
+            sub_batches.into_iter(),
+            true,
+            1e-4,
+            &step_config(),
+        )
+        .unwrap();
+        let after = net.rnd_statistics();
+        assert!((after.count - before.count - 2.0 * BATCH_SIZE as f64).abs() < 1e-6);
+        assert!(after.mean > 0.0);
+        assert_ne!(after, before);
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Flush after this many rows, so that a crashed run still has most of its
+/// metrics.
+const ROWS_PER_FLUSH: usize = 100;
+
+const HEADER: &str = "step,loss,loss_policy,loss_value,loss_ube,loss_wdl,loss_short_value,\
+                      loss_score,loss_ownership,learning_rate,exploitation_buffer,reanalyze_buffer";
+
+/// The losses of a single training step.
+#[derive(Debug, Clone, Copy)]
+pub struct Losses {
+    pub total: f64,
+    pub policy: f64,
+    pub value: f64,
+    /// `None` when UBE is not trained.
+    pub ube: Option<f64>,
+    /// `None` when the network has no WDL head.
+    pub wdl: Option<f64>,
+    /// `None` when the network has no short value head.
+    pub short_value: Option<f64>,
+    /// `None` when the network has no score head.
+    pub score: Option<f64>,
+    /// `None` when the network has no ownership head.
+    pub ownership: Option<f64>,
+}
+
+/// Writes training metrics to a CSV file, one row per step.
+pub struct Metrics {
+    writer: BufWriter<File>,
+    unflushed: usize,
+}
+
+impl Metrics {
+    /// Append to the file at `path`, writing the header if it is new.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        let mut writer = BufWriter::new(file);
+        if is_new {
+            writeln!(writer, "{HEADER}")?;
+        }
+        Ok(Self {
+            writer,
+            unflushed: 0,
+        })
+    }
+
+    pub fn write(
+        &mut self,
+        step: usize,
+        losses: &Losses,
+        learning_rate: f64,
+        exploitation_buffer: usize,
+        reanalyze_buffer: usize,
+    ) -> io::Result<()> {
+        let optional = |loss: Option<f64>| loss.map_or_else(String::new, |loss| loss.to_string());
+        let ube = optional(losses.ube);
+        let wdl = optional(losses.wdl);
+        let short_value = optional(losses.short_value);
+        let score = optional(losses.score);
+        let ownership = optional(losses.ownership);
+        writeln!(
+            self.writer,
+            "{step},{},{},{},{ube},{wdl},{short_value},{score},{ownership},{learning_rate},\
+             {exploitation_buffer},{reanalyze_buffer}",
+            losses.total, losses.policy, losses.value,
+        )?;
+        self.unflushed += 1;
+        if self.unflushed >= ROWS_PER_FLUSH {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        self.writer.flush()
+    }
+}
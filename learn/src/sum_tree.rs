@@ -0,0 +1,130 @@
+/// Binary tree of priorities where every node holds the sum of its children,
+/// so that sampling proportionally to the priorities, and changing one of
+/// them, take `O(log n)` instead of `O(n)`.
+///
+/// The leaves are in the same order as the targets of the buffer, with the
+/// same `push` and `swap_remove` operations, so that both stay in sync.
+#[derive(Debug, Default)]
+pub struct SumTree {
+    /// Nodes in breadth-first order, with the root at index 1
+    /// and the leaves from `capacity` on.
+    nodes: Vec<f64>,
+    capacity: usize,
+    len: usize,
+}
+
+impl SumTree {
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn total(&self) -> f64 {
+        self.nodes.get(1).copied().unwrap_or_default()
+    }
+
+    pub fn get(&self, index: usize) -> f64 {
+        assert!(index < self.len, "index out of bounds");
+        self.nodes[self.capacity + index]
+    }
+
+    pub fn set(&mut self, index: usize, priority: f64) {
+        assert!(index < self.len, "index out of bounds");
+        let mut node = self.capacity + index;
+        self.nodes[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    pub fn push(&mut self, priority: f64) {
+        if self.len == self.capacity {
+            let priorities: Vec<_> = (0..self.len).map(|i| self.get(i)).collect();
+            self.rebuild(&priorities, (2 * self.capacity).max(1));
+        }
+        self.len += 1;
+        self.set(self.len - 1, priority);
+    }
+
+    /// Remove the priority at `index` and replace it with the last one,
+    /// like [`Vec::swap_remove`]. Returns the removed priority.
+    pub fn swap_remove(&mut self, index: usize) -> f64 {
+        let priority = self.get(index);
+        let last = self.get(self.len - 1);
+        self.set(index, last);
+        self.set(self.len - 1, 0.0);
+        self.len -= 1;
+        priority
+    }
+
+    /// Find the index of the leaf where the running sum of the priorities
+    /// goes past `value`, which should be in `[0, total)`.
+    pub fn find(&self, mut value: f64) -> usize {
+        assert!(self.len > 0, "cannot find in an empty tree");
+        let mut node = 1;
+        while node < self.capacity {
+            let left = self.nodes[2 * node];
+            if value < left {
+                node *= 2;
+            } else {
+                value -= left;
+                node = 2 * node + 1;
+            }
+        }
+        // Rounding can overshoot into empty leaves at the end.
+        (node - self.capacity).min(self.len - 1)
+    }
+
+    fn rebuild(&mut self, priorities: &[f64], capacity: usize) {
+        self.capacity = capacity;
+        self.len = priorities.len();
+        self.nodes = vec![0.0; 2 * capacity];
+        self.nodes[capacity..capacity + priorities.len()].copy_from_slice(priorities);
+        for node in (1..capacity).rev() {
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+}
+
+impl FromIterator<f64> for SumTree {
+    fn from_iter<T: IntoIterator<Item = f64>>(iter: T) -> Self {
+        let priorities: Vec<_> = iter.into_iter().collect();
+        let mut tree = Self::default();
+        tree.rebuild(&priorities, priorities.len().next_power_of_two());
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SumTree;
+
+    #[test]
+    fn sums_follow_changes() {
+        let mut tree: SumTree = [1.0, 2.0, 3.0].into_iter().collect();
+        assert!((tree.total() - 6.0).abs() < f64::EPSILON);
+        tree.push(4.0);
+        tree.push(5.0);
+        assert_eq!(tree.len(), 5);
+        assert!((tree.total() - 15.0).abs() < f64::EPSILON);
+
+        tree.set(0, 0.5);
+        assert!((tree.total() - 14.5).abs() < f64::EPSILON);
+        assert!((tree.swap_remove(1) - 2.0).abs() < f64::EPSILON);
+        assert_eq!(tree.len(), 4);
+        assert!((tree.get(1) - 5.0).abs() < f64::EPSILON);
+        assert!((tree.total() - 12.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn find_goes_by_running_sums() {
+        let tree: SumTree = [1.0, 0.0, 2.0, 3.0, 4.0].into_iter().collect();
+        assert_eq!(tree.find(0.0), 0);
+        assert_eq!(tree.find(0.999), 0);
+        assert_eq!(tree.find(1.0), 2);
+        assert_eq!(tree.find(2.999), 2);
+        assert_eq!(tree.find(3.0), 3);
+        assert_eq!(tree.find(9.5), 4);
+        assert_eq!(tree.find(10.0), 4);
+    }
+}
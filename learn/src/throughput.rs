@@ -0,0 +1,126 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Measures how fast the learner trains over a window of steps,
+/// which is restarted after every report so that stalls are not
+/// averaged away over the whole run.
+#[derive(Debug)]
+pub struct Throughput {
+    start: Instant,
+    steps: usize,
+    targets: usize,
+    waiting: Duration,
+    stepping: Duration,
+}
+
+impl Throughput {
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            steps: 0,
+            targets: 0,
+            waiting: Duration::ZERO,
+            stepping: Duration::ZERO,
+        }
+    }
+
+    /// Time spent loading targets or waiting for enough of them.
+    pub fn waited(&mut self, duration: Duration) {
+        self.waiting += duration;
+    }
+
+    /// A step which trained on `targets` targets, including sampling
+    /// them and putting them back into the buffers.
+    pub fn stepped(&mut self, targets: usize, duration: Duration) {
+        self.steps += 1;
+        self.targets += targets;
+        self.stepping += duration;
+    }
+
+    /// Rates since the last report, and start a new window.
+    pub fn report(&mut self) -> Rates {
+        let rates = Rates::new(
+            self.steps,
+            self.targets,
+            self.waiting,
+            self.stepping,
+            self.start.elapsed(),
+        );
+        *self = Self::start();
+        rates
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rates {
+    pub steps_per_second: f64,
+    pub targets_per_second: f64,
+    /// Fractions of the wall-clock time, the rest is spent on
+    /// saving, evaluating, and so on.
+    pub waiting_fraction: f64,
+    pub stepping_fraction: f64,
+}
+
+impl Rates {
+    fn new(
+        steps: usize,
+        targets: usize,
+        waiting: Duration,
+        stepping: Duration,
+        elapsed: Duration,
+    ) -> Self {
+        let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+        Self {
+            steps_per_second: steps as f64 / seconds,
+            targets_per_second: targets as f64 / seconds,
+            waiting_fraction: waiting.as_secs_f64() / seconds,
+            stepping_fraction: stepping.as_secs_f64() / seconds,
+        }
+    }
+}
+
+impl fmt::Display for Rates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} steps/s, {:.0} targets/s, {:.1}% waiting for targets, {:.1}% stepping",
+            self.steps_per_second,
+            self.targets_per_second,
+            self.waiting_fraction * 100.0,
+            self.stepping_fraction * 100.0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Rates, Throughput};
+
+    #[test]
+    fn rates_cover_the_window() {
+        let rates = Rates::new(
+            100,
+            12_800,
+            Duration::from_secs(15),
+            Duration::from_secs(20),
+            Duration::from_secs(40),
+        );
+        assert!((rates.steps_per_second - 2.5).abs() < 1e-9);
+        assert!((rates.targets_per_second - 320.0).abs() < 1e-9);
+        assert!((rates.waiting_fraction - 0.375).abs() < 1e-9);
+        assert!((rates.stepping_fraction - 0.5).abs() < 1e-9);
+
+        // A report starts a new window.
+        let mut throughput = Throughput::start();
+        throughput.waited(Duration::from_millis(5));
+        throughput.stepped(128, Duration::from_millis(1));
+        assert!(throughput.report().steps_per_second > 0.0);
+        let rates = throughput.report();
+        assert!(rates.steps_per_second.abs() < f64::EPSILON);
+        assert!(rates.waiting_fraction.abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,49 @@
+use std::{
+    path::Path,
+    sync::mpsc::{channel, Receiver},
+    time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Wakes up the learner when a target file in the directory changes.
+pub struct TargetWatcher {
+    // Dropping the watcher stops the events.
+    _watcher: RecommendedWatcher,
+    events: Receiver<()>,
+}
+
+impl TargetWatcher {
+    pub fn new(directory: &Path) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let is_target_file = event.paths.iter().any(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("targets-"))
+                });
+                if is_target_file {
+                    // The receiver only goes away when the learner stops.
+                    let _ = sender.send(());
+                }
+            })?;
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Block until a target file changes, or until the timeout runs out.
+    /// Returns whether a change was seen.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let changed = self.events.recv_timeout(timeout).is_ok();
+        // A single write usually causes several events.
+        while self.events.try_recv().is_ok() {}
+        changed
+    }
+}
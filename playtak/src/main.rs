@@ -0,0 +1,270 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use fast_tak::takparse::Color;
+use notation::{from_playtak, to_playtak};
+use takzero::{
+    network::{
+        net5::{Env, Net, HALF_KOMI, N},
+        Network,
+    },
+    search::{env::Environment, node::Node},
+};
+use tch::Device;
+
+mod notation;
+
+const BETA: f32 = 0.0;
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const MINIMUM_THINKING_TIME: Duration = Duration::from_millis(200);
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Path to the model to play with
+    #[arg(long)]
+    model_path: PathBuf,
+    /// PlayTak username, falls back to the `PLAYTAK_USERNAME` environment
+    /// variable
+    #[arg(long)]
+    username: Option<String>,
+    /// PlayTak password, falls back to the `PLAYTAK_PASSWORD` environment
+    /// variable
+    #[arg(long)]
+    password: Option<String>,
+    /// Address of the PlayTak server
+    #[arg(long, default_value = "playtak.com:10000")]
+    server: String,
+    /// Keep playing proven losses instead of resigning
+    #[arg(long)]
+    no_resign: bool,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    line: String,
+    last_sent: Instant,
+}
+
+impl Connection {
+    fn connect(server: &str) -> io::Result<Self> {
+        let writer = TcpStream::connect(server)?;
+        // Wake up regularly so that we can keep the connection alive.
+        writer.set_read_timeout(Some(PING_INTERVAL / 2))?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            line: String::new(),
+            last_sent: Instant::now(),
+        })
+    }
+
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        log::debug!("> {message}");
+        self.last_sent = Instant::now();
+        writeln!(self.writer, "{message}")
+    }
+
+    /// Returns `None` if no full message arrived before the read timeout.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        if self.last_sent.elapsed() >= PING_INTERVAL {
+            self.send("PING")?;
+        }
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => {
+                let message = self.line.trim().to_string();
+                self.line.clear();
+                log::debug!("< {message}");
+                Ok(Some(message))
+            }
+            // A partially read line stays in the buffer until the rest arrives.
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+struct Game {
+    id: String,
+    color: Color,
+    env: Env,
+    node: Node<Env>,
+    time_left: Duration,
+    increment: Duration,
+}
+
+impl Game {
+    fn is_my_turn(&self) -> bool {
+        self.env.to_move == self.color && self.env.terminal().is_none()
+    }
+
+    fn thinking_time(&self) -> Duration {
+        (self.time_left / 10 + 3 * self.increment / 4).max(MINIMUM_THINKING_TIME)
+    }
+
+    /// Search and send the chosen move, or resign if the game is lost.
+    fn play(&mut self, connection: &mut Connection, net: &Net, resign: bool) -> io::Result<()> {
+        let deadline = Instant::now() + self.thinking_time();
+        let visits = self
+            .node
+            .simulate_for_duration(net, &self.env, BETA, deadline);
+        if resign && self.node.evaluation.is_loss() {
+            log::info!("resigning game {} after {visits} visits", self.id);
+            return connection.send(&format!("Game#{} Resign", self.id));
+        }
+        let mv = self.node.select_best_action();
+        if !self.env.is_legal(&mv) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the search chose {mv}, which is illegal in game {}",
+                    self.id
+                ),
+            ));
+        }
+        log::info!(
+            "playing {mv} in game {} after {visits} visits, evaluation {}",
+            self.id,
+            self.node.evaluation
+        );
+        connection.send(&format!("Game#{} {}", self.id, to_playtak(mv)))?;
+        self.env.step(mv);
+        self.node.descend(&mv);
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn main() -> io::Result<()> {
+    env_logger::init();
+    let args = Args::parse();
+    let username = args
+        .username
+        .or_else(|| std::env::var("PLAYTAK_USERNAME").ok())
+        .expect("username should be given with --username or PLAYTAK_USERNAME");
+    let password = args
+        .password
+        .or_else(|| std::env::var("PLAYTAK_PASSWORD").ok())
+        .expect("password should be given with --password or PLAYTAK_PASSWORD");
+
+    let net =
+        Net::load(&args.model_path, Device::cuda_if_available()).expect("model should be loadable");
+
+    let mut connection = Connection::connect(&args.server)?;
+    connection.send(&format!("Login {username} {password}"))?;
+
+    let mut game: Option<Game> = None;
+    let mut increment = Duration::ZERO;
+    loop {
+        let Some(message) = connection.receive()? else {
+            continue;
+        };
+        let words: Vec<_> = message.split_whitespace().collect();
+        match words.as_slice() {
+            ["Welcome", name] => log::info!("logged in as {}", name.trim_end_matches('!')),
+            ["Authentication", "failure", ..] => {
+                log::error!("could not log in as {username}");
+                return Ok(());
+            }
+            // Seek new <no> <name> <size> <time> <incr> <color> <komi> <pieces> <capstones>
+            // <unrated> <tournament> <trigger move> <trigger time> <opponent>
+            ["Seek", "new", id, name, size, _time, seek_increment, _color, komi, rest @ ..] => {
+                let for_someone_else = rest
+                    .get(6)
+                    .is_some_and(|opponent| !opponent.is_empty() && *opponent != username);
+                if game.is_some()
+                    || *name == username
+                    || for_someone_else
+                    || size.parse::<usize>() != Ok(N)
+                    || komi.parse::<i8>() != Ok(HALF_KOMI)
+                {
+                    continue;
+                }
+                increment = Duration::from_secs(seek_increment.parse().unwrap_or_default());
+                log::info!("accepting seek {id} from {name}");
+                connection.send(&format!("Accept {id}"))?;
+            }
+            // Game Start <no> <size> <white> vs <black> <your color> <time> ...
+            ["Game", "Start", id, _size, white, "vs", black, color, time, ..] => {
+                log::info!("game {id} started: {white} vs {black}");
+                let mut new_game = Game {
+                    id: (*id).to_string(),
+                    color: if *color == "white" {
+                        Color::White
+                    } else {
+                        Color::Black
+                    },
+                    env: Env::default(),
+                    node: Node::default(),
+                    time_left: Duration::from_secs(time.parse().unwrap_or_default()),
+                    increment,
+                };
+                if new_game.is_my_turn() {
+                    new_game.play(&mut connection, &net, !args.no_resign)?;
+                }
+                game = Some(new_game);
+            }
+            [prefix, rest @ ..] if prefix.starts_with("Game#") => {
+                let Some(current) = game.as_mut().filter(|g| prefix[5..] == g.id) else {
+                    continue;
+                };
+                match rest {
+                    ["P" | "M", ..] => {
+                        let mv = match from_playtak(rest) {
+                            Ok(mv) => mv,
+                            Err(err) => {
+                                log::error!("could not parse move `{message}`: {err}");
+                                continue;
+                            }
+                        };
+                        if let Err(err) = current.env.play(mv) {
+                            log::error!("opponent move {mv} is invalid: {err}");
+                            continue;
+                        }
+                        current.node.descend(&mv);
+                        if current.is_my_turn() {
+                            current.play(&mut connection, &net, !args.no_resign)?;
+                        }
+                    }
+                    ["Time", white, black] => {
+                        let time = if current.color == Color::White {
+                            white
+                        } else {
+                            black
+                        };
+                        if let Some(time_left) = time
+                            .parse()
+                            .ok()
+                            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        {
+                            current.time_left = time_left;
+                        }
+                    }
+                    ["Over", result] => {
+                        log::info!("game {} is over: {result}", current.id);
+                        game = None;
+                    }
+                    ["Abandoned", ..] => {
+                        log::info!("game {} was abandoned", current.id);
+                        game = None;
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// PlayTak uses its own move notation, for example `P A1 C` or `M A1 A3 2 1`.
+// Conversions go through PTN, so that `takparse` does the actual parsing.
+// https://github.com/chaitu236/TakServer#client-to-server-commands
+
+use std::{cmp::Ordering, fmt::Write, str::FromStr};
+
+use fast_tak::takparse::{Move, ParseMoveError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum NotationError {
+    #[error("missing square")]
+    MissingSquare,
+    #[error("invalid square `{0}`")]
+    InvalidSquare(String),
+    #[error("invalid piece `{0}`")]
+    InvalidPiece(String),
+    #[error("invalid drop count `{0}`")]
+    InvalidDrop(String),
+    #[error("spread must move in a straight line")]
+    NotStraight,
+    #[error("unknown move kind `{0}`")]
+    UnknownKind(String),
+    #[error("move parse error: {0}")]
+    ParseMove(#[from] ParseMoveError),
+}
+
+/// Parse the words of a PlayTak move, starting from `P` or `M`.
+pub fn from_playtak(words: &[&str]) -> Result<Move, NotationError> {
+    let ptn = match words {
+        ["P", square, rest @ ..] => {
+            let piece = match rest.first() {
+                None => "",
+                Some(&"W") => "S",
+                Some(&"C") => "C",
+                Some(other) => return Err(NotationError::InvalidPiece((*other).to_string())),
+            };
+            format!("{piece}{}", parse_square(square)?.0)
+        }
+        ["M", from, to, drops @ ..] => {
+            let (from, from_column, from_row) = parse_square(from)?;
+            let (_, to_column, to_row) = parse_square(to)?;
+            let direction = match (to_column.cmp(&from_column), to_row.cmp(&from_row)) {
+                (Ordering::Equal, Ordering::Greater) => '+',
+                (Ordering::Equal, Ordering::Less) => '-',
+                (Ordering::Greater, Ordering::Equal) => '>',
+                (Ordering::Less, Ordering::Equal) => '<',
+                _ => return Err(NotationError::NotStraight),
+            };
+            let mut count = 0;
+            for drop in drops {
+                count += drop
+                    .parse::<u32>()
+                    .map_err(|_| NotationError::InvalidDrop((*drop).to_string()))?;
+            }
+            format!("{count}{from}{direction}{}", drops.concat())
+        }
+        [kind, ..] => return Err(NotationError::UnknownKind((*kind).to_string())),
+        [] => return Err(NotationError::MissingSquare),
+    };
+    Ok(Move::from_str(&ptn)?)
+}
+
+/// Write a move in PlayTak notation.
+pub fn to_playtak(mv: Move) -> String {
+    let ptn = mv.to_string();
+    let ptn = ptn.trim_end_matches(['*', '\'', '!', '?']);
+    let count_length = ptn.chars().take_while(char::is_ascii_digit).count();
+    let (count, rest) = ptn.split_at(count_length);
+    let (piece, rest) = match rest.strip_prefix(['F', 'S', 'C']) {
+        Some(square) => (&rest[..1], square),
+        None => ("F", rest),
+    };
+    let (square, spread) = rest.split_at(2);
+    let square = square.to_ascii_uppercase();
+
+    let Some(direction) = spread.chars().next() else {
+        return match piece {
+            "S" => format!("P {square} W"),
+            "C" => format!("P {square} C"),
+            _ => format!("P {square}"),
+        };
+    };
+    let count: u32 = count.parse().unwrap_or(1);
+    let drops: Vec<u32> = if spread.len() > 1 {
+        spread[1..].chars().filter_map(|c| c.to_digit(10)).collect()
+    } else {
+        vec![count]
+    };
+    let distance = drops.len() as u8;
+    let &[column, row] = square.as_bytes() else {
+        unreachable!("square should be two characters")
+    };
+    let (column, row) = match direction {
+        '+' => (column, row + distance),
+        '-' => (column, row - distance),
+        '>' => (column + distance, row),
+        _ => (column - distance, row),
+    };
+    let mut message = format!("M {square} {}{}", column as char, row as char);
+    for drop in drops {
+        write!(message, " {drop}").expect("writing to a string should not fail");
+    }
+    message
+}
+
+/// Returns the square in PTN, and its column and row bytes.
+fn parse_square(square: &str) -> Result<(String, u8, u8), NotationError> {
+    match square.to_ascii_lowercase().as_bytes() {
+        &[column @ b'a'..=b'h', row @ b'1'..=b'8'] => {
+            Ok((square.to_ascii_lowercase(), column, row))
+        }
+        _ => Err(NotationError::InvalidSquare(square.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use fast_tak::takparse::Move;
+
+    use super::{from_playtak, to_playtak};
+
+    /// Check that the move is written as `playtak`, and read back.
+    fn assert_round_trip(ptn: &str, playtak: &str) {
+        let mv = Move::from_str(ptn).unwrap();
+        assert_eq!(to_playtak(mv), playtak, "{ptn}");
+        let words: Vec<_> = playtak.split_whitespace().collect();
+        assert_eq!(from_playtak(&words).unwrap(), mv, "{playtak}");
+    }
+
+    #[test]
+    fn placements_round_trip() {
+        assert_round_trip("a1", "P A1");
+        assert_round_trip("Fe5", "P E5");
+        assert_round_trip("Sc3", "P C3 W");
+        assert_round_trip("Cf6", "P F6 C");
+        assert_round_trip("Ch8", "P H8 C");
+    }
+
+    #[test]
+    fn spreads_round_trip() {
+        assert_round_trip("c3+", "M C3 C4 1");
+        assert_round_trip("c3-", "M C3 C2 1");
+        assert_round_trip("c3>", "M C3 D3 1");
+        assert_round_trip("c3<", "M C3 B3 1");
+        assert_round_trip("3a1+", "M A1 A2 3");
+        assert_round_trip("3a1+12", "M A1 A3 1 2");
+        assert_round_trip("4d6-211", "M D6 D3 2 1 1");
+        assert_round_trip("5a4>1112", "M A4 E4 1 1 1 2");
+        assert_round_trip("2f2<11", "M F2 D2 1 1");
+    }
+
+    #[test]
+    fn invalid_moves_are_rejected() {
+        assert!(from_playtak(&["P", "A1", "X"]).is_err());
+        assert!(from_playtak(&["P", "I1"]).is_err());
+        assert!(from_playtak(&["M", "A1", "B2", "1"]).is_err());
+        assert!(from_playtak(&["M", "A1", "A2", "x"]).is_err());
+        assert!(from_playtak(&["R", "A1"]).is_err());
+        assert!(from_playtak(&[]).is_err());
+    }
+}
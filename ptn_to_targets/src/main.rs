@@ -0,0 +1,64 @@
+use std::{
+    fs::{read_to_string, OpenOptions},
+    io::BufWriter,
+    path::PathBuf,
+};
+
+use clap::Parser;
+use takzero::{
+    network::net6_simhash::{HALF_KOMI, N},
+    search::DISCOUNT_FACTOR,
+    target::{
+        ptn::{split_games, targets_from_ptn},
+        write_targets,
+    },
+};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// PTN files to convert, each may contain several games
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+    /// File to append the targets to
+    #[arg(long)]
+    output: PathBuf,
+    /// Discount applied to the game result for each ply until the end,
+    /// use 1.0 for undiscounted targets
+    #[arg(long, default_value_t = DISCOUNT_FACTOR)]
+    discount: f32,
+}
+
+fn main() {
+    env_logger::init();
+    let args = Args::parse();
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.output)
+        .expect("output file should be writable");
+    let mut writer = BufWriter::new(file);
+
+    let mut games = 0;
+    let mut targets = 0;
+    for input in &args.inputs {
+        let text = match read_to_string(input) {
+            Ok(text) => text,
+            Err(err) => {
+                log::error!("could not read {}: {err}", input.display());
+                continue;
+            }
+        };
+        for (index, game) in split_games(&text).into_iter().enumerate() {
+            match targets_from_ptn::<N, HALF_KOMI>(game, args.discount) {
+                Ok(game_targets) => {
+                    write_targets(&mut writer, &game_targets).expect("targets should be writable");
+                    games += 1;
+                    targets += game_targets.len();
+                }
+                Err(err) => log::warn!("skipping game {index} in {}: {err}", input.display()),
+            }
+        }
+    }
+    log::info!("wrote {targets} targets from {games} games");
+}
@@ -18,7 +18,7 @@ use takzero::{
         net5::{Env, Net, N},
         Network,
     },
-    search::node::{batched::BatchedMCTS, Node},
+    search::node::batched::BatchedMCTS,
 };
 use tch::Device;
 
@@ -197,7 +197,7 @@ fn benchmark(agent: &Net, statement: Statement, win: bool) -> PuzzleResult {
             .nodes_and_envs_mut()
             .zip(puzzle_batch)
             .for_each(|((node, env), puzzle)| {
-                *node = Node::default();
+                node.clear();
                 *env = puzzle.clone();
             });
 
@@ -241,11 +241,11 @@ fn benchmark(agent: &Net, statement: Statement, win: bool) -> PuzzleResult {
             batched_mcts
                 .nodes_and_envs()
                 .filter(|(node, _)| {
-                    node.children
+                    node.children()
                         .iter()
                         .filter(|(_, child)| child.evaluation.is_win())
                         .count()
-                        == node.children.len() - 1
+                        == node.children().len() - 1
                 })
                 .count()
         };
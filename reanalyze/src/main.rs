@@ -1,7 +1,7 @@
 use std::{
     fmt,
     fs::{read_dir, OpenOptions},
-    io::{BufRead, BufReader, Read, Seek, Write},
+    io::{BufRead, BufReader, Seek, Write},
     path::{Path, PathBuf},
 };
 
@@ -15,9 +15,11 @@ use takzero::{
     search::{
         agent::Agent,
         env::Environment,
-        node::{batched::BatchedMCTS, Node},
+        node::batched::BatchedMCTS,
+        parse_discount,
+        DISCOUNT_FACTOR,
     },
-    target::{Augment, Replay, Target},
+    target::{policy_target_from_proportional_visits, Augment, Replay, Target},
 };
 use tch::{Device, TchError};
 use thiserror::Error;
@@ -54,6 +56,10 @@ struct Args {
     /// and also where to save targets.
     #[arg(long)]
     directory: PathBuf,
+    /// Discount per ply of the value, in `(0, 1]`, for the search and
+    /// the value targets. It should match the `--discount` of the learner.
+    #[arg(long, default_value_t = DISCOUNT_FACTOR, value_parser = parse_discount)]
+    discount: f32,
 }
 
 #[allow(clippy::too_many_lines)]
@@ -66,7 +72,7 @@ fn main() {
     let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
 
     let mut net;
-    let mut batched_mcts = BatchedMCTS::<BATCH_SIZE, _>::new(&mut rng);
+    let mut batched_mcts = BatchedMCTS::<BATCH_SIZE, _>::new(&mut rng).with_discount(args.discount);
     let mut position_buffer = Vec::new();
     let mut replays_seek = 0;
     #[cfg(feature = "exploration")]
@@ -160,7 +166,7 @@ fn main() {
             .nodes_and_envs_mut()
             .zip(batch)
             .for_each(|((node, env), replay_env)| {
-                *node = Node::default();
+                node.clear();
                 *env = replay_env;
             });
 
@@ -184,7 +190,7 @@ fn main() {
                 let value = if node.evaluation.is_known() {
                     node.evaluation
                 } else {
-                    node.children
+                    node.children()
                         .iter()
                         .find(|(a, _)| *a == selected_action)
                         .expect("all non-terminal nodes should have at least one child")
@@ -192,20 +198,15 @@ fn main() {
                         .evaluation
                         .negate()
                 }
-                .into();
-                let policy = node
-                    .children
-                    .iter()
-                    .map(|(a, _)| a)
-                    .copied()
-                    .zip(node.improved_policy(node.most_visited_count()))
-                    .collect(); // policy_target_from_proportional_visits(node);
-                let ube = node.ube_target(UBE_TARGET_BETA).into_inner();
+                .discounted(args.discount)
+                .into_inner();
+                let policy = policy_target_from_proportional_visits(node);
+                let ube = node.ube_target(UBE_TARGET_BETA, args.discount).into_inner();
 
                 // Log UBE statistics.
                 // let root = node.std_dev * node.std_dev;
                 // let max_std_dev = node
-                //     .children
+                //     .children()
                 //     .iter()
                 //     .map(|(_, child)| child.std_dev)
                 //     .max()
@@ -222,6 +223,10 @@ fn main() {
                     policy,
                     value,
                     ube,
+                    // Reanalyzed positions do not know how the game ended.
+                    score: None,
+                    ownership: None,
+                    result: None,
                 }
                 .to_string()
             })
@@ -232,7 +237,7 @@ fn main() {
             .append(true)
             .create(true)
             .open(args.directory.join("targets-reanalyze.txt"))
-            .map(|mut file| file.write_all(contents.as_bytes()))
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
         {
             log::error!(
                 "Could not save targets to file [{err}], so here they are instead:\n{contents}"
@@ -249,7 +254,7 @@ fn main() {
 #[allow(unused)]
 fn get_model_path_with_most_steps(directory: &PathBuf) -> Option<(u32, PathBuf)> {
     read_dir(directory)
-        .unwrap()
+        .ok()?
         .filter_map(|res| res.ok().map(|entry| entry.path()))
         .filter(|p| p.extension().is_some_and(|ext| ext == "ot"))
         .filter_map(|p| {
@@ -267,6 +272,8 @@ fn get_model_path_with_most_steps(directory: &PathBuf) -> Option<(u32, PathBuf)>
 }
 
 /// Fill the buffer with new positions from the replay file.
+/// Only the bytes after `replays_seek` are read, and a replay which is
+/// still being written is left for the next read.
 fn fill_buffer_with_positions_from_replays(
     buffer: &mut Vec<Env>,
     replays_seek: &mut u64,
@@ -276,29 +283,23 @@ fn fill_buffer_with_positions_from_replays(
     reader
         .seek(std::io::SeekFrom::Start(*replays_seek))
         .expect("Replay file should not get shorter.");
-    buffer.extend(
-        reader
-            .by_ref()
-            .lines()
-            .filter_map(|line| line.unwrap().parse().ok())
-            .flat_map(|replay: Replay<Env>| {
-                let mut env = replay.env;
-                replay
-                    .actions
-                    .into_iter()
-                    .map(|a| {
-                        let ret = env.clone();
-                        env.step(a);
-                        ret
-                    })
-                    .collect::<Vec<_>>()
-                    .into_iter()
-            }),
-    );
-    *replays_seek = reader
-        .stream_position()
-        .expect("Replay file should not get shorter.");
-    Ok(())
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 || !line.ends_with('\n') {
+            break Ok(());
+        }
+        *replays_seek += read as u64;
+        let Ok(replay) = line.trim_end().parse::<Replay<Env>>() else {
+            continue;
+        };
+        let mut env = replay.env;
+        for action in replay.actions {
+            buffer.push(env.clone());
+            env.step(action);
+        }
+    }
 }
 
 /// Sample a Vec of replays in the `directory`.
@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     // collections::VecDeque,
     fmt,
     fs::{read_dir, OpenOptions},
@@ -6,21 +7,36 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use clap::Parser;
-use fast_tak::takparse::Move;
+use clap::{error::ErrorKind as ClapErrorKind, CommandFactory, Parser};
+use fast_tak::takparse::{Color, Move};
+use flate2::{write::GzEncoder, Compression};
 use ordered_float::NotNan;
 use rand::prelude::*;
-use takzero::network::net6_simhash::{Env, Net};
 use takzero::{
-    network::Network,
+    network::{
+        net6_simhash::{Env, Net},
+        Network,
+    },
     search::{
         agent::Agent,
-        env::Environment,
+        book::OpeningBook,
+        env::{Environment, Terminal},
         eval::Eval,
-        node::batched::BatchedMCTS,
-        // DISCOUNT_FACTOR,
+        node::{batched::BatchedMCTS, Node},
+        parse_discount,
+        resign::Resignation,
+        DISCOUNT_FACTOR,
+    },
+    target::{
+        final_ownership,
+        final_score,
+        policy_target_from_proportional_visits,
+        ptn::write_ptn,
+        socket::TargetSender,
+        Augment,
+        Replay,
+        Target,
     },
-    target::{Augment, Replay, Target},
 };
 use tch::{Device, TchError};
 use thiserror::Error;
@@ -44,12 +60,6 @@ const MAX_SELFPLAY_BUFFER_LEN: usize = 32_000;
 
 const SAMPLED_ACTIONS: usize = 64;
 const SEARCH_BUDGET: u32 = 768;
-const IMPROVED_POLICY_VISITATIONS: u32 = {
-    let log_sampled = SAMPLED_ACTIONS.ilog2();
-    let per_step_per_action = SEARCH_BUDGET / log_sampled / SAMPLED_ACTIONS as u32;
-    let power_series = 2u32.pow(log_sampled) - 1;
-    per_step_per_action * power_series
-};
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -57,27 +67,149 @@ struct Args {
     /// and also where to save targets.
     #[arg(long)]
     directory: PathBuf,
+    /// Search budget per move. Must be a multiple of k*log2(k)
+    /// where k is the number of sampled actions.
+    #[arg(long, default_value_t = SEARCH_BUDGET, value_parser = parse_visits)]
+    visits: u32,
+    /// Stop after this many games have been completed.
+    /// Runs forever if not set.
+    #[arg(long)]
+    games: Option<usize>,
+    /// Use deterministic CUDA kernels, which is slower,
+    /// see `takzero::network::set_deterministic`.
+    #[arg(long)]
+    deterministic: bool,
+    /// Also save finished games as PTN to `games.ptn` for inspection.
+    #[arg(long)]
+    ptn: bool,
+    /// Write targets in the binary format to `targets-selfplay.bin`
+    /// instead of `targets-selfplay.txt`.
+    #[arg(long)]
+    binary_targets: bool,
+    /// Write targets to `targets-selfplay-<SHARD>.txt`, so that several
+    /// selfplay processes can share a directory without sharing a file.
+    #[arg(long, conflicts_with = "binary_targets")]
+    shard: Option<String>,
+    /// Compress the target file with gzip and add `.gz` to its name.
+    /// Every batch of targets is appended as its own gzip member.
+    #[arg(long)]
+    compress_targets: bool,
+    /// Resign when the root value stays below this threshold
+    /// (never resigns by default).
+    #[arg(long, allow_negative_numbers = true)]
+    resign_threshold: Option<f32>,
+    /// Number of moves in a row below the threshold before resigning.
+    #[arg(long, default_value_t = 5)]
+    resign_moves: usize,
+    /// Fraction of games which are played out instead of resigning,
+    /// to measure how often resigning would have been wrong.
+    #[arg(long, default_value_t = 0.1)]
+    resign_playout_fraction: f64,
+    /// Start games from positions sampled from this opening book,
+    /// see `takzero::search::book::OpeningBook` for the format.
+    /// Games start with random openings if not set.
+    #[arg(long)]
+    opening_book: Option<PathBuf>,
+    /// Send targets to a learner started with `--target-source socket`
+    /// at this address instead of writing them to a file.
+    #[arg(long, conflicts_with_all = ["binary_targets", "shard", "compress_targets"])]
+    target_address: Option<String>,
+    /// Stop games which have not ended after this many plies
+    /// and decide them by flat count, including komi.
+    /// Games are played until they end if not set.
+    #[arg(long)]
+    max_plies: Option<u16>,
+    /// Discount per ply of the value, in `(0, 1]`, for the search and
+    /// the value targets. It should match the `--discount` of the learner.
+    #[arg(long, default_value_t = DISCOUNT_FACTOR, value_parser = parse_discount)]
+    discount: f32,
+}
+
+/// Resignation state of a single game.
+struct GameResignation {
+    /// One for each color.
+    sides: [Resignation; 2],
+    /// Games where resignation is disabled are played out to the end.
+    enabled: bool,
+    /// The first side which would have resigned if it was enabled.
+    would_have_resigned: Option<Color>,
+}
+
+impl GameResignation {
+    fn new(args: &Args, threshold: f32, rng: &mut impl Rng) -> Self {
+        let resignation = Resignation::new(threshold, args.resign_moves);
+        Self {
+            sides: [resignation.clone(), resignation],
+            enabled: !rng.gen_bool(args.resign_playout_fraction),
+            would_have_resigned: None,
+        }
+    }
+
+    /// Record the root value before a move, with known results discounted by
+    /// `discount` per ply. If the player to move resigns, returns the result
+    /// from the perspective of the opponent, who is to move after the move is
+    /// played.
+    fn update(&mut self, node: &Node<Env>, env: &Env, discount: f32) -> Option<Terminal> {
+        let side = usize::from(env.to_move == Color::Black);
+        let value = node.evaluation.discounted(discount).into_inner();
+        if !self.sides[side].update(value) {
+            return None;
+        }
+        if self.enabled {
+            return Some(Terminal::Win);
+        }
+        self.would_have_resigned.get_or_insert(env.to_move);
+        None
+    }
 }
 
 #[allow(clippy::too_many_lines)]
 fn main() {
     env_logger::init();
     let args = Args::parse();
+    let book = args.opening_book.as_ref().map(|path| {
+        OpeningBook::<Env>::load(path).unwrap_or_else(|err| {
+            Args::command()
+                .error(
+                    ClapErrorKind::Io,
+                    format!("cannot read opening book {}: {err}", path.display()),
+                )
+                .exit()
+        })
+    });
+    if let Some(book) = &book {
+        log::info!("Loaded {} opening positions.", book.positions().len());
+    }
 
     let seed: u64 = rand::thread_rng().gen();
     log::info!("seed = {seed}");
     let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    takzero::network::set_deterministic(args.deterministic);
 
     let mut net = Net::new(DEVICE, Some(rng.gen()));
 
     // Initialize buffers.
     let mut policy_targets: [_; BATCH_SIZE] = std::array::from_fn(|_| Vec::new());
     let mut targets = Vec::new();
+    let mut target_sender = args.target_address.as_deref().map(TargetSender::new);
     let mut complete_replays = Vec::new();
     #[cfg(feature = "exploration")]
     let mut exploration_replays = Vec::new();
 
-    let mut batched_mcts = BatchedMCTS::new(&mut rng);
+    let mut finished_games = 0;
+
+    let mut batched_mcts = match &book {
+        Some(book) => BatchedMCTS::from_envs(std::array::from_fn(|_| book.sample(&mut rng))),
+        None => BatchedMCTS::new(&mut rng),
+    }
+    .with_discount(args.discount);
+    let mut resignations: Option<[GameResignation; BATCH_SIZE]> = args
+        .resign_threshold
+        .map(|threshold| std::array::from_fn(|_| GameResignation::new(&args, threshold, &mut rng)));
+    // Played out games which would have resigned, and how many of those
+    // were not lost by the side which would have resigned.
+    let mut resign_playouts = 0u32;
+    let mut resign_false_positives = 0u32;
     let betas: [f32; BATCH_SIZE] = std::array::from_fn(|i| {
         if cfg!(feature = "exploration") && i < BATCH_SIZE / 2 {
             BETA
@@ -139,7 +271,7 @@ fn main() {
             &net,
             &betas,
             SAMPLED_ACTIONS,
-            SEARCH_BUDGET,
+            args.visits,
             &mut rng,
         );
         selected_actions
@@ -158,13 +290,13 @@ fn main() {
         //     .for_each(|((node, env), action)| {
         //         let root = node.std_dev;
         //         let max = node
-        //             .children
+        //             .children()
         //             .iter()
         //             .map(|(_, child)| child.std_dev)
         //             .max()
         //             .unwrap_or_default();
         //         let selected = node
-        //             .children
+        //             .children()
         //             .iter()
         //             .find(|(a, _)| a == action)
         //             .map(|(_, child)| child.std_dev)
@@ -174,8 +306,29 @@ fn main() {
         // {selected:.5}",             env.ply,
         //         );
         //     });
+        let mut resigned = [None; BATCH_SIZE];
+        if let Some(resignations) = &mut resignations {
+            let discount = batched_mcts.discount();
+            resignations
+                .iter_mut()
+                .zip(batched_mcts.nodes_and_envs())
+                .zip(&mut resigned)
+                .for_each(|((resignation, (node, env)), resigned)| {
+                    *resigned = resignation.update(node, env, discount);
+                });
+        }
         take_a_step(&mut batched_mcts, &mut policy_targets, &selected_actions);
-        restart_envs_and_complete_targets(
+        if let Some(max_plies) = args.max_plies {
+            resigned
+                .iter_mut()
+                .zip(batched_mcts.adjudicate_long_games(max_plies))
+                .for_each(|(resigned, adjudicated)| *resigned = resigned.or(adjudicated));
+        }
+        let to_move: Vec<_> = batched_mcts
+            .nodes_and_envs()
+            .map(|(_, env)| env.to_move)
+            .collect();
+        let finished = restart_envs_and_complete_targets(
             &mut batched_mcts,
             &mut policy_targets,
             &mut targets,
@@ -184,12 +337,53 @@ fn main() {
             &mut exploration_replays,
             &mut rng,
             &betas,
+            resigned,
+            book.as_ref(),
         );
+        if let (Some(resignations), Some(threshold)) = (&mut resignations, args.resign_threshold) {
+            for ((resignation, terminal), to_move) in
+                resignations.iter_mut().zip(finished).zip(to_move)
+            {
+                let Some(terminal) = terminal else {
+                    continue;
+                };
+                if let Some(color) = resignation.would_have_resigned {
+                    let lost = match terminal {
+                        Terminal::Win => to_move != color,
+                        Terminal::Loss => to_move == color,
+                        Terminal::Draw => false,
+                    };
+                    resign_playouts += 1;
+                    if !lost {
+                        resign_false_positives += 1;
+                    }
+                    log::info!(
+                        "Resignation false positives: {resign_false_positives}/{resign_playouts}"
+                    );
+                }
+                *resignation = GameResignation::new(&args, threshold, &mut rng);
+            }
+        }
 
         if !targets.is_empty() {
-            save_targets_to_file(&mut targets, &args.directory);
+            if let Some(sender) = &mut target_sender {
+                send_targets(sender, &mut targets);
+            } else if args.binary_targets {
+                save_binary_targets_to_file(&mut targets, &args.directory, args.compress_targets);
+            } else {
+                save_targets_to_file(
+                    &mut targets,
+                    &args.directory,
+                    args.shard.as_deref(),
+                    args.compress_targets,
+                );
+            }
         }
         if !complete_replays.is_empty() {
+            finished_games += complete_replays.len();
+            if args.ptn {
+                save_games_as_ptn(&complete_replays, &args.directory);
+            }
             save_replays_to_file(&mut complete_replays, &args.directory, "replays.txt");
             #[cfg(feature = "exploration")]
             {
@@ -200,6 +394,11 @@ fn main() {
                 );
             }
         }
+
+        if args.games.is_some_and(|games| finished_games >= games) {
+            log::info!("Finished {finished_games} games, stopping.");
+            break;
+        }
     }
 }
 
@@ -209,7 +408,7 @@ fn main() {
 #[allow(unused)]
 fn get_model_path_with_most_steps(directory: &PathBuf) -> Option<(u32, PathBuf)> {
     read_dir(directory)
-        .unwrap()
+        .ok()?
         .filter_map(|res| res.ok().map(|entry| entry.path()))
         .filter(|p| p.extension().is_some_and(|ext| ext == "ot"))
         .filter_map(|p| {
@@ -239,25 +438,24 @@ fn take_a_step(
     policy_targets: &mut [Vec<IncompleteTarget>],
     selected_actions: &[Move],
 ) {
+    let discount = batched_mcts.discount();
     batched_mcts
         .nodes_and_envs()
         .zip(policy_targets)
         .for_each(|((node, env), policy_targets)| {
             policy_targets.push(IncompleteTarget {
                 env: env.clone(),
-                policy: node
-                    .improved_policy(IMPROVED_POLICY_VISITATIONS as f32)
-                    .zip(node.children.iter())
-                    .map(|(p, (a, _))| (*a, p))
-                    .collect(), // policy_target_from_proportional_visits(node),
-                root_ube_metric: node.ube_target(BETA),
+                policy: policy_target_from_proportional_visits(node),
+                root_ube_metric: node.ube_target(BETA, discount),
             });
         });
     batched_mcts.step(selected_actions);
 }
 
-/// Restart any finished environments.
+/// Restart any finished or resigned environments,
+/// from a position of the opening book if there is one.
 /// Complete targets of finished games using the game result.
+/// Returns the result of each game which finished.
 #[allow(clippy::too_many_arguments)]
 fn restart_envs_and_complete_targets(
     batched_mcts: &mut BatchedMCTS<BATCH_SIZE, Env>,
@@ -267,13 +465,20 @@ fn restart_envs_and_complete_targets(
     #[cfg(feature = "exploration")] exploration_replays: &mut Vec<Replay<Env>>,
     rng: &mut impl Rng,
     betas: &[f32],
-) {
+    resigned: [Option<Terminal>; BATCH_SIZE],
+    book: Option<&OpeningBook<Env>>,
+) -> Vec<Option<Terminal>> {
+    let mut finished = Vec::with_capacity(BATCH_SIZE);
+    let discount = batched_mcts.discount();
     #[allow(unused_variables)]
     batched_mcts
-        .restart_terminal_envs(rng)
+        .restart_terminal_or_resigned_envs_with(rng, resigned, |rng, actions| {
+            book.map_or_else(|| Env::new_opening(rng, actions), |book| book.sample(rng))
+        })
         .zip(policy_targets)
         .zip(betas)
         .for_each(|((terminal_and_replay, policy_targets), beta)| {
+            finished.push(terminal_and_replay.as_ref().map(|(terminal, _)| *terminal));
             if let Some((terminal, replay)) = terminal_and_replay {
                 #[cfg(feature = "exploration")]
                 if *beta > 0.0 {
@@ -287,6 +492,10 @@ fn restart_envs_and_complete_targets(
                             .collect(),
                     });
                 }
+                // Resigned games have no final score or ownership.
+                let mut last = replay.env.clone();
+                replay.actions.iter().for_each(|action| last.step(*action));
+                let last = last.terminal().is_some().then_some(last);
                 finished_replays.push(replay);
 
                 // Create targets.
@@ -313,35 +522,91 @@ fn restart_envs_and_complete_targets(
                     // Only generate targets from non-exploratory episodes.
                     // (Or after the initial exploration.)
                     if *beta == 0.0 || env.ply > WEIGHTED_RANDOM_PLIES {
+                        let score = last.as_ref().map(|last| final_score(last, env.to_move));
+                        let ownership =
+                            last.as_ref().map(|last| final_ownership(last, env.to_move));
                         targets.push(Target {
                             env,
-                            value: f32::from(value),
+                            value: value.discounted(discount).into_inner(),
                             // average_std_dev * average_std_dev
                             // ube_window.iter().last().copied().unwrap_or_default().into(),
                             ube: root_ube_metric.into_inner(),
+                            score,
+                            ownership,
+                            result: Some(value),
                             policy,
                         });
                     }
                 }
             }
         });
+    finished
 }
 
 /// Save targets to a file. Drains the target Vec.
-fn save_targets_to_file(targets: &mut Vec<Target<Env>>, directory: &Path) {
+fn save_targets_to_file(
+    targets: &mut Vec<Target<Env>>,
+    directory: &Path,
+    shard: Option<&str>,
+    compress: bool,
+) {
     let contents: String = targets.drain(..).map(|target| target.to_string()).collect();
-    if let Err(err) = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(directory.join("targets-selfplay.txt"))
-        .map(|mut file| file.write_all(contents.as_bytes()))
-    {
+    let file_name = shard.map_or_else(
+        || "targets-selfplay.txt".to_string(),
+        |shard| format!("targets-selfplay-{shard}.txt"),
+    );
+    if let Err(err) = append_to_file(&directory.join(file_name), contents.as_bytes(), compress) {
         log::error!(
             "Could not save targets to file [{err}], so here they are instead:\n{contents}"
         );
     }
 }
 
+/// Send targets to the learner. Drains the target Vec if they were sent,
+/// otherwise they are kept to be sent again together with the next ones.
+fn send_targets(sender: &mut TargetSender, targets: &mut Vec<Target<Env>>) {
+    match sender.send(targets.iter()) {
+        Ok(()) => targets.clear(),
+        Err(err) => log::error!(
+            "Could not send {} targets, trying again with the next ones [{err}]",
+            targets.len()
+        ),
+    }
+}
+
+/// Save targets to a file in the binary format. Drains the target Vec.
+fn save_binary_targets_to_file(targets: &mut Vec<Target<Env>>, directory: &Path, compress: bool) {
+    let mut contents = Vec::new();
+    for target in targets.drain(..) {
+        target
+            .write_bin(&mut contents)
+            .expect("writing to a Vec should not fail");
+    }
+    if let Err(err) = append_to_file(&directory.join("targets-selfplay.bin"), &contents, compress) {
+        log::error!("Could not save binary targets to file [{err}]");
+    }
+}
+
+/// Append the contents to the file. If `compress` is set, `.gz` is added
+/// to the name of the file and the contents are appended as a gzip member.
+/// The learner reads a file of concatenated members one member at a time.
+fn append_to_file(path: &Path, contents: &[u8], compress: bool) -> std::io::Result<()> {
+    let (path, contents) = if compress {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(contents)?;
+        let mut name = path.as_os_str().to_owned();
+        name.push(".gz");
+        (PathBuf::from(name), Cow::Owned(encoder.finish()?))
+    } else {
+        (path.to_path_buf(), Cow::Borrowed(contents))
+    };
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?
+        .write_all(&contents)
+}
+
 /// Save replays to a file. Drains the replays Vec.
 fn save_replays_to_file(replays: &mut Vec<Replay<Env>>, directory: &Path, name: &str) {
     let contents: String = replays.drain(..).map(|target| target.to_string()).collect();
@@ -349,7 +614,7 @@ fn save_replays_to_file(replays: &mut Vec<Replay<Env>>, directory: &Path, name:
         .append(true)
         .create(true)
         .open(directory.join(name))
-        .map(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
     {
         log::error!(
             "Could not save replays to file [{err}], so here they are instead:\n{contents}"
@@ -357,6 +622,25 @@ fn save_replays_to_file(replays: &mut Vec<Replay<Env>>, directory: &Path, name:
     }
 }
 
+/// Append finished games as PTN to `games.ptn`.
+fn save_games_as_ptn(replays: &[Replay<Env>], directory: &Path) {
+    let contents: String = replays
+        .iter()
+        .map(|replay| {
+            let moves: Vec<_> = replay.actions.iter().copied().collect();
+            write_ptn(&replay.env, &moves, None) + "\n"
+        })
+        .collect();
+    if let Err(err) = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(directory.join("games.ptn"))
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+    {
+        log::error!("Could not save games as PTN [{err}]");
+    }
+}
+
 #[derive(Debug, Error)]
 enum ReadBufferLengthsError {
     #[error("io: {0}")]
@@ -384,3 +668,17 @@ fn read_buffer_lengths(directory: &Path) -> Result<(usize, usize), ReadBufferLen
     }
     Ok((selfplay, reanalyze))
 }
+
+/// The search budget is split evenly over the `log2(k)` rounds of
+/// sequential halving and over the actions which are left in each round.
+fn parse_visits(s: &str) -> Result<u32, String> {
+    let visits: u32 = s.parse().map_err(|err| format!("{err}"))?;
+    let step = SAMPLED_ACTIONS.ilog2() * SAMPLED_ACTIONS as u32;
+    if visits > 0 && visits % step == 0 {
+        Ok(visits)
+    } else {
+        Err(format!(
+            "the visits should be a positive multiple of k*log2(k) = {step}, got {visits}"
+        ))
+    }
+}
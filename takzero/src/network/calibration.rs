@@ -0,0 +1,117 @@
+/// Logistic mapping from a value in `[-1, 1]` to the probability of winning,
+/// `sigmoid(scale * value + bias)`, which is fitted to the results of games.
+///
+/// Draws count as half a win, so the probability is really the expected
+/// score, which is what resignation and adjudication thresholds need.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub scale: f32,
+    pub bias: f32,
+}
+
+impl Calibration {
+    /// Regularization of the scale, which keeps it finite
+    /// when the values separate wins from losses perfectly.
+    const L2: f64 = 1e-3;
+    /// Newton's method converges in a handful of steps,
+    /// this is just a limit in case it does not.
+    const MAX_ITERATIONS: usize = 100;
+
+    /// Fit the mapping by logistic regression on pairs of a value and the
+    /// score which the player to move got in the end: 1 for a win, 0.5 for
+    /// a draw, and 0 for a loss.
+    ///
+    /// Returns `None` if there are no samples.
+    #[must_use]
+    pub fn fit(samples: &[(f32, f32)]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let regularization = Self::L2 * samples.len() as f64;
+        let (mut scale, mut bias) = (1.0_f64, 0.0_f64);
+        for _ in 0..Self::MAX_ITERATIONS {
+            // Gradient and Hessian of the cross-entropy.
+            let (mut g_scale, mut g_bias) = (regularization * scale, 0.0);
+            let (mut h_scale, mut h_mixed, mut h_bias) = (regularization, 0.0, 0.0);
+            for &(value, score) in samples {
+                let (value, score) = (f64::from(value), f64::from(score));
+                let p = sigmoid(scale.mul_add(value, bias));
+                let weight = p * (1.0 - p);
+                g_scale += (p - score) * value;
+                g_bias += p - score;
+                h_scale += weight * value * value;
+                h_mixed += weight * value;
+                h_bias += weight;
+            }
+            let determinant = h_scale.mul_add(h_bias, -h_mixed * h_mixed);
+            if determinant.abs() < f64::EPSILON {
+                break;
+            }
+            let step_scale = h_bias.mul_add(g_scale, -h_mixed * g_bias) / determinant;
+            let step_bias = h_scale.mul_add(g_bias, -h_mixed * g_scale) / determinant;
+            scale -= step_scale;
+            bias -= step_bias;
+            if step_scale.abs().max(step_bias.abs()) < 1e-9 {
+                break;
+            }
+        }
+        Some(Self {
+            scale: scale as f32,
+            bias: bias as f32,
+        })
+    }
+
+    #[must_use]
+    pub fn win_probability(self, value: f32) -> f32 {
+        sigmoid(f64::from(self.scale.mul_add(value, self.bias))) as f32
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{sigmoid, Calibration};
+
+    #[test]
+    fn fitted_mapping_is_monotonic_and_centered() {
+        const SEED: u64 = 2468;
+        const SCALE: f64 = 3.0;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let samples: Vec<_> = (0..5000)
+            .map(|_| {
+                let value: f32 = rng.gen_range(-1.0..=1.0);
+                let score = if rng.gen_bool(0.1) {
+                    0.5
+                } else if rng.gen_bool(sigmoid(SCALE * f64::from(value))) {
+                    1.0
+                } else {
+                    0.0
+                };
+                (value, score)
+            })
+            .collect();
+        let calibration = Calibration::fit(&samples).unwrap();
+        assert!(
+            (f64::from(calibration.scale) - SCALE).abs() < 0.5,
+            "{calibration:?}"
+        );
+        assert!((calibration.win_probability(0.0) - 0.5).abs() < 0.05);
+        let probabilities: Vec<_> = (-10..=10)
+            .map(|i| calibration.win_probability(i as f32 / 10.0))
+            .collect();
+        assert!(probabilities.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn separable_results_give_a_finite_scale() {
+        let samples = [(-0.5, 0.0), (-0.1, 0.0), (0.1, 1.0), (0.5, 1.0)];
+        let calibration = Calibration::fit(&samples).unwrap();
+        assert!(calibration.scale.is_finite() && calibration.scale > 0.0);
+        assert!(Calibration::fit(&[]).is_none());
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::Path;
+
+use fast_tak::{takparse::Move, Game, Reserves};
+use ordered_float::NotNan;
+use tch::Device;
+
+use super::{
+    generic::{Net, MAXIMUM_VARIANCE},
+    Network,
+};
+use crate::search::{agent::Agent, node::policy::softmax};
+
+/// Several independently trained networks which are used as one agent.
+///
+/// The policy is the average of the members' policies, the value is the
+/// mean of their values, and the uncertainty is the variance of the values
+/// between members. Disagreement between members is a less noisy estimate
+/// of epistemic uncertainty than RND or UBE of a single network.
+#[derive(Debug)]
+pub struct Ensemble<const N: usize, const HALF_KOMI: i8> {
+    members: Vec<Net<N, HALF_KOMI>>,
+}
+
+impl<const N: usize, const HALF_KOMI: i8> Ensemble<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    /// # Panics
+    ///
+    /// Panics if there are no members.
+    #[must_use]
+    pub fn new(members: Vec<Net<N, HALF_KOMI>>) -> Self {
+        assert!(!members.is_empty(), "an ensemble needs at least one member");
+        Self { members }
+    }
+
+    /// Load every `.ot` file in `directory` as a member,
+    /// in the order of their file names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be read, contains no models,
+    /// or any of the models fails to load.
+    pub fn load(directory: impl AsRef<Path>, device: Device) -> Result<Self, tch::TchError> {
+        let directory = directory.as_ref();
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "ot") {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Err(tch::TchError::FileFormat(format!(
+                "no models found in {}",
+                directory.display()
+            )));
+        }
+        paths.sort();
+        let members = paths
+            .into_iter()
+            .map(|path| Net::load(path, device))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { members })
+    }
+
+    #[must_use]
+    pub fn members(&self) -> &[Net<N, HALF_KOMI>] {
+        &self.members
+    }
+}
+
+impl<const N: usize, const HALF_KOMI: i8> Agent<Game<N, HALF_KOMI>> for Ensemble<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    fn policy_value_uncertainty(
+        &self,
+        env_batch: &[Game<N, HALF_KOMI>],
+        actions_batch: &[Vec<Move>],
+    ) -> impl Iterator<Item = (Vec<(Move, NotNan<f32>)>, f32, f32)> {
+        let mut probabilities: Vec<Vec<f32>> = actions_batch
+            .iter()
+            .map(|actions| vec![0.0; actions.len()])
+            .collect();
+        let mut values: Vec<Vec<f32>> =
+            vec![Vec::with_capacity(self.members.len()); env_batch.len()];
+        for member in &self.members {
+            for ((policy, value, _), (sums, member_values)) in member
+                .policy_value_uncertainty(env_batch, actions_batch)
+                .zip(probabilities.iter_mut().zip(&mut values))
+            {
+                for (sum, p) in sums
+                    .iter_mut()
+                    .zip(softmax(policy.into_iter().map(|(_, logit)| logit)))
+                {
+                    *sum += p.into_inner();
+                }
+                member_values.push(value);
+            }
+        }
+
+        let members = self.members.len() as f32;
+        actions_batch
+            .iter()
+            .zip(probabilities)
+            .zip(values)
+            .map(move |((actions, sums), values)| {
+                // Logits of the average policy.
+                let policy = actions
+                    .iter()
+                    .zip(sums)
+                    .map(|(action, sum)| {
+                        let logit = (sum / members).max(f32::MIN_POSITIVE).ln();
+                        (
+                            *action,
+                            NotNan::new(logit).expect("logit should not be NaN"),
+                        )
+                    })
+                    .collect();
+                let mean = values.iter().sum::<f32>() / members;
+                let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / members;
+                (policy, mean, variance.min(MAXIMUM_VARIANCE as f32))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fast_tak::Game;
+    use tch::Device;
+
+    use super::Ensemble;
+    use crate::{
+        network::{net5::Net, Network},
+        search::{agent::Agent, env::Environment},
+    };
+
+    #[test]
+    fn ensemble_of_identical_members_is_certain() {
+        let net = Net::new(Device::Cpu, Some(42));
+        let ensemble = Ensemble::new(vec![net.clone(Device::Cpu), net.clone(Device::Cpu)]);
+        let game: Game<5, 4> = Game::default();
+        let mut actions = Vec::new();
+        game.populate_actions(&mut actions);
+
+        let (policy, value, _) = net
+            .policy_value_uncertainty(&[game.clone()], &[actions.clone()])
+            .next()
+            .unwrap();
+        let (ensemble_policy, ensemble_value, uncertainty) = ensemble
+            .policy_value_uncertainty(&[game], &[actions])
+            .next()
+            .unwrap();
+        assert!((value - ensemble_value).abs() < 1e-5);
+        assert!(uncertainty.abs() < 1e-6);
+        assert_eq!(policy.len(), ensemble_policy.len());
+    }
+
+    #[test]
+    fn load_from_directory() {
+        let directory = std::env::temp_dir().join("takzero-ensemble");
+        std::fs::create_dir_all(&directory).unwrap();
+        for seed in 0..3 {
+            Net::new(Device::Cpu, Some(seed))
+                .save(directory.join(format!("member_{seed}.ot")))
+                .unwrap();
+        }
+        let ensemble: Ensemble<5, 4> = Ensemble::load(&directory, Device::Cpu).unwrap();
+        assert_eq!(ensemble.members().len(), 3);
+
+        let games: Vec<Game<5, 4>> = vec![Game::default(); 4];
+        let actions: Vec<_> = games
+            .iter()
+            .map(|game| {
+                let mut actions = Vec::new();
+                game.populate_actions(&mut actions);
+                actions
+            })
+            .collect();
+        for (policy, value, uncertainty) in ensemble.policy_value_uncertainty(&games, &actions) {
+            assert_eq!(policy.len(), actions[0].len());
+            assert!((-1.0..=1.0).contains(&value));
+            assert!(uncertainty >= 0.0);
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
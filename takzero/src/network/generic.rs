@@ -0,0 +1,1043 @@
+use fast_tak::{takparse::Move, Game, Reserves};
+use ordered_float::NotNan;
+use tch::{
+    nn::{self, ModuleT},
+    Device,
+    Kind,
+    Tensor,
+};
+
+use super::{
+    calibration::Calibration,
+    normalizer::{RndStatistics, RunningNormalizer, PATH as NORMALIZER_PATH},
+    onnx,
+    repr::{
+        debug_assert_shape,
+        games_to_tensor,
+        input_channels,
+        input_size,
+        move_index,
+        output_channels,
+    },
+    residual::{NormKind, ResidualBlock},
+    AuxiliaryHeads,
+    Network,
+    RndNetwork,
+    TrainingNetwork,
+    TrainingOutputs,
+};
+use crate::{
+    network::repr::output_size,
+    search::{agent::Agent, eval::Eval},
+};
+
+const CALIBRATION_PATH: &str = "calibration";
+
+// Value is [-1, 1], which is size 2, so variance can be 2*2 = 4.
+pub const MAXIMUM_VARIANCE: f64 = 4.0;
+
+/// Architecture of the network.
+/// It is saved together with the weights, so that a model is always loaded
+/// with the architecture it was trained with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetConfig {
+    pub filters: i64,
+    pub core_res_blocks: u32,
+    /// Size of the hidden layers of RND.
+    pub linear_size: i64,
+    /// Whether to add a win/draw/loss value head.
+    pub wdl_head: bool,
+    /// Reduction ratio of squeeze-and-excitation in the residual blocks,
+    /// or `None` for plain residual blocks.
+    pub se_ratio: Option<i64>,
+    /// Whether to add a head which predicts the final flat margin.
+    pub score_head: bool,
+    /// Whether to add a head which predicts who owns each square at the end.
+    pub ownership_head: bool,
+    /// Probability of dropout before the last layer of the policy and value
+    /// heads, which is only active while training.
+    pub dropout: f32,
+    /// Normalization after the convolutions of the core.
+    pub norm: NormKind,
+    /// Whether to add a second value head with a shorter horizon,
+    /// see [`short_value_target`].
+    pub short_value_head: bool,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            filters: 256,
+            core_res_blocks: 20,
+            linear_size: 1024,
+            wdl_head: false,
+            se_ratio: None,
+            score_head: false,
+            ownership_head: false,
+            dropout: 0.0,
+            norm: NormKind::BatchNorm,
+            short_value_head: false,
+        }
+    }
+}
+
+impl NetConfig {
+    /// Name of the variable which stores the config.
+    const VARIABLE: &'static str = "config";
+
+    /// A small network which can be evaluated quickly without a GPU,
+    /// for trying out the search and for tests.
+    #[must_use]
+    pub const fn cpu() -> Self {
+        Self {
+            filters: 32,
+            core_res_blocks: 4,
+            linear_size: 128,
+            wdl_head: false,
+            se_ratio: None,
+            score_head: false,
+            ownership_head: false,
+            dropout: 0.0,
+            norm: NormKind::BatchNorm,
+            short_value_head: false,
+        }
+    }
+
+    fn to_tensor(self) -> Tensor {
+        Tensor::from_slice(&[
+            self.filters as f32,
+            self.core_res_blocks as f32,
+            self.linear_size as f32,
+            f32::from(u8::from(self.wdl_head)),
+            self.se_ratio.unwrap_or_default() as f32,
+            f32::from(u8::from(self.score_head)),
+            f32::from(u8::from(self.ownership_head)),
+            self.dropout,
+            f32::from(u8::from(self.norm == NormKind::GroupNorm)),
+            f32::from(u8::from(self.short_value_head)),
+        ])
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn from_tensor(tensor: &Tensor) -> Option<Self> {
+        let values = Vec::<f32>::try_from(tensor.to_kind(Kind::Float)).ok()?;
+        // Configs saved before squeeze-and-excitation, the score and
+        // ownership heads, dropout, group norm, or the short value head were
+        // added are shorter.
+        let (&[filters, core_res_blocks, linear_size, wdl_head], rest) =
+            values.split_first_chunk::<4>()?;
+        let se_ratio = rest.first().copied().unwrap_or_default();
+        let score_head = rest.get(1).copied().unwrap_or_default();
+        let ownership_head = rest.get(2).copied().unwrap_or_default();
+        let dropout = rest.get(3).copied().unwrap_or_default();
+        let group_norm = rest.get(4).copied().unwrap_or_default();
+        let short_value_head = rest.get(5).copied().unwrap_or_default();
+        Some(Self {
+            filters: filters as i64,
+            core_res_blocks: core_res_blocks as u32,
+            linear_size: linear_size as i64,
+            wdl_head: wdl_head > 0.0,
+            se_ratio: (se_ratio > 0.0).then_some(se_ratio as i64),
+            score_head: score_head > 0.0,
+            ownership_head: ownership_head > 0.0,
+            dropout,
+            norm: if group_norm > 0.0 {
+                NormKind::GroupNorm
+            } else {
+                NormKind::BatchNorm
+            },
+            short_value_head: short_value_head > 0.0,
+        })
+    }
+}
+
+/// Residual network with RND for any board size.
+/// Each size only needs a type alias, see [`super::net5::Net`].
+#[derive(Debug)]
+pub struct Net<const N: usize, const HALF_KOMI: i8> {
+    vs: nn::VarStore,
+    core: nn::SequentialT,
+    policy_net: nn::SequentialT,
+    value_net: nn::SequentialT,
+    ube_net: nn::SequentialT,
+    wdl_net: Option<nn::SequentialT>,
+    short_value_net: Option<nn::SequentialT>,
+    score_net: Option<nn::SequentialT>,
+    ownership_net: Option<nn::SequentialT>,
+    pub(super) rnd: Rnd,
+    /// Scale and bias of the [`Calibration`], both zero if there is none.
+    calibration: Tensor,
+    config: NetConfig,
+}
+
+#[derive(Debug)]
+pub(super) struct Rnd {
+    target: nn::SequentialT,
+    learning: nn::SequentialT,
+    // Normalization variables
+    pub(super) min: Tensor,
+    pub(super) max: Tensor,
+    normalizer: RunningNormalizer,
+}
+
+fn core<const N: usize>(
+    path: &nn::Path,
+    filters: i64,
+    res_blocks: u32,
+    se_ratio: Option<i64>,
+    norm: NormKind,
+) -> nn::SequentialT {
+    let mut core = nn::seq_t()
+        .add(nn::conv2d(
+            path / "input_conv2d",
+            input_channels::<N>() as i64,
+            filters,
+            3,
+            nn::ConvConfig {
+                stride: 1,
+                padding: 1,
+                bias: false,
+                ..Default::default()
+            },
+        ))
+        .add(norm.layer(path, filters))
+        .add_fn(Tensor::relu);
+    for n in 0..res_blocks {
+        core = core.add(ResidualBlock::with_se_ratio_and_norm(
+            &(path / format!("res_block_{n}")),
+            filters,
+            filters,
+            se_ratio,
+            norm,
+        ));
+    }
+    core
+}
+
+/// Inputs have the shape `[batch, input_channels::<N>(), N, N]`.
+fn debug_assert_input<const N: usize>(xs: &Tensor)
+where
+    Reserves<N>: Default,
+{
+    debug_assert_shape(
+        xs,
+        &[-1, input_channels::<N>() as i64, N as i64, N as i64],
+        "network input",
+    );
+}
+
+fn policy_net<const N: usize>(path: &nn::Path, filters: i64, dropout: f32) -> nn::SequentialT {
+    with_dropout(nn::seq_t(), dropout).add(nn::conv2d(
+        path / "conv2d",
+        filters,
+        output_channels::<N>() as i64,
+        3,
+        nn::ConvConfig {
+            stride: 1,
+            padding: 1,
+            ..Default::default()
+        },
+    ))
+}
+
+fn value_net<const N: usize>(path: &nn::Path, filters: i64, dropout: f32) -> nn::SequentialT {
+    let value_net = nn::seq_t()
+        .add(nn::conv2d(path / "conv2d", filters, 1, 1, nn::ConvConfig {
+            stride: 1,
+            ..Default::default()
+        }))
+        .add_fn(Tensor::relu)
+        .add_fn(|x| x.view([-1, (N * N) as i64]));
+    with_dropout(value_net, dropout)
+        .add(nn::linear(
+            path / "linear",
+            (N * N) as i64,
+            1,
+            nn::LinearConfig::default(),
+        ))
+        .add_fn(Tensor::tanh)
+}
+
+/// Add dropout with the probability, unless it is zero.
+fn with_dropout(seq: nn::SequentialT, dropout: f32) -> nn::SequentialT {
+    if dropout > 0.0 {
+        seq.add_fn_t(move |x, train| x.dropout(f64::from(dropout), train))
+    } else {
+        seq
+    }
+}
+
+/// Predicts logits for {loss, draw, win}.
+fn wdl_net<const N: usize>(path: &nn::Path, filters: i64) -> nn::SequentialT {
+    nn::seq_t()
+        .add(nn::conv2d(path / "conv2d", filters, 1, 1, nn::ConvConfig {
+            stride: 1,
+            ..Default::default()
+        }))
+        .add_fn(Tensor::relu)
+        .add_fn(|x| x.view([-1, (N * N) as i64]))
+        .add(nn::linear(
+            path / "linear",
+            (N * N) as i64,
+            3,
+            nn::LinearConfig::default(),
+        ))
+}
+
+/// Expected value of a {loss, draw, win} distribution given as logits.
+#[must_use]
+pub fn wdl_expectation(wdl_logits: &Tensor) -> Tensor {
+    let probabilities = wdl_logits.softmax(1, Kind::Float);
+    probabilities.narrow(1, 2, 1) - probabilities.narrow(1, 0, 1)
+}
+
+/// Categorical {loss, draw, win} target with the same expectation as the
+/// scalar value target. Whatever is not won or lost is treated as a draw.
+#[must_use]
+pub fn wdl_target(target_value: &Tensor) -> Tensor {
+    let win = target_value.clamp_min(0.0);
+    let loss = (-target_value).clamp_min(0.0);
+    let draw = 1.0 - &win - &loss;
+    Tensor::cat(&[loss, draw, win], 1)
+}
+
+/// Categorical {loss, draw, win} target of a batch. Targets with the result
+/// of their game get it as a one-hot distribution, and the others get the
+/// [`wdl_target`] of their value.
+#[must_use]
+pub fn result_wdl_target(results: &[Option<Eval>], target_value: &Tensor) -> Tensor {
+    let one_hot: Vec<f32> = results
+        .iter()
+        .flat_map(|result| match result {
+            Some(Eval::Loss(_)) => [1.0, 0.0, 0.0],
+            Some(Eval::Draw(_)) => [0.0, 1.0, 0.0],
+            Some(Eval::Win(_)) => [0.0, 0.0, 1.0],
+            Some(Eval::Value(_)) | None => [0.0; 3],
+        })
+        .collect();
+    let has_result: Vec<f32> = results
+        .iter()
+        .map(|result| f32::from(u8::from(result.is_some_and(|r| r.is_known()))))
+        .collect();
+    let device = target_value.device();
+    let one_hot = Tensor::from_slice(&one_hot).view([-1, 3]).to(device);
+    let has_result = Tensor::from_slice(&has_result).unsqueeze(1).to(device);
+    one_hot * &has_result + wdl_target(target_value) * (1.0 - has_result)
+}
+
+/// Cross-entropy between the predicted distribution and a categorical
+/// target, see [`wdl_target`] and [`result_wdl_target`]. The cross-entropy
+/// of each target is scaled by its weight in `weights`, which has the shape
+/// `[batch, 1]`, like the other losses.
+#[must_use]
+pub fn wdl_loss(wdl_logits: &Tensor, target_wdl: &Tensor, weights: &Tensor) -> Tensor {
+    -((wdl_logits.log_softmax(1, Kind::Float) * target_wdl).sum_dim_intlist(1, true, Kind::Float)
+        * weights)
+        .mean(Kind::Float)
+}
+
+/// Short-horizon value target and mask of a batch for [`short_value_loss`].
+///
+/// The target is the result of the game discounted by `short_discount` for
+/// every ply until the end, like the value targets of selfplay are
+/// discounted by their own discount. Targets without a result, like those
+/// of reanalyze, get a zero in both.
+#[must_use]
+pub fn short_value_target(
+    results: &[Option<Eval>],
+    short_discount: f32,
+    device: Device,
+) -> (Tensor, Tensor) {
+    let results: Vec<_> = results
+        .iter()
+        .map(|result| result.filter(Eval::is_known))
+        .collect();
+    let target: Vec<f32> = results
+        .iter()
+        .map(|result| result.map_or(0.0, |r| r.discounted(short_discount).into_inner()))
+        .collect();
+    let has_target: Vec<f32> = results
+        .iter()
+        .map(|result| f32::from(u8::from(result.is_some())))
+        .collect();
+    (
+        Tensor::from_slice(&target).unsqueeze(1).to(device),
+        Tensor::from_slice(&has_target).unsqueeze(1).to(device),
+    )
+}
+
+/// Squared error of the short-horizon value, averaged over the targets which
+/// have a [`short_value_target`]. `has_target` is one for those targets and
+/// zero otherwise, and the error of each target is scaled by its weight in
+/// `weights`. This is meant to be added to the value loss with its own
+/// weight.
+#[must_use]
+pub fn short_value_loss(
+    short_value: &Tensor,
+    target_short_value: &Tensor,
+    has_target: &Tensor,
+    weights: &Tensor,
+) -> Tensor {
+    ((short_value - target_short_value).square() * has_target * weights).sum(Kind::Float)
+        / has_target.sum(Kind::Float).clamp_min(1.0)
+}
+
+/// Predicts the final flat margin, see [`crate::target::final_score`].
+fn score_net<const N: usize>(path: &nn::Path, filters: i64) -> nn::SequentialT {
+    nn::seq_t()
+        .add(nn::conv2d(path / "conv2d", filters, 1, 1, nn::ConvConfig {
+            stride: 1,
+            ..Default::default()
+        }))
+        .add_fn(Tensor::relu)
+        .add_fn(|x| x.view([-1, (N * N) as i64]))
+        .add(nn::linear(
+            path / "linear",
+            (N * N) as i64,
+            1,
+            nn::LinearConfig::default(),
+        ))
+}
+
+/// Squared error of the predicted score, averaged over the targets which
+/// have a score. `has_score` is one for those targets and zero otherwise,
+/// and the error of each target is scaled by its weight in `weights`.
+/// This is meant to be added to the loss with a small weight.
+#[must_use]
+pub fn score_loss(
+    score: &Tensor,
+    target_score: &Tensor,
+    has_score: &Tensor,
+    weights: &Tensor,
+) -> Tensor {
+    ((score - target_score).square() * has_score * weights).sum(Kind::Float)
+        / has_score.sum(Kind::Float).clamp_min(1.0)
+}
+
+/// Score target and mask of a batch for [`score_loss`].
+/// Targets without a score get a zero in both.
+#[must_use]
+pub fn score_target(scores: &[Option<f32>], device: Device) -> (Tensor, Tensor) {
+    let target: Vec<f32> = scores.iter().map(|s| s.unwrap_or_default()).collect();
+    let has_score: Vec<f32> = scores
+        .iter()
+        .map(|s| f32::from(u8::from(s.is_some())))
+        .collect();
+    (
+        Tensor::from_slice(&target).unsqueeze(1).to(device),
+        Tensor::from_slice(&has_score).unsqueeze(1).to(device),
+    )
+}
+
+/// Predicts the owner of each square, see [`crate::target::final_ownership`].
+fn ownership_net<const N: usize>(path: &nn::Path, filters: i64) -> nn::SequentialT {
+    nn::seq_t()
+        .add(nn::conv2d(path / "conv2d", filters, 1, 1, nn::ConvConfig {
+            stride: 1,
+            ..Default::default()
+        }))
+        .add_fn(|x| x.view([-1, (N * N) as i64]).tanh())
+}
+
+/// Squared error of the predicted ownership, averaged over the squares of
+/// the targets which have an ownership. `has_ownership` is one for those
+/// targets and zero otherwise, and the errors of each target are scaled by
+/// its weight in `weights`.
+#[must_use]
+pub fn ownership_loss(
+    ownership: &Tensor,
+    target_ownership: &Tensor,
+    has_ownership: &Tensor,
+    weights: &Tensor,
+) -> Tensor {
+    ((ownership - target_ownership).square() * has_ownership * weights).sum(Kind::Float)
+        / (has_ownership.sum(Kind::Float) * ownership.size()[1] as f64).clamp_min(1.0)
+}
+
+/// Ownership target with shape `[batch, N * N]` and mask with shape
+/// `[batch, 1]` of a batch for [`ownership_loss`].
+/// Targets without an ownership get zeros in both.
+#[must_use]
+pub fn ownership_target<const N: usize>(
+    ownerships: &[Option<&[i8]>],
+    device: Device,
+) -> (Tensor, Tensor) {
+    let mut target = vec![0.0; ownerships.len() * N * N];
+    for (chunk, ownership) in target.chunks_exact_mut(N * N).zip(ownerships) {
+        if let Some(ownership) = ownership {
+            for (t, &o) in chunk.iter_mut().zip(ownership.iter()) {
+                *t = f32::from(o);
+            }
+        }
+    }
+    let has_ownership: Vec<f32> = ownerships
+        .iter()
+        .map(|o| f32::from(u8::from(o.is_some())))
+        .collect();
+    (
+        Tensor::from_slice(&target)
+            .view([-1, (N * N) as i64])
+            .to(device),
+        Tensor::from_slice(&has_ownership).unsqueeze(1).to(device),
+    )
+}
+
+fn ube_net<const N: usize>(path: &nn::Path, filters: i64) -> nn::SequentialT {
+    nn::seq_t()
+        .add(nn::conv2d(path / "conv2d", filters, 1, 1, nn::ConvConfig {
+            stride: 1,
+            ..Default::default()
+        }))
+        .add_fn(Tensor::relu)
+        .add_fn(|x| x.view([-1, (N * N) as i64]))
+        .add(nn::linear(
+            path / "linear",
+            (N * N) as i64,
+            1,
+            nn::LinearConfig::default(),
+        ))
+}
+
+/// Size of the outputs of RND, see [`Net::rnd_embeddings`].
+pub const RND_OUTPUT: i64 = 512;
+
+fn rnd<const N: usize>(path: &nn::Path, hidden_layer: i64) -> nn::SequentialT
+where
+    Reserves<N>: Default,
+{
+    nn::seq_t()
+        .add_fn(|x| x.view([-1, input_size::<N>() as i64]))
+        .add_fn(|x| x / x.square().sum_dim_intlist(1, true, None))
+        .add(nn::linear(
+            path / "input_linear",
+            input_size::<N>() as i64,
+            hidden_layer,
+            nn::LinearConfig::default(),
+        ))
+        .add_fn(Tensor::relu)
+        .add(nn::linear(
+            path / "hidden_linear",
+            hidden_layer,
+            hidden_layer,
+            nn::LinearConfig::default(),
+        ))
+        .add_fn(Tensor::relu)
+        .add(nn::linear(
+            path / "final_linear",
+            hidden_layer,
+            RND_OUTPUT,
+            nn::LinearConfig::default(),
+        ))
+}
+
+impl<const N: usize, const HALF_KOMI: i8> Network for Net<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    fn new(device: Device, seed: Option<i64>) -> Self {
+        Self::with_config(device, seed, NetConfig::default())
+    }
+
+    fn vs(&self) -> &nn::VarStore {
+        &self.vs
+    }
+
+    fn vs_mut(&mut self) -> &mut nn::VarStore {
+        &mut self.vs
+    }
+
+    /// Load a model with the architecture it was saved with.
+    /// Models saved before the config was stored use the default config.
+    fn load(path: impl AsRef<std::path::Path>, device: Device) -> Result<Self, tch::TchError> {
+        let path = path.as_ref();
+        Self::from_named_tensors(
+            Tensor::load_multi(path)?,
+            device,
+            &path.display().to_string(),
+        )
+    }
+
+    fn load_from_bytes(bytes: &[u8], device: Device) -> Result<Self, tch::TchError> {
+        Self::from_named_tensors(
+            Tensor::load_multi_from_stream(std::io::Cursor::new(bytes))?,
+            device,
+            "bytes",
+        )
+    }
+
+    /// Same as [`Net::load_partial_with_config`] with the default config.
+    fn load_partial(
+        path: impl AsRef<std::path::Path>,
+        device: Device,
+    ) -> Result<Self, tch::TchError> {
+        Self::load_partial_with_config(path, device, NetConfig::default())
+    }
+
+    fn clone(&self, device: Device) -> Self {
+        let mut nn = Self::with_config(device, None, self.config);
+        nn.vs_mut()
+            .copy(self.vs())
+            .expect("variables in both VarStores should have identical names");
+        nn
+    }
+}
+
+impl<const N: usize, const HALF_KOMI: i8> Net<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    /// Load every variable whose name and shape match the architecture of
+    /// `config`, leaving the others freshly initialized. The config which
+    /// was saved with the model is ignored, so that a network can be
+    /// warm-started from one with a different architecture.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or a variable cannot
+    /// be copied.
+    pub fn load_partial_with_config(
+        path: impl AsRef<std::path::Path>,
+        device: Device,
+        config: NetConfig,
+    ) -> Result<Self, tch::TchError> {
+        let named_tensors: std::collections::HashMap<_, _> =
+            Tensor::load_multi(path)?.into_iter().collect();
+        let net = Self::with_config(device, None, config);
+        tch::no_grad(|| {
+            for (name, mut variable) in net.vs.variables() {
+                // The config should describe this network, not the saved one.
+                if name == NetConfig::VARIABLE {
+                    continue;
+                }
+                match named_tensors.get(&name) {
+                    Some(tensor) if tensor.size() == variable.size() => {
+                        variable.f_copy_(tensor)?;
+                    }
+                    Some(tensor) => log::info!(
+                        "Skipping {name} because its shape {:?} does not match {:?}",
+                        tensor.size(),
+                        variable.size()
+                    ),
+                    None => log::info!("Skipping {name} because it is missing"),
+                }
+            }
+            Ok(())
+        })?;
+        Ok(net)
+    }
+
+    /// Create a network with the given architecture.
+    #[must_use]
+    pub fn with_config(device: Device, seed: Option<i64>, config: NetConfig) -> Self {
+        if let Some(seed) = seed {
+            tch::manual_seed(seed);
+        }
+
+        let vs = nn::VarStore::new(device);
+        let root = vs.root();
+        let filters = config.filters;
+        let mut saved_config = root.zeros_no_train(NetConfig::VARIABLE, &[10]);
+        tch::no_grad(|| saved_config.copy_(&config.to_tensor()));
+        Self {
+            core: core::<N>(
+                &(&root / "core"),
+                filters,
+                config.core_res_blocks,
+                config.se_ratio,
+                config.norm,
+            ),
+            policy_net: policy_net::<N>(&(&root / "policy"), filters, config.dropout),
+            value_net: value_net::<N>(&(&root / "value"), filters, config.dropout),
+            ube_net: ube_net::<N>(&(&root / "ube"), filters),
+            wdl_net: config
+                .wdl_head
+                .then(|| wdl_net::<N>(&(&root / "wdl"), filters)),
+            short_value_net: config
+                .short_value_head
+                .then(|| value_net::<N>(&(&root / "value_short"), filters, config.dropout)),
+            score_net: config
+                .score_head
+                .then(|| score_net::<N>(&(&root / "score"), filters)),
+            ownership_net: config
+                .ownership_head
+                .then(|| ownership_net::<N>(&(&root / "ownership"), filters)),
+            rnd: Rnd {
+                learning: rnd::<N>(&(&root / "rnd_learning"), config.linear_size),
+                target: rnd::<N>(&(&root / "rnd_target"), config.linear_size),
+                min: root.var("min", &[1], nn::Init::Const(0.0)),
+                // TODO: Think about a good default
+                max: root.var("max", &[1], nn::Init::Const(1.0)),
+                normalizer: RunningNormalizer::new(&(&root / NORMALIZER_PATH)),
+            },
+            calibration: root.zeros_no_train(CALIBRATION_PATH, &[2]),
+            config,
+            vs,
+        }
+    }
+
+    /// Create a network with the saved architecture and copy the saved
+    /// variables into it. `source` is only used for error messages.
+    fn from_named_tensors(
+        named_tensors: Vec<(String, Tensor)>,
+        device: Device,
+        source: &str,
+    ) -> Result<Self, tch::TchError> {
+        let saved_config = named_tensors
+            .iter()
+            .find(|(name, _)| name == NetConfig::VARIABLE)
+            .map(|(_, tensor)| {
+                NetConfig::from_tensor(tensor)
+                    .ok_or_else(|| tch::TchError::Convert(format!("invalid config in {source}")))
+            })
+            .transpose()?;
+        let net = Self::with_config(device, None, saved_config.unwrap_or_default());
+        let named_tensors: std::collections::HashMap<_, _> = named_tensors.into_iter().collect();
+        tch::no_grad(|| {
+            for (name, mut variable) in net.vs.variables() {
+                // The network was already created with the saved config.
+                if name == NetConfig::VARIABLE {
+                    continue;
+                }
+                match named_tensors.get(&name) {
+                    Some(tensor) => {
+                        variable.f_copy_(tensor)?;
+                    }
+                    // Models saved before the running statistics were added
+                    // start without any.
+                    None if name.starts_with(NORMALIZER_PATH) => {}
+                    // Models saved before calibration are not calibrated.
+                    None if name == CALIBRATION_PATH => {}
+                    None => {
+                        return Err(tch::TchError::TensorNameNotFound(name, source.to_string()));
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        Ok(net)
+    }
+
+    /// Create a network which also has a win/draw/loss value head.
+    /// Networks created with [`Network::new`] do not have one,
+    /// so that models saved without it can still be loaded.
+    #[must_use]
+    pub fn new_with_wdl(device: Device, seed: Option<i64>) -> Self {
+        Self::with_config(device, seed, NetConfig {
+            wdl_head: true,
+            ..NetConfig::default()
+        })
+    }
+
+    #[must_use]
+    pub const fn config(&self) -> NetConfig {
+        self.config
+    }
+
+    /// The calibration which is saved with the model, if it has one.
+    #[must_use]
+    pub fn calibration(&self) -> Option<Calibration> {
+        let [scale, bias]: [f32; 2] = Vec::<f32>::try_from(&self.calibration)
+            .expect("calibration should have one dimension")
+            .try_into()
+            .expect("calibration should have two elements");
+        #[allow(clippy::float_cmp)]
+        let calibrated = scale != 0.0;
+        calibrated.then_some(Calibration { scale, bias })
+    }
+
+    /// Store the calibration in the model, so that it is saved with it.
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        let tensor = Tensor::from_slice(&[calibration.scale, calibration.bias]);
+        tch::no_grad(|| self.calibration.copy_(&tensor));
+    }
+
+    /// Write the core, policy head, and value head as an ONNX model, so that
+    /// it can be evaluated without libtorch.
+    ///
+    /// The input is named `input` and has the same shape as the output of
+    /// [`games_to_tensor`], which is `[batch, input_channels::<N>(), N, N]`.
+    /// The outputs are `policy` with the logits, which have the shape
+    /// `[batch, output_channels::<N>(), N, N]`, and `value` with the shape
+    /// `[batch, 1]`. RND, UBE, and the other heads are not included.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn export_onnx(&self, path: impl AsRef<std::path::Path>) -> Result<(), tch::TchError> {
+        let variables = onnx::Variables::new(&self.vs);
+        let mut graph = onnx::Graph::default();
+        let n = N as i64;
+        let input = graph.input("input", &[input_channels::<N>() as i64, n, n]);
+
+        let core = graph.conv2d(&variables, "core.input_conv2d", &input, false)?;
+        let core = graph.norm(&variables, "core", self.config.norm, &core, false)?;
+        let mut core = graph.relu(&core);
+        for i in 0..self.config.core_res_blocks {
+            core = graph.residual_block(
+                &variables,
+                &format!("core.res_block_{i}"),
+                self.config.norm,
+                self.config.se_ratio.is_some(),
+                &core,
+            )?;
+        }
+
+        let policy = graph.conv2d(&variables, "policy.conv2d", &core, false)?;
+        graph.output("policy", &policy, &[output_channels::<N>() as i64, n, n]);
+
+        let value = graph.conv2d(&variables, "value.conv2d", &core, false)?;
+        let value = graph.relu(&value);
+        let value = graph.flatten(&value);
+        let value = graph.linear(&variables, "value.linear", &value)?;
+        let value = graph.tanh(&value);
+        graph.output("value", &value, &[1]);
+
+        std::fs::write(path, graph.to_model())?;
+        Ok(())
+    }
+
+    /// Like [`RndNetwork::forward_t`], but the value is given as logits over
+    /// {loss, draw, win}. Returns `None` if the network has no such head.
+    #[must_use]
+    pub fn forward_wdl_t(&self, xs: &Tensor, train: bool) -> Option<(Tensor, Tensor, Tensor)> {
+        let wdl_net = self.wdl_net.as_ref()?;
+        debug_assert_input::<N>(xs);
+        let core = self.core.forward_t(xs, train);
+        let policy = self.policy_net.forward_t(&core, train);
+        let wdl = wdl_net.forward_t(&core, train);
+        let ube = self.ube_net.forward_t(&core.detach(), train);
+        Some((policy, wdl, ube))
+    }
+
+    /// Outputs of the learned and the (detached) target network of RND,
+    /// each with shape `[batch, RND_OUTPUT]`. [`RndNetwork::forward_rnd`] is
+    /// the squared distance between them, but they can also be used to
+    /// cluster positions or to compute other novelty metrics.
+    #[must_use]
+    pub fn rnd_embeddings(&self, xs: &Tensor) -> (Tensor, Tensor) {
+        self.rnd_embeddings_t(xs, false)
+    }
+
+    fn rnd_embeddings_t(&self, xs: &Tensor, train: bool) -> (Tensor, Tensor) {
+        let learning = self
+            .rnd
+            .learning
+            .forward_t(&xs.set_requires_grad(false), train);
+        let target = self
+            .rnd
+            .target
+            .forward_t(&xs.set_requires_grad(false), false)
+            .detach();
+        debug_assert_shape(&learning, &[-1, RND_OUTPUT], "RND embedding");
+        (learning, target)
+    }
+
+    /// Value with a shorter horizon than the value of [`RndNetwork::forward_t`]
+    /// with shape `[batch, 1]`, see [`short_value_target`].
+    /// Returns `None` if the network has no short value head.
+    #[must_use]
+    pub fn forward_short_value_t(&self, xs: &Tensor, train: bool) -> Option<Tensor> {
+        let short_value_net = self.short_value_net.as_ref()?;
+        let core = self.core.forward_t(xs, train);
+        Some(short_value_net.forward_t(&core, train))
+    }
+
+    /// Predicted final flat margin with shape `[batch, 1]`.
+    /// Returns `None` if the network has no score head.
+    #[must_use]
+    pub fn forward_score_t(&self, xs: &Tensor, train: bool) -> Option<Tensor> {
+        let score_net = self.score_net.as_ref()?;
+        let core = self.core.forward_t(xs, train);
+        Some(score_net.forward_t(&core, train))
+    }
+
+    /// Predicted ownership of each square with shape `[batch, N * N]`,
+    /// from the perspective of the player to move.
+    /// Returns `None` if the network has no ownership head.
+    #[must_use]
+    pub fn forward_ownership_t(&self, xs: &Tensor, train: bool) -> Option<Tensor> {
+        let ownership_net = self.ownership_net.as_ref()?;
+        let core = self.core.forward_t(xs, train);
+        Some(ownership_net.forward_t(&core, train))
+    }
+}
+
+impl<const N: usize, const HALF_KOMI: i8> RndNetwork for Net<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    fn forward_t(&self, xs: &Tensor, train: bool) -> (Tensor, Tensor, Tensor) {
+        debug_assert_input::<N>(xs);
+        let core = self.core.forward_t(xs, train);
+        let policy = self.policy_net.forward_t(&core, train);
+        let value = self.value_net.forward_t(&core, train);
+        // Detached UBE so it does not mess with baseline
+        let ube = self.ube_net.forward_t(&core.detach(), train);
+        debug_assert_shape(
+            &policy,
+            &[-1, output_channels::<N>() as i64, N as i64, N as i64],
+            "policy output",
+        );
+        debug_assert_shape(&value, &[-1, 1], "value output");
+        debug_assert_shape(&ube, &[-1, 1], "UBE output");
+        (policy, value, ube)
+    }
+
+    fn forward_rnd(&self, xs: &Tensor, train: bool) -> Tensor {
+        let (learning, target) = self.rnd_embeddings_t(xs, train);
+        (learning - target).square().sum_dim_intlist(1, false, None)
+    }
+
+    fn normalized_rnd(&self, xs: &Tensor) -> Tensor {
+        let errors = self.forward_rnd(xs, false);
+        // States which are one standard deviation more novel than average
+        // get the full bonus.
+        let normalized = self.rnd.normalizer.normalize(&errors).unwrap_or_else(|| {
+            let min = self.rnd.min.detach();
+            let max = self.rnd.max.detach();
+            (errors - &min) / (max - min)
+        });
+        normalized.clamp(0.0, 1.0) * MAXIMUM_VARIANCE
+    }
+
+    fn update_rnd_normalization(&mut self, min: &Tensor, max: &Tensor) {
+        log::debug!("Updating RND normalization to min: {min:?} and max: {max:?}");
+        self.rnd.min.set_data(min);
+        self.rnd.max.set_data(max);
+    }
+
+    fn update_rnd_statistics(&mut self, xs: &Tensor) {
+        let errors = tch::no_grad(|| self.forward_rnd(xs, false));
+        self.rnd.normalizer.update(&errors);
+    }
+
+    fn rnd_statistics(&self) -> RndStatistics {
+        self.rnd.normalizer.statistics()
+    }
+}
+
+impl<const N: usize, const HALF_KOMI: i8> TrainingNetwork for Net<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    fn forward_training_t(&self, xs: &Tensor, train: bool) -> TrainingOutputs {
+        debug_assert_input::<N>(xs);
+        let core = self.core.forward_t(xs, train);
+        let policy = self.policy_net.forward_t(&core, train);
+        let value = self.value_net.forward_t(&core, train);
+        // Detached UBE so it does not mess with baseline
+        let ube = self.ube_net.forward_t(&core.detach(), train);
+        TrainingOutputs {
+            wdl: self
+                .wdl_net
+                .as_ref()
+                .map(|wdl_net| wdl_net.forward_t(&core, train)),
+            short_value: self
+                .short_value_net
+                .as_ref()
+                .map(|short_value_net| short_value_net.forward_t(&core, train)),
+            score: self
+                .score_net
+                .as_ref()
+                .map(|score_net| score_net.forward_t(&core, train)),
+            ownership: self
+                .ownership_net
+                .as_ref()
+                .map(|ownership_net| ownership_net.forward_t(&core, train)),
+            ..TrainingOutputs::new(policy, value, ube)
+        }
+    }
+
+    fn auxiliary_heads(&self) -> AuxiliaryHeads {
+        AuxiliaryHeads {
+            wdl: self.wdl_net.is_some(),
+            short_value: self.short_value_net.is_some(),
+            score: self.score_net.is_some(),
+            ownership: self.ownership_net.is_some(),
+        }
+    }
+
+    fn observe_batch(&mut self, xs: &Tensor) {
+        self.update_rnd_statistics(xs);
+    }
+}
+
+impl<const N: usize, const HALF_KOMI: i8> Agent<Game<N, HALF_KOMI>> for Net<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    fn policy_value_uncertainty(
+        &self,
+        env_batch: &[Game<N, HALF_KOMI>],
+        actions_batch: &[Vec<Move>],
+    ) -> impl Iterator<Item = (Vec<(Move, NotNan<f32>)>, f32, f32)> {
+        assert_eq!(env_batch.len(), actions_batch.len());
+        assert!(!env_batch.is_empty());
+        let device = self.vs.device();
+
+        let xs = games_to_tensor(env_batch, device);
+        let (policy, values, ube_uncertainties) = self.forward_wdl_t(&xs, false).map_or_else(
+            || self.forward_t(&xs, false),
+            |(policy, wdl, ube)| (policy, wdl_expectation(&wdl), ube),
+        );
+        let policy = policy.view([-1, output_size::<N>() as i64]);
+        // The indices and the gathered logits are kept in flat buffers,
+        // padded to the largest number of actions, instead of a vector for
+        // every position.
+        let width = actions_batch
+            .iter()
+            .map(Vec::len)
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        let mut index = Vec::with_capacity(actions_batch.len() * width);
+        for actions in actions_batch {
+            index.extend(actions.iter().map(|a| move_index::<N>(a) as i64));
+            index.resize(index.len() + width - actions.len(), 0);
+        }
+        let index = Tensor::from_slice(&index)
+            .view([actions_batch.len() as i64, width as i64])
+            .to(device);
+        let logits = Vec::<f32>::try_from(policy.gather(1, &index, false).view([-1]))
+            .expect("tensor should have one dimension");
+
+        let indexed_policy: Vec<Vec<_>> = actions_batch
+            .iter()
+            .zip(logits.chunks_exact(width))
+            .map(|(actions, p)| {
+                actions
+                    .iter()
+                    .zip(p)
+                    .map(|(a, &p)| (*a, NotNan::new(p).expect("logit should not be NaN")))
+                    .collect()
+            })
+            .collect();
+        let values: Vec<_> = values.view([-1]).try_into().unwrap();
+
+        // Uncertainty.
+        let rnd_uncertainties = self.normalized_rnd(&xs);
+        let uncertainties: Vec<_> = ube_uncertainties
+            .exp() // Exponent because UBE prediction is log(variance)
+            .maximum(&rnd_uncertainties)
+            .clamp(0.0, MAXIMUM_VARIANCE)
+            .view([-1])
+            .try_into()
+            .unwrap();
+
+        indexed_policy
+            .into_iter()
+            .zip(values)
+            .zip(uncertainties)
+            .map(|((p, v), u)| (p, v, u))
+    }
+
+    fn short_horizon_values(&self, env_batch: &[Game<N, HALF_KOMI>]) -> Option<Vec<f32>> {
+        let xs = games_to_tensor(env_batch, self.vs.device());
+        let values = self.forward_short_value_t(&xs, false)?;
+        Some(values.view([-1]).try_into().unwrap())
+    }
+
+    fn win_probability(&self, value: f32) -> f32 {
+        self.calibration().map_or_else(
+            || (value + 1.0) / 2.0,
+            |calibration| calibration.win_probability(value),
+        )
+    }
+}
@@ -1,9 +1,14 @@
+pub mod calibration;
+pub mod ensemble;
+pub mod generic;
 pub mod net4_ensemble;
 pub mod net4_lcghash;
 pub mod net4_rnd;
 pub mod net4_simhash;
 pub mod net5;
 pub mod net6_simhash;
+pub mod normalizer;
+mod onnx;
 pub mod repr;
 pub mod residual;
 
@@ -12,9 +17,15 @@ pub trait Network: Sized {
     fn vs(&self) -> &tch::nn::VarStore;
     fn vs_mut(&mut self) -> &mut tch::nn::VarStore;
 
+    /// Save the variables to a temporary file first and then rename it,
+    /// so that an interrupted save never leaves a truncated model behind.
     #[allow(clippy::missing_errors_doc)]
     fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), tch::TchError> {
-        self.vs().save(path)
+        let path = path.as_ref();
+        let temp_path = temporary_path(path);
+        self.vs().save(&temp_path)?;
+        std::fs::rename(temp_path, path)?;
+        Ok(())
     }
 
     #[allow(clippy::missing_errors_doc)]
@@ -24,6 +35,21 @@ pub trait Network: Sized {
         Ok(nn)
     }
 
+    /// Serialize the variables, for example to send them to another thread.
+    #[allow(clippy::missing_errors_doc)]
+    fn save_to_bytes(&self) -> Result<Vec<u8>, tch::TchError> {
+        let mut bytes = Vec::new();
+        self.vs().save_to_stream(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    #[allow(clippy::missing_errors_doc)]
+    fn load_from_bytes(bytes: &[u8], device: tch::Device) -> Result<Self, tch::TchError> {
+        let mut nn = Self::new(device, None);
+        nn.vs_mut().load_from_stream(std::io::Cursor::new(bytes))?;
+        Ok(nn)
+    }
+
     #[allow(clippy::missing_errors_doc)]
     fn load_partial(
         path: impl AsRef<std::path::Path>,
@@ -34,6 +60,80 @@ pub trait Network: Sized {
         Ok(nn)
     }
 
+    /// Total number of elements in the trainable variables of the
+    /// `VarStore`, leaving out buffers like the saved config and the
+    /// normalization statistics.
+    #[must_use]
+    fn num_parameters(&self) -> usize {
+        self.vs()
+            .trainable_variables()
+            .iter()
+            .map(tch::Tensor::numel)
+            .sum()
+    }
+
+    /// List every variable with its shape and number of elements,
+    /// grouped by the part of the network it belongs to.
+    /// Only trainable variables count towards the totals, the others are
+    /// marked as not trained.
+    #[must_use]
+    fn summary(&self) -> String {
+        use std::fmt::Write;
+
+        let trainable: std::collections::HashSet<_> = self
+            .vs()
+            .trainable_variables()
+            .iter()
+            .map(tch::Tensor::data_ptr)
+            .collect();
+        let is_trainable = |tensor: &tch::Tensor| trainable.contains(&tensor.data_ptr());
+        let mut variables: Vec<_> = self.vs().variables().into_iter().collect();
+        variables.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        // Variables like `rnd_target.linear.weight` belong to `rnd`, and
+        // everything without a known prefix is listed at the end.
+        let group = |name: &str| {
+            PARAMETER_GROUPS
+                .into_iter()
+                .find(|group| name.starts_with(group))
+                .unwrap_or("other")
+        };
+
+        let mut summary = String::new();
+        for g in PARAMETER_GROUPS.into_iter().chain(["other"]) {
+            let members: Vec<_> = variables
+                .iter()
+                .filter(|(name, _)| group(name) == g)
+                .collect();
+            if members.is_empty() {
+                continue;
+            }
+            let total: usize = members
+                .iter()
+                .filter(|(_, t)| is_trainable(t))
+                .map(|(_, t)| t.numel())
+                .sum();
+            writeln!(summary, "{g}: {total} parameters")
+                .expect("writing to a string should not fail");
+            for (name, tensor) in members {
+                let note = if is_trainable(tensor) {
+                    ""
+                } else {
+                    " (not trained)"
+                };
+                writeln!(
+                    summary,
+                    "  {name} {:?} {}{note}",
+                    tensor.size(),
+                    tensor.numel()
+                )
+                .expect("writing to a string should not fail");
+            }
+        }
+        write!(summary, "total: {} parameters", self.num_parameters())
+            .expect("writing to a string should not fail");
+        summary
+    }
+
     #[must_use]
     fn clone(&self, device: tch::Device) -> Self {
         let mut nn = Self::new(device, None);
@@ -44,12 +144,74 @@ pub trait Network: Sized {
     }
 }
 
+/// Make forward passes on CUDA reproducible, so that the same seed and
+/// input always give bitwise identical outputs.
+///
+/// This turns off cuDNN, whose fastest convolution algorithms are not
+/// deterministic, together with its autotuning. Convolutions fall back to
+/// the native kernels instead, which makes inference and training
+/// noticeably slower, so this is meant for debugging and tests.
+/// It also sets `CUBLAS_WORKSPACE_CONFIG`, which is what the
+/// deterministic-algorithms mode of torch needs for matrix multiplications
+/// to be reproducible. `tch` has no binding for that mode itself, so
+/// operations without a deterministic kernel run without an error.
+/// The setting is global to the process and should be changed before any
+/// network runs, since cuBLAS only reads its config once.
+pub fn set_deterministic(deterministic: bool) {
+    if deterministic {
+        tch::Cuda::cudnn_set_benchmark(false);
+        if std::env::var_os(CUBLAS_WORKSPACE_CONFIG).is_none() {
+            std::env::set_var(CUBLAS_WORKSPACE_CONFIG, ":4096:8");
+        }
+    }
+    tch::Cuda::set_user_enabled_cudnn(!deterministic);
+}
+
+/// Parse a device for a command line argument: `cpu`, `mps`, `cuda`,
+/// which is the first GPU, or `cuda:N`.
+///
+/// # Errors
+///
+/// Returns an error message for any other device.
+pub fn parse_device(s: &str) -> Result<tch::Device, String> {
+    match s {
+        "cpu" => Ok(tch::Device::Cpu),
+        "mps" => Ok(tch::Device::Mps),
+        "cuda" => Ok(tch::Device::Cuda(0)),
+        _ => s
+            .strip_prefix("cuda:")
+            .and_then(|index| index.parse().ok())
+            .map(tch::Device::Cuda)
+            .ok_or_else(|| format!("unknown device `{s}`, expected `cpu`, `cuda:N`, or `mps`")),
+    }
+}
+
+/// Environment variable which makes cuBLAS pick deterministic algorithms.
+const CUBLAS_WORKSPACE_CONFIG: &str = "CUBLAS_WORKSPACE_CONFIG";
+
+/// Prefixes of the variable names of the parts of a network.
+pub const PARAMETER_GROUPS: [&str; 5] = ["core", "policy", "value", "ube", "rnd"];
+
+/// Sibling path which is used while saving to `path`.
+#[must_use]
+pub fn temporary_path(path: &std::path::Path) -> std::path::PathBuf {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    temp_path.into()
+}
+
 pub trait RndNetwork: Network {
     fn forward_t(&self, xs: &tch::Tensor, train: bool) -> (tch::Tensor, tch::Tensor, tch::Tensor);
 
     fn forward_rnd(&self, xs: &tch::Tensor, train: bool) -> tch::Tensor;
+    /// RND error scaled to `[0, MAXIMUM_VARIANCE]`. Uses the running
+    /// statistics once there are some, and the min and max otherwise.
     fn normalized_rnd(&self, xs: &tch::Tensor) -> tch::Tensor;
     fn update_rnd_normalization(&mut self, min: &tch::Tensor, max: &tch::Tensor);
+    /// Add the RND errors of a batch to the running statistics.
+    /// This should only be called during training.
+    fn update_rnd_statistics(&mut self, xs: &tch::Tensor);
+    fn rnd_statistics(&self) -> normalizer::RndStatistics;
 }
 
 pub trait EnsembleNetwork: Network {
@@ -70,3 +232,59 @@ pub trait HashNetwork<E: crate::search::env::Environment>: Network {
     fn update_counts(&mut self, xs: &tch::Tensor);
     fn forward_hash(&self, xs: &tch::Tensor) -> tch::Tensor;
 }
+
+/// Outputs of a forward pass for training.
+pub struct TrainingOutputs {
+    pub policy: tch::Tensor,
+    pub value: tch::Tensor,
+    pub ube: tch::Tensor,
+    /// Logits over {loss, draw, win}, if the network has a WDL head.
+    pub wdl: Option<tch::Tensor>,
+    /// Value with a shorter horizon, if the network has a short value head.
+    pub short_value: Option<tch::Tensor>,
+    /// Predicted final flat margin, if the network has a score head.
+    pub score: Option<tch::Tensor>,
+    /// Predicted owner of each square, if the network has an ownership head.
+    pub ownership: Option<tch::Tensor>,
+}
+
+impl TrainingOutputs {
+    /// Outputs of a network without any of the optional heads.
+    #[must_use]
+    pub const fn new(policy: tch::Tensor, value: tch::Tensor, ube: tch::Tensor) -> Self {
+        Self {
+            policy,
+            value,
+            ube,
+            wdl: None,
+            short_value: None,
+            score: None,
+            ownership: None,
+        }
+    }
+}
+
+/// Which of the optional heads of [`TrainingOutputs`] a network has.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct AuxiliaryHeads {
+    pub wdl: bool,
+    pub short_value: bool,
+    pub score: bool,
+    pub ownership: bool,
+}
+
+/// A network which the learner can train.
+pub trait TrainingNetwork: Network {
+    /// Run the shared core once and every head on it.
+    fn forward_training_t(&self, xs: &tch::Tensor, train: bool) -> TrainingOutputs;
+
+    /// The optional heads which [`Self::forward_training_t`] has outputs for.
+    fn auxiliary_heads(&self) -> AuxiliaryHeads {
+        AuxiliaryHeads::default()
+    }
+
+    /// Update what the network keeps track of about the positions it was
+    /// trained on. This should only be called during training.
+    fn observe_batch(&mut self, _xs: &tch::Tensor) {}
+}
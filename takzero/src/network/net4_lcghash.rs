@@ -10,7 +10,7 @@ use tch::{
 };
 
 use super::{
-    repr::{game_to_tensor, input_channels, move_index, output_channels},
+    repr::{games_to_tensor, input_channels, move_index, output_channels},
     residual::ResidualBlock,
     HashNetwork,
     Network,
@@ -274,13 +274,7 @@ impl Agent<Env> for Net {
         assert!(!env_batch.is_empty());
         let device = self.vs.device();
 
-        let xs = Tensor::cat(
-            &env_batch
-                .iter()
-                .map(|env| game_to_tensor(env, device))
-                .collect::<Vec<_>>(),
-            0,
-        );
+        let xs = games_to_tensor(env_batch, device);
         let (policy, values, ube_uncertainties) = self.forward_t(&xs, false);
         let policy = policy.view([-1, output_size::<N>() as i64]);
         let max_actions = actions_batch.iter().map(Vec::len).max().unwrap_or_default();
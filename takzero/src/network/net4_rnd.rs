@@ -7,7 +7,8 @@ use tch::{
 };
 
 use super::{
-    repr::{game_to_tensor, input_channels, move_index, output_channels},
+    normalizer::{RndStatistics, RunningNormalizer, PATH as NORMALIZER_PATH},
+    repr::{games_to_tensor, input_channels, move_index, output_channels},
     residual::ResidualBlock,
     Network,
     RndNetwork,
@@ -40,9 +41,9 @@ struct Rnd {
     target: nn::SequentialT,
     predictor: nn::SequentialT,
     // Normalization variables
-    // TODO: Replace by running mean and std_dev
     min: Tensor,
     max: Tensor,
+    normalizer: RunningNormalizer,
 }
 
 fn core(path: &nn::Path) -> nn::SequentialT {
@@ -183,6 +184,7 @@ impl Network for Net {
                 target: rnd(&(&root / "rnd_target")),
                 min: root.var("min", &[1], nn::Init::Const(0.0)),
                 max: root.var("max", &[1], nn::Init::Const(1.0)),
+                normalizer: RunningNormalizer::new(&(&root / NORMALIZER_PATH)),
             },
             vs,
         }
@@ -223,9 +225,14 @@ impl RndNetwork for Net {
     }
 
     fn normalized_rnd(&self, xs: &Tensor) -> Tensor {
-        let min = self.rnd.min.detach();
-        let max = self.rnd.max.detach();
-        let normalized = (self.forward_rnd(xs, false) - &min) / (max - min);
+        let errors = self.forward_rnd(xs, false);
+        // States which are one standard deviation more novel than average
+        // get the full bonus.
+        let normalized = self.rnd.normalizer.normalize(&errors).unwrap_or_else(|| {
+            let min = self.rnd.min.detach();
+            let max = self.rnd.max.detach();
+            (errors - &min) / (max - min)
+        });
         normalized.clamp(0.0, 1.0) * MAXIMUM_VARIANCE
     }
 
@@ -234,6 +241,15 @@ impl RndNetwork for Net {
         self.rnd.min.set_data(min);
         self.rnd.max.set_data(max);
     }
+
+    fn update_rnd_statistics(&mut self, xs: &Tensor) {
+        let errors = tch::no_grad(|| self.forward_rnd(xs, false));
+        self.rnd.normalizer.update(&errors);
+    }
+
+    fn rnd_statistics(&self) -> RndStatistics {
+        self.rnd.normalizer.statistics()
+    }
 }
 
 impl Agent<Env> for Net {
@@ -246,13 +262,7 @@ impl Agent<Env> for Net {
         assert!(!env_batch.is_empty());
         let device = self.vs.device();
 
-        let xs = Tensor::cat(
-            &env_batch
-                .iter()
-                .map(|env| game_to_tensor(env, device))
-                .collect::<Vec<_>>(),
-            0,
-        );
+        let xs = games_to_tensor(env_batch, device);
         let (policy, values, ube_uncertainties) = self.forward_t(&xs, false);
         let policy = policy.view([-1, output_size::<N>() as i64]);
         let max_actions = actions_batch.iter().map(Vec::len).max().unwrap_or_default();
@@ -1,300 +1,66 @@
-use fast_tak::{takparse::Move, Game};
-use ordered_float::NotNan;
-use tch::{
-    nn::{self, ModuleT},
-    Device,
-    Tensor,
+use fast_tak::Game;
+
+pub use super::generic::{
+    ownership_loss,
+    ownership_target,
+    result_wdl_target,
+    score_loss,
+    score_target,
+    short_value_loss,
+    short_value_target,
+    wdl_expectation,
+    wdl_loss,
+    wdl_target,
+    NetConfig,
+    MAXIMUM_VARIANCE,
+    RND_OUTPUT,
 };
 
-use super::{
-    repr::{game_to_tensor, input_channels, input_size, move_index, output_channels},
-    residual::ResidualBlock,
-    Network,
-    RndNetwork,
-};
-use crate::{network::repr::output_size, search::agent::Agent};
-
 pub const N: usize = 5;
 pub const HALF_KOMI: i8 = 4;
 pub type Env = Game<N, HALF_KOMI>;
-const FILTERS: i64 = 256;
-
-// Value is [-1, 1], which is size 2, so variance can be 2*2 = 4.
-pub const MAXIMUM_VARIANCE: f64 = 4.0;
-
-#[derive(Debug)]
-pub struct Net {
-    vs: nn::VarStore,
-    core: nn::SequentialT,
-    policy_net: nn::SequentialT,
-    value_net: nn::SequentialT,
-    ube_net: nn::SequentialT,
-    rnd: Rnd,
-}
-
-#[derive(Debug)]
-struct Rnd {
-    target: nn::SequentialT,
-    learning: nn::SequentialT,
-    // Normalization variables
-    min: Tensor,
-    max: Tensor,
-}
-
-fn core(path: &nn::Path) -> nn::SequentialT {
-    const CORE_RES_BLOCKS: u32 = 20;
-    let mut core = nn::seq_t()
-        .add(nn::conv2d(
-            path / "input_conv2d",
-            input_channels::<N>() as i64,
-            FILTERS,
-            3,
-            nn::ConvConfig {
-                stride: 1,
-                padding: 1,
-                bias: false,
-                ..Default::default()
-            },
-        ))
-        .add(nn::batch_norm2d(
-            path / "batch_norm",
-            FILTERS,
-            nn::BatchNormConfig::default(),
-        ))
-        .add_fn(Tensor::relu);
-    for n in 0..CORE_RES_BLOCKS {
-        core = core.add(ResidualBlock::new(
-            &(path / format!("res_block_{n}")),
-            FILTERS,
-            FILTERS,
-        ));
-    }
-    core
-}
-
-fn policy_net(path: &nn::Path) -> nn::SequentialT {
-    nn::seq_t().add(nn::conv2d(
-        path / "conv2d",
-        FILTERS,
-        output_channels::<N>() as i64,
-        3,
-        nn::ConvConfig {
-            stride: 1,
-            padding: 1,
-            ..Default::default()
-        },
-    ))
-}
-
-fn value_net(path: &nn::Path) -> nn::SequentialT {
-    nn::seq_t()
-        .add(nn::conv2d(path / "conv2d", FILTERS, 1, 1, nn::ConvConfig {
-            stride: 1,
-            ..Default::default()
-        }))
-        .add_fn(Tensor::relu)
-        .add_fn(|x| x.view([-1, (N * N) as i64]))
-        .add(nn::linear(
-            path / "linear",
-            (N * N) as i64,
-            1,
-            nn::LinearConfig::default(),
-        ))
-        .add_fn(Tensor::tanh)
-}
-
-fn ube_net(path: &nn::Path) -> nn::SequentialT {
-    nn::seq_t()
-        .add(nn::conv2d(path / "conv2d", FILTERS, 1, 1, nn::ConvConfig {
-            stride: 1,
-            ..Default::default()
-        }))
-        .add_fn(Tensor::relu)
-        .add_fn(|x| x.view([-1, (N * N) as i64]))
-        .add(nn::linear(
-            path / "linear",
-            (N * N) as i64,
-            1,
-            nn::LinearConfig::default(),
-        ))
-}
-
-fn rnd(path: &nn::Path) -> nn::SequentialT {
-    const HIDDEN_LAYER: i64 = 1024;
-    const OUTPUT: i64 = 512;
-    nn::seq_t()
-        .add_fn(|x| x.view([-1, input_size::<N>() as i64]))
-        .add_fn(|x| x / x.square().sum_dim_intlist(1, true, None))
-        .add(nn::linear(
-            path / "input_linear",
-            input_size::<N>() as i64,
-            HIDDEN_LAYER,
-            nn::LinearConfig::default(),
-        ))
-        .add_fn(Tensor::relu)
-        .add(nn::linear(
-            path / "hidden_linear",
-            HIDDEN_LAYER,
-            HIDDEN_LAYER,
-            nn::LinearConfig::default(),
-        ))
-        .add_fn(Tensor::relu)
-        .add(nn::linear(
-            path / "final_linear",
-            HIDDEN_LAYER,
-            OUTPUT,
-            nn::LinearConfig::default(),
-        ))
-}
-
-impl Network for Net {
-    fn new(device: Device, seed: Option<i64>) -> Self {
-        if let Some(seed) = seed {
-            tch::manual_seed(seed);
-        }
-
-        let vs = nn::VarStore::new(device);
-        let root = vs.root();
-        Self {
-            core: core(&(&root / "core")),
-            policy_net: policy_net(&(&root / "policy")),
-            value_net: value_net(&(&root / "value")),
-            ube_net: ube_net(&(&root / "ube")),
-            rnd: Rnd {
-                learning: rnd(&(&root / "rnd_learning")),
-                target: rnd(&(&root / "rnd_target")),
-                min: root.var("min", &[1], nn::Init::Const(0.0)),
-                // TODO: Think about a good default
-                max: root.var("max", &[1], nn::Init::Const(1.0)),
-            },
-            vs,
-        }
-    }
-
-    fn vs(&self) -> &nn::VarStore {
-        &self.vs
-    }
-
-    fn vs_mut(&mut self) -> &mut nn::VarStore {
-        &mut self.vs
-    }
-}
-
-impl RndNetwork for Net {
-    fn forward_t(&self, xs: &Tensor, train: bool) -> (Tensor, Tensor, Tensor) {
-        let core = self.core.forward_t(xs, train);
-        let policy = self.policy_net.forward_t(&core, train);
-        let value = self.value_net.forward_t(&core, train);
-        // Detached UBE so it does not mess with baseline
-        let ube = self.ube_net.forward_t(&core.detach(), train);
-        (policy, value, ube)
-    }
-
-    fn forward_rnd(&self, xs: &Tensor, train: bool) -> Tensor {
-        let learning = self
-            .rnd
-            .learning
-            .forward_t(&xs.set_requires_grad(false), train);
-        let target = self
-            .rnd
-            .target
-            .forward_t(&xs.set_requires_grad(false), false)
-            .detach();
-        (learning - target).square().sum_dim_intlist(1, false, None)
-    }
-
-    fn normalized_rnd(&self, xs: &Tensor) -> Tensor {
-        let min = self.rnd.min.detach();
-        let max = self.rnd.max.detach();
-        let normalized = (self.forward_rnd(xs, false) - &min) / (max - min);
-        normalized.clamp(0.0, 1.0) * MAXIMUM_VARIANCE
-    }
-
-    fn update_rnd_normalization(&mut self, min: &Tensor, max: &Tensor) {
-        log::debug!("Updating RND normalization to min: {min:?} and max: {max:?}");
-        self.rnd.min.set_data(min);
-        self.rnd.max.set_data(max);
-    }
-}
-
-impl Agent<Env> for Net {
-    fn policy_value_uncertainty(
-        &self,
-        env_batch: &[Env],
-        actions_batch: &[Vec<<Env as crate::search::env::Environment>::Action>],
-    ) -> impl Iterator<Item = (Vec<(Move, NotNan<f32>)>, f32, f32)> {
-        assert_eq!(env_batch.len(), actions_batch.len());
-        assert!(!env_batch.is_empty());
-        let device = self.vs.device();
-
-        let xs = Tensor::cat(
-            &env_batch
-                .iter()
-                .map(|env| game_to_tensor(env, device))
-                .collect::<Vec<_>>(),
-            0,
-        );
-        let (policy, values, ube_uncertainties) = self.forward_t(&xs, false);
-        let policy = policy.view([-1, output_size::<N>() as i64]);
-        let max_actions = actions_batch.iter().map(Vec::len).max().unwrap_or_default();
-        let index = Tensor::from_slice2(
-            &actions_batch
-                .iter()
-                .map(|actions| {
-                    actions
-                        .iter()
-                        .map(|a| move_index::<N>(a) as i64)
-                        .chain(std::iter::repeat(0))
-                        .take(max_actions)
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<_>>(),
-        )
-        .to(device);
-
-        let indexed_policy = actions_batch
-            .iter()
-            .zip(
-                Vec::<Vec<_>>::try_from(policy.gather(1, &index, false))
-                    .expect("tensor should have two dimensions"),
-            )
-            .map(|(actions, p)| {
-                actions
-                    .iter()
-                    .zip(p)
-                    .map(|(a, p)| (*a, NotNan::new(p).expect("logit should not be NaN")))
-                    .collect()
-            });
-        let values: Vec<_> = values.view([-1]).try_into().unwrap();
-
-        // Uncertainty.
-        let rnd_uncertainties = self.normalized_rnd(&xs);
-        let uncertainties: Vec<_> = ube_uncertainties
-            .exp() // Exponent because UBE prediction is log(variance)
-            .maximum(&rnd_uncertainties)
-            .clamp(0.0, MAXIMUM_VARIANCE)
-            .view([-1])
-            .try_into()
-            .unwrap();
-
-        indexed_policy
-            .zip(values)
-            .zip(uncertainties)
-            .map(|((p, v), u)| (p, v, u))
-    }
-}
+pub type Net = super::generic::Net<N, HALF_KOMI>;
+pub type Net5 = Net;
+pub type Ensemble = super::ensemble::Ensemble<N, HALF_KOMI>;
 
 #[cfg(test)]
 mod tests {
-    use std::array;
+    use std::{
+        array,
+        time::{Duration, Instant},
+    };
 
     use fast_tak::Game;
-    use tch::Device;
-
-    use super::{Env, Net};
+    use rand::{rngs::StdRng, SeedableRng};
+    use tch::{Device, Tensor};
+
+    use super::{
+        ownership_loss,
+        ownership_target,
+        result_wdl_target,
+        score_loss,
+        score_target,
+        short_value_loss,
+        short_value_target,
+        wdl_expectation,
+        wdl_target,
+        Env,
+        Net,
+        NetConfig,
+        N,
+        RND_OUTPUT,
+    };
     use crate::{
-        network::{Network, RndNetwork},
-        search::{agent::Agent, env::Environment},
+        network::{
+            calibration::Calibration,
+            repr::games_to_tensor,
+            residual::NormKind,
+            set_deterministic,
+            temporary_path,
+            Network,
+            RndNetwork,
+        },
+        search::{agent::Agent, env::Environment, eval::Eval, node::Node},
     };
 
     #[test]
@@ -309,6 +75,25 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn search_on_cpu() {
+        const VISITS: u32 = 100;
+        const TIME_LIMIT: Duration = Duration::from_secs(30);
+        let start = Instant::now();
+        let net = Net::with_config(Device::Cpu, Some(864), NetConfig::cpu());
+        let mut root = Node::default();
+        for _ in 0..VISITS {
+            root.simulate_simple(&net, Game::default(), 0.0);
+        }
+        assert_eq!(root.visit_count(), VISITS);
+        assert!(root.best_action().is_some());
+        assert!(
+            start.elapsed() < TIME_LIMIT,
+            "{VISITS} visits took {:?}",
+            start.elapsed()
+        );
+    }
+
     #[test]
     fn evaluate_batch() {
         const BATCH_SIZE: usize = 128;
@@ -345,4 +130,416 @@ mod tests {
         assert!((f32::try_from(&net.rnd.min).unwrap() - NEW_MIN).abs() < f32::EPSILON);
         assert!((f32::try_from(&net.rnd.max).unwrap() - NEW_MAX).abs() < f32::EPSILON);
     }
+
+    #[test]
+    fn rnd_statistics_persistance() {
+        let path = std::env::temp_dir().join("takzero-rnd-statistics.ot");
+        let mut net = Net::new(Device::cuda_if_available(), Some(654));
+        assert!(net.rnd_statistics().count.abs() < f64::EPSILON);
+
+        let mut rng = StdRng::seed_from_u64(654);
+        let mut actions = Vec::new();
+        let games: Vec<Env> = (0..8)
+            .map(|_| Env::new_opening_with_random_steps(&mut rng, &mut actions, 6))
+            .collect();
+        let xs = games_to_tensor(&games, Device::cuda_if_available());
+        net.update_rnd_statistics(&xs);
+        net.update_rnd_statistics(&xs);
+        let statistics = net.rnd_statistics();
+        assert!((statistics.count - 16.0).abs() < f64::EPSILON);
+        assert!(statistics.mean > 0.0);
+
+        let errors = net.forward_rnd(&xs, false);
+        let mean = f64::try_from(errors.mean(tch::Kind::Float)).unwrap();
+        assert!((statistics.mean - mean).abs() < 1e-4);
+
+        net.save(&path).unwrap();
+        let loaded = Net::load(&path, Device::cuda_if_available()).unwrap();
+        assert_eq!(loaded.rnd_statistics(), statistics);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rnd_embeddings_give_the_rnd_error() {
+        let device = Device::Cpu;
+        let net = Net::with_config(device, Some(246), NetConfig::cpu());
+        let mut rng = StdRng::seed_from_u64(246);
+        let mut actions = Vec::new();
+        let games: Vec<Env> = (0..8)
+            .map(|_| Env::new_opening_with_random_steps(&mut rng, &mut actions, 6))
+            .collect();
+        let xs = games_to_tensor(&games, device);
+
+        let (learning, target) = net.rnd_embeddings(&xs);
+        assert_eq!(learning.size(), [8, RND_OUTPUT]);
+        assert_eq!(target.size(), [8, RND_OUTPUT]);
+        assert!(!target.requires_grad());
+        let errors = (learning - target).square().sum_dim_intlist(1, false, None);
+        assert!(errors.allclose(&net.forward_rnd(&xs, false), 1e-5, 1e-5, false));
+    }
+
+    #[test]
+    fn calibration_is_saved_with_the_model() {
+        let device = Device::Cpu;
+        let mut net = Net::with_config(device, Some(258), NetConfig::cpu());
+        assert!(net.calibration().is_none());
+        assert!((net.win_probability(0.5) - 0.75).abs() < f32::EPSILON);
+
+        let calibration = Calibration {
+            scale: 2.0,
+            bias: 0.25,
+        };
+        net.set_calibration(calibration);
+        let loaded = Net::load_from_bytes(&net.save_to_bytes().unwrap(), device).unwrap();
+        assert_eq!(loaded.calibration(), Some(calibration));
+        assert!(
+            (loaded.win_probability(0.5) - calibration.win_probability(0.5)).abs() < f32::EPSILON
+        );
+    }
+
+    #[test]
+    fn parameter_count_is_stable() {
+        let first = Net::new(Device::Cpu, Some(111));
+        let second = Net::new(Device::Cpu, Some(222));
+        assert!(first.num_parameters() > 0);
+        assert_eq!(first.num_parameters(), second.num_parameters());
+
+        let summary = first.summary();
+        for group in ["core:", "policy:", "value:", "ube:", "rnd:"] {
+            assert!(summary.contains(group), "{summary}");
+        }
+        assert!(summary.ends_with(&format!("total: {} parameters", first.num_parameters())));
+
+        // The saved config is a variable, but not a parameter.
+        let all: usize = first.vs().variables().values().map(Tensor::numel).sum();
+        assert!(first.num_parameters() < all);
+        assert!(
+            summary.contains("  config [10] 10 (not trained)"),
+            "{summary}"
+        );
+    }
+
+    #[test]
+    fn deterministic_forward_on_cuda() {
+        const CHILD: &str = "TAKZERO_DETERMINISTIC_TEST";
+        if !tch::Cuda::is_available() {
+            return;
+        }
+        // The setting is global to the process, so it is only changed in a
+        // process of its own which runs just this test, instead of under the
+        // feet of the other tests which run in parallel.
+        if std::env::var_os(CHILD).is_none() {
+            let status = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "network::net5::tests::deterministic_forward_on_cuda",
+                    "--test-threads=1",
+                ])
+                .env(CHILD, "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+        set_deterministic(true);
+        let device = Device::Cuda(0);
+        let net = Net::new(device, Some(987));
+        let mut rng = StdRng::seed_from_u64(987);
+        let mut actions = Vec::new();
+        let games: Vec<Env> = (0..64)
+            .map(|_| Env::new_opening_with_random_steps(&mut rng, &mut actions, 10))
+            .collect();
+        let xs = games_to_tensor(&games, device);
+
+        let (policy_a, value_a, ube_a) = net.forward_t(&xs, false);
+        let (policy_b, value_b, ube_b) = net.forward_t(&xs, false);
+        assert!(policy_a.equal(&policy_b));
+        assert!(value_a.equal(&value_b));
+        assert!(ube_a.equal(&ube_b));
+    }
+
+    #[test]
+    fn interrupted_save_keeps_previous_model() {
+        let path = std::env::temp_dir().join("takzero-interrupted-save.ot");
+        let net = Net::new(Device::cuda_if_available(), Some(789));
+        net.save(&path).unwrap();
+        assert!(!temporary_path(&path).exists());
+
+        // Simulate a save which was killed halfway through writing.
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(temporary_path(&path), &bytes[..bytes.len() / 2]).unwrap();
+
+        assert!(Net::load(&path, Device::cuda_if_available()).is_ok());
+        std::fs::remove_file(temporary_path(&path)).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wdl_target_expectation() {
+        let values = Tensor::from_slice(&[-1.0f32, -0.5, 0.0, 0.25, 1.0]).unsqueeze(1);
+        let target = wdl_target(&values);
+        let sums: Vec<f32> = target.sum_dim_intlist(1, false, None).try_into().unwrap();
+        assert!(sums.iter().all(|s| (s - 1.0).abs() < 1e-6));
+        // Logits which reproduce the target distribution exactly.
+        let expectation = wdl_expectation(&target.clamp_min(1e-12).log());
+        let difference = f32::try_from((expectation - values).abs().max()).unwrap();
+        assert!(difference < 1e-5);
+    }
+
+    #[test]
+    fn results_are_one_hot_wdl_targets() {
+        let values = Tensor::from_slice(&[0.2f32, 0.5, -0.1]).unsqueeze(1);
+        let target = result_wdl_target(&[Some(Eval::Win(3)), None, Some(Eval::Draw(8))], &values);
+        let expected =
+            Tensor::from_slice(&[0.0f32, 0.0, 1.0, 0.0, 0.5, 0.5, 0.0, 1.0, 0.0]).view([3, 3]);
+        assert!(target.allclose(&expected, 1e-6, 1e-6, false), "{target}");
+    }
+
+    #[test]
+    fn evaluate_with_wdl() {
+        let net = Net::new_with_wdl(Device::cuda_if_available(), Some(321));
+        let game: Env = Game::default();
+        let mut moves = Vec::new();
+        game.possible_moves(&mut moves);
+        let (_policy, value, _uncertainty) = net
+            .policy_value_uncertainty(&[game], &[moves])
+            .next()
+            .unwrap();
+        assert!((-1.0..=1.0).contains(&value));
+    }
+
+    #[test]
+    fn config_is_saved_with_model() {
+        let path = std::env::temp_dir().join("takzero-config.ot");
+        let config = NetConfig {
+            filters: 32,
+            core_res_blocks: 2,
+            linear_size: 64,
+            wdl_head: true,
+            se_ratio: Some(4),
+            score_head: true,
+            ownership_head: true,
+            dropout: 0.25,
+            norm: NormKind::GroupNorm,
+            short_value_head: true,
+        };
+        let net = Net::with_config(Device::cuda_if_available(), Some(654), config);
+        net.save(&path).unwrap();
+        drop(net);
+
+        let net = Net::load(&path, Device::cuda_if_available()).unwrap();
+        assert_eq!(net.config(), config);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dropout_is_only_active_in_training() {
+        let device = Device::Cpu;
+        let net = Net::with_config(device, Some(975), NetConfig {
+            dropout: 0.5,
+            ..NetConfig::cpu()
+        });
+        let mut rng = StdRng::seed_from_u64(975);
+        let mut actions = Vec::new();
+        let games: Vec<Env> = (0..8)
+            .map(|_| Env::new_opening_with_random_steps(&mut rng, &mut actions, 10))
+            .collect();
+        let xs = games_to_tensor(&games, device);
+
+        let (policy_a, value_a, _) = net.forward_t(&xs, true);
+        let (policy_b, value_b, _) = net.forward_t(&xs, true);
+        assert!(!policy_a.equal(&policy_b));
+        assert!(!value_a.equal(&value_b));
+
+        let (policy_a, value_a, _) = net.forward_t(&xs, false);
+        let (policy_b, value_b, _) = net.forward_t(&xs, false);
+        assert!(policy_a.equal(&policy_b));
+        assert!(value_a.equal(&value_b));
+    }
+
+    #[test]
+    fn group_norm_is_the_same_in_training() {
+        let device = Device::Cpu;
+        let net = Net::with_config(device, Some(531), NetConfig {
+            norm: NormKind::GroupNorm,
+            ..NetConfig::cpu()
+        });
+        let mut rng = StdRng::seed_from_u64(531);
+        let mut actions = Vec::new();
+        let games: Vec<Env> = (0..8)
+            .map(|_| Env::new_opening_with_random_steps(&mut rng, &mut actions, 10))
+            .collect();
+        let xs = games_to_tensor(&games, device);
+
+        // Without running statistics a single position is evaluated
+        // the same as in a batch, and training matches inference.
+        let (policy, value, _) = net.forward_t(&xs, false);
+        let (single_policy, single_value, _) = net.forward_t(&xs.narrow(0, 0, 1), false);
+        assert!(policy
+            .narrow(0, 0, 1)
+            .allclose(&single_policy, 1e-5, 1e-5, false));
+        assert!(value
+            .narrow(0, 0, 1)
+            .allclose(&single_value, 1e-5, 1e-5, false));
+        let (train_policy, train_value, _) = net.forward_t(&xs, true);
+        assert!(policy.allclose(&train_policy, 1e-5, 1e-5, false));
+        assert!(value.allclose(&train_value, 1e-5, 1e-5, false));
+    }
+
+    #[test]
+    fn short_value_head_is_optional() {
+        let device = Device::Cpu;
+        let games = [Env::default(), Env::default()];
+        let xs = games_to_tensor(&games, device);
+        let net = Net::with_config(device, Some(468), NetConfig::cpu());
+        assert!(net.forward_short_value_t(&xs, false).is_none());
+        assert!(net.short_horizon_values(&games).is_none());
+
+        let net = Net::with_config(device, Some(468), NetConfig {
+            short_value_head: true,
+            ..NetConfig::cpu()
+        });
+        let short_value = net.forward_short_value_t(&xs, true).unwrap();
+        assert_eq!(short_value.size(), [2, 1]);
+        let values = net.short_horizon_values(&games).unwrap();
+        assert_eq!(values.len(), 2);
+        assert!(values.iter().all(|value| (-1.0..=1.0).contains(value)));
+        let (target, has_target) = short_value_target(&[Some(Eval::Win(4)), None], 0.9, device);
+        let weights = Tensor::ones_like(&has_target);
+        let loss = short_value_loss(&short_value, &target, &has_target, &weights);
+        assert!(f64::try_from(loss).unwrap().is_finite());
+    }
+
+    #[test]
+    fn short_value_target_uses_the_short_discount() {
+        const SHORT_DISCOUNT: f32 = 0.9;
+        let plies = 10;
+        let (target, has_target) = short_value_target(
+            &[
+                Some(Eval::Win(plies)),
+                Some(Eval::Loss(plies)),
+                Some(Eval::Draw(plies)),
+                None,
+            ],
+            SHORT_DISCOUNT,
+            Device::Cpu,
+        );
+        let short = SHORT_DISCOUNT.powi(plies as i32);
+        let expected = Tensor::from_slice(&[short, -short, 0.0, 0.0]).unsqueeze(1);
+        assert!(target.allclose(&expected, 1e-6, 1e-6, false), "{target}");
+        let expected = Tensor::from_slice(&[1.0f32, 1.0, 1.0, 0.0]).unsqueeze(1);
+        assert!(has_target.allclose(&expected, 0.0, 0.0, false));
+    }
+
+    #[test]
+    fn score_head_is_optional() {
+        let device = Device::cuda_if_available();
+        let game: Env = Game::default();
+        let xs = games_to_tensor(&[game.clone(), game], device);
+        assert!(Net::new(device, Some(135))
+            .forward_score_t(&xs, false)
+            .is_none());
+
+        let net = Net::with_config(device, Some(135), NetConfig {
+            score_head: true,
+            ..NetConfig::default()
+        });
+        let score = net.forward_score_t(&xs, true).unwrap();
+        assert_eq!(score.size(), [2, 1]);
+
+        let (target, has_score) = score_target(&[Some(0.5), None], device);
+        let loss = score_loss(&score, &target, &has_score, &Tensor::ones_like(&has_score));
+        let expected = (score.get(0) - 0.5).square().sum(tch::Kind::Float);
+        assert!(loss.allclose(&expected, 1e-6, 1e-6, false));
+    }
+
+    #[test]
+    fn ownership_head_is_optional() {
+        let device = Device::cuda_if_available();
+        let game: Env = Game::default();
+        let xs = games_to_tensor(&[game.clone(), game], device);
+        assert!(Net::new(device, Some(246))
+            .forward_ownership_t(&xs, false)
+            .is_none());
+
+        let net = Net::with_config(device, Some(246), NetConfig {
+            ownership_head: true,
+            ..NetConfig::default()
+        });
+        let ownership = net.forward_ownership_t(&xs, true).unwrap();
+        assert_eq!(ownership.size(), [2, (N * N) as i64]);
+
+        let owned = [1; N * N];
+        let (target, has_ownership) = ownership_target::<N>(&[Some(&owned[..]), None], device);
+        let weights = Tensor::ones_like(&has_ownership);
+        let loss = ownership_loss(&ownership, &target, &has_ownership, &weights);
+        let expected = (ownership.get(0) - 1.0).square().mean(tch::Kind::Float);
+        assert!(loss.allclose(&expected, 1e-6, 1e-6, false));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "network input should have shape")]
+    fn input_of_another_board_size_is_rejected() {
+        let net = Net::new(Device::Cpu, Some(975));
+        let game: Game<6, 4> = Game::default();
+        let _ = net.forward_t(&games_to_tensor(&[game], Device::Cpu), false);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let device = Device::cuda_if_available();
+        let net = Net::new(device, Some(147));
+        let bytes = net.save_to_bytes().unwrap();
+        let loaded = Net::load_from_bytes(&bytes, device).unwrap();
+
+        let game: Env = Game::default();
+        let xs = crate::network::repr::game_to_tensor(&game, device);
+        let (policy, value, ube) = net.forward_t(&xs, false);
+        let (loaded_policy, loaded_value, loaded_ube) = loaded.forward_t(&xs, false);
+        assert!(policy.equal(&loaded_policy));
+        assert!(value.equal(&loaded_value));
+        assert!(ube.equal(&loaded_ube));
+    }
+
+    #[test]
+    fn load_partial_from_smaller_network() {
+        let path = std::env::temp_dir().join("takzero-partial.ot");
+        let device = Device::cuda_if_available();
+        let small = Net::with_config(device, Some(258), NetConfig {
+            core_res_blocks: 2,
+            linear_size: 64,
+            ..NetConfig::default()
+        });
+        small.save(&path).unwrap();
+
+        let net = Net::load_partial(&path, device).unwrap();
+        assert_eq!(net.config(), NetConfig::default());
+        let small_variables = small.vs().variables();
+        let variables = net.vs().variables();
+        let name = "core.input_conv2d.weight";
+        assert!(variables[name].equal(&small_variables[name]));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_partial_into_larger_config() {
+        let path = std::env::temp_dir().join("takzero-partial-config.ot");
+        let device = Device::cuda_if_available();
+        let net = Net::new(device, Some(259));
+        net.save(&path).unwrap();
+
+        let config = NetConfig {
+            core_res_blocks: NetConfig::default().core_res_blocks + 1,
+            ..NetConfig::default()
+        };
+        let larger = Net::load_partial_with_config(&path, device, config).unwrap();
+        assert_eq!(larger.config(), config);
+        let variables = net.vs().variables();
+        let larger_variables = larger.vs().variables();
+        assert!(larger_variables.len() > variables.len());
+        let name = "core.input_conv2d.weight";
+        assert!(larger_variables[name].equal(&variables[name]));
+        std::fs::remove_file(path).unwrap();
+    }
 }
@@ -10,10 +10,12 @@ use tch::{
 };
 
 use super::{
-    repr::{game_to_tensor, input_channels, move_index, output_channels},
+    repr::{games_to_tensor, input_channels, move_index, output_channels},
     residual::ResidualBlock,
     HashNetwork,
     Network,
+    TrainingNetwork,
+    TrainingOutputs,
 };
 use crate::{
     network::repr::{input_size, output_size},
@@ -256,6 +258,17 @@ impl HashNetwork<Env> for Net {
     }
 }
 
+impl TrainingNetwork for Net {
+    fn forward_training_t(&self, xs: &Tensor, train: bool) -> TrainingOutputs {
+        let (policy, value, ube) = HashNetwork::forward_t(self, xs, train);
+        TrainingOutputs::new(policy, value, ube)
+    }
+
+    fn observe_batch(&mut self, xs: &Tensor) {
+        self.update_counts(xs);
+    }
+}
+
 impl Agent<Env> for Net {
     fn policy_value_uncertainty(
         &self,
@@ -266,13 +279,7 @@ impl Agent<Env> for Net {
         assert!(!env_batch.is_empty());
         let device = self.vs.device();
 
-        let xs = Tensor::cat(
-            &env_batch
-                .iter()
-                .map(|env| game_to_tensor(env, device))
-                .collect::<Vec<_>>(),
-            0,
-        );
+        let xs = games_to_tensor(env_batch, device);
         let (policy, values, ube_uncertainties) = self.forward_t(&xs, false);
         let policy = policy.view([-1, output_size::<N>() as i64]);
         let max_actions = actions_batch.iter().map(Vec::len).max().unwrap_or_default();
@@ -0,0 +1,76 @@
+use tch::{nn, Kind, Tensor};
+
+/// Name of the path under which the statistics are stored.
+pub(super) const PATH: &str = "rnd_normalizer";
+
+/// Running statistics of the raw RND error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RndStatistics {
+    pub mean: f64,
+    pub variance: f64,
+    /// Number of errors the statistics were computed from.
+    pub count: f64,
+}
+
+/// Running mean and variance which are stored in the `VarStore`,
+/// so that they are saved and loaded together with the model.
+#[derive(Debug)]
+pub(super) struct RunningNormalizer {
+    mean: Tensor,
+    variance: Tensor,
+    count: Tensor,
+}
+
+impl RunningNormalizer {
+    pub(super) fn new(path: &nn::Path) -> Self {
+        Self {
+            mean: path.zeros_no_train("mean", &[1]),
+            variance: path.ones_no_train("variance", &[1]),
+            count: path.zeros_no_train("count", &[1]),
+        }
+    }
+
+    /// Combine the statistics with those of a new batch of errors.
+    pub(super) fn update(&mut self, errors: &Tensor) {
+        tch::no_grad(|| {
+            let errors = errors.detach().to_kind(Kind::Float).view([-1]);
+            let batch_count = errors.size()[0] as f64;
+            if batch_count == 0.0 {
+                return;
+            }
+            let batch_mean = errors.mean(Kind::Float);
+            let batch_variance = errors.var(false);
+
+            let total = &self.count + batch_count;
+            let delta = &batch_mean - &self.mean;
+            let mean = &self.mean + &delta * batch_count / &total;
+            let sum_of_squares = &self.variance * &self.count
+                + batch_variance * batch_count
+                + delta.square() * &self.count * batch_count / &total;
+            let variance = sum_of_squares / &total;
+
+            self.mean.set_data(&mean);
+            self.variance.set_data(&variance);
+            self.count.set_data(&total);
+        });
+    }
+
+    /// Errors as the number of standard deviations above the mean,
+    /// or `None` if no statistics were collected yet.
+    pub(super) fn normalize(&self, errors: &Tensor) -> Option<Tensor> {
+        if self.statistics().count == 0.0 {
+            return None;
+        }
+        let std_dev = (self.variance.detach() + 1e-8).sqrt();
+        Some((errors - self.mean.detach()) / std_dev)
+    }
+
+    pub(super) fn statistics(&self) -> RndStatistics {
+        let scalar = |t: &Tensor| f64::try_from(t).expect("statistics should be scalars");
+        RndStatistics {
+            mean: scalar(&self.mean),
+            variance: scalar(&self.variance),
+            count: scalar(&self.count),
+        }
+    }
+}
@@ -0,0 +1,768 @@
+//! Writer of ONNX models, see [`super::generic::Net::export_onnx`].
+//!
+//! ONNX models are protocol buffers, which are simple enough to write by
+//! hand. Only the messages and fields which the exported networks need are
+//! written, with the field numbers of
+//! <https://github.com/onnx/onnx/blob/main/onnx/onnx.proto>.
+
+use std::collections::HashMap;
+
+use tch::{nn, Device, Kind, TchError, Tensor};
+
+use super::residual::NormKind;
+
+const IR_VERSION: i64 = 7;
+const OPSET_VERSION: i64 = 13;
+/// Epsilon of the normalization layers, which is the default of `tch`.
+const EPSILON: f32 = 1e-5;
+/// Name of the dynamic batch dimension of the inputs and outputs.
+const BATCH: &str = "batch";
+
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+
+/// Protocol buffer message which is written field by field.
+#[derive(Debug, Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.varint(u64::from((field << 3) | wire_type));
+    }
+
+    fn int(&mut self, field: u32, value: i64) {
+        self.key(field, 0);
+        self.varint(u64::from_le_bytes(value.to_le_bytes()));
+    }
+
+    fn float(&mut self, field: u32, value: f32) {
+        self.key(field, 5);
+        self.0.extend(value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, field: u32, bytes: &[u8]) {
+        self.key(field, 2);
+        self.varint(bytes.len() as u64);
+        self.0.extend(bytes);
+    }
+
+    fn string(&mut self, field: u32, string: &str) {
+        self.bytes(field, string.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, message: &Self) {
+        self.bytes(field, &message.0);
+    }
+}
+
+/// Attribute of a node.
+#[derive(Debug)]
+enum Attribute {
+    Float(&'static str, f32),
+    Int(&'static str, i64),
+    Ints(&'static str, Vec<i64>),
+}
+
+impl Attribute {
+    fn to_message(&self) -> Message {
+        let mut message = Message::default();
+        match self {
+            Self::Float(name, value) => {
+                message.string(1, name);
+                message.float(2, *value);
+                message.int(20, 1);
+            }
+            Self::Int(name, value) => {
+                message.string(1, name);
+                message.int(3, *value);
+                message.int(20, 2);
+            }
+            Self::Ints(name, values) => {
+                message.string(1, name);
+                for value in values {
+                    message.int(8, *value);
+                }
+                message.int(20, 7);
+            }
+        }
+        message
+    }
+}
+
+/// Variables of a network by name.
+pub(super) struct Variables(HashMap<String, Tensor>);
+
+impl Variables {
+    pub(super) fn new(vs: &nn::VarStore) -> Self {
+        Self(vs.variables())
+    }
+
+    /// `tch` gives a variable whose name is already taken the suffix
+    /// `__{count}`, so the second layer with the same name under a path is
+    /// `repeated`.
+    fn get(&self, name: &str, repeated: bool) -> Result<&Tensor, TchError> {
+        let not_found = || TchError::TensorNameNotFound(name.to_string(), "the network".into());
+        if repeated {
+            let prefix = format!("{name}__");
+            let mut matches = self
+                .0
+                .iter()
+                .filter(|(other, _)| other.starts_with(&prefix));
+            match (matches.next(), matches.next()) {
+                (Some((_, tensor)), None) => Ok(tensor),
+                _ => Err(not_found()),
+            }
+        } else {
+            self.0.get(name).ok_or_else(not_found)
+        }
+    }
+}
+
+/// Graph of an ONNX model which is built node by node.
+/// Values are referred to by their names.
+#[derive(Debug, Default)]
+pub(super) struct Graph {
+    nodes: Vec<Message>,
+    initializers: Vec<Message>,
+    inputs: Vec<Message>,
+    outputs: Vec<Message>,
+}
+
+impl Graph {
+    /// Add a float input with the dynamic batch dimension in front of `dims`.
+    pub(super) fn input(&mut self, name: &str, dims: &[i64]) -> String {
+        self.inputs.push(value_info(name, dims));
+        name.to_string()
+    }
+
+    /// Name the value as an output with the dynamic batch dimension in front
+    /// of `dims`.
+    pub(super) fn output(&mut self, name: &str, value: &str, dims: &[i64]) {
+        let mut node = Message::default();
+        node.string(1, value);
+        node.string(2, name);
+        node.string(3, name);
+        node.string(4, "Identity");
+        self.nodes.push(node);
+        self.outputs.push(value_info(name, dims));
+    }
+
+    /// Add a node with one output and return the name of the output.
+    fn node(&mut self, op_type: &str, inputs: &[&str], attributes: &[Attribute]) -> String {
+        let output = format!("{op_type}_{}", self.nodes.len());
+        let mut node = Message::default();
+        for input in inputs {
+            node.string(1, input);
+        }
+        node.string(2, &output);
+        node.string(3, &output);
+        node.string(4, op_type);
+        for attribute in attributes {
+            node.message(5, &attribute.to_message());
+        }
+        self.nodes.push(node);
+        output
+    }
+
+    /// Add a float tensor which is stored in the model.
+    fn initializer(&mut self, name: &str, tensor: &Tensor) -> Result<String, TchError> {
+        let values = Vec::<f32>::try_from(
+            tensor
+                .detach()
+                .to_device(Device::Cpu)
+                .to_kind(Kind::Float)
+                .flatten(0, -1),
+        )?;
+        let raw: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.add_initializer(name, &tensor.size(), FLOAT, &raw);
+        Ok(name.to_string())
+    }
+
+    /// Add a constant shape, for example for `Reshape`.
+    fn shape(&mut self, dims: &[i64]) -> String {
+        let name = format!("shape_{}", self.initializers.len());
+        let raw: Vec<u8> = dims.iter().flat_map(|d| d.to_le_bytes()).collect();
+        self.add_initializer(&name, &[dims.len() as i64], INT64, &raw);
+        name
+    }
+
+    fn add_initializer(&mut self, name: &str, dims: &[i64], data_type: i64, raw: &[u8]) {
+        let mut tensor = Message::default();
+        for dim in dims {
+            tensor.int(1, *dim);
+        }
+        tensor.int(2, data_type);
+        tensor.string(8, name);
+        tensor.bytes(9, raw);
+        self.initializers.push(tensor);
+    }
+
+    /// Serialize the graph as a model.
+    pub(super) fn to_model(&self) -> Vec<u8> {
+        let mut graph = Message::default();
+        for node in &self.nodes {
+            graph.message(1, node);
+        }
+        graph.string(2, "takzero");
+        for initializer in &self.initializers {
+            graph.message(5, initializer);
+        }
+        for input in &self.inputs {
+            graph.message(11, input);
+        }
+        for output in &self.outputs {
+            graph.message(12, output);
+        }
+
+        let mut opset = Message::default();
+        opset.int(2, OPSET_VERSION);
+        let mut model = Message::default();
+        model.int(1, IR_VERSION);
+        model.string(2, "takzero");
+        model.message(7, &graph);
+        model.message(8, &opset);
+        model.0
+    }
+
+    /// Convolution with the weight and optional bias under `path`,
+    /// padded to keep the size of the board.
+    pub(super) fn conv2d(
+        &mut self,
+        variables: &Variables,
+        path: &str,
+        xs: &str,
+        repeated: bool,
+    ) -> Result<String, TchError> {
+        let weight = variables.get(&format!("{path}.weight"), repeated)?;
+        let kernel = weight.size()[2];
+        let padding = kernel / 2;
+        let mut inputs = vec![self.initializer(&unique_name(path, "weight", repeated), weight)?];
+        if let Ok(bias) = variables.get(&format!("{path}.bias"), repeated) {
+            inputs.push(self.initializer(&unique_name(path, "bias", repeated), bias)?);
+        }
+        let inputs: Vec<_> = std::iter::once(xs)
+            .chain(inputs.iter().map(String::as_str))
+            .collect();
+        Ok(self.node("Conv", &inputs, &[
+            Attribute::Ints("kernel_shape", vec![kernel, kernel]),
+            Attribute::Ints("pads", vec![padding; 4]),
+            Attribute::Ints("strides", vec![1, 1]),
+        ]))
+    }
+
+    /// Fully connected layer with the weight and bias under `path`.
+    pub(super) fn linear(
+        &mut self,
+        variables: &Variables,
+        path: &str,
+        xs: &str,
+    ) -> Result<String, TchError> {
+        let weight = self.initializer(
+            &format!("{path}.weight"),
+            variables.get(&format!("{path}.weight"), false)?,
+        )?;
+        let bias = self.initializer(
+            &format!("{path}.bias"),
+            variables.get(&format!("{path}.bias"), false)?,
+        )?;
+        Ok(self.node("Gemm", &[xs, &weight, &bias], &[Attribute::Int(
+            "transB", 1,
+        )]))
+    }
+
+    /// Normalization layer of [`NormKind::layer`] under `path`.
+    pub(super) fn norm(
+        &mut self,
+        variables: &Variables,
+        path: &str,
+        norm: NormKind,
+        xs: &str,
+        repeated: bool,
+    ) -> Result<String, TchError> {
+        let get = |name: &str| variables.get(&format!("{path}.{name}"), repeated);
+        let name = |name: &str| unique_name(path, name, repeated);
+        match norm {
+            NormKind::BatchNorm => {
+                let inputs = [
+                    self.initializer(&name("batch_norm.weight"), get("batch_norm.weight")?)?,
+                    self.initializer(&name("batch_norm.bias"), get("batch_norm.bias")?)?,
+                    self.initializer(
+                        &name("batch_norm.running_mean"),
+                        get("batch_norm.running_mean")?,
+                    )?,
+                    self.initializer(
+                        &name("batch_norm.running_var"),
+                        get("batch_norm.running_var")?,
+                    )?,
+                ];
+                Ok(self.node(
+                    "BatchNormalization",
+                    &[xs, &inputs[0], &inputs[1], &inputs[2], &inputs[3]],
+                    &[Attribute::Float("epsilon", EPSILON)],
+                ))
+            }
+            // ONNX only has group normalization from opset 18 on, so it is
+            // written as instance normalization of the groups instead.
+            NormKind::GroupNorm => {
+                let weight = get("group_norm.weight")?;
+                let bias = get("group_norm.bias")?;
+                let channels = weight.size()[0];
+                let groups = NormKind::groups(channels);
+                let weight =
+                    self.initializer(&name("group_norm.weight"), &weight.view([-1, 1, 1]))?;
+                let bias = self.initializer(&name("group_norm.bias"), &bias.view([-1, 1, 1]))?;
+                let ones = self.initializer(
+                    &name("group_norm.ones"),
+                    &Tensor::ones([groups], (Kind::Float, Device::Cpu)),
+                )?;
+                let zeros = self.initializer(
+                    &name("group_norm.zeros"),
+                    &Tensor::zeros([groups], (Kind::Float, Device::Cpu)),
+                )?;
+                let group_shape = self.shape(&[0, groups, -1]);
+                let grouped = self.node("Reshape", &[xs, &group_shape], &[]);
+                let normalized = self.node("InstanceNormalization", &[&grouped, &ones, &zeros], &[
+                    Attribute::Float("epsilon", EPSILON),
+                ]);
+                let shape = self.node("Shape", &[xs], &[]);
+                let ungrouped = self.node("Reshape", &[&normalized, &shape], &[]);
+                let scaled = self.node("Mul", &[&ungrouped, &weight], &[]);
+                Ok(self.node("Add", &[&scaled, &bias], &[]))
+            }
+        }
+    }
+
+    /// Residual block of [`super::residual::ResidualBlock`] under `path`.
+    pub(super) fn residual_block(
+        &mut self,
+        variables: &Variables,
+        path: &str,
+        norm: NormKind,
+        squeeze_excitation: bool,
+        xs: &str,
+    ) -> Result<String, TchError> {
+        let conv = format!("{path}.conv2d");
+        let out = self.conv2d(variables, &conv, xs, false)?;
+        let out = self.norm(variables, path, norm, &out, false)?;
+        let out = self.node("Relu", &[&out], &[]);
+        let out = self.conv2d(variables, &conv, &out, true)?;
+        let out = self.norm(variables, path, norm, &out, true)?;
+        let out = if squeeze_excitation {
+            let pooled = self.node("GlobalAveragePool", &[&out], &[]);
+            let pooled = self.node("Flatten", &[&pooled], &[Attribute::Int("axis", 1)]);
+            let hidden = self.linear(variables, &format!("{path}.se_squeeze"), &pooled)?;
+            let hidden = self.node("Relu", &[&hidden], &[]);
+            let gate = self.linear(variables, &format!("{path}.se_excite"), &hidden)?;
+            let gate = self.node("Sigmoid", &[&gate], &[]);
+            let gate_shape = self.shape(&[0, -1, 1, 1]);
+            let gate = self.node("Reshape", &[&gate, &gate_shape], &[]);
+            self.node("Mul", &[&out, &gate], &[])
+        } else {
+            out
+        };
+        let out = self.node("Add", &[&out, xs], &[]);
+        Ok(self.node("Relu", &[&out], &[]))
+    }
+
+    /// Flatten the board of each position in the batch, `[batch, N * N]`.
+    pub(super) fn flatten(&mut self, xs: &str) -> String {
+        self.node("Flatten", &[xs], &[Attribute::Int("axis", 1)])
+    }
+
+    pub(super) fn relu(&mut self, xs: &str) -> String {
+        self.node("Relu", &[xs], &[])
+    }
+
+    pub(super) fn tanh(&mut self, xs: &str) -> String {
+        self.node("Tanh", &[xs], &[])
+    }
+}
+
+/// Name of the initializer of a variable. Initializers need unique names,
+/// even if the variables are repeated.
+fn unique_name(path: &str, name: &str, repeated: bool) -> String {
+    if repeated {
+        format!("{path}.repeated.{name}")
+    } else {
+        format!("{path}.{name}")
+    }
+}
+
+/// Float tensor type with the dynamic batch dimension in front of `dims`.
+fn value_info(name: &str, dims: &[i64]) -> Message {
+    let mut shape = Message::default();
+    let mut batch = Message::default();
+    batch.string(2, BATCH);
+    shape.message(1, &batch);
+    for dim in dims {
+        let mut fixed = Message::default();
+        fixed.int(1, *dim);
+        shape.message(1, &fixed);
+    }
+    let mut tensor_type = Message::default();
+    tensor_type.int(1, FLOAT);
+    tensor_type.message(2, &shape);
+    let mut type_proto = Message::default();
+    type_proto.message(1, &tensor_type);
+    let mut value_info = Message::default();
+    value_info.string(1, name);
+    value_info.message(2, &type_proto);
+    value_info
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use fast_tak::Game;
+    use tch::{Device, Kind, Tensor};
+
+    use super::{Message, BATCH};
+    use crate::network::{
+        generic::NetConfig,
+        net5::{Env, Net, N},
+        repr::{games_to_tensor, input_channels, output_channels},
+        residual::NormKind,
+        RndNetwork,
+    };
+
+    /// Field of a protocol buffer message.
+    #[derive(Debug, Clone, Copy)]
+    enum Field<'a> {
+        Varint(u64),
+        Fixed32([u8; 4]),
+        Bytes(&'a [u8]),
+    }
+
+    impl<'a> Field<'a> {
+        fn int(self) -> i64 {
+            let Self::Varint(value) = self else {
+                panic!("{self:?} should be a varint");
+            };
+            i64::from_le_bytes(value.to_le_bytes())
+        }
+
+        fn float(self) -> f32 {
+            let Self::Fixed32(bytes) = self else {
+                panic!("{self:?} should be fixed32");
+            };
+            f32::from_le_bytes(bytes)
+        }
+
+        fn bytes(self) -> &'a [u8] {
+            let Self::Bytes(bytes) = self else {
+                panic!("{self:?} should be length-delimited");
+            };
+            bytes
+        }
+
+        fn string(self) -> String {
+            String::from_utf8(self.bytes().to_vec()).expect("string should be UTF-8")
+        }
+
+        fn message(self) -> Vec<(u32, Self)> {
+            parse(self.bytes())
+        }
+    }
+
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first().expect("varint should not be truncated");
+            *bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return value;
+            }
+        }
+        panic!("varint should be at most 10 bytes");
+    }
+
+    /// Minimal reader of protocol buffers, which is enough for ONNX models.
+    fn parse(mut bytes: &[u8]) -> Vec<(u32, Field<'_>)> {
+        let mut fields = Vec::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes);
+            let field = u32::try_from(key >> 3).expect("field number should fit");
+            let value = match key & 7 {
+                0 => Field::Varint(varint(&mut bytes)),
+                2 => {
+                    let length = usize::try_from(varint(&mut bytes)).unwrap();
+                    let (value, rest) = bytes.split_at(length);
+                    bytes = rest;
+                    Field::Bytes(value)
+                }
+                5 => {
+                    let (value, rest) = bytes.split_first_chunk::<4>().unwrap();
+                    bytes = rest;
+                    Field::Fixed32(*value)
+                }
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+            fields.push((field, value));
+        }
+        fields
+    }
+
+    fn all<'a>(fields: &[(u32, Field<'a>)], number: u32) -> impl Iterator<Item = Field<'a>> + '_ {
+        fields
+            .iter()
+            .filter(move |(n, _)| *n == number)
+            .map(|(_, field)| *field)
+    }
+
+    fn one<'a>(fields: &[(u32, Field<'a>)], number: u32) -> Field<'a> {
+        all(fields, number)
+            .next()
+            .unwrap_or_else(|| panic!("field {number} should be present"))
+    }
+
+    #[derive(Debug)]
+    struct Node {
+        op_type: String,
+        inputs: Vec<String>,
+        output: String,
+        ints: HashMap<String, Vec<i64>>,
+        floats: HashMap<String, f32>,
+    }
+
+    struct Model {
+        nodes: Vec<Node>,
+        initializers: HashMap<String, Tensor>,
+        inputs: Vec<(String, Vec<Option<i64>>)>,
+        outputs: Vec<(String, Vec<Option<i64>>)>,
+    }
+
+    fn value_infos(graph: &[(u32, Field)], number: u32) -> Vec<(String, Vec<Option<i64>>)> {
+        all(graph, number)
+            .map(|value_info| {
+                let value_info = value_info.message();
+                let tensor_type = one(&one(&value_info, 2).message(), 1).message();
+                assert_eq!(one(&tensor_type, 1).int(), super::FLOAT);
+                let dims = all(&one(&tensor_type, 2).message(), 1)
+                    .map(|dim| {
+                        let dim = dim.message();
+                        match &dim[..] {
+                            [(1, value)] => Some(value.int()),
+                            [(2, param)] => {
+                                assert_eq!(param.string(), BATCH);
+                                None
+                            }
+                            _ => panic!("dimension should have a value or a parameter"),
+                        }
+                    })
+                    .collect();
+                (one(&value_info, 1).string(), dims)
+            })
+            .collect()
+    }
+
+    fn read(bytes: &[u8]) -> Model {
+        let model = parse(bytes);
+        assert_eq!(one(&model, 1).int(), super::IR_VERSION);
+        let opset = one(&model, 8).message();
+        assert_eq!(one(&opset, 2).int(), super::OPSET_VERSION);
+        let graph = one(&model, 7).message();
+
+        let nodes = all(&graph, 1)
+            .map(|node| {
+                let node = node.message();
+                let mut ints = HashMap::new();
+                let mut floats = HashMap::new();
+                for attribute in all(&node, 5) {
+                    let attribute = attribute.message();
+                    let name = one(&attribute, 1).string();
+                    match one(&attribute, 20).int() {
+                        1 => {
+                            floats.insert(name, one(&attribute, 2).float());
+                        }
+                        2 => {
+                            ints.insert(name, vec![one(&attribute, 3).int()]);
+                        }
+                        7 => {
+                            ints.insert(name, all(&attribute, 8).map(Field::int).collect());
+                        }
+                        kind => panic!("unexpected attribute type {kind}"),
+                    }
+                }
+                Node {
+                    op_type: one(&node, 4).string(),
+                    inputs: all(&node, 1).map(Field::string).collect(),
+                    output: one(&node, 2).string(),
+                    ints,
+                    floats,
+                }
+            })
+            .collect();
+
+        let initializers = all(&graph, 5)
+            .map(|tensor| {
+                let tensor = tensor.message();
+                let dims: Vec<i64> = all(&tensor, 1).map(Field::int).collect();
+                let raw = one(&tensor, 9).bytes();
+                let values = match one(&tensor, 2).int() {
+                    super::FLOAT => Tensor::from_slice(
+                        &raw.chunks_exact(4)
+                            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+                            .collect::<Vec<_>>(),
+                    ),
+                    super::INT64 => Tensor::from_slice(
+                        &raw.chunks_exact(8)
+                            .map(|c| i64::from_le_bytes(c.try_into().unwrap()))
+                            .collect::<Vec<_>>(),
+                    ),
+                    kind => panic!("unexpected data type {kind}"),
+                };
+                (one(&tensor, 8).string(), values.view(dims.as_slice()))
+            })
+            .collect();
+
+        Model {
+            nodes,
+            initializers,
+            inputs: value_infos(&graph, 11),
+            outputs: value_infos(&graph, 12),
+        }
+    }
+
+    /// Evaluate the operators which the exported networks use.
+    fn evaluate(model: &Model, xs: &Tensor) -> HashMap<String, Tensor> {
+        let mut values: HashMap<String, Tensor> = model
+            .initializers
+            .iter()
+            .map(|(name, tensor)| (name.clone(), tensor.shallow_clone()))
+            .collect();
+        values.insert(model.inputs[0].0.clone(), xs.shallow_clone());
+        for node in &model.nodes {
+            let inputs: Vec<&Tensor> = node
+                .inputs
+                .iter()
+                .map(|input| {
+                    values
+                        .get(input)
+                        .unwrap_or_else(|| panic!("{input} should be defined before {node:?}"))
+                })
+                .collect();
+            let output = match (node.op_type.as_str(), inputs.as_slice()) {
+                ("Conv", [xs, weight, bias @ ..]) => {
+                    let padding = node.ints["pads"][0];
+                    xs.conv2d(
+                        weight,
+                        bias.first().copied(),
+                        [1, 1],
+                        [padding; 2],
+                        [1, 1],
+                        1,
+                    )
+                }
+                ("BatchNormalization", [xs, weight, bias, mean, var]) => xs.batch_norm(
+                    Some(*weight),
+                    Some(*bias),
+                    Some(*mean),
+                    Some(*var),
+                    false,
+                    0.1,
+                    f64::from(node.floats["epsilon"]),
+                    false,
+                ),
+                ("InstanceNormalization", [xs, weight, bias]) => {
+                    let mean = xs.mean_dim(2, true, Kind::Float);
+                    let var = xs.var_dim(2, false, true);
+                    (*xs - mean) / (var + f64::from(node.floats["epsilon"])).sqrt()
+                        * weight.view([1, -1, 1])
+                        + bias.view([1, -1, 1])
+                }
+                ("Reshape", [xs, shape]) => {
+                    let size = xs.size();
+                    let shape: Vec<i64> = Vec::<i64>::try_from(*shape)
+                        .unwrap()
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, dim)| if dim == 0 { size[i] } else { dim })
+                        .collect();
+                    xs.reshape(shape.as_slice())
+                }
+                ("Shape", [xs]) => Tensor::from_slice(&xs.size()),
+                ("Flatten", [xs]) => xs.flatten(1, -1),
+                ("GlobalAveragePool", [xs]) => xs.adaptive_avg_pool2d([1, 1]),
+                ("Gemm", [xs, weight, bias]) => xs.linear(weight, Some(*bias)),
+                ("Mul", [a, b]) => *a * *b,
+                ("Add", [a, b]) => *a + *b,
+                ("Relu", [xs]) => xs.relu(),
+                ("Sigmoid", [xs]) => xs.sigmoid(),
+                ("Tanh", [xs]) => xs.tanh(),
+                ("Identity", [xs]) => xs.shallow_clone(),
+                _ => panic!("unexpected node {node:?}"),
+            };
+            values.insert(node.output.clone(), output);
+        }
+        values
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            let mut message = Message::default();
+            message.varint(value);
+            assert_eq!(varint(&mut message.0.as_slice()), value);
+        }
+    }
+
+    fn exported_model_matches_the_network(config: NetConfig, seed: i64) {
+        let path = std::env::temp_dir().join(format!("takzero-export-{seed}.onnx"));
+        let net = Net::with_config(Device::Cpu, Some(seed), config);
+        net.export_onnx(&path).unwrap();
+        let model = read(&std::fs::read(&path).unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        let n = N as i64;
+        assert_eq!(model.inputs, [("input".to_string(), vec![
+            None,
+            Some(input_channels::<N>() as i64),
+            Some(n),
+            Some(n)
+        ])]);
+        assert_eq!(model.outputs, [
+            ("policy".to_string(), vec![
+                None,
+                Some(output_channels::<N>() as i64),
+                Some(n),
+                Some(n)
+            ]),
+            ("value".to_string(), vec![None, Some(1)]),
+        ]);
+
+        let mut game: Env = Game::default();
+        game.play("a1".parse().unwrap()).unwrap();
+        let xs = games_to_tensor(&[Game::default(), game], Device::Cpu);
+        let values = evaluate(&model, &xs);
+        let (policy, value, _) = net.forward_t(&xs, false);
+        assert!(values["policy"].allclose(&policy, 1e-4, 1e-4, false));
+        assert!(values["value"].allclose(&value, 1e-4, 1e-4, false));
+    }
+
+    #[test]
+    fn exported_model_matches_the_default_network() {
+        exported_model_matches_the_network(NetConfig::cpu(), 864);
+    }
+
+    #[test]
+    fn exported_model_matches_a_network_with_group_norm_and_se() {
+        exported_model_matches_the_network(
+            NetConfig {
+                se_ratio: Some(4),
+                norm: NormKind::GroupNorm,
+                ..NetConfig::cpu()
+            },
+            975,
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use fast_tak::{
-    takparse::{Color, Direction, Move, MoveKind, Piece},
+    takparse::{Color, Direction, Move, MoveKind, Pattern, Piece, Square, Tps},
     Game,
     Reserves,
+    Symmetry,
 };
 use ordered_float::NotNan;
 use tch::{Device, Tensor};
@@ -70,30 +71,132 @@ pub fn move_index<const N: usize>(m: &Move) -> usize {
     channel * N * N + row * N + column
 }
 
+/// Get the move for an index of the network output.
+/// This is the inverse of [`move_index`].
+///
+/// # Panics
+///
+/// Panics if the index is not smaller than [`output_size`].
+#[must_use]
+pub fn move_from_index<const N: usize>(index: usize) -> Move {
+    assert!(
+        index < output_size::<N>(),
+        "move index {index} is out of range for a board of size {N}"
+    );
+    let (channel, square) = (index / (N * N), index % (N * N));
+    let square = Square::new((square % N) as u8, (square / N) as u8);
+    let kind = match channel {
+        0 => MoveKind::Place(Piece::Flat),
+        1 => MoveKind::Place(Piece::Wall),
+        2 => MoveKind::Place(Piece::Cap),
+        _ => {
+            let spread = channel - 3;
+            let direction = match spread / possible_patterns::<N>() {
+                0 => Direction::Up,
+                1 => Direction::Right,
+                2 => Direction::Down,
+                _ => Direction::Left,
+            };
+            let pattern_offset = spread % possible_patterns::<N>();
+            let mask = ((pattern_offset + 1) << (8 - N)) as u8;
+            MoveKind::Spread(direction, Pattern::from_mask(mask))
+        }
+    };
+    Move::new(square, kind)
+}
+
+/// Check in debug builds that a tensor has the expected shape,
+/// where `-1` matches any size.
+///
+/// This names the tensor and both shapes, which is much easier to act on than
+/// the error of a failed `view`, for example when a network of one board size
+/// is given inputs or targets of another.
+///
+/// # Panics
+///
+/// Panics in debug builds if the shapes do not match.
+pub fn debug_assert_shape(tensor: &Tensor, expected: &[i64], name: &str) {
+    if cfg!(debug_assertions) {
+        let actual = tensor.size();
+        let matches = actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(actual, expected)| *expected == -1 || actual == expected);
+        assert!(
+            matches,
+            "{name} should have shape {expected:?}, but has shape {actual:?}"
+        );
+    }
+}
+
+/// Check in debug builds that a move fits the output for a board of size `N`.
+fn debug_assert_index<const N: usize>(mov: &Move, index: usize) {
+    debug_assert!(
+        index < output_size::<N>(),
+        "move {mov} has index {index}, but the output for a board of size {N} only has {} entries",
+        output_size::<N>()
+    );
+}
+
 /// Create a mask for all the impossible moves.
 /// Possible moves are false, impossible are true.
 pub fn move_mask<const N: usize>(moves: &[Move], device: Device) -> Tensor {
     let mut mask = vec![true; output_size::<N>()];
     for mov in moves {
-        mask[move_index::<N>(mov)] = false;
+        let index = move_index::<N>(mov);
+        debug_assert_index::<N>(mov, index);
+        mask[index] = false;
     }
     // FIXME: Can we prevent this copy?
     // `Tensor::from_blob` will not work because backing data will deallocate.
-    Tensor::from_slice(&mask)
+    let mask = Tensor::from_slice(&mask)
         .reshape([1, output_channels::<N>() as i64, N as i64, N as i64])
-        .to(device)
+        .to(device);
+    debug_assert_shape(
+        &mask,
+        &[1, output_channels::<N>() as i64, N as i64, N as i64],
+        "move mask",
+    );
+    mask
+}
+
+/// Which entries of the network output are legal moves in the position,
+/// indexed like [`move_index`]. This is the opposite of [`move_mask`].
+#[must_use]
+pub fn legal_mask<const N: usize, const HALF_KOMI: i8>(game: &Game<N, HALF_KOMI>) -> Vec<bool>
+where
+    Reserves<N>: Default,
+{
+    let mut moves = Vec::new();
+    game.possible_moves(&mut moves);
+    let mut mask = vec![false; output_size::<N>()];
+    for mov in &moves {
+        let index = move_index::<N>(mov);
+        debug_assert_index::<N>(mov, index);
+        mask[index] = true;
+    }
+    mask
 }
 
 /// Create a tensor containing the given policy.
 pub fn policy_tensor<const N: usize>(policy: &[(Move, NotNan<f32>)], device: Device) -> Tensor {
     let mut data = vec![0.0; output_size::<N>()];
     for (mov, p) in policy {
-        data[move_index::<N>(mov)] = p.into_inner();
+        let index = move_index::<N>(mov);
+        debug_assert_index::<N>(mov, index);
+        data[index] = p.into_inner();
     }
     // FIXME: Can we prevent this copy?
-    Tensor::from_slice(&data)
+    let tensor = Tensor::from_slice(&data)
         .reshape([1, output_channels::<N>() as i64, N as i64, N as i64])
-        .to(device)
+        .to(device);
+    debug_assert_shape(
+        &tensor,
+        &[1, output_channels::<N>() as i64, N as i64, N as i64],
+        "policy tensor",
+    );
+    tensor
 }
 
 /// Get the number of channels needed to encode each move type.
@@ -139,6 +242,18 @@ pub fn input_channels<const N: usize>() -> usize {
     2 * (stack_size::<N>() + RESERVES) + TO_MOVE + FCD
 }
 
+/// Number of planes added for each previous position in the history.
+pub const HISTORY_PLANES_PER_POSITION: usize = 2;
+
+/// Number of input channels with `K` previous positions of history.
+/// With `K = 0` this is the same as [`input_channels`],
+/// so models without history are unaffected.
+#[inline]
+#[must_use]
+pub fn input_channels_with_history<const N: usize, const K: usize>() -> usize {
+    input_channels::<N>() + K * HISTORY_PLANES_PER_POSITION
+}
+
 #[inline]
 #[must_use]
 pub fn input_size<const N: usize>() -> usize
@@ -166,6 +281,9 @@ where
 
 /// Write 1s into the passed buffer to represent the game.
 /// Assumes the buffer is of correct size and filled with zeroes.
+///
+/// The last plane is the flat difference minus the komi, so that the
+/// value of the same board can depend on the komi of the game.
 fn game_repr<const N: usize, const HALF_KOMI: i8>(buffer: &mut [f32], game: &Game<N, HALF_KOMI>)
 where
     Reserves<N>: Default,
@@ -238,17 +356,233 @@ where
     let mut buffer = vec![0.0; input_size::<N>()];
     game_repr(&mut buffer, game);
     // FIXME: Can we prevent this copy?
-    Tensor::from_slice(&buffer)
+    let tensor = Tensor::from_slice(&buffer)
         .reshape([1, input_channels::<N>() as i64, N as i64, N as i64])
+        .to(device);
+    debug_assert_shape(
+        &tensor,
+        &[1, input_channels::<N>() as i64, N as i64, N as i64],
+        "game tensor",
+    );
+    tensor
+}
+
+/// Create a CUDA tensor which represents the game and up to `K` previous
+/// positions, ordered from the most recent one.
+///
+/// The first [`input_channels`] channels are the same as in
+/// [`game_to_tensor`]. They are followed by two planes per previous position,
+/// marking the squares where the top piece belongs to the player to move in
+/// the current position, and to the opponent. Missing history, for example at
+/// the start of the game, is left as zeroes.
+pub fn game_to_tensor_with_history<const N: usize, const HALF_KOMI: i8, const K: usize>(
+    game: &Game<N, HALF_KOMI>,
+    history: &[Game<N, HALF_KOMI>],
+    device: Device,
+) -> Tensor
+where
+    Reserves<N>: Default,
+{
+    let mut buffer = vec![0.0; input_channels_with_history::<N, K>() * N * N];
+    let (current, previous) = buffer.split_at_mut(input_size::<N>());
+    game_repr(current, game);
+    for (planes, old) in previous
+        .chunks_exact_mut(HISTORY_PLANES_PER_POSITION * N * N)
+        .zip(history)
+    {
+        for (y, row) in old.board.iter().enumerate() {
+            for (x, stack) in row.enumerate() {
+                if let Some((_, color)) = stack.top() {
+                    let plane = usize::from(color != game.to_move);
+                    planes[N * N * plane + N * y + x] = 1.0;
+                }
+            }
+        }
+    }
+    Tensor::from_slice(&buffer)
+        .reshape([
+            1,
+            input_channels_with_history::<N, K>() as i64,
+            N as i64,
+            N as i64,
+        ])
         .to(device)
 }
 
+/// Create a single tensor which represents a batch of games.
+/// This is the same as concatenating the output of [`game_to_tensor`]
+/// for each game, but without allocating a tensor per game.
+pub fn games_to_tensor<const N: usize, const HALF_KOMI: i8>(
+    games: &[Game<N, HALF_KOMI>],
+    device: Device,
+) -> Tensor
+where
+    Reserves<N>: Default,
+{
+    let mut buffer = vec![0.0; games.len() * input_size::<N>()];
+    for (chunk, game) in buffer.chunks_exact_mut(input_size::<N>()).zip(games) {
+        game_repr(chunk, game);
+    }
+    Tensor::from_slice(&buffer)
+        .reshape([
+            games.len() as i64,
+            input_channels::<N>() as i64,
+            N as i64,
+            N as i64,
+        ])
+        .to(device)
+}
+
+/// Read the game back from a buffer written by [`game_repr`].
+///
+/// # Panics
+///
+/// Panics if the buffer does not describe a valid position.
+fn game_from_repr<const N: usize, const HALF_KOMI: i8>(buffer: &[f32]) -> Game<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    debug_assert_eq!(buffer.len(), input_size::<N>());
+
+    let index = |row, column, channel| N * N * channel + N * row + column;
+    let is_set = |row, column, channel| buffer[index(row, column, channel)] > 0.5;
+    let to_move = if buffer[2 * board_size::<N>() + 4 * N * N] > 0.5 {
+        Color::Black
+    } else {
+        Color::White
+    };
+    let digit = |mine: bool| {
+        if mine == (to_move == Color::White) {
+            '1'
+        } else {
+            '2'
+        }
+    };
+
+    let mut rows = Vec::with_capacity(N);
+    let mut pieces = 0;
+    for y in (0..N).rev() {
+        let mut squares = Vec::with_capacity(N);
+        for x in 0..N {
+            let top = (0..3)
+                .map(|piece| (piece, true))
+                .chain((0..3).map(|piece| (stack_size::<N>() + piece, false)))
+                .find(|&(channel, _)| is_set(y, x, channel));
+            let Some((channel, mine)) = top else {
+                squares.push("x".to_string());
+                continue;
+            };
+            // Colors below the top are stored from the top down.
+            let mut stack: String = (0..stack_size::<N>() - 3)
+                .map_while(|i| {
+                    if is_set(y, x, 3 + i) {
+                        Some(digit(true))
+                    } else if is_set(y, x, 3 + stack_size::<N>() + i) {
+                        Some(digit(false))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .collect();
+            stack.push(digit(mine));
+            pieces += stack.len();
+            match channel % stack_size::<N>() {
+                1 => stack.push('S'),
+                2 => stack.push('C'),
+                _ => {}
+            }
+            squares.push(stack);
+        }
+        rows.push(squares.join(","));
+    }
+
+    // The move number is not encoded, so only the opening is distinguished.
+    let move_number = if pieces == 0 || (pieces == 1 && to_move == Color::Black) {
+        1
+    } else {
+        2
+    };
+    let player = if to_move == Color::White { 1 } else { 2 };
+    let tps: Tps = format!("{} {player} {move_number}", rows.join("/"))
+        .parse()
+        .expect("decoded position should be valid TPS");
+    tps.into()
+}
+
+/// Reconstruct the game from a tensor created by [`game_to_tensor`].
+/// This is the inverse of the encoding, up to what the encoding drops:
+/// the move number (beyond the opening) and the bottom of stacks
+/// which are taller than [`stack_size`] allows. Reserves follow from the board.
+///
+/// # Panics
+///
+/// Panics if the tensor does not contain a single valid position.
+#[must_use]
+pub fn tensor_to_game<const N: usize, const HALF_KOMI: i8>(tensor: &Tensor) -> Game<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    let buffer: Vec<f32> = tensor
+        .to(Device::Cpu)
+        .reshape([input_size::<N>() as i64])
+        .try_into()
+        .expect("tensor should contain floats");
+    game_from_repr(&buffer)
+}
+
+/// Find the canonical form of a game among its 8 symmetries,
+/// which is the one with the lexicographically smallest TPS.
+/// Also returns the index of the symmetry which was applied,
+/// in the order of [`Symmetry::symmetries`].
+#[must_use]
+pub fn canonicalize<const N: usize, const HALF_KOMI: i8>(
+    game: &Game<N, HALF_KOMI>,
+) -> (Game<N, HALF_KOMI>, usize)
+where
+    Reserves<N>: Default,
+{
+    game.symmetries()
+        .into_iter()
+        .enumerate()
+        .map(|(symmetry, game)| (Tps::from(game.clone()).to_string(), symmetry, game))
+        .min_by(|(a, ..), (b, ..)| a.cmp(b))
+        .map(|(_, symmetry, game)| (game, symmetry))
+        .expect("there should always be 8 symmetries")
+}
+
+/// Transform a move with the symmetry returned by [`canonicalize`].
+#[must_use]
+pub fn apply_symmetry_to_move<const N: usize>(mv: Move, symmetry: usize) -> Move {
+    Symmetry::<N>::symmetries(&mv)[symmetry]
+}
+
 #[cfg(test)]
 mod tests {
-    use fast_tak::{takparse::Tps, Game};
+    use fast_tak::{
+        takparse::{Move, Tps},
+        Game,
+    };
+    use rand::{seq::IteratorRandom, SeedableRng};
     use tch::Device;
 
-    use super::{game_repr, input_size};
+    use super::{
+        apply_symmetry_to_move,
+        canonicalize,
+        game_repr,
+        game_to_tensor,
+        game_to_tensor_with_history,
+        games_to_tensor,
+        input_channels,
+        input_size,
+        legal_mask,
+        move_from_index,
+        move_index,
+        stack_size,
+        tensor_to_game,
+    };
     use crate::{
         network::repr::{output_size, policy_tensor},
         search::{
@@ -497,4 +831,171 @@ mod tests {
 
         assert_eq!(buffer, handmade);
     }
+
+    /// TPS without the move number, which is not part of the encoding.
+    fn board_and_player(game: &Game<5, 4>) -> String {
+        let tps = Tps::from(game.clone()).to_string();
+        tps.rsplit_once(' ').unwrap().0.to_string()
+    }
+
+    #[test]
+    fn decode_random_positions() {
+        const SEED: u64 = 321;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        let mut checked = 0;
+        for _ in 0..50 {
+            let mut game: Game<5, 4> = Game::default();
+            while game.terminal().is_none() {
+                let original = board_and_player(&game);
+                // Stacks taller than the encoding are truncated.
+                let too_tall = original.split(['/', ',', ' ']).any(|stack| {
+                    stack.chars().filter(char::is_ascii_digit).count() > stack_size::<5>() - 2
+                });
+                if !too_tall {
+                    let decoded: Game<5, 4> = tensor_to_game(&game_to_tensor(&game, Device::Cpu));
+                    assert_eq!(board_and_player(&decoded), original);
+                    assert_eq!(decoded.white_reserves.stones, game.white_reserves.stones);
+                    assert_eq!(decoded.white_reserves.caps, game.white_reserves.caps);
+                    assert_eq!(decoded.black_reserves.stones, game.black_reserves.stones);
+                    assert_eq!(decoded.black_reserves.caps, game.black_reserves.caps);
+                    checked += 1;
+                }
+                game.populate_actions(&mut actions);
+                game.step(actions.drain(..).choose(&mut rng).unwrap());
+            }
+        }
+        assert!(checked > 1000);
+    }
+
+    #[test]
+    fn komi_changes_the_input() {
+        let tps = "x3/2S,2,x/1,1,x 1 3";
+        let no_komi: Game<3, 0> = tps.parse::<Tps>().unwrap().into();
+        let komi: Game<3, 2> = tps.parse::<Tps>().unwrap().into();
+        let no_komi = game_to_tensor(&no_komi, Device::Cpu);
+        let komi = game_to_tensor(&komi, Device::Cpu);
+        assert!(!no_komi.equal(&komi));
+        // Only the flat difference plane changes.
+        let last = input_channels::<3>() as i64 - 1;
+        assert!(no_komi.narrow(1, 0, last).equal(&komi.narrow(1, 0, last)));
+    }
+
+    #[test]
+    fn legal_mask_permits_only_legal_moves() {
+        const SEED: u64 = 852;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        for plies in [0, 1, 5, 20, 40] {
+            let game: Game<5, 4> =
+                Game::new_opening_with_random_steps(&mut rng, &mut actions, plies);
+            actions.clear();
+            game.populate_actions(&mut actions);
+            let mask = legal_mask(&game);
+            assert_eq!(mask.iter().filter(|&&legal| legal).count(), actions.len());
+            for (index, _) in mask.iter().enumerate().filter(|(_, &legal)| legal) {
+                assert!(game.is_legal(&move_from_index::<5>(index)));
+            }
+            actions.clear();
+        }
+    }
+
+    #[test]
+    fn batched_games_match_single_games() {
+        const SEED: u64 = 654;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        let games: Vec<Game<5, 4>> = (0..128)
+            .map(|_| Game::new_opening_with_random_steps(&mut rng, &mut actions, 20))
+            .collect();
+        let batched = games_to_tensor(&games, Device::Cpu);
+        let single = tch::Tensor::cat(
+            &games
+                .iter()
+                .map(|game| game_to_tensor(game, Device::Cpu))
+                .collect::<Vec<_>>(),
+            0,
+        );
+        assert_eq!(batched.size(), single.size());
+        assert!(batched.equal(&single));
+    }
+
+    #[test]
+    fn history_planes() {
+        let moves = ["a1", "c3", "b2", "a3"];
+        let history: Vec<Game<3, 0>> = (1..moves.len())
+            .rev()
+            .map(|ply| Game::from_ptn_moves(&moves[..ply]))
+            .collect();
+        let game: Game<3, 0> = Game::from_ptn_moves(&moves);
+
+        let without = game_to_tensor_with_history::<3, 0, 0>(&game, &history, Device::Cpu);
+        assert!(without.equal(&game_to_tensor(&game, Device::Cpu)));
+
+        let with: Vec<f32> = game_to_tensor_with_history::<3, 0, 4>(&game, &history, Device::Cpu)
+            .reshape([-1])
+            .try_into()
+            .unwrap();
+        let planes = &with[input_size::<3>()..];
+        assert_eq!(planes.len(), 4 * 2 * 9);
+        // White to move. The previous position has a black a1 (from the swap),
+        // and white stones on c3 and b2.
+        let (mine, theirs) = planes[..18].split_at(9);
+        assert_eq!(mine, [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(theirs, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        // Only three previous positions exist, so the last planes are empty.
+        assert!(planes[3 * 18..].iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn canonical_form_is_shared_by_symmetries() {
+        const SEED: u64 = 987;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        for _ in 0..100 {
+            let game: Game<5, 4> = Game::new_opening_with_random_steps(&mut rng, &mut actions, 12);
+            let (canonical, symmetry) = canonicalize(&game);
+            for other in game.symmetries() {
+                assert_eq!(canonicalize(&other).0, canonical);
+            }
+
+            // Moves carry over to the canonical position.
+            game.populate_actions(&mut actions);
+            let mut canonical_actions = Vec::new();
+            canonical.populate_actions(&mut canonical_actions);
+            assert_eq!(actions.len(), canonical_actions.len());
+            for action in actions.drain(..) {
+                assert!(canonical_actions.contains(&apply_symmetry_to_move::<5>(action, symmetry)));
+            }
+        }
+    }
+
+    #[test]
+    fn move_from_index_inverts_move_index() {
+        for index in 0..output_size::<5>() {
+            assert_eq!(move_index::<5>(&move_from_index::<5>(index)), index);
+        }
+
+        // Every piece type and spreads in each direction with several lengths.
+        for text in [
+            "a1", "Sb2", "Ce5", "a1+", "a1>", "e5-", "e5<", "3c3+12", "5a1>212", "4e5<13", "2b4-11",
+        ] {
+            let action: Move = text.parse().unwrap();
+            assert_eq!(move_from_index::<5>(move_index::<5>(&action)), action);
+        }
+
+        const SEED: u64 = 654;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        for _ in 0..20 {
+            let mut game: Game<5, 4> = Game::default();
+            while game.terminal().is_none() {
+                game.populate_actions(&mut actions);
+                for action in &actions {
+                    assert_eq!(move_from_index::<5>(move_index::<5>(action)), *action);
+                }
+                game.step(actions.drain(..).choose(&mut rng).unwrap());
+            }
+        }
+    }
 }
@@ -5,6 +5,48 @@ use tch::{nn, Tensor};
 // <https://medium.com/@bentou.pub/
 // alphazero-from-scratch-in-pytorch-for-the-game-of-chain-reaction-part-3-c3fbf0d6f986>
 
+/// Normalization layer after each convolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormKind {
+    #[default]
+    BatchNorm,
+    /// Normalizes groups of channels of each position on its own, so that
+    /// training and inference behave the same for any batch size.
+    GroupNorm,
+}
+
+impl NormKind {
+    /// Largest number of channel groups of [`NormKind::GroupNorm`].
+    const MAX_GROUPS: i64 = 8;
+
+    /// Number of channel groups of [`NormKind::GroupNorm`], which is the
+    /// largest one up to [`NormKind::MAX_GROUPS`] that divides the channels.
+    #[must_use]
+    pub fn groups(channels: i64) -> i64 {
+        (1..=Self::MAX_GROUPS)
+            .rev()
+            .find(|groups| channels % groups == 0)
+            .unwrap_or(1)
+    }
+
+    #[must_use]
+    pub fn layer(self, vs: &nn::Path, channels: i64) -> nn::SequentialT {
+        match self {
+            Self::BatchNorm => nn::seq_t().add(nn::batch_norm2d(
+                vs / "batch_norm",
+                channels,
+                nn::BatchNormConfig::default(),
+            )),
+            Self::GroupNorm => nn::seq_t().add(nn::group_norm(
+                vs / "group_norm",
+                Self::groups(channels),
+                channels,
+                nn::GroupNormConfig::default(),
+            )),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SmallBlock {
     model: nn::SequentialT,
@@ -13,6 +55,11 @@ pub struct SmallBlock {
 impl SmallBlock {
     #[must_use]
     pub fn new(vs: &nn::Path, in_channels: i64, out_channels: i64) -> Self {
+        Self::with_norm(vs, in_channels, out_channels, NormKind::BatchNorm)
+    }
+
+    #[must_use]
+    pub fn with_norm(vs: &nn::Path, in_channels: i64, out_channels: i64, norm: NormKind) -> Self {
         let model = nn::seq_t()
             .add(nn::conv2d(
                 vs / "conv2d",
@@ -26,11 +73,7 @@ impl SmallBlock {
                     ..Default::default()
                 },
             ))
-            .add(nn::batch_norm2d(
-                vs / "batch_norm",
-                out_channels,
-                nn::BatchNormConfig::default(),
-            ));
+            .add(norm.layer(vs, out_channels));
         Self { model }
     }
 }
@@ -44,20 +87,72 @@ impl nn::ModuleT for SmallBlock {
 #[derive(Debug)]
 pub struct ResidualBlock {
     model: nn::SequentialT,
+    squeeze_excitation: Option<nn::SequentialT>,
 }
 
 impl ResidualBlock {
     pub fn new(vs: &nn::Path, in_channels: i64, mid_channels: i64) -> Self {
+        Self::with_se_ratio(vs, in_channels, mid_channels, None)
+    }
+
+    /// Residual block which optionally gates the channels with
+    /// squeeze-and-excitation before the residual is added.
+    /// The hidden layer of the gate has `in_channels / se_ratio` units.
+    pub fn with_se_ratio(
+        vs: &nn::Path,
+        in_channels: i64,
+        mid_channels: i64,
+        se_ratio: Option<i64>,
+    ) -> Self {
+        Self::with_se_ratio_and_norm(vs, in_channels, mid_channels, se_ratio, NormKind::BatchNorm)
+    }
+
+    /// Same as [`ResidualBlock::with_se_ratio`], but with the normalization
+    /// layer of the convolutions.
+    pub fn with_se_ratio_and_norm(
+        vs: &nn::Path,
+        in_channels: i64,
+        mid_channels: i64,
+        se_ratio: Option<i64>,
+        norm: NormKind,
+    ) -> Self {
         let model = nn::seq_t()
-            .add(SmallBlock::new(vs, in_channels, mid_channels))
+            .add(SmallBlock::with_norm(vs, in_channels, mid_channels, norm))
             .add_fn(Tensor::relu)
-            .add(SmallBlock::new(vs, mid_channels, in_channels));
-        Self { model }
+            .add(SmallBlock::with_norm(vs, mid_channels, in_channels, norm));
+        let squeeze_excitation = se_ratio.map(|ratio| {
+            let hidden = (in_channels / ratio).max(1);
+            nn::seq_t()
+                .add_fn(|x| x.adaptive_avg_pool2d([1, 1]).flatten(1, -1))
+                .add(nn::linear(
+                    vs / "se_squeeze",
+                    in_channels,
+                    hidden,
+                    nn::LinearConfig::default(),
+                ))
+                .add_fn(Tensor::relu)
+                .add(nn::linear(
+                    vs / "se_excite",
+                    hidden,
+                    in_channels,
+                    nn::LinearConfig::default(),
+                ))
+                .add_fn(move |x| x.sigmoid().view([-1, in_channels, 1, 1]))
+        });
+        Self {
+            model,
+            squeeze_excitation,
+        }
     }
 }
 
 impl nn::ModuleT for ResidualBlock {
     fn forward_t(&self, xs: &tch::Tensor, train: bool) -> tch::Tensor {
-        self.model.forward_t(xs, train).add(xs).relu()
+        let out = self.model.forward_t(xs, train);
+        let out = match &self.squeeze_excitation {
+            Some(se) => &out * se.forward_t(&out, train),
+            None => out,
+        };
+        out.add(xs).relu()
     }
 }
@@ -10,6 +10,20 @@ pub trait Agent<E: Environment> {
         env_batch: &[E],
         actions_batch: &[Vec<E::Action>],
     ) -> impl Iterator<Item = (Vec<(E::Action, NotNan<f32>)>, f32, f32)>;
+
+    /// Values with a shorter horizon than the values of
+    /// [`Agent::policy_value_uncertainty`], for agents which predict them.
+    /// The search only uses the values with the long horizon.
+    fn short_horizon_values(&self, _env_batch: &[E]) -> Option<Vec<f32>> {
+        None
+    }
+
+    /// Probability of winning, counting draws as half a win, for a value
+    /// given by [`Agent::policy_value_uncertainty`]. Agents without a
+    /// calibration map the value linearly.
+    fn win_probability(&self, value: f32) -> f32 {
+        (value + 1.0) / 2.0
+    }
 }
 
 pub mod dummy {
@@ -63,7 +77,8 @@ pub mod simple {
         ) -> impl Iterator<Item = (Vec<(Move, NotNan<f32>)>, f32, f32)> {
             debug_assert_eq!(env_batch.len(), actions_batch.len());
             env_batch.iter().zip(actions_batch).map(|(env, actions)| {
-                let mut fcd = f32::from(env.board.flat_diff() - HALF_KOMI / 2) / (N * N) as f32;
+                let mut fcd = (f32::from(env.board.flat_diff()) - f32::from(HALF_KOMI) / 2.0)
+                    / (N * N) as f32;
                 if env.to_move == Color::Black {
                     fcd = -fcd;
                 }
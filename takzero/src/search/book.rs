@@ -0,0 +1,225 @@
+use std::{num::ParseFloatError, path::Path};
+
+use fast_tak::{
+    takparse::{Move, ParseMoveError, ParseTpsError, Tps},
+    Game,
+    PlayError,
+    Reserves,
+};
+use rand::prelude::*;
+use rand_distr::{WeightedError, WeightedIndex};
+use thiserror::Error;
+
+use super::env::Environment;
+
+#[derive(Error, Debug)]
+pub enum OpeningBookError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {0}: {1}")]
+    Tps(usize, #[source] ParseTpsError),
+    #[error("line {0}: {1}")]
+    Action(usize, #[source] ParseMoveError),
+    #[error("line {0}: invalid action")]
+    Invalid(usize, #[source] PlayError),
+    #[error("line {0}: {1}")]
+    Weight(usize, #[source] ParseFloatError),
+    #[error("line {0}: weights must be finite and not negative")]
+    NegativeWeight(usize),
+    #[error("line {0}: the position is not for a board of size {1}")]
+    WrongSize(usize, usize),
+    #[error("line {0}: the game is already over")]
+    Terminal(usize),
+    #[error("the book has no positions")]
+    Empty,
+    #[error("{0}")]
+    Weights(#[from] WeightedError),
+}
+
+/// A set of opening positions to start games from.
+///
+/// Each line of a book is either a TPS, or the moves of a game from the
+/// starting position separated by whitespace, like `a1 f6 c3`. A line can
+/// end with `;<weight>` to make it more or less likely to be sampled,
+/// lines without one have a weight of 1. Empty lines and lines starting
+/// with `#` are skipped.
+#[derive(Debug)]
+pub struct OpeningBook<E> {
+    positions: Vec<E>,
+    /// `None` if all positions are equally likely.
+    weights: Option<WeightedIndex<f64>>,
+}
+
+impl<const N: usize, const HALF_KOMI: i8> OpeningBook<Game<N, HALF_KOMI>>
+where
+    Reserves<N>: Default,
+{
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or [`OpeningBook::parse`]
+    /// fails.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, OpeningBookError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// # Errors
+    ///
+    /// Returns an error naming the line if any position cannot be parsed,
+    /// is for another board size, or is already over, and if the book is
+    /// empty.
+    pub fn parse(text: &str) -> Result<Self, OpeningBookError> {
+        let mut positions = Vec::new();
+        let mut weights = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (position, weight) = match line.split_once(';') {
+                Some((position, weight)) => {
+                    let weight: f64 = weight
+                        .trim()
+                        .parse()
+                        .map_err(|err| OpeningBookError::Weight(line_number, err))?;
+                    if !weight.is_finite() || weight < 0.0 {
+                        return Err(OpeningBookError::NegativeWeight(line_number));
+                    }
+                    (position.trim(), weight)
+                }
+                None => (line, 1.0),
+            };
+            let env = parse_position::<N, HALF_KOMI>(position, line_number)?;
+            if env.terminal().is_some() {
+                return Err(OpeningBookError::Terminal(line_number));
+            }
+            positions.push(env);
+            weights.push(weight);
+        }
+        if positions.is_empty() {
+            return Err(OpeningBookError::Empty);
+        }
+        #[allow(clippy::float_cmp)]
+        let uniform = weights.iter().all(|&weight| weight == weights[0]);
+        let weights = if uniform {
+            None
+        } else {
+            Some(WeightedIndex::new(weights)?)
+        };
+        Ok(Self { positions, weights })
+    }
+}
+
+impl<E: Clone> OpeningBook<E> {
+    #[must_use]
+    pub fn positions(&self) -> &[E] {
+        &self.positions
+    }
+
+    /// Sample a position, proportionally to the weights if there are any.
+    pub fn sample(&self, rng: &mut impl Rng) -> E {
+        let index = self.weights.as_ref().map_or_else(
+            || rng.gen_range(0..self.positions.len()),
+            |weights| weights.sample(rng),
+        );
+        self.positions[index].clone()
+    }
+}
+
+fn parse_position<const N: usize, const HALF_KOMI: i8>(
+    position: &str,
+    line_number: usize,
+) -> Result<Game<N, HALF_KOMI>, OpeningBookError>
+where
+    Reserves<N>: Default,
+{
+    // Only TPS contains rows separated by slashes.
+    if position.contains('/') {
+        let tps: Tps = position
+            .parse()
+            .map_err(|err| OpeningBookError::Tps(line_number, err))?;
+        if tps_board_size(position) != Some(N) {
+            return Err(OpeningBookError::WrongSize(line_number, N));
+        }
+        return Ok(tps.into());
+    }
+
+    let mut env = Game::default();
+    for action in position.split_whitespace() {
+        let action: Move = action
+            .parse()
+            .map_err(|err| OpeningBookError::Action(line_number, err))?;
+        env.play(action)
+            .map_err(|err| OpeningBookError::Invalid(line_number, err))?;
+    }
+    Ok(env)
+}
+
+/// Size of the board described by the TPS, or `None` if it is not square.
+fn tps_board_size(tps: &str) -> Option<usize> {
+    let board = tps.split_whitespace().next()?;
+    let rows: Vec<_> = board.split('/').collect();
+    let square = rows.iter().all(|row| {
+        let width: usize = row
+            .split(',')
+            .map(|square| {
+                square
+                    .strip_prefix('x')
+                    .map_or(1, |count| count.parse().unwrap_or(1))
+            })
+            .sum();
+        width == rows.len()
+    });
+    square.then_some(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use fast_tak::Game;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{OpeningBook, OpeningBookError};
+    use crate::search::env::Environment;
+
+    const BOOK: &str = "\
+# Both kinds of entries, with and without weights.
+x5/x5/x5/x5/2,x3,1 1 2
+x5/x5/x2,1,x2/x5/2,x3,1 2 2;3
+a1 e5
+a5 e1 c3;0.5
+";
+
+    #[test]
+    fn every_book_entry_is_a_legal_position() {
+        let book: OpeningBook<Game<5, 4>> = OpeningBook::parse(BOOK).unwrap();
+        assert_eq!(book.positions().len(), 4);
+        let mut actions = Vec::new();
+        for env in book.positions() {
+            assert!(env.terminal().is_none());
+            env.populate_actions(&mut actions);
+            assert!(!actions.is_empty());
+            actions.clear();
+        }
+        assert_eq!(book.positions()[3].ply, 3);
+
+        let mut rng = StdRng::seed_from_u64(123);
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            let env = book.sample(&mut rng);
+            let index = book.positions().iter().position(|e| *e == env).unwrap();
+            counts[index] += 1;
+        }
+        // The second entry is three times as likely as the first.
+        assert!(counts[1] > 2 * counts[0], "{counts:?}");
+    }
+
+    #[test]
+    fn invalid_entries_name_the_line() {
+        let wrong_size = OpeningBook::<Game<6, 4>>::parse(BOOK).unwrap_err();
+        assert!(matches!(wrong_size, OpeningBookError::WrongSize(2, 6)));
+        let invalid = OpeningBook::<Game<5, 4>>::parse("a1\na1 a1").unwrap_err();
+        assert!(matches!(invalid, OpeningBookError::Invalid(2, _)));
+        let empty = OpeningBook::<Game<5, 4>>::parse("# nothing\n").unwrap_err();
+        assert!(matches!(empty, OpeningBookError::Empty));
+    }
+}
@@ -1,7 +1,7 @@
-use std::fmt;
+use std::{cmp::Ordering, fmt, hash::Hash};
 
 use fast_tak::{
-    takparse::{Move, MoveKind, Piece, Square},
+    takparse::{Color, Direction, Move, MoveKind, Piece, Square},
     Game,
     Reserves,
     Symmetry,
@@ -13,9 +13,30 @@ pub trait Environment: Send + Sync + Clone + Default {
 
     fn populate_actions(&self, actions: &mut Vec<Self::Action>);
     fn step(&mut self, action: Self::Action);
+
+    /// Whether the action is one of the actions of
+    /// [`Environment::populate_actions`], so that it can be given to
+    /// [`Environment::step`]. Actions from outside the search, for example
+    /// decoded from a network output, should be checked with this first.
+    fn is_legal(&self, action: &Self::Action) -> bool {
+        let mut actions = Vec::new();
+        self.populate_actions(&mut actions);
+        actions.contains(action)
+    }
     fn terminal(&self) -> Option<Terminal>;
     fn steps(&self) -> u16;
 
+    /// Hash which is equal for all transpositions of this position,
+    /// or `None` if the environment does not recognize them.
+    /// See [`Transposable::zobrist_hash`].
+    fn transposition_hash(&self) -> Option<u64> {
+        None
+    }
+
+    /// Result from the perspective of the player to move if the game is
+    /// stopped before it ends, for example because it took too long.
+    fn adjudicate(&self) -> Terminal;
+
     fn new_opening(rng: &mut impl Rng, actions: &mut Vec<Self::Action>) -> Self;
     fn new_opening_with_random_steps(
         rng: &mut impl Rng,
@@ -24,6 +45,22 @@ pub trait Environment: Send + Sync + Clone + Default {
     ) -> Self;
 }
 
+/// Environments in which the same position can be reached
+/// through different sequences of actions.
+pub trait Transposable: Environment {
+    type Key: Eq + Hash;
+
+    /// Key which is equal for all transpositions of this position.
+    fn transposition_key(&self) -> Self::Key;
+
+    /// Zobrist hash of the position, which is equal for all transpositions
+    /// and differs between other positions except for negligible collisions.
+    /// It is computed from the whole position, see [`HashedGame`] for a hash
+    /// which is updated with every step instead.
+    fn zobrist_hash(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Terminal {
     Win,
     Loss,
@@ -62,6 +99,24 @@ where
         self.ply
     }
 
+    fn transposition_hash(&self) -> Option<u64> {
+        Some(self.zobrist_hash())
+    }
+
+    /// The flat count, including komi.
+    fn adjudicate(&self) -> Terminal {
+        let margin = 2 * i16::from(self.board.flat_diff()) - i16::from(HALF_KOMI);
+        let margin = match self.to_move {
+            Color::White => margin,
+            Color::Black => -margin,
+        };
+        match margin.cmp(&0) {
+            Ordering::Greater => Terminal::Win,
+            Ordering::Less => Terminal::Loss,
+            Ordering::Equal => Terminal::Draw,
+        }
+    }
+
     fn new_opening(rng: &mut impl Rng, _actions: &mut Vec<Move>) -> Self {
         let mut env = Self::default();
         // Pick random symmetry.
@@ -95,6 +150,187 @@ where
     }
 }
 
+impl<const N: usize, const HALF_KOMI: i8> Transposable for Game<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    type Key = u64;
+
+    /// The Zobrist hash, which is much cheaper to compute, store,
+    /// and compare than the TPS.
+    fn transposition_key(&self) -> Self::Key {
+        self.zobrist_hash()
+    }
+
+    /// The XOR of a key for every piece in every stack, for the kind of
+    /// the top piece of every stack, and for the player to move.
+    /// The reserves follow from the pieces on the board and the move number
+    /// is left out, so the hash only depends on the position.
+    fn zobrist_hash(&self) -> u64 {
+        let mut hash = if self.to_move == Color::Black {
+            BLACK_TO_MOVE
+        } else {
+            0
+        };
+        for (row, stacks) in self.board.iter().enumerate() {
+            for (column, stack) in stacks.enumerate() {
+                hash ^= stack_hash(
+                    row * N + column,
+                    stack.colors(),
+                    stack.top().map(|(piece, _)| piece),
+                );
+            }
+        }
+        hash
+    }
+}
+
+/// A game together with its Zobrist hash, which [`HashedGame::step`] updates
+/// from the squares that the move changes instead of hashing the whole board.
+/// The hash is always equal to [`Transposable::zobrist_hash`] of the game.
+#[derive(Clone)]
+pub struct HashedGame<const N: usize, const HALF_KOMI: i8> {
+    game: Game<N, HALF_KOMI>,
+    hash: u64,
+}
+
+impl<const N: usize, const HALF_KOMI: i8> HashedGame<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    #[must_use]
+    pub fn new(game: Game<N, HALF_KOMI>) -> Self {
+        let hash = game.zobrist_hash();
+        Self { game, hash }
+    }
+
+    #[must_use]
+    pub const fn game(&self) -> &Game<N, HALF_KOMI> {
+        &self.game
+    }
+
+    #[must_use]
+    pub fn into_game(self) -> Game<N, HALF_KOMI> {
+        self.game
+    }
+
+    #[must_use]
+    pub const fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Play the move and update the hash. Only the stacks which the move can
+    /// change are hashed again, the others keep their part of the hash.
+    pub fn step(&mut self, action: Move) {
+        let squares = changed_squares::<N>(action);
+        for &(row, column) in &squares {
+            self.hash ^= square_hash(&self.game, row, column);
+        }
+        self.game.step(action);
+        for &(row, column) in &squares {
+            self.hash ^= square_hash(&self.game, row, column);
+        }
+        self.hash ^= BLACK_TO_MOVE;
+    }
+}
+
+impl<const N: usize, const HALF_KOMI: i8> Default for HashedGame<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    fn default() -> Self {
+        Self::new(Game::default())
+    }
+}
+
+/// Row and column of the squares which a move can change. A spread can only
+/// change its own square and the squares up to the edge in its direction.
+fn changed_squares<const N: usize>(action: Move) -> Vec<(usize, usize)> {
+    let square = action.square();
+    let (mut row, mut column) = (usize::from(square.row()), usize::from(square.column()));
+    let mut squares = vec![(row, column)];
+    if let MoveKind::Spread(direction, _) = action.kind() {
+        loop {
+            match direction {
+                Direction::Up if row + 1 < N => row += 1,
+                Direction::Down if row > 0 => row -= 1,
+                Direction::Right if column + 1 < N => column += 1,
+                Direction::Left if column > 0 => column -= 1,
+                _ => break,
+            }
+            squares.push((row, column));
+        }
+    }
+    squares
+}
+
+/// The part of the Zobrist hash for the stack on one square.
+fn square_hash<const N: usize, const HALF_KOMI: i8>(
+    game: &Game<N, HALF_KOMI>,
+    row: usize,
+    column: usize,
+) -> u64 {
+    game.board
+        .iter()
+        .nth(row)
+        .and_then(|mut stacks| stacks.nth(column))
+        .map_or(0, |stack| {
+            stack_hash(
+                row * N + column,
+                stack.colors(),
+                stack.top().map(|(piece, _)| piece),
+            )
+        })
+}
+
+/// The XOR of a key for every piece of the stack by height and color,
+/// and for the kind of its top piece.
+fn stack_hash(square: usize, colors: impl IntoIterator<Item = Color>, top: Option<Piece>) -> u64 {
+    let square = square as u64;
+    let mut hash = 0;
+    for (height, color) in colors.into_iter().enumerate() {
+        hash ^= zobrist_key(
+            Feature::Piece,
+            square,
+            height as u64,
+            u64::from(color == Color::Black),
+        );
+    }
+    if let Some(piece) = top {
+        hash ^= zobrist_key(Feature::Top, square, 0, piece_index(piece));
+    }
+    hash
+}
+
+const fn piece_index(piece: Piece) -> u64 {
+    match piece {
+        Piece::Flat => 0,
+        Piece::Wall => 1,
+        Piece::Cap => 2,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Feature {
+    Piece = 1,
+    Top,
+    BlackToMove,
+}
+
+const BLACK_TO_MOVE: u64 = zobrist_key(Feature::BlackToMove, 0, 0, 0);
+
+/// Random key for a feature of a position.
+/// Instead of storing a table of keys for every board size, the keys are
+/// created by the `SplitMix64` finalizer, which is a bijection, so that every
+/// feature gets a distinct key which looks random.
+const fn zobrist_key(feature: Feature, square: u64, height: u64, value: u64) -> u64 {
+    let mut z = (((feature as u64) << 56) | (square << 40) | (height << 8) | value)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 impl From<Terminal> for f32 {
     fn from(value: Terminal) -> Self {
         match value {
@@ -172,6 +408,10 @@ pub mod safecrack {
             unimplemented!("not necessary for the test");
         }
 
+        fn adjudicate(&self) -> Terminal {
+            Terminal::Draw
+        }
+
         fn new_opening(_rng: &mut impl rand::prelude::Rng, _actions: &mut Vec<Option<u8>>) -> Self {
             unimplemented!("not necessary for the test");
         }
@@ -207,3 +447,89 @@ pub mod safecrack {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use fast_tak::{takparse::Tps, Game};
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::{Environment, HashedGame, Terminal, Transposable};
+
+    #[test]
+    fn transpositions_have_the_same_hash() {
+        // White plays b2 and c2 in a different order.
+        let first: Game<3, 0> = Game::from_ptn_moves(&["a1", "c3", "b2", "a3", "c2"]);
+        let second: Game<3, 0> = Game::from_ptn_moves(&["a1", "c3", "c2", "a3", "b2"]);
+        let other: Game<3, 0> = Game::from_ptn_moves(&["a1", "c3", "c2", "a3", "b1"]);
+        assert_eq!(first.zobrist_hash(), second.zobrist_hash());
+        assert_ne!(first.zobrist_hash(), other.zobrist_hash());
+
+        // The same pieces, but stacked in a different order.
+        let first: Game<3, 0> = "x3/x3/12,x2 1 3".parse::<Tps>().unwrap().into();
+        let second: Game<3, 0> = "x3/x3/21,x2 1 3".parse::<Tps>().unwrap().into();
+        assert_ne!(first.zobrist_hash(), second.zobrist_hash());
+    }
+
+    #[test]
+    fn distinct_positions_rarely_collide() {
+        const SEED: u64 = 4321;
+        let mut rng = StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        let mut positions = HashSet::new();
+        let mut hashes = HashSet::new();
+        for plies in 0..2000 {
+            let env: Game<6, 4> =
+                Game::new_opening_with_random_steps(&mut rng, &mut actions, plies % 60);
+            actions.clear();
+            let tps = Tps::from(env.clone()).to_string();
+            // The board and the player to move, without the move number.
+            let (position, _move_number) = tps.rsplit_once(' ').unwrap();
+            if positions.insert(position.to_string()) {
+                assert!(hashes.insert(env.zobrist_hash()), "collision for {tps}");
+            }
+        }
+        assert!(positions.len() > 1000);
+    }
+
+    #[test]
+    fn hash_is_updated_with_every_step() {
+        let mut rng = StdRng::seed_from_u64(8765);
+        let mut actions = Vec::new();
+        for _ in 0..20 {
+            let mut game = HashedGame::<5, 4>::default();
+            while game.game().terminal().is_none() {
+                assert_eq!(game.hash(), game.game().zobrist_hash());
+                game.game().populate_actions(&mut actions);
+                let action = *actions.choose(&mut rng).unwrap();
+                actions.clear();
+                game.step(action);
+            }
+            assert_eq!(game.hash(), game.game().zobrist_hash());
+        }
+
+        // Transpositions reach the same hash.
+        let mut first = HashedGame::<3, 0>::default();
+        let mut second = HashedGame::<3, 0>::default();
+        for ptn in ["a1", "c3", "b2", "a3", "c2"] {
+            first.step(ptn.parse().unwrap());
+        }
+        for ptn in ["a1", "c3", "c2", "a3", "b2"] {
+            second.step(ptn.parse().unwrap());
+        }
+        assert_eq!(first.hash(), second.hash());
+    }
+
+    #[test]
+    fn adjudication_counts_flats_with_komi() {
+        // White has one flat more than black.
+        let white_ahead: Game<3, 0> = "x3/2S,2,x/1,1,x 1 3".parse::<Tps>().unwrap().into();
+        assert_eq!(white_ahead.adjudicate(), Terminal::Win);
+        let black_to_move: Game<3, 0> = "x3/2S,2,x/1,1,x 2 3".parse::<Tps>().unwrap().into();
+        assert_eq!(black_to_move.adjudicate(), Terminal::Loss);
+        // A komi of one flat makes it a draw.
+        let komi: Game<3, 2> = "x3/2S,2,x/1,1,x 1 3".parse::<Tps>().unwrap().into();
+        assert_eq!(komi.adjudicate(), Terminal::Draw);
+    }
+}
@@ -74,6 +74,25 @@ impl Eval {
         }
     }
 
+    /// The value of the evaluation. Known results are discounted by
+    /// `discount` for every ply until the end of the game,
+    /// values have already been discounted during the search.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the discount is NaN.
+    #[must_use]
+    pub fn discounted(self, discount: f32) -> NotNan<f32> {
+        let result = match self {
+            Self::Value(x) => return x,
+            Self::Win(_) => 1.0,
+            Self::Loss(_) => -1.0,
+            Self::Draw(_) => 0.0,
+        };
+        let ply = self.ply().unwrap_or_default() as i32;
+        NotNan::new(discount.powi(ply) * result).expect("known evaluations cannot give NaN")
+    }
+
     /// # Panics
     ///
     /// Panics if the return value of the function is NaN.
@@ -92,26 +111,19 @@ impl Default for Eval {
     }
 }
 
+/// Known results are discounted with [`DISCOUNT_FACTOR`]. Everything which
+/// searches with a [`crate::search::SearchConfig`] should use
+/// [`Eval::discounted`] with its discount instead.
 impl From<Eval> for f32 {
     fn from(value: Eval) -> Self {
-        DISCOUNT_FACTOR.powi(value.ply().unwrap_or_default() as i32)
-            * match value {
-                Eval::Value(x) => x.into(),
-                Eval::Win(_) => 1.0,
-                Eval::Loss(_) => -1.0,
-                Eval::Draw(_) => 0.0,
-            }
+        value.discounted(DISCOUNT_FACTOR).into_inner()
     }
 }
 
+/// See `From<Eval> for f32`.
 impl From<Eval> for NotNan<f32> {
     fn from(eval: Eval) -> Self {
-        match eval {
-            Eval::Value(x) => x,
-            Eval::Win(_) | Eval::Draw(_) | Eval::Loss(_) => {
-                Self::new(f32::from(eval)).expect("known evaluations cannot give NaN")
-            }
-        }
+        eval.discounted(DISCOUNT_FACTOR)
     }
 }
 
@@ -164,7 +176,11 @@ impl Ord for Eval {
 
 #[cfg(test)]
 mod tests {
+    use fast_tak::Game;
+    use ordered_float::NotNan;
+
     use super::{Eval, CONTEMPT};
+    use crate::search::{env::Environment, DISCOUNT_FACTOR};
 
     #[test]
     fn eval_order() {
@@ -192,4 +208,35 @@ mod tests {
             Eval::Win(5),
         ]);
     }
+
+    #[test]
+    fn discounted_value_targets() {
+        // White builds a road on the c-file.
+        let moves = ["a1", "c1", "c2", "a2", "c3"];
+        let states: Vec<Game<3, 0>> = (0..moves.len())
+            .map(|ply| Game::from_ptn_moves(&moves[..ply]))
+            .collect();
+        let game: Game<3, 0> = Game::from_ptn_moves(&moves);
+
+        // Same procedure as the targets which are created during pre-training.
+        let mut value = Eval::from(game.terminal().expect("game should be over"));
+        for (distance, env) in states.iter().rev().enumerate() {
+            value = value.negate();
+            let expected = DISCOUNT_FACTOR.powi(distance as i32 + 1);
+            // Player who made the last move won.
+            let sign = if distance % 2 == 0 { 1.0 } else { -1.0 };
+            assert!(env.terminal().is_none());
+            assert!((f32::from(value) - sign * expected).abs() < f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn discount_is_applied_per_ply() {
+        assert!((Eval::Win(2).discounted(0.5).into_inner() - 0.25).abs() < f32::EPSILON);
+        assert!((Eval::Loss(1).discounted(0.5).into_inner() + 0.5).abs() < f32::EPSILON);
+        assert!((Eval::Win(7).discounted(1.0).into_inner() - 1.0).abs() < f32::EPSILON);
+        assert!(Eval::Draw(3).discounted(0.5).into_inner().abs() < f32::EPSILON);
+        let value = Eval::new_value(0.3).unwrap();
+        assert_eq!(value.discounted(0.5), NotNan::new(0.3).unwrap());
+    }
 }
@@ -1,8 +1,99 @@
 pub mod agent;
+pub mod book;
 pub mod env;
 pub mod eval;
 pub mod node;
+pub mod resign;
 
 // Discount, also known as gamma.
 pub const DISCOUNT_FACTOR: f32 = 0.997;
 pub const SERIES_DISCOUNT: f32 = 1.0 / (1.0 - DISCOUNT_FACTOR * DISCOUNT_FACTOR);
+
+/// Parse a discount per ply for a command line argument, which has to be
+/// in `(0, 1]`.
+///
+/// # Errors
+///
+/// Returns an error message if it is not a number in `(0, 1]`.
+pub fn parse_discount(s: &str) -> Result<f32, String> {
+    let discount: f32 = s.parse().map_err(|err| format!("{err}"))?;
+    if discount > 0.0 && discount <= 1.0 {
+        Ok(discount)
+    } else {
+        Err(format!("the discount should be in (0, 1], got {discount}"))
+    }
+}
+
+/// Settings for the selection step of the search.
+///
+/// PUCT selects the child which maximizes `Q + U + beta * std_dev`, where
+/// `U = C(s) * P(s, a) * sqrt(N(s)) / (1 + N(s, a))` and
+/// `C(s) = ln((1 + N(s) + base) / base) + init`.
+/// The uncertainty bonus scaled by `beta` is added on top of the
+/// prior-driven exploration, so raising either one explores more.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchConfig {
+    /// Weight of the uncertainty bonus.
+    pub beta: f32,
+    /// Exploration constant `c_puct` at zero visits.
+    pub exploration_init: f32,
+    /// Number of visits over which the exploration constant grows.
+    pub exploration_base: f32,
+    /// Value of children which have not been visited yet.
+    pub fpu: Fpu,
+    /// With `Some(k)`, every visited child of the root gets at least
+    /// `k * sqrt(P(s, a) * N(s))` visits, and those forced visits are
+    /// pruned again from the policy target, see
+    /// [`node::Node::improved_policy_target_with_config`].
+    pub forced_playouts: Option<f32>,
+    /// Loss counted for every simulation through a child which has not
+    /// been backed up yet, so that simulations which are selected before
+    /// the previous ones are evaluated spread over different paths.
+    /// Zero disables it.
+    pub virtual_loss: f32,
+    /// Discount per ply, also known as gamma. Backed up values are
+    /// multiplied by it and their variances by its square at every ply,
+    /// and known results are discounted by their distance to the end of
+    /// the game when they are compared to values during selection.
+    pub discount: f32,
+    /// Share the statistics of positions which are reached by different
+    /// sequences of actions in [`node::Node::simulate_with_config`],
+    /// see [`node::transposition`]. Environments without a
+    /// [`env::Environment::transposition_hash`] ignore it.
+    pub transpositions: bool,
+}
+
+/// First play urgency, the value used for unvisited children during
+/// selection, from the perspective of the parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fpu {
+    /// The value of the parent at the time the child was created.
+    Parent,
+    /// The current value of the parent minus the constant times the
+    /// total prior probability of the children which have been visited,
+    /// so that fewer new children are tried once the likely ones have been.
+    Reduction(f32),
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self::with_beta(0.0)
+    }
+}
+
+impl SearchConfig {
+    /// Default exploration with the given uncertainty bonus.
+    #[must_use]
+    pub const fn with_beta(beta: f32) -> Self {
+        Self {
+            beta,
+            exploration_init: node::policy::EXPLORATION_INIT,
+            exploration_base: node::policy::EXPLORATION_BASE,
+            fpu: Fpu::Parent,
+            forced_playouts: None,
+            virtual_loss: 0.0,
+            discount: DISCOUNT_FACTOR,
+            transpositions: false,
+        }
+    }
+}
@@ -4,7 +4,7 @@ use ordered_float::NotNan;
 use rand::Rng;
 use rand_distr::{Distribution, Gumbel};
 
-use super::Node;
+use super::{Node, ROOT};
 use crate::{
     search::{
         agent::Agent,
@@ -14,6 +14,8 @@ use crate::{
             mcts::{ActionPolicy, Forward},
             policy::{sigma_select, softmax},
         },
+        SearchConfig,
+        DISCOUNT_FACTOR,
     },
     target::Replay,
 };
@@ -27,6 +29,7 @@ pub struct BatchedMCTS<const BATCH_SIZE: usize, E: Environment> {
     actions: [Vec<E::Action>; BATCH_SIZE],
     trajectories: [Vec<usize>; BATCH_SIZE],
     replays: [Replay<E>; BATCH_SIZE],
+    discount: f32,
 }
 
 impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
@@ -43,6 +46,28 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
             trajectories: std::array::from_fn(|_| Vec::new()),
             replays: std::array::from_fn(|i| Replay::new(envs[i].clone())),
             envs,
+            discount: DISCOUNT_FACTOR,
+        }
+    }
+
+    /// Search with this discount per ply instead of [`DISCOUNT_FACTOR`],
+    /// see [`SearchConfig::discount`].
+    #[must_use]
+    pub const fn with_discount(mut self, discount: f32) -> Self {
+        self.discount = discount;
+        self
+    }
+
+    /// Discount per ply of the search, which the targets should use too.
+    pub const fn discount(&self) -> f32 {
+        self.discount
+    }
+
+    /// Settings of the search with the uncertainty bonus `beta`.
+    const fn config(&self, beta: f32) -> SearchConfig {
+        SearchConfig {
+            discount: self.discount,
+            ..SearchConfig::with_beta(beta)
         }
     }
 
@@ -63,6 +88,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
     pub fn simulate<A: Agent<E>>(&mut self, agent: &A, betas: &[f32]) {
         assert!(self.actions.iter().all(Vec::is_empty));
         assert!(self.trajectories.iter().all(Vec::is_empty));
+        let configs: Vec<_> = betas.iter().map(|beta| self.config(*beta)).collect();
 
         // Forward pass.
         let (batch, forward): (Vec<_>, Vec<_>) = self
@@ -71,18 +97,21 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
             .zip(&self.envs)
             .zip(&mut self.actions)
             .zip(&mut self.trajectories)
-            .zip(betas)
-            .filter_map(|((((node, env), actions), trajectory), beta)| {
-                match node.forward(trajectory, env.clone(), *beta) {
+            .zip(&configs)
+            .filter_map(|((((node, env), actions), trajectory), config)| {
+                match node.forward_with_config(trajectory, env.clone(), config) {
                     Forward::Known(eval) => {
                         // If the result is known just propagate it now.
-                        node.backward_known_eval(trajectory.drain(..), eval);
+                        node.backward_known_eval_with_config(trajectory.drain(..), eval, config);
                         None
                     }
                     Forward::NeedsNetwork(env) => {
                         env.populate_actions(actions);
                         // We are taking the actions because we need owned Vecs.
-                        Some(((env, std::mem::take(actions)), (node, trajectory, actions)))
+                        Some((
+                            (env, std::mem::take(actions)),
+                            (node, trajectory, actions, config),
+                        ))
                     }
                 }
             })
@@ -102,13 +131,13 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
             )
             .zip(actions_batch)
             .for_each(|((forward, output), mut moved_actions)| {
-                let (node, trajectory, old_actions) = forward;
+                let (node, trajectory, old_actions, config) = forward;
                 let (policy, value, uncertainty) = output;
 
                 // Calculate probabilities from logits.
                 let probabilities = softmax(policy.clone().into_iter().map(|(_, p)| p));
                 // Do backwards pass.
-                node.backward_network_eval(
+                node.backward_network_eval_with_config(
                     trajectory.drain(..),
                     policy
                         .into_iter()
@@ -120,6 +149,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                         }),
                     value,
                     uncertainty,
+                    config,
                 );
                 // Restore old actions.
                 moved_actions.clear();
@@ -183,21 +213,63 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
             .expect("the number of nodes and envs should be equal to BATCH_SIZE")
     }
 
+    /// Adjudicate the games which reached `max_plies` without ending,
+    /// see [`Environment::adjudicate`]. The results can be passed to
+    /// [`BatchedMCTS::restart_terminal_or_resigned_envs`].
+    #[allow(clippy::missing_panics_doc)]
+    pub fn adjudicate_long_games(&self, max_plies: u16) -> [Option<Terminal>; BATCH_SIZE] {
+        self.envs
+            .iter()
+            .map(|env| {
+                (env.terminal().is_none() && env.steps() >= max_plies).then(|| env.adjudicate())
+            })
+            .collect::<Vec<_>>()
+            .try_into()
+            .expect("the number of envs should be equal to BATCH_SIZE")
+    }
+
     pub fn restart_terminal_envs<'a>(
         &'a mut self,
         rng: &'a mut impl Rng,
+    ) -> impl Iterator<Item = Option<(Terminal, Replay<E>)>> + 'a {
+        self.restart_terminal_or_resigned_envs(rng, std::iter::repeat(None))
+    }
+
+    /// Same as [`BatchedMCTS::restart_terminal_envs`], but also restarts
+    /// games which were adjudicated. The adjudicated result is from the
+    /// perspective of the player to move, and is only used if the game
+    /// did not end on its own.
+    pub fn restart_terminal_or_resigned_envs<'a, R: Rng>(
+        &'a mut self,
+        rng: &'a mut R,
+        adjudicated: impl IntoIterator<Item = Option<Terminal>> + 'a,
+    ) -> impl Iterator<Item = Option<(Terminal, Replay<E>)>> + 'a {
+        self.restart_terminal_or_resigned_envs_with(rng, adjudicated, |rng: &mut R, actions| {
+            E::new_opening(rng, actions)
+        })
+    }
+
+    /// Same as [`BatchedMCTS::restart_terminal_or_resigned_envs`], but the
+    /// new games start from the positions returned by `opening`,
+    /// for example from an opening book.
+    pub fn restart_terminal_or_resigned_envs_with<'a, R: Rng>(
+        &'a mut self,
+        rng: &'a mut R,
+        adjudicated: impl IntoIterator<Item = Option<Terminal>> + 'a,
+        mut opening: impl FnMut(&mut R, &mut Vec<E::Action>) -> E + 'a,
     ) -> impl Iterator<Item = Option<(Terminal, Replay<E>)>> + 'a {
         self.nodes
             .iter_mut()
             .zip(&mut self.envs)
             .zip(&mut self.actions)
             .zip(&mut self.replays)
-            .map(|(((node, env), actions), replay)| {
-                let terminal = env.terminal();
+            .zip(adjudicated)
+            .map(move |((((node, env), actions), replay), adjudicated)| {
+                let terminal = env.terminal().or(adjudicated);
                 if terminal.is_some() {
                     // Reset game.
-                    *env = E::new_opening(rng, actions);
-                    *node = Node::default();
+                    *env = opening(rng, actions);
+                    node.clear();
                 }
                 terminal.map(|t| (t, std::mem::replace(replay, Replay::new(env.clone()))))
             })
@@ -222,6 +294,8 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
 
         // Do a single batched step to make sure all roots are initialized.
         self.simulate(agent, betas);
+        let discount = self.discount;
+        let config = self.config(0.0);
 
         // Generate Gumbel noise.
         let gumbel_distr = Gumbel::new(0.0, 1.0).unwrap();
@@ -230,13 +304,16 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
         // Sample actions based on logits + Gumbel noise.
         let mut selected_sets: Vec<Vec<_>> = self
             .nodes
-            .iter_mut()
+            .iter()
             .map(|node| {
                 let mut selected_set: Vec<_> = node
-                    .children
-                    .iter_mut()
+                    .children()
+                    .iter()
                     .zip(gumbel_noise.by_ref())
-                    .map(|((a, child), gumbel_noise)| (child.logit + gumbel_noise, a, child))
+                    .enumerate()
+                    .map(|(index, ((a, child), gumbel_noise))| {
+                        (child.logit + gumbel_noise, a.clone(), index)
+                    })
                     .collect();
                 selected_set.sort_by_key(|(x, ..)| Reverse(*x));
                 selected_set.truncate(sampled_actions);
@@ -254,13 +331,15 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
 
             for i in 0..remaining_actions {
                 let mut nodes_and_envs: Vec<_> = selected_sets
-                    .iter_mut()
+                    .iter()
+                    .zip(&mut self.nodes)
                     .zip(&self.envs)
-                    .map(|(set, env)| {
+                    .map(|((set, node), env)| {
                         let mut env = env.clone();
                         let i: usize = i % set.len();
                         env.step(set[i].1.clone());
-                        (&mut *set[i].2, env)
+                        let id = node.child_id(ROOT, set[i].2);
+                        (node, id, env)
                     })
                     .collect();
                 for _ in 0..visits_per_action {
@@ -276,11 +355,16 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                         .zip(&mut self.actions)
                         .zip(&mut self.trajectories)
                         .zip(betas)
-                        .filter_map(|((((node, env), actions), trajectory), _beta)| {
-                            match node.forward(trajectory, env.clone(), 0.0 /* *beta */) {
+                        .filter_map(|((((node, id, env), actions), trajectory), _beta)| {
+                            match node.forward_from(*id, trajectory, env.clone(), &config) {
                                 Forward::Known(eval) => {
                                     // If the result is known just propagate it now.
-                                    node.backward_known_eval(trajectory.drain(..), eval);
+                                    node.backward_known_eval_from(
+                                        *id,
+                                        trajectory.drain(..),
+                                        eval,
+                                        &config,
+                                    );
                                     None
                                 }
                                 Forward::NeedsNetwork(env) => {
@@ -288,7 +372,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                                     // We are taking the actions because we need owned Vecs.
                                     Some((
                                         (env, std::mem::take(actions)),
-                                        (node, trajectory, actions),
+                                        (node, *id, trajectory, actions),
                                     ))
                                 }
                             }
@@ -309,13 +393,14 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                         )
                         .zip(actions_batch)
                         .for_each(|((forward, output), mut moved_actions)| {
-                            let (node, trajectory, old_actions) = forward;
+                            let (node, id, trajectory, old_actions) = forward;
                             let (policy, value, uncertainty) = output;
 
                             // Calculate probabilities from logits.
                             let probabilities = softmax(policy.clone().into_iter().map(|(_, p)| p));
                             // Do backwards pass.
-                            node.backward_network_eval(
+                            node.backward_network_eval_from(
+                                id,
                                 trajectory.drain(..),
                                 policy.into_iter().zip(probabilities).map(
                                     |((action, logit), probability)| ActionPolicy {
@@ -326,6 +411,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                                 ),
                                 value,
                                 uncertainty,
+                                &config,
                             );
                             // Restore old actions.
                             moved_actions.clear();
@@ -340,12 +426,15 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
             remaining_actions /= 2;
 
             // Halve the number of actions.
-            for (selected_set, &beta) in selected_sets.iter_mut().zip(betas) {
-                selected_set.sort_by_key(|(logits_plus_gumbel, _, child)| {
+            for ((selected_set, node), &beta) in
+                selected_sets.iter_mut().zip(&self.nodes).zip(betas)
+            {
+                selected_set.sort_by_key(|(logits_plus_gumbel, _, index)| {
+                    let child = &node.children()[*index].1;
                     Reverse(
                         logits_plus_gumbel
                             + sigma_select(
-                                child.evaluation.negate().into(),
+                                child.q_value(discount),
                                 child.std_dev,
                                 beta,
                                 visits_to_most_visited_action as f32,
@@ -364,7 +453,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                     1,
                     "After sequential halving, every set should have exactly 1 action left"
                 );
-                selected_set.pop().unwrap().1.clone()
+                selected_set.pop().unwrap().1
             })
             .collect::<Vec<_>>()
             .try_into()
@@ -373,13 +462,13 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
         // Recompute root statistics.
         self.nodes.iter_mut().for_each(|node| {
             node.visit_count = node
-                .children
+                .children()
                 .iter()
                 .map(|(_, child)| child.visit_count)
                 .sum::<u32>()
                 + 1;
 
-            let evaluations = node.children.iter().map(|(_, child)| &child.evaluation);
+            let evaluations = node.children().iter().map(|(_, child)| &child.evaluation);
             if evaluations.clone().any(Eval::is_loss) || evaluations.clone().all(Eval::is_known) {
                 // Node is solved.
                 node.evaluation = evaluations.min().unwrap().negate();
@@ -389,7 +478,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                 // Here we are ignoring the original network eval because we no longer have
                 // access to it.
                 let visited_children = node
-                    .children
+                    .children()
                     .iter()
                     .map(|(_, child)| child)
                     .filter(|child| child.visit_count > 0);
@@ -398,7 +487,7 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
                     .map(|child| child.probability)
                     .sum();
                 let weighted_q: NotNan<f32> = visited_children
-                    .map(|child| child.probability * f32::from(child.evaluation.negate()))
+                    .map(|child| child.probability * child.q_value(discount))
                     .sum();
                 node.evaluation = Eval::new_not_nan_value(weighted_q / sum_of_probabilities);
             }
@@ -409,3 +498,41 @@ impl<const BATCH_SIZE: usize, E: Environment> BatchedMCTS<BATCH_SIZE, E> {
         selected
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fast_tak::{takparse::Tps, Game};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::BatchedMCTS;
+    use crate::search::env::{Environment, Terminal};
+
+    #[test]
+    fn games_past_the_ply_cap_are_adjudicated() {
+        const MAX_PLIES: u16 = 4;
+        // White to move with one flat more than black.
+        let long: Game<3, 0> = "x3/2S,2,x/1,1,x 1 3".parse::<Tps>().unwrap().into();
+        assert_eq!(long.steps(), MAX_PLIES);
+        let short = Game::default();
+        let mut batched_mcts = BatchedMCTS::from_envs([long.clone(), short]);
+
+        let adjudicated = batched_mcts.adjudicate_long_games(MAX_PLIES);
+        assert_eq!(adjudicated, [Some(Terminal::Win), None]);
+        assert_eq!(batched_mcts.adjudicate_long_games(MAX_PLIES + 1), [
+            None, None
+        ]);
+
+        let mut rng = StdRng::seed_from_u64(975);
+        let finished: Vec<_> = batched_mcts
+            .restart_terminal_or_resigned_envs(&mut rng, adjudicated)
+            .collect();
+        let (terminal, replay) = finished[0].as_ref().unwrap();
+        assert_eq!(*terminal, Terminal::Win);
+        assert!(replay.env == long);
+        assert!(finished[1].is_none());
+        // The adjudicated game was restarted.
+        assert!(batched_mcts
+            .nodes_and_envs()
+            .all(|(_, env)| env.steps() < MAX_PLIES));
+    }
+}
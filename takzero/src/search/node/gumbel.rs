@@ -0,0 +1,141 @@
+use std::cmp::Reverse;
+
+use ordered_float::NotNan;
+use rand::Rng;
+use rand_distr::{Distribution, Gumbel};
+
+use super::{policy::sigma_select, Node};
+use crate::search::{agent::Agent, env::Environment, SearchConfig};
+
+impl<E: Environment> Node<E> {
+    /// Search from this node (the root) with Gumbel `AlphaZero`, and return
+    /// the selected action together with the improved policy target.
+    ///
+    /// `num_considered` actions are sampled without replacement by adding
+    /// Gumbel noise to the logits, and the visits are split between them with
+    /// sequential halving: after every phase the half with the lowest
+    /// `logit + gumbel + sigma(completed Q)` is dropped, until one is left.
+    /// Below the root, children are selected with PUCT as usual.
+    ///
+    /// The search uses at most `num_visits` simulations after the root has
+    /// been initialized, spread evenly over the phases.
+    /// Unlike [`BatchedMCTS::gumbel_sequential_halving`], the number of
+    /// visits does not need to be a multiple of anything.
+    ///
+    /// [`BatchedMCTS::gumbel_sequential_halving`]:
+    /// super::batched::BatchedMCTS::gumbel_sequential_halving
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_considered` is zero, if the position is terminal,
+    /// or if the agent does not return a prediction when needed.
+    #[allow(clippy::type_complexity)]
+    pub fn gumbel_root_action<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: &E,
+        num_considered: usize,
+        num_visits: u32,
+        rng: &mut impl Rng,
+    ) -> (E::Action, Box<[(E::Action, NotNan<f32>)]>) {
+        assert!(num_considered > 0, "at least one action must be considered");
+        let config = SearchConfig::default();
+        if self.needs_initialization() {
+            self.simulate_with_config(agent, env.clone(), &config);
+        }
+        assert!(
+            !self.children().is_empty(),
+            "cannot select an action in a terminal position"
+        );
+
+        // Sample actions without replacement with the Gumbel top-k trick.
+        let gumbel = Gumbel::<f32>::new(0.0, 1.0).expect("standard Gumbel should be valid");
+        let perturbed: Vec<NotNan<f32>> = self
+            .children()
+            .iter()
+            .zip(gumbel.sample_iter(rng))
+            .map(|((_, child), noise)| child.logit + noise)
+            .collect();
+        let mut considered: Vec<usize> = (0..self.children().len()).collect();
+        considered.sort_by_key(|&index| Reverse(perturbed[index]));
+        considered.truncate(num_considered);
+
+        // Sequential halving.
+        let phases = considered.len().next_power_of_two().ilog2().max(1);
+        let mut remaining_visits = num_visits;
+        for _ in 0..phases {
+            let visits_per_action = (num_visits / (phases * considered.len() as u32)).max(1);
+            for &index in &considered {
+                for _ in 0..visits_per_action.min(remaining_visits) {
+                    self.simulate_child(agent, env.clone(), index, &config);
+                    remaining_visits -= 1;
+                }
+            }
+
+            let max_visits = self.most_visited_count();
+            considered.sort_by_key(|&index| {
+                Reverse(perturbed[index] + self.completed_sigma(index, max_visits))
+            });
+            considered.truncate(considered.len().div_ceil(2));
+        }
+
+        let selected = self.children()[considered[0]].0.clone();
+        let target = self.improved_policy_target(self.most_visited_count());
+        (selected, target)
+    }
+
+    /// The sigma transform of the completed Q-value of the child, which is
+    /// the value of this node if the child has not been visited.
+    fn completed_sigma(&self, index: usize, max_visits: f32) -> NotNan<f32> {
+        let child = &self.children()[index].1;
+        let completed_value = if child.visit_count > 0 {
+            child.evaluation.negate()
+        } else {
+            self.evaluation
+        };
+        sigma_select(completed_value.into(), child.std_dev, 0.0, max_visits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::super::Node;
+    use crate::search::env::safecrack::{SafeCrack, SafeCracker};
+
+    #[test]
+    fn gumbel_root_action_finds_the_key() {
+        const VISITS: u32 = 100;
+        let env = SafeCrack::new(vec![3]);
+        let mut rng = StdRng::seed_from_u64(123);
+        let mut root = Node::default();
+        let (action, policy) = root.gumbel_root_action(&SafeCracker, &env, 16, VISITS, &mut rng);
+
+        // There are fewer actions than can be considered, so all are tried.
+        assert!(root
+            .children()
+            .iter()
+            .all(|(_, child)| child.visit_count > 0));
+        assert_eq!(action, Some(3));
+        let (best, _) = policy.iter().max_by_key(|(_, p)| *p).unwrap();
+        assert_eq!(*best, Some(3));
+        let sum: f32 = policy.iter().map(|(_, p)| p.into_inner()).sum();
+        assert!((sum - 1.0).abs() < 1e-5);
+        // The budget includes neither the initialization nor more than asked.
+        assert!(root.visit_count() <= VISITS + 1);
+        assert!(root.visit_count() > VISITS / 2);
+    }
+
+    #[test]
+    fn a_single_considered_action_gets_every_visit() {
+        const VISITS: u32 = 20;
+        let env = SafeCrack::new(vec![7]);
+        let mut rng = StdRng::seed_from_u64(456);
+        let mut root = Node::default();
+        let (action, _) = root.gumbel_root_action(&SafeCracker, &env, 1, VISITS, &mut rng);
+        let (_, child) = root.children().iter().find(|(a, _)| *a == action).unwrap();
+        assert_eq!(child.visit_count, VISITS);
+        assert_eq!(root.visit_count, VISITS + 1);
+    }
+}
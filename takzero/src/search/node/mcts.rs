@@ -17,12 +17,16 @@
 //! this node is a win, or if all children are wins then
 //! this is a loss.
 
+use std::time::Instant;
+
 use ordered_float::NotNan;
 
 use super::{
-    super::{agent::Agent, env::Environment, eval::Eval, DISCOUNT_FACTOR},
+    super::{agent::Agent, env::Environment, eval::Eval, SearchConfig},
     policy::softmax,
     Node,
+    Statistics,
+    ROOT,
 };
 
 /// Return value from [`Node::forward`] indicating if the evaluation is known
@@ -44,7 +48,7 @@ pub struct ActionPolicy<E: Environment> {
     pub probability: NotNan<f32>,
 }
 
-impl<E: Environment> Node<E> {
+impl Statistics {
     #[inline]
     fn update_mean_value(&mut self, value: f32) {
         if let Eval::Value(mean_value) = &mut self.evaluation {
@@ -59,65 +63,94 @@ impl<E: Environment> Node<E> {
         }
         self.std_dev += (-self.std_dev + variance.sqrt()) / (self.visit_count as f32);
     }
+}
 
+impl<E: Environment> Node<E> {
     // TODO: Once pruning is added back, we can skip traversing all evaluations to
     // find min in the case of a loss because that is will be the first loss we
     // find.
-    fn node_solver(&mut self, child_eval: Eval) {
-        let evaluations = self.children.iter().map(|(_, node)| node.evaluation);
+    fn node_solver(&mut self, id: usize, child_eval: Eval) {
+        let evaluations = self
+            .children_of(self.node(id))
+            .iter()
+            .map(|(_, node)| node.evaluation);
 
         // If we can choose a loss for the opponent, this position is a win.
         // If all moves are wins for the opponent, this node is a loss.
         // If all moves are wins or draws for the opponent, we choose to draw.
         if child_eval.is_loss() || evaluations.clone().all(|e| e.is_known()) {
-            self.evaluation = evaluations.min().unwrap().negate();
-            self.std_dev = NotNan::default();
+            let evaluation = evaluations.min().unwrap().negate();
+            let node = self.node_mut(id);
+            node.evaluation = evaluation;
+            node.std_dev = NotNan::default();
         }
     }
 
     fn propagate_child_eval(
         &mut self,
+        id: usize,
         child_eval: Eval,
         child_variance: NotNan<f32>,
+        discount: f32,
     ) -> Propagated {
-        self.node_solver(child_eval);
+        self.node_solver(id, child_eval);
+        let node = self.node_mut(id);
 
         // If the position is solved, we just propagate the solved value instead.
-        if self.evaluation.is_known() {
+        if node.evaluation.is_known() {
             return Propagated {
-                eval: self.evaluation,
-                variance: self.std_dev * self.std_dev,
+                eval: node.evaluation,
+                variance: node.std_dev * node.std_dev,
             };
         }
         // Otherwise this position is not known and we just
         // back-propagate the child result.
-        let negated = child_eval.negate().into();
-        self.update_mean_value(negated);
-        self.update_standard_deviation(child_variance);
+        let negated = child_eval.negate().discounted(discount).into_inner();
+        node.update_mean_value(negated);
+        node.update_standard_deviation(child_variance);
 
         Propagated {
-            eval: Eval::new_value(negated * DISCOUNT_FACTOR).unwrap(),
-            variance: child_variance * DISCOUNT_FACTOR * DISCOUNT_FACTOR,
+            eval: Eval::new_value(negated * discount).unwrap(),
+            variance: child_variance * discount * discount,
         }
     }
 
     /// Run the forward part of MCTS.
     /// One of `backward_known_eval` and `backward_network_eval`
     /// must be called afterwards.
-    pub fn forward(&mut self, trajectory: &mut Vec<usize>, mut env: E, beta: f32) -> Forward<E> {
+    pub fn forward(&mut self, trajectory: &mut Vec<usize>, env: E, beta: f32) -> Forward<E> {
+        self.forward_with_config(trajectory, env, &SearchConfig::with_beta(beta))
+    }
+
+    /// Same as [`Node::forward`], but with all search settings.
+    pub fn forward_with_config(
+        &mut self,
+        trajectory: &mut Vec<usize>,
+        env: E,
+        config: &SearchConfig,
+    ) -> Forward<E> {
+        self.forward_from(ROOT, trajectory, env, config)
+    }
+
+    /// Same as [`Node::forward_with_config`], but from the node `id`,
+    /// which the trajectory starts from.
+    pub(super) fn forward_from(
+        &mut self,
+        mut id: usize,
+        trajectory: &mut Vec<usize>,
+        mut env: E,
+        config: &SearchConfig,
+    ) -> Forward<E> {
         debug_assert!(trajectory.is_empty());
-        let mut node = self;
 
         loop {
+            let node = self.node_mut(id);
             node.visit_count += 1;
-            #[cfg(feature = "virtual")]
-            {
-                node.virtual_visits += 1;
-            }
-            // TODO: Prune all known results earlier
-            // once visit count is not used for policy target.
-            // Or don't - searching can still help find slower losses.
-            if node.is_terminal() {
+            node.virtual_visits += 1;
+            // Solved nodes are not searched any further, their result is
+            // certain. The visit is still counted, so that the visit counts
+            // of the children still add up for the policy target.
+            if node.evaluation.is_known() {
                 break Forward::Known(node.evaluation);
             }
             if node.needs_initialization() {
@@ -129,37 +162,61 @@ impl<E: Environment> Node<E> {
                 break Forward::NeedsNetwork(env);
             }
 
-            let index = node.select_with_puct(beta);
+            // Forced playouts only apply to the root.
+            let node = self.node(id);
+            let index = trajectory
+                .is_empty()
+                .then(|| self.select_forced_playout_of(node, config))
+                .flatten()
+                .unwrap_or_else(|| self.select_with_puct_config_of(node, config));
             trajectory.push(index);
-            let (action, child) = &mut node.children[index];
+            let (action, _) = &self.children_of(node)[index];
             env.step(action.clone());
-            node = child;
+            id = self.child_id(id, index);
         }
     }
 
     /// Propagate a known eval through the tree.
     pub fn backward_known_eval(
         &mut self,
+        trajectory: impl Iterator<Item = usize>,
+        eval: Eval,
+    ) -> Propagated {
+        self.backward_known_eval_with_config(trajectory, eval, &SearchConfig::default())
+    }
+
+    /// Same as [`Node::backward_known_eval`], but with all search settings.
+    pub fn backward_known_eval_with_config(
+        &mut self,
+        trajectory: impl Iterator<Item = usize>,
+        eval: Eval,
+        config: &SearchConfig,
+    ) -> Propagated {
+        self.backward_known_eval_from(ROOT, trajectory, eval, config)
+    }
+
+    /// Same as [`Node::backward_known_eval_with_config`], but for a
+    /// trajectory from the node `id`, see [`Node::forward_from`].
+    pub(super) fn backward_known_eval_from(
+        &mut self,
+        id: usize,
         mut trajectory: impl Iterator<Item = usize>,
         eval: Eval,
+        config: &SearchConfig,
     ) -> Propagated {
-        if let Some(index) = trajectory.next() {
-            let Propagated {
-                eval: child_eval,
-                variance: child_variance,
-            } = self.children[index].1.backward_known_eval(trajectory, eval);
-            #[cfg(feature = "virtual")]
-            {
-                self.virtual_visits -= 1;
-            }
-            self.propagate_child_eval(child_eval, child_variance)
-        } else {
+        self.node_mut(id).virtual_visits -= 1;
+        let Some(index) = trajectory.next() else {
             // Leaf reached, time to propagate upwards.
-            Propagated {
+            return Propagated {
                 eval,
                 variance: NotNan::default(),
-            }
-        }
+            };
+        };
+        let Propagated {
+            eval: child_eval,
+            variance: child_variance,
+        } = self.backward_known_eval_from(self.child_id(id, index), trajectory, eval, config);
+        self.propagate_child_eval(id, child_eval, child_variance, config.discount)
     }
 
     /// Initialize a leaf node and propagate a network evaluation
@@ -170,56 +227,106 @@ impl<E: Environment> Node<E> {
     /// Panics if any of the policies is NaN.
     pub fn backward_network_eval(
         &mut self,
+        trajectory: impl Iterator<Item = usize>,
+        policy: impl Iterator<Item = ActionPolicy<E>>,
+        value: f32,
+        variance: f32,
+    ) -> Propagated {
+        self.backward_network_eval_with_config(
+            trajectory,
+            policy,
+            value,
+            variance,
+            &SearchConfig::default(),
+        )
+    }
+
+    /// Same as [`Node::backward_network_eval`], but with all search settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the policies is NaN.
+    pub fn backward_network_eval_with_config(
+        &mut self,
+        trajectory: impl Iterator<Item = usize>,
+        policy: impl Iterator<Item = ActionPolicy<E>>,
+        value: f32,
+        variance: f32,
+        config: &SearchConfig,
+    ) -> Propagated {
+        self.backward_network_eval_from(ROOT, trajectory, policy, value, variance, config)
+    }
+
+    /// Same as [`Node::backward_network_eval_with_config`], but for a
+    /// trajectory from the node `id`, see [`Node::forward_from`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the policies is NaN.
+    pub(super) fn backward_network_eval_from(
+        &mut self,
+        id: usize,
         mut trajectory: impl Iterator<Item = usize>,
         policy: impl Iterator<Item = ActionPolicy<E>>,
         value: f32,
         variance: f32,
+        config: &SearchConfig,
     ) -> Propagated {
+        self.node_mut(id).virtual_visits -= 1;
         if let Some(index) = trajectory.next() {
             let Propagated {
                 eval: child_eval,
                 variance: child_variance,
-            } = self.children[index]
-                .1
-                .backward_network_eval(trajectory, policy, value, variance);
-            #[cfg(feature = "virtual")]
-            {
-                self.virtual_visits -= 1;
-            }
-            self.propagate_child_eval(child_eval, child_variance)
+            } = self.backward_network_eval_from(
+                self.child_id(id, index),
+                trajectory,
+                policy,
+                value,
+                variance,
+                config,
+            );
+            self.propagate_child_eval(id, child_eval, child_variance, config.discount)
         } else {
             // Update mean value and standard deviation.
             // Note that this is not the same as self.propagate_child_eval()
             // because we do not negate!
-            self.update_mean_value(value);
+            let node = self.node_mut(id);
+            node.update_mean_value(value);
             let variance = NotNan::new(variance).expect("uncertainty/variance should not be NaN");
-            self.update_standard_deviation(variance);
-
-            // Finish leaf initialization.
-            self.children = policy
-                .map(
-                    |ActionPolicy {
-                         action,
-                         logit,
-                         probability,
-                     }| {
-                        (
-                            action,
-                            Self::from_logit_and_probability_and_parent_value_and_std_dev(
-                                logit,
-                                probability,
-                                self.evaluation.into(),
-                                self.std_dev,
-                            ),
-                        )
-                    },
-                )
-                .collect();
+            node.update_standard_deviation(variance);
+
+            // Finish leaf initialization. Simulations which run at the same
+            // time can reach the same leaf, only the first expansion is kept
+            // so that the sub-tree of the others is not thrown away.
+            if node.children.is_empty() {
+                let parent_value = node.evaluation.discounted(config.discount);
+                let parent_std_dev = node.std_dev;
+                self.expand(
+                    id,
+                    policy.map(
+                        |ActionPolicy {
+                             action,
+                             logit,
+                             probability,
+                         }| {
+                            (
+                                action,
+                                Statistics::from_logit_and_probability_and_parent_value_and_std_dev(
+                                    logit,
+                                    probability,
+                                    parent_value,
+                                    parent_std_dev,
+                                ),
+                            )
+                        },
+                    ),
+                );
+            }
 
             Propagated {
-                eval: Eval::new_value(value * DISCOUNT_FACTOR)
+                eval: Eval::new_value(value * config.discount)
                     .expect("value prediction should not be NaN"),
-                variance: variance * DISCOUNT_FACTOR * DISCOUNT_FACTOR,
+                variance: variance * config.discount * config.discount,
             }
         }
     }
@@ -233,9 +340,45 @@ impl<E: Environment> Node<E> {
     /// Panics if the agent does not return a prediction
     /// when needed.
     pub fn simulate_simple<A: Agent<E>>(&mut self, agent: &A, env: E, beta: f32) -> Propagated {
+        self.simulate_with_config(agent, env, &SearchConfig::with_beta(beta))
+    }
+
+    /// Same as [`Node::simulate_simple`], but with all search settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the agent does not return a prediction
+    /// when needed.
+    pub fn simulate_with_config<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: E,
+        config: &SearchConfig,
+    ) -> Propagated {
+        if config.transpositions && env.transposition_hash().is_some() {
+            return self.simulate_with_transpositions(agent, env, config);
+        }
+        self.simulate_from(ROOT, agent, env, config)
+    }
+
+    /// Run a simulation from the node `id`, without transpositions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the agent does not return a prediction
+    /// when needed.
+    fn simulate_from<A: Agent<E>>(
+        &mut self,
+        id: usize,
+        agent: &A,
+        env: E,
+        config: &SearchConfig,
+    ) -> Propagated {
         let mut trajectory = Vec::new();
-        match self.forward(&mut trajectory, env, beta) {
-            Forward::Known(eval) => self.backward_known_eval(trajectory.into_iter(), eval),
+        match self.forward_from(id, &mut trajectory, env, config) {
+            Forward::Known(eval) => {
+                self.backward_known_eval_from(id, trajectory.into_iter(), eval, config)
+            }
             Forward::NeedsNetwork(env) => {
                 let mut actions = [Vec::new()];
                 env.populate_actions(&mut actions[0]);
@@ -246,7 +389,8 @@ impl<E: Environment> Node<E> {
                 // Calculate probabilities from logits.
                 let probabilities = softmax(policy.clone().into_iter().map(|(_, p)| p));
                 // Do backwards pass.
-                self.backward_network_eval(
+                self.backward_network_eval_from(
+                    id,
                     trajectory.into_iter(),
                     policy
                         .into_iter()
@@ -258,24 +402,140 @@ impl<E: Environment> Node<E> {
                         }),
                     value,
                     uncertainty,
+                    config,
                 )
             }
         }
     }
+
+    /// Run a simulation through the child at `index`, no matter which child
+    /// selection would pick. Used by root searches which decide themselves
+    /// which children to visit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the agent does not return a prediction
+    /// when needed.
+    pub(super) fn simulate_child<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        mut env: E,
+        index: usize,
+        config: &SearchConfig,
+    ) -> Propagated {
+        self.visit_count += 1;
+        env.step(self.children()[index].0.clone());
+        let Propagated { eval, variance } =
+            self.simulate_from(self.child_id(ROOT, index), agent, env, config);
+        self.propagate_child_eval(ROOT, eval, variance, config.discount)
+    }
+
+    /// Run up to `batch_size` simulations, evaluating all leaves which need
+    /// the network in a single call to the agent.
+    /// Visits are counted on the way down, which steers later simulations in
+    /// the same batch away from paths that are already being evaluated.
+    /// With a virtual loss in the config, the simulations which are
+    /// still waiting for the agent also count as losses.
+    /// Returns the number of simulations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the agent does not return a prediction for every leaf.
+    pub fn simulate_batch<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: &E,
+        config: &SearchConfig,
+        batch_size: usize,
+    ) -> usize {
+        let mut trajectories = Vec::with_capacity(batch_size);
+        let mut envs = Vec::with_capacity(batch_size);
+        let mut actions = Vec::with_capacity(batch_size);
+        for _ in 0..batch_size {
+            let mut trajectory = Vec::new();
+            match self.forward_with_config(&mut trajectory, env.clone(), config) {
+                Forward::Known(eval) => {
+                    self.backward_known_eval_with_config(trajectory.into_iter(), eval, config);
+                }
+                Forward::NeedsNetwork(leaf) => {
+                    let mut leaf_actions = Vec::new();
+                    leaf.populate_actions(&mut leaf_actions);
+                    trajectories.push(trajectory);
+                    envs.push(leaf);
+                    actions.push(leaf_actions);
+                }
+            }
+        }
+        if envs.is_empty() {
+            return batch_size;
+        }
+
+        let outputs: Vec<_> = agent.policy_value_uncertainty(&envs, &actions).collect();
+        assert_eq!(outputs.len(), trajectories.len());
+        for (trajectory, (policy, value, uncertainty)) in trajectories.into_iter().zip(outputs) {
+            // Calculate probabilities from logits.
+            let probabilities = softmax(policy.clone().into_iter().map(|(_, p)| p));
+            self.backward_network_eval_with_config(
+                trajectory.into_iter(),
+                policy
+                    .into_iter()
+                    .zip(probabilities)
+                    .map(|((action, logit), probability)| ActionPolicy {
+                        action,
+                        logit,
+                        probability,
+                    }),
+                value,
+                uncertainty,
+                config,
+            );
+        }
+        batch_size
+    }
+
+    /// Run simulations until the deadline, returning the number of visits.
+    /// At least one simulation is always run, and the clock is only checked
+    /// every few simulations to keep it cheap.
+    pub fn simulate_for_duration<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: &E,
+        beta: f32,
+        deadline: Instant,
+    ) -> usize {
+        const SIMULATIONS_BETWEEN_CHECKS: usize = 16;
+        let config = SearchConfig::with_beta(beta);
+        let mut visits = 0;
+        loop {
+            for _ in 0..SIMULATIONS_BETWEEN_CHECKS {
+                self.simulate_with_config(agent, env.clone(), &config);
+            }
+            visits += SIMULATIONS_BETWEEN_CHECKS;
+            if Instant::now() >= deadline {
+                break visits;
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use fast_tak::Game;
+    use std::time::{Duration, Instant};
+
+    use fast_tak::{takparse::Tps, Game};
+    use ordered_float::NotNan;
 
     use super::super::{
         super::{agent::dummy::Dummy, eval::Eval},
         Node,
+        Statistics,
     };
     use crate::search::{
         agent::simple::Simple,
         env::safecrack::{SafeCrack, SafeCracker},
         node::mcts::Propagated,
+        SearchConfig,
+        DISCOUNT_FACTOR,
     };
 
     #[test]
@@ -302,7 +562,7 @@ mod tests {
 
         println!("{root}");
         assert_eq!(
-            root.children
+            root.children()
                 .iter()
                 .find(|(_, node)| node.evaluation.is_loss())
                 .unwrap()
@@ -338,7 +598,7 @@ mod tests {
 
         println!("{root}");
         let winning_move = root
-            .children
+            .children()
             .iter()
             .find(|(_, node)| node.evaluation.is_loss())
             .unwrap()
@@ -346,6 +606,87 @@ mod tests {
         assert!(winning_move == "b2".parse().unwrap() || winning_move == "c2".parse().unwrap());
     }
 
+    /// Simulate until the root is solved, and return the number of visits.
+    fn simulate_until_solved(root: &mut Node<Game<3, 0>>, game: &Game<3, 0>) -> usize {
+        const MAX_VISITS: usize = 5_000;
+        (1..=MAX_VISITS)
+            .find(|_| {
+                root.simulate_simple(&Dummy, game.clone(), 0.0);
+                root.evaluation.is_known()
+            })
+            .expect("the position should be solved with MAX_VISITS")
+    }
+
+    #[test]
+    fn terminal_leaves_are_scored_from_the_result() {
+        // White completes the road on the first row with c1,
+        // black would do the same on the last row.
+        let game: Game<3, 0> = "2,2,x/x3/1,1,x 1 3".parse::<Tps>().unwrap().into();
+        let mut root = Node::default();
+        simulate_until_solved(&mut root, &game);
+        assert_eq!(root.evaluation, Eval::Win(1));
+        assert!((f32::from(root.evaluation) - DISCOUNT_FACTOR).abs() < f32::EPSILON);
+
+        // The winning move is scored without being expanded.
+        let (_, winning) = root
+            .children()
+            .iter()
+            .find(|(action, _)| *action == "c1".parse().unwrap())
+            .unwrap();
+        assert_eq!(winning.evaluation, Eval::Loss(0));
+        assert!(root.children_of(winning).is_empty());
+    }
+
+    #[test]
+    fn flat_count_draw_is_proven() {
+        // White can only place on c1. A flat fills the board with one flat
+        // each, which is a draw, while a wall loses on flats.
+        let game: Game<3, 0> = "2S,2S,2S/2S,2S,2S/2S,2,x 1 10"
+            .parse::<Tps>()
+            .unwrap()
+            .into();
+        let mut root = Node::default();
+        simulate_until_solved(&mut root, &game);
+        assert_eq!(root.children().len(), 2);
+        assert!(root.evaluation.is_draw());
+        assert!(f32::from(root.evaluation).abs() < f32::EPSILON);
+        let child_evaluations: Vec<_> = root.children().iter().map(|(_, c)| c.evaluation).collect();
+        assert!(child_evaluations.contains(&Eval::Draw(0)));
+        assert!(child_evaluations.contains(&Eval::Win(0)));
+    }
+
+    fn tree_size(tree: &Node<Game<3, 0>>, node: &Statistics) -> usize {
+        1 + tree
+            .children_of(node)
+            .iter()
+            .map(|(_, child)| tree_size(tree, child))
+            .sum::<usize>()
+    }
+
+    #[test]
+    fn solved_trees_are_not_searched_further() {
+        // Mate in one, and the position of `find_tinue_easy`, a mate in two.
+        let mate_in_one: Game<3, 0> = "2,2,x/x3/1,1,x 1 3".parse::<Tps>().unwrap().into();
+        let mate_in_two = Game::from_ptn_moves(&["a3", "c1", "c2", "c3", "b3", "c3-"]);
+        for game in [mate_in_one, mate_in_two] {
+            let mut root = Node::default();
+            simulate_until_solved(&mut root, &game);
+            assert!(root.evaluation.is_win());
+
+            let (size, visits) = (tree_size(&root, &root), root.visit_count);
+            for _ in 0..100 {
+                root.simulate_simple(&Dummy, game.clone(), 0.0);
+            }
+            assert_eq!(tree_size(&root, &root), size);
+            assert_eq!(root.visit_count, visits + 100);
+            // The win is proven by a move which leaves the opponent lost.
+            assert!(root
+                .children()
+                .iter()
+                .any(|(_, child)| child.evaluation.is_loss()));
+        }
+    }
+
     #[test]
     fn safe_cracker_value_propagation() {
         const VISITS: usize = 100_000;
@@ -360,12 +701,12 @@ mod tests {
 
         for k in KEY {
             println!("eval: {}", root.evaluation);
-            for (action, child) in &root.children {
+            for (action, child) in root.children() {
                 println!("\t{}: eval: {}", action.unwrap(), child.evaluation);
             }
             assert!(f32::from(root.evaluation) > 0.0);
 
-            for (action, child) in &root.children {
+            for (action, child) in root.children() {
                 if action.is_some_and(|x| x == k) {
                     assert!(f32::from(child.evaluation) < 0.0);
                 } else {
@@ -379,4 +720,172 @@ mod tests {
 
         assert!(f32::from(root.evaluation) > 0.0);
     }
+
+    #[test]
+    fn principal_variation_follows_visits() {
+        const VISITS: usize = 10_000;
+        const DEPTH: usize = 6;
+        let env = SafeCrack::new(vec![3, 1, 4]);
+        let mut root = Node::default();
+        for _ in 0..VISITS {
+            root.simulate_simple(&SafeCracker, env.clone(), 0.0);
+        }
+
+        let principal_variation: Vec<_> = root.principal_variation().take(DEPTH).collect();
+        assert_eq!(principal_variation.len(), DEPTH);
+        assert_eq!(root.best_action(), principal_variation.first().copied());
+
+        let mut node: &Statistics = &root;
+        for action in principal_variation {
+            let children = root.children_of(node);
+            let most_visits = children
+                .iter()
+                .map(|(_, child)| child.visit_count)
+                .max()
+                .unwrap();
+            let (_, child) = children.iter().find(|(a, _)| *a == action).unwrap();
+            assert_eq!(child.visit_count, most_visits);
+            node = child;
+        }
+    }
+
+    #[test]
+    fn batched_simulations_are_counted() {
+        const BATCHES: usize = 100;
+        const BATCH_SIZE: usize = 16;
+        let env = SafeCrack::new(vec![2, 7]);
+        let mut root = Node::default();
+        let simulations: usize = (0..BATCHES)
+            .map(|_| root.simulate_batch(&SafeCracker, &env, &SearchConfig::default(), BATCH_SIZE))
+            .sum();
+
+        assert_eq!(simulations, BATCHES * BATCH_SIZE);
+        assert_eq!(root.visit_count as usize, simulations);
+        assert_eq!(root.children().len(), 10);
+    }
+
+    #[test]
+    fn take_child_keeps_statistics() {
+        const VISITS: usize = 1_000;
+        let env = SafeCrack::new(vec![5, 5]);
+        let mut root = Node::default();
+        for _ in 0..VISITS {
+            root.simulate_simple(&SafeCracker, env.clone(), 0.0);
+        }
+
+        let (action, child) = root
+            .children()
+            .iter()
+            .max_by_key(|(_, child)| child.visit_count)
+            .unwrap();
+        let (action, visit_count, evaluation) = (*action, child.visit_count, child.evaluation);
+        let mut subtree = root.take_child(&action);
+        assert_eq!(subtree.visit_count, visit_count);
+        assert_eq!(subtree.evaluation, evaluation);
+
+        // An action which was never expanded gives a fresh node.
+        let mut fresh: Node<SafeCrack> = Node::default();
+        assert_eq!(fresh.take_child(&action).visit_count, 0);
+
+        // Follow the opponent's reply as well.
+        let reply = subtree.take_child(&None);
+        assert!(reply.visit_count > 0);
+    }
+
+    /// Visit counts of the sub-tree of a node, depth first.
+    fn visit_counts(tree: &Node<SafeCrack>, node: &Statistics) -> Vec<u32> {
+        let mut visit_counts_of_tree = vec![node.visit_count];
+        for (_, child) in tree.children_of(node) {
+            visit_counts_of_tree.extend(visit_counts(tree, child));
+        }
+        visit_counts_of_tree
+    }
+
+    #[test]
+    fn descend_moves_the_whole_sub_tree() {
+        const VISITS: usize = 500;
+        let env = SafeCrack::new(vec![4, 7]);
+        let mut root = Node::default();
+        for _ in 0..VISITS {
+            root.simulate_simple(&SafeCracker, env.clone(), 0.0);
+        }
+
+        let (action, child) = root
+            .children()
+            .iter()
+            .max_by_key(|(_, child)| child.visit_count)
+            .unwrap();
+        let action = *action;
+        let expected = visit_counts(&root, child);
+        assert!(expected.len() > 1);
+        root.descend(&action);
+        assert_eq!(visit_counts(&root, &root), expected);
+        // Only the sub-tree is left in the arena.
+        assert_eq!(root.arena.len(), expected.len() - 1);
+    }
+
+    #[test]
+    fn simulate_until_deadline() {
+        let env = SafeCrack::default();
+        let mut root = Node::default();
+        // Even a deadline in the past runs a simulation.
+        let visits = root.simulate_for_duration(&SafeCracker, &env, 0.0, Instant::now());
+        assert!(visits > 0);
+        assert_eq!(root.visit_count as usize, visits);
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let more_visits = root.simulate_for_duration(&SafeCracker, &env, 0.0, deadline);
+        assert!(Instant::now() >= deadline);
+        assert_eq!(root.visit_count as usize, visits + more_visits);
+    }
+
+    #[test]
+    fn virtual_loss_spreads_pending_simulations() {
+        let env = SafeCrack::new(vec![1]);
+        let new_root = || {
+            // The first child is a bit better, neither has been expanded.
+            let mut root: Node<SafeCrack> =
+                Node::with_children([(Some(0), -0.3), (Some(1), -0.2)].into_iter().map(
+                    |(action, value)| {
+                        (action, Statistics {
+                            evaluation: Eval::new_value(value).unwrap(),
+                            visit_count: 5,
+                            probability: NotNan::new(0.5).unwrap(),
+                            ..Default::default()
+                        })
+                    },
+                ));
+            root.evaluation = Eval::new_value(-0.25).unwrap();
+            root.visit_count = 10;
+            root
+        };
+
+        let select_twice = |root: &mut Node<SafeCrack>, virtual_loss| {
+            let config = SearchConfig {
+                exploration_init: 0.0,
+                virtual_loss,
+                ..SearchConfig::default()
+            };
+            let (mut first, mut second) = (Vec::new(), Vec::new());
+            let _ = root.forward_with_config(&mut first, env.clone(), &config);
+            let _ = root.forward_with_config(&mut second, env.clone(), &config);
+            // Back up both, which removes the pending visits again.
+            for trajectory in [first.clone(), second.clone()] {
+                root.backward_known_eval(trajectory.into_iter(), Eval::new_value(0.0).unwrap());
+            }
+            (first, second)
+        };
+
+        let (first, second) = select_twice(&mut new_root(), 0.0);
+        assert_eq!(first, second);
+        let mut root = new_root();
+        let (first, second) = select_twice(&mut root, 1.0);
+        assert_eq!(first, [0]);
+        assert_eq!(second, [1]);
+        assert_eq!(root.virtual_visits, 0);
+        assert!(root
+            .children()
+            .iter()
+            .all(|(_, child)| child.virtual_visits == 0));
+    }
 }
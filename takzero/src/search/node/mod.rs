@@ -1,68 +1,99 @@
+use std::ops::{Deref, DerefMut, Range};
+
 use ordered_float::NotNan;
 use rand::Rng;
 use rand_distr::{Distribution, WeightedIndex};
 
-use super::{env::Environment, eval::Eval};
+use self::transposition::TranspositionTable;
+use super::{env::Environment, eval::Eval, SearchConfig};
 
 pub mod batched;
 pub mod debug;
-// pub mod gumbel;
+pub mod gumbel;
 pub mod mcts;
 pub mod noise;
+pub mod parallel;
 pub mod policy;
+pub mod transposition;
 
-#[rustfmt::skip]
+/// A search tree, which is used through the statistics of its root.
+///
+/// The nodes below the root are kept in an arena. Expanding a node appends
+/// all of its children to the arena at once, and the node only keeps the
+/// range of their indices, so a search grows one allocation instead of
+/// making one for every expanded node, and [`Node::clear`] keeps it for the
+/// next search. The children are reached with [`Node::children`] and
+/// [`Node::children_of`].
 pub struct Node<E: Environment> {
+    root: Statistics,
+    arena: Vec<(E::Action, Statistics)>,
+    pub transpositions: Option<Box<TranspositionTable<E>>>, // shared statistics of positions
+}
+
+/// A node of a [`Node`] tree.
+#[rustfmt::skip]
+#[derive(Clone, Default)]
+pub struct Statistics {
     pub evaluation: Eval,         // V(s_t) or Q(s_prev, a)
     pub visit_count: u32,         // N(s_prev, a)
-    #[cfg(feature = "virtual")]
     pub virtual_visits: u32,      // count number of unevaluated trajectories through this node
     pub logit: NotNan<f32>,       // log(P(s_prev, a)) (network output)
     pub probability: NotNan<f32>, // P(s_prev, a) (normalized)
     pub std_dev: NotNan<f32>,     // average sqrt(clamp(max(UBE(s_t), geo_sum_discount * RND(s_t))))
-    pub children: Box<[(E::Action, Self)]>,
+    children: Range<u32>,         // indices of the children in the arena of the tree
 }
 
+/// Id of the root for the methods which address the nodes of a tree by id.
+/// The node at index `i` of the arena has the id `i + 1`.
+const ROOT: usize = 0;
+
 impl<E: Environment> Default for Node<E> {
     fn default() -> Self {
         Self {
-            evaluation: Eval::default(),
-            visit_count: Default::default(),
-            #[cfg(feature = "virtual")]
-            virtual_visits: Default::default(),
-            logit: NotNan::default(),
-            probability: NotNan::default(),
-            std_dev: NotNan::default(),
-            children: Box::default(),
+            root: Statistics::default(),
+            arena: Vec::new(),
+            transpositions: None,
         }
     }
 }
 
+impl<E: Environment> Deref for Node<E> {
+    type Target = Statistics;
+
+    fn deref(&self) -> &Self::Target {
+        &self.root
+    }
+}
+
+impl<E: Environment> DerefMut for Node<E> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.root
+    }
+}
+
 struct PrincipalVariation<'a, E: Environment> {
-    node: &'a Node<E>,
+    tree: &'a Node<E>,
+    node: &'a Statistics,
 }
 
-impl<'a, E: Environment> Iterator for PrincipalVariation<'a, E> {
+impl<E: Environment> Iterator for PrincipalVariation<'_, E> {
     type Item = E::Action;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.node.needs_initialization() {
-            return None;
-        }
-        let best_action = self.node.select_best_action();
+        let best_action = self.tree.best_action_of(self.node)?;
         let (_, best_child) = self
-            .node
-            .children
+            .tree
+            .children_of(self.node)
             .iter()
             .find(|(action, _)| *action == best_action)
             .expect("Best action not found among node's children");
 
         self.node = best_child;
-        Some(best_action.clone())
+        Some(best_action)
     }
 }
 
-impl<E: Environment> Node<E> {
+impl Statistics {
     #[must_use]
     pub fn from_logit_and_probability_and_parent_value_and_std_dev(
         logit: NotNan<f32>,
@@ -82,24 +113,7 @@ impl<E: Environment> Node<E> {
     #[inline]
     #[must_use]
     pub const fn needs_initialization(&self) -> bool {
-        self.children.is_empty() && !self.evaluation.is_known()
-    }
-
-    /// Returns an iterator over the Principal Variation of the search tree
-    pub fn principal_variation(&self) -> impl Iterator<Item = E::Action> + '_ {
-        PrincipalVariation { node: self }
-    }
-
-    /// Descend in the tree, replacing the root the sub-tree for a given action.
-    /// This allows for tree reuse.
-    /// If the action was not visited, the node will `Node::default()`.
-    pub fn descend(&mut self, action: &E::Action) {
-        let mut me = std::mem::take(self);
-        let Some((_, child)) = me.children.iter_mut().find(|(a, _)| action == a) else {
-            return;
-        };
-        std::mem::swap(self, child);
-        // TODO: Maybe deallocate children on another thread.
+        self.children.start == self.children.end && !self.evaluation.is_known()
     }
 
     #[inline]
@@ -108,33 +122,186 @@ impl<E: Environment> Node<E> {
         self.evaluation.ply().is_some_and(|ply| ply == 0)
     }
 
-    /// Returns the visit count, accounting for virtual visits
-    /// if the feature is enabled.
+    /// Returns the visit count. Visits are counted when a simulation
+    /// passes through the node, before its result is known.
     #[inline]
     #[must_use]
     pub const fn visit_count(&self) -> u32 {
-        #[cfg(feature = "virtual")]
-        {
-            self.visit_count + self.virtual_visits
-        }
-        #[cfg(not(feature = "virtual"))]
         self.visit_count
     }
 
-    /// Returns the negated value of this node.
-    /// When using virtual visits, they are counted as losses.
+    /// Returns the negated value of this node, with known results
+    /// discounted by `discount` per ply.
+    #[inline]
+    #[must_use]
+    pub fn q_value(&self, discount: f32) -> NotNan<f32> {
+        self.evaluation.negate().discounted(discount)
+    }
+
+    /// Same as [`Statistics::q_value`], but with known results discounted by
+    /// the discount of the config. With a virtual loss, every simulation
+    /// through this node which has not been backed up yet counts as a loss
+    /// of that size for the parent, so that other simulations avoid this path.
     #[inline]
     #[must_use]
-    pub fn q_value(&self) -> NotNan<f32> {
-        #[cfg(feature = "virtual")]
-        {
-            let negated_eval: NotNan<f32> = self.evaluation.negate().into();
-            let multiplied_by_count = negated_eval * self.visit_count as f32;
-            let including_virtual_losses = multiplied_by_count + self.virtual_visits as f32;
-            including_virtual_losses / self.visit_count() as f32
+    pub fn q_value_with_config(&self, config: &SearchConfig) -> NotNan<f32> {
+        let q_value = self.evaluation.negate().discounted(config.discount);
+        if self.virtual_visits == 0 {
+            return q_value;
+        }
+        let visit_count = self.visit_count as f32;
+        (q_value * visit_count - config.virtual_loss * self.virtual_visits as f32) / visit_count
+    }
+}
+
+/// Copy the children in `range` of one arena to the end of another,
+/// and return their range in it.
+fn copy_children<A: Clone>(
+    from: &[(A, Statistics)],
+    to: &mut Vec<(A, Statistics)>,
+    range: &Range<u32>,
+) -> Range<u32> {
+    let start = to.len() as u32;
+    to.extend_from_slice(&from[range.start as usize..range.end as usize]);
+    start..to.len() as u32
+}
+
+impl<E: Environment> Node<E> {
+    /// A tree whose root is expanded with the given children.
+    #[must_use]
+    pub fn with_children(children: impl IntoIterator<Item = (E::Action, Statistics)>) -> Self {
+        let mut tree = Self::default();
+        tree.expand(ROOT, children);
+        tree
+    }
+
+    /// The children of the root.
+    #[must_use]
+    pub fn children(&self) -> &[(E::Action, Statistics)] {
+        self.children_of(&self.root)
+    }
+
+    /// The children of the root, to change their statistics.
+    #[must_use]
+    pub fn children_mut(&mut self) -> &mut [(E::Action, Statistics)] {
+        let Range { start, end } = self.root.children;
+        &mut self.arena[start as usize..end as usize]
+    }
+
+    /// The children of a node of this tree, which can be the root or any
+    /// node returned by this method. The children of a node of another
+    /// tree are meaningless.
+    #[must_use]
+    pub fn children_of(&self, node: &Statistics) -> &[(E::Action, Statistics)] {
+        &self.arena[node.children.start as usize..node.children.end as usize]
+    }
+
+    /// Replace the tree with a new one without any statistics, keeping the
+    /// memory of the arena for the next search.
+    pub fn clear(&mut self) {
+        self.root = Statistics::default();
+        self.arena.clear();
+        self.transpositions = None;
+    }
+
+    /// The node with the given id, see [`ROOT`].
+    fn node(&self, id: usize) -> &Statistics {
+        if id == ROOT {
+            &self.root
+        } else {
+            &self.arena[id - 1].1
+        }
+    }
+
+    fn node_mut(&mut self, id: usize) -> &mut Statistics {
+        if id == ROOT {
+            &mut self.root
+        } else {
+            &mut self.arena[id - 1].1
+        }
+    }
+
+    /// Id of the child at `index` among the children of the node `id`.
+    fn child_id(&self, id: usize, index: usize) -> usize {
+        let children = &self.node(id).children;
+        debug_assert!(index < children.len(), "the child should exist");
+        children.start as usize + index + 1
+    }
+
+    /// Ids of the nodes along a trajectory from the node `id`,
+    /// starting with the node itself.
+    fn path(&self, mut id: usize, trajectory: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut path = vec![id];
+        for index in trajectory {
+            id = self.child_id(id, index);
+            path.push(id);
+        }
+        path
+    }
+
+    /// Append the children of a leaf to the arena.
+    fn expand(&mut self, id: usize, children: impl IntoIterator<Item = (E::Action, Statistics)>) {
+        let start = self.arena.len() as u32;
+        self.arena.extend(children);
+        let end = self.arena.len() as u32;
+        self.node_mut(id).children = start..end;
+    }
+
+    /// Returns an iterator over the Principal Variation of the search tree.
+    /// It follows [`Node::best_action`] until it reaches a leaf,
+    /// use `take` to limit the depth.
+    pub fn principal_variation(&self) -> impl Iterator<Item = E::Action> + '_ {
+        PrincipalVariation {
+            tree: self,
+            node: &self.root,
+        }
+    }
+
+    /// Descend in the tree, replacing the root the sub-tree for a given action.
+    /// This allows for tree reuse.
+    /// If the action was not visited, the node will `Node::default()`.
+    pub fn descend(&mut self, action: &E::Action) {
+        *self = self.take_child(action);
+        // TODO: Maybe deallocate children on another thread.
+    }
+
+    /// Take the sub-tree for a given action out of the tree,
+    /// keeping its statistics so that it can become a new root.
+    /// The sub-tree is moved to an arena of its own, and the transposition
+    /// table goes along with it.
+    /// If the action was not expanded, `Node::default()` is returned.
+    #[must_use]
+    pub fn take_child(&mut self, action: &E::Action) -> Self {
+        let mut child = self
+            .children()
+            .iter()
+            .position(|(a, _)| action == a)
+            .map(|index| self.take_subtree(self.child_id(ROOT, index)))
+            .unwrap_or_default();
+        child.transpositions = self.transpositions.take();
+        child
+    }
+
+    /// Move the sub-tree of the node `id` to a tree of its own,
+    /// leaving a node without statistics or children in its place.
+    fn take_subtree(&mut self, id: usize) -> Self {
+        let mut root = std::mem::take(self.node_mut(id));
+        let mut arena = Vec::new();
+        root.children = copy_children(&self.arena, &mut arena, &root.children);
+        // The children of every node are copied after all nodes before it,
+        // so that siblings stay next to each other.
+        let mut copied = 0;
+        while copied < arena.len() {
+            let children = arena[copied].1.children.clone();
+            let children = copy_children(&self.arena, &mut arena, &children);
+            arena[copied].1.children = children;
+            copied += 1;
+        }
+        Self {
+            root,
+            arena,
+            transpositions: None,
         }
-        #[cfg(not(feature = "virtual"))]
-        self.evaluation.negate().into()
     }
 
     /// Return the best action after search.
@@ -144,16 +311,21 @@ impl<E: Environment> Node<E> {
     /// Panics if there are no children.
     #[must_use]
     pub fn select_best_action(&self) -> E::Action {
-        let best_eval = self
-            .children
+        self.select_best_action_of(&self.root)
+    }
+
+    /// Same as [`Node::select_best_action`], for any node of the tree.
+    fn select_best_action_of(&self, node: &Statistics) -> E::Action {
+        let children = self.children_of(node);
+        let best_eval = children
             .iter()
             .map(|(_, child)| child.evaluation)
             .min()
             .expect("there should be at least one child");
-        self.children
+        children
             .iter()
             // If the node is solved, filter for optimal actions.
-            .filter(|(_, child)| !self.evaluation.is_known() || child.evaluation == best_eval)
+            .filter(|(_, child)| !node.evaluation.is_known() || child.evaluation == best_eval)
             // Select the action with the most visits.
             .max_by_key(|(_, child)| child.visit_count)
             .expect("there should be at least one child")
@@ -161,6 +333,21 @@ impl<E: Environment> Node<E> {
             .clone()
     }
 
+    /// Return the best action after search,
+    /// or `None` if the node has not been expanded.
+    #[must_use]
+    pub fn best_action(&self) -> Option<E::Action> {
+        self.best_action_of(&self.root)
+    }
+
+    /// Same as [`Node::best_action`], for any node of the tree.
+    fn best_action_of(&self, node: &Statistics) -> Option<E::Action> {
+        if node.children.is_empty() {
+            return None;
+        }
+        Some(self.select_best_action_of(node))
+    }
+
     /// Return an action to use in selfplay.
     ///
     /// # Panics
@@ -178,7 +365,7 @@ impl<E: Environment> Node<E> {
             self.select_best_action()
         } else if proportional_sample {
             // Select an action randomly, proportional to visits.
-            let weighted_index = WeightedIndex::new(self.children.iter().map(|(_, child)| {
+            let weighted_index = WeightedIndex::new(self.children().iter().map(|(_, child)| {
                 if child.visit_count < THRESHOLD_VISITS {
                     0
                 } else {
@@ -186,10 +373,10 @@ impl<E: Environment> Node<E> {
                 }
             }))
             .expect("there should be at least one child and visits cannot be negative");
-            self.children[weighted_index.sample(rng)].0.clone()
+            self.children()[weighted_index.sample(rng)].0.clone()
         } else {
             // Select the action with the most visits.
-            self.children
+            self.children()
                 .iter()
                 .max_by_key(|(_, child)| child.visit_count)
                 .expect("there should be at least one child")
@@ -199,22 +386,23 @@ impl<E: Environment> Node<E> {
     }
 
     /// Get the UBE target from the root after search.
+    /// Known results are discounted by `discount` per ply.
     ///
     /// # Panics
     ///
     /// Panics if there are no children.
     #[must_use]
-    pub fn ube_target(&self, beta: f32) -> NotNan<f32> {
+    pub fn ube_target(&self, beta: f32, discount: f32) -> NotNan<f32> {
         // UBE target = 0.0 when node is solved.
         if self.evaluation.is_known() || self.needs_initialization() {
             NotNan::default()
         } else {
             // Child with maximum value + beta * std_dev.
             let std_dev = self
-                .children
+                .children()
                 .iter()
                 .map(|(_, child)| child)
-                .max_by_key(|child| NotNan::from(child.evaluation.negate()) + child.std_dev * beta)
+                .max_by_key(|child| child.q_value(discount) + child.std_dev * beta)
                 .expect("There should be at least one child")
                 .std_dev;
             std_dev * std_dev
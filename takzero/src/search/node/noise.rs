@@ -2,8 +2,8 @@ use ordered_float::NotNan;
 use rand::Rng;
 use rand_distr::{Dirichlet, Distribution};
 
-use super::Node;
-use crate::search::env::Environment;
+use super::{mcts::Propagated, Node};
+use crate::search::{agent::Agent, env::Environment};
 
 impl<E: Environment> Node<E> {
     #[allow(clippy::missing_panics_doc)]
@@ -12,10 +12,10 @@ impl<E: Environment> Node<E> {
             !self.needs_initialization(),
             "cannot apply dirichlet noise without initialized policy"
         );
-        let dirichlet = Dirichlet::new(&vec![alpha; self.children.len()]).unwrap();
+        let dirichlet = Dirichlet::new(&vec![alpha; self.children().len()]).unwrap();
         let samples = dirichlet.sample(rng);
 
-        self.children
+        self.children_mut()
             .iter_mut()
             .zip(samples)
             .for_each(|((_, child), noise)| {
@@ -24,6 +24,26 @@ impl<E: Environment> Node<E> {
                     .expect("Logit from probability should not be NaN");
             });
     }
+
+    /// Simulate like [`Node::simulate_simple`], but mix Dirichlet noise into
+    /// the policy of this node (the root) right after it is expanded.
+    /// `epsilon` is the weight of the noise.
+    pub fn simulate_with_noise<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: E,
+        beta: f32,
+        alpha: f32,
+        epsilon: f32,
+        rng: &mut impl Rng,
+    ) -> Propagated {
+        let first_expansion = self.needs_initialization();
+        let propagated = self.simulate_simple(agent, env, beta);
+        if first_expansion && !self.children().is_empty() {
+            self.apply_dirichlet(rng, alpha, epsilon);
+        }
+        propagated
+    }
 }
 
 #[cfg(test)]
@@ -34,12 +54,18 @@ mod tests {
 
     use crate::search::{
         agent::dummy::Dummy,
-        env::Environment,
-        node::{policy::softmax, Node},
+        node::{policy::softmax, Node, Statistics},
     };
 
-    fn sum_of_probabilities<E: Environment>(node: &Node<E>) -> NotNan<f32> {
-        node.children
+    fn is_uniform<A>(children: &[(A, Statistics)]) -> bool {
+        let uniform = 1.0 / children.len() as f32;
+        children
+            .iter()
+            .all(|(_, child)| (child.probability.into_inner() - uniform).abs() < f32::EPSILON)
+    }
+
+    fn sum_of_probabilities<A>(children: &[(A, Statistics)]) -> NotNan<f32> {
+        children
             .iter()
             .map(|(_, child)| child.probability)
             .sum::<NotNan<f32>>()
@@ -54,15 +80,37 @@ mod tests {
 
         println!("{node}");
         // Sum of probabilities is 1 before noise.
-        assert!((sum_of_probabilities(&node) - 1.0).abs() < 1.1 * f32::EPSILON);
+        assert!((sum_of_probabilities(node.children()) - 1.0).abs() < 1.1 * f32::EPSILON);
         node.apply_dirichlet(&mut rng, 0.5, 0.2);
 
         println!("{node}");
         // Sum of probabilities is 1 after noise.
-        assert!((sum_of_probabilities(&node) - 1.0).abs() < 1.1 * f32::EPSILON);
+        assert!((sum_of_probabilities(node.children()) - 1.0).abs() < 1.1 * f32::EPSILON);
         // Softmax of new logits equals probabilities.
-        softmax(node.children.iter().map(|(_, child)| child.logit))
-            .zip(node.children.iter().map(|(_, child)| child.probability))
+        softmax(node.children().iter().map(|(_, child)| child.logit))
+            .zip(node.children().iter().map(|(_, child)| child.probability))
             .for_each(|(a, b)| assert!((a - b).abs() < f32::EPSILON));
     }
+
+    #[test]
+    fn noise_only_at_root() {
+        let mut rng = StdRng::seed_from_u64(456);
+        let mut node = Node::default();
+        let env = Game::<3, 0>::from_ptn_moves(&["a1", "c3"]);
+        for _ in 0..50 {
+            node.simulate_with_noise(&Dummy, env.clone(), 0.0, 0.5, 0.25, &mut rng);
+        }
+
+        // The dummy agent gives a uniform policy, so only noise changes it.
+        assert!(!is_uniform(node.children()));
+        assert!((sum_of_probabilities(node.children()) - 1.0).abs() < 1e-5);
+        let expanded: Vec<_> = node
+            .children()
+            .iter()
+            .map(|(_, child)| node.children_of(child))
+            .filter(|children| !children.is_empty())
+            .collect();
+        assert!(!expanded.is_empty());
+        assert!(expanded.iter().all(|children| is_uniform(children)));
+    }
 }
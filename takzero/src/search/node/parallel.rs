@@ -0,0 +1,599 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+        Mutex,
+        MutexGuard,
+        OnceLock,
+    },
+};
+
+use ordered_float::NotNan;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    policy::{config_upper_confidence_bound_with_predictor, softmax},
+    Node,
+    Statistics,
+    ROOT,
+};
+use crate::search::{agent::Agent, env::Environment, eval::Eval, SearchConfig};
+
+/// Virtual loss used by [`Node::parallel_simulate`], so that threads which
+/// select at the same time spread over different paths.
+const PARALLEL_VIRTUAL_LOSS: f32 = 1.0;
+
+type Prediction<E> = (Vec<(<E as Environment>::Action, NotNan<f32>)>, f32, f32);
+type Children<E> = Box<[(<E as Environment>::Action, SharedNode<E>)]>;
+
+/// A leaf which a worker needs evaluated.
+struct Request<E: Environment> {
+    env: E,
+    actions: Vec<E::Action>,
+    reply: mpsc::Sender<Prediction<E>>,
+}
+
+/// The statistics of a node which change during search.
+#[derive(Debug, Clone, Copy)]
+struct LockedStatistics {
+    evaluation: Eval,
+    visit_count: u32,
+    virtual_visits: u32,
+    std_dev: NotNan<f32>,
+}
+
+impl LockedStatistics {
+    /// See [`Statistics::q_value_with_config`].
+    fn q_value(&self, config: &SearchConfig) -> NotNan<f32> {
+        let q_value = self.evaluation.negate().discounted(config.discount);
+        if self.virtual_visits == 0 {
+            return q_value;
+        }
+        let visit_count = self.visit_count as f32;
+        (q_value * visit_count - config.virtual_loss * self.virtual_visits as f32) / visit_count
+    }
+
+    fn update_mean_value(&mut self, value: f32) {
+        if let Eval::Value(mean_value) = &mut self.evaluation {
+            *mean_value += (-*mean_value + value) / (self.visit_count as f32);
+        }
+    }
+
+    fn update_standard_deviation(&mut self, variance: NotNan<f32>) {
+        if self.evaluation.is_known() {
+            return;
+        }
+        self.std_dev += (-self.std_dev + variance.sqrt()) / (self.visit_count as f32);
+    }
+}
+
+/// A node of a tree which several threads search at the same time.
+///
+/// Every node has its own lock around its statistics, so threads only wait
+/// for each other when they pass through the same node at the same time,
+/// and only for as long as it takes to update it. A lock is never held
+/// while waiting for the lock of a parent, so threads cannot deadlock.
+/// The children are created once, by whichever thread evaluates the node
+/// first.
+struct SharedNode<E: Environment> {
+    statistics: Mutex<LockedStatistics>,
+    logit: NotNan<f32>,
+    probability: NotNan<f32>,
+    children: OnceLock<Children<E>>,
+}
+
+/// Return value of [`SharedNode::forward`], like [`super::mcts::Forward`].
+enum Forward<E: Environment> {
+    Known(Eval),
+    NeedsNetwork(E),
+}
+
+/// Evaluation and variance which are backed up to the parent.
+#[derive(Clone, Copy)]
+struct Propagated {
+    eval: Eval,
+    variance: NotNan<f32>,
+}
+
+impl<E: Environment> SharedNode<E> {
+    /// Copy a tree into shared nodes.
+    fn new(tree: &Node<E>) -> Self {
+        Self::from_node(tree, tree)
+    }
+
+    fn from_node(tree: &Node<E>, node: &Statistics) -> Self {
+        let mut shared = Self::leaf(node);
+        let children = tree.children_of(node);
+        if !children.is_empty() {
+            shared.children = OnceLock::from(
+                children
+                    .iter()
+                    .map(|(action, child)| (action.clone(), Self::from_node(tree, child)))
+                    .collect::<Children<E>>(),
+            );
+        }
+        shared
+    }
+
+    /// A node with the statistics of `node`, but without children.
+    const fn leaf(node: &Statistics) -> Self {
+        Self {
+            statistics: Mutex::new(LockedStatistics {
+                evaluation: node.evaluation,
+                visit_count: node.visit_count,
+                virtual_visits: node.virtual_visits,
+                std_dev: node.std_dev,
+            }),
+            logit: node.logit,
+            probability: node.probability,
+            children: OnceLock::new(),
+        }
+    }
+
+    fn into_parts(self) -> (Statistics, Option<Children<E>>) {
+        let LockedStatistics {
+            evaluation,
+            visit_count,
+            virtual_visits,
+            std_dev,
+        } = self
+            .statistics
+            .into_inner()
+            .expect("search threads should not have panicked");
+        let statistics = Statistics {
+            evaluation,
+            visit_count,
+            virtual_visits,
+            logit: self.logit,
+            probability: self.probability,
+            std_dev,
+            ..Default::default()
+        };
+        (statistics, self.children.into_inner())
+    }
+
+    /// Move the statistics back into `tree`, replacing what was in it.
+    /// The memory of its arena is reused.
+    fn into_tree(self, tree: &mut Node<E>) {
+        tree.clear();
+        let (root, children) = self.into_parts();
+        *tree.node_mut(ROOT) = root;
+        // Breadth first, like the expansion of a sequential search.
+        let mut queue: VecDeque<_> = children
+            .map(|children| (ROOT, children))
+            .into_iter()
+            .collect();
+        while let Some((id, children)) = queue.pop_front() {
+            let (children, grandchildren): (Vec<_>, Vec<_>) = children
+                .into_vec()
+                .into_iter()
+                .map(|(action, child)| {
+                    let (statistics, children) = child.into_parts();
+                    ((action, statistics), children)
+                })
+                .unzip();
+            tree.expand(id, children);
+            for (index, grandchildren) in grandchildren.into_iter().enumerate() {
+                if let Some(grandchildren) = grandchildren {
+                    queue.push_back((tree.child_id(id, index), grandchildren));
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LockedStatistics> {
+        self.statistics
+            .lock()
+            .expect("another search thread should not have panicked")
+    }
+
+    fn children(&self) -> &[(E::Action, Self)] {
+        self.children.get().map_or(&[], |children| children)
+    }
+
+    /// Like [`Node::forward_with_config`]: count a visit of every node on the
+    /// way to a leaf and return the leaf, or its result if it is known.
+    fn forward(
+        &self,
+        trajectory: &mut Vec<usize>,
+        mut env: E,
+        config: &SearchConfig,
+        rng: &mut impl Rng,
+    ) -> Forward<E> {
+        debug_assert!(trajectory.is_empty());
+        let mut node = self;
+        loop {
+            let (parent, is_loss) = {
+                let mut statistics = node.lock();
+                statistics.visit_count += 1;
+                statistics.virtual_visits += 1;
+                if statistics.evaluation.is_known() {
+                    break Forward::Known(statistics.evaluation);
+                }
+                if node.children().is_empty() {
+                    if let Some(terminal) = env.terminal() {
+                        statistics.evaluation = terminal.into();
+                        statistics.std_dev = NotNan::default();
+                        break Forward::Known(statistics.evaluation);
+                    }
+                    break Forward::NeedsNetwork(env);
+                }
+                (statistics.visit_count, statistics.evaluation.is_loss())
+            };
+
+            let index = node.select(parent as f32, is_loss, config, rng);
+            trajectory.push(index);
+            let (action, child) = &node.children()[index];
+            env.step(action.clone());
+            node = child;
+        }
+    }
+
+    /// Index of the child which maximizes PUCT, like
+    /// [`Node::select_with_puct_config`]. Ties are broken at random, so that
+    /// threads which reach a new node at the same time take different paths.
+    fn select(
+        &self,
+        parent_visit_count: f32,
+        is_loss: bool,
+        config: &SearchConfig,
+        rng: &mut impl Rng,
+    ) -> usize {
+        let children = self.children();
+        let start = rng.gen_range(0..children.len());
+        (0..children.len())
+            .map(|i| (start + i) % children.len())
+            .filter_map(|i| {
+                let (_, child) = &children[i];
+                let statistics = *child.lock();
+                // Prune only losing moves to preserve optimality.
+                if !is_loss && statistics.evaluation.is_win() {
+                    return None;
+                }
+                let puct = config_upper_confidence_bound_with_predictor(
+                    config,
+                    parent_visit_count,
+                    statistics.visit_count as f32,
+                    child.probability.into_inner(),
+                );
+                Some((
+                    i,
+                    statistics.q_value(config) + puct + statistics.std_dev * config.beta,
+                ))
+            })
+            .max_by_key(|(_, score)| *score)
+            .map(|(i, _)| i)
+            .expect("there should always be a child to simulate")
+    }
+
+    /// Like [`Node::backward_known_eval_with_config`] and
+    /// [`Node::backward_network_eval_with_config`]: the leaf is given either
+    /// its known result or the prediction for it.
+    fn backward(
+        &self,
+        mut trajectory: impl Iterator<Item = usize>,
+        leaf: Leaf<E>,
+        config: &SearchConfig,
+    ) -> Propagated {
+        if let Some(index) = trajectory.next() {
+            let child = self.children()[index].1.backward(trajectory, leaf, config);
+            self.propagate_child_eval(child, config)
+        } else {
+            match leaf {
+                Leaf::Known(eval) => {
+                    self.lock().virtual_visits -= 1;
+                    Propagated {
+                        eval,
+                        variance: NotNan::default(),
+                    }
+                }
+                Leaf::Predicted {
+                    policy,
+                    value,
+                    variance,
+                } => {
+                    let variance =
+                        NotNan::new(variance).expect("uncertainty/variance should not be NaN");
+                    let statistics = {
+                        let mut statistics = self.lock();
+                        statistics.virtual_visits -= 1;
+                        statistics.update_mean_value(value);
+                        statistics.update_standard_deviation(variance);
+                        *statistics
+                    };
+                    // Other threads may have evaluated this leaf at the same
+                    // time, only the first expansion is kept.
+                    self.children.get_or_init(|| {
+                        let probabilities: Vec<_> =
+                            softmax(policy.iter().map(|(_, logit)| *logit)).collect();
+                        policy
+                            .into_iter()
+                            .zip(probabilities)
+                            .map(|((action, logit), probability)| {
+                                (action, Self::child(logit, probability, &statistics, config))
+                            })
+                            .collect()
+                    });
+                    Propagated {
+                        eval: Eval::new_value(value * config.discount)
+                            .expect("value prediction should not be NaN"),
+                        variance: variance * config.discount * config.discount,
+                    }
+                }
+            }
+        }
+    }
+
+    /// See [`Statistics::from_logit_and_probability_and_parent_value_and_std_dev`].
+    fn child(
+        logit: NotNan<f32>,
+        probability: NotNan<f32>,
+        parent: &LockedStatistics,
+        config: &SearchConfig,
+    ) -> Self {
+        Self::leaf(
+            &Statistics::from_logit_and_probability_and_parent_value_and_std_dev(
+                logit,
+                probability,
+                parent.evaluation.discounted(config.discount),
+                parent.std_dev,
+            ),
+        )
+    }
+
+    /// Back up the result of a child, and solve this node if the results
+    /// of its children allow it, like the node solver of [`Node`].
+    fn propagate_child_eval(&self, child: Propagated, config: &SearchConfig) -> Propagated {
+        let mut statistics = self.lock();
+        statistics.virtual_visits -= 1;
+
+        // If we can choose a loss for the opponent, this position is a win.
+        // If all moves are wins for the opponent, this node is a loss.
+        // If all moves are wins or draws for the opponent, we choose to draw.
+        let evaluations = || {
+            self.children()
+                .iter()
+                .map(|(_, child)| child.lock().evaluation)
+        };
+        if !statistics.evaluation.is_known()
+            && (child.eval.is_loss() || evaluations().all(|e| e.is_known()))
+        {
+            statistics.evaluation = evaluations()
+                .min()
+                .expect("a node with a child result should have children")
+                .negate();
+            statistics.std_dev = NotNan::default();
+        }
+
+        if statistics.evaluation.is_known() {
+            return Propagated {
+                eval: statistics.evaluation,
+                variance: statistics.std_dev * statistics.std_dev,
+            };
+        }
+        let negated = child.eval.negate().discounted(config.discount).into_inner();
+        statistics.update_mean_value(negated);
+        statistics.update_standard_deviation(child.variance);
+        drop(statistics);
+        Propagated {
+            eval: Eval::new_value(negated * config.discount).unwrap(),
+            variance: child.variance * config.discount * config.discount,
+        }
+    }
+}
+
+/// What a worker learned about the leaf it selected.
+enum Leaf<E: Environment> {
+    Known(Eval),
+    Predicted {
+        policy: Vec<(E::Action, NotNan<f32>)>,
+        value: f32,
+        variance: f32,
+    },
+}
+
+impl<E: Environment> Node<E> {
+    /// Run `total_visits` simulations with `threads` worker threads which
+    /// share this tree. Returns the number of simulations.
+    ///
+    /// While searching, every node of the tree has its own lock, which is
+    /// only held while a thread selects a child of it or backs up a value
+    /// through it, see [`SharedNode`]. The leaves are evaluated outside of
+    /// the locks: the workers send them to the calling thread, which
+    /// evaluates all leaves that are waiting in a single call to the agent.
+    /// Virtual loss keeps the workers from all selecting the same path, and
+    /// `rng` seeds the workers, which break ties between children at random.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero, if a worker panics,
+    /// or if the agent does not return a prediction for every leaf.
+    pub fn parallel_simulate<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: &E,
+        beta: f32,
+        threads: usize,
+        total_visits: usize,
+        rng: &mut impl Rng,
+    ) -> usize {
+        assert!(threads > 0, "there should be at least one worker thread");
+        let config = SearchConfig {
+            virtual_loss: PARALLEL_VIRTUAL_LOSS,
+            ..SearchConfig::with_beta(beta)
+        };
+        let remaining = AtomicUsize::new(total_visits);
+        // The workers do not use the transposition table, but it is kept.
+        let transpositions = self.transpositions.take();
+        let tree = SharedNode::new(self);
+        self.clear();
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                let sender = sender.clone();
+                let (tree, config, remaining) = (&tree, &config, &remaining);
+                let mut rng = StdRng::seed_from_u64(rng.gen());
+                scope.spawn(move || work(tree, env, config, remaining, &sender, &mut rng));
+            }
+            // Only the workers hold senders now,
+            // so evaluation stops once all of them are done.
+            drop(sender);
+            evaluate_requests(agent, &receiver, threads);
+        });
+        tree.into_tree(self);
+        self.transpositions = transpositions;
+        total_visits
+    }
+}
+
+fn work<E: Environment>(
+    tree: &SharedNode<E>,
+    env: &E,
+    config: &SearchConfig,
+    remaining: &AtomicUsize,
+    sender: &mpsc::Sender<Request<E>>,
+    rng: &mut impl Rng,
+) {
+    let mut trajectory = Vec::new();
+    while remaining
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |r| r.checked_sub(1))
+        .is_ok()
+    {
+        let leaf = match tree.forward(&mut trajectory, env.clone(), config, rng) {
+            Forward::Known(eval) => Leaf::Known(eval),
+            Forward::NeedsNetwork(leaf) => {
+                let mut actions = Vec::new();
+                leaf.populate_actions(&mut actions);
+                let (reply, response) = mpsc::channel();
+                sender
+                    .send(Request {
+                        env: leaf,
+                        actions,
+                        reply,
+                    })
+                    .expect("the evaluator should run until all workers are done");
+                let (policy, value, variance) = response
+                    .recv()
+                    .expect("the evaluator should answer every request");
+                Leaf::Predicted {
+                    policy,
+                    value,
+                    variance,
+                }
+            }
+        };
+        tree.backward(trajectory.drain(..), leaf, config);
+    }
+}
+
+/// Evaluate the requests of the workers until all of them are done,
+/// up to `max_batch_size` at a time.
+fn evaluate_requests<E: Environment, A: Agent<E>>(
+    agent: &A,
+    receiver: &mpsc::Receiver<Request<E>>,
+    max_batch_size: usize,
+) {
+    while let Ok(first) = receiver.recv() {
+        let mut envs = Vec::with_capacity(max_batch_size);
+        let mut actions = Vec::with_capacity(max_batch_size);
+        let mut replies = Vec::with_capacity(max_batch_size);
+        for request in std::iter::once(first).chain(receiver.try_iter().take(max_batch_size - 1)) {
+            envs.push(request.env);
+            actions.push(request.actions);
+            replies.push(request.reply);
+        }
+
+        let outputs: Vec<_> = agent.policy_value_uncertainty(&envs, &actions).collect();
+        assert_eq!(
+            outputs.len(),
+            replies.len(),
+            "agent should return exactly one prediction per leaf"
+        );
+        for (reply, output) in replies.into_iter().zip(outputs) {
+            reply
+                .send(output)
+                .expect("the worker should wait for its prediction");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{
+        super::{Node, Statistics},
+        SharedNode,
+    };
+    use crate::search::{
+        env::safecrack::{SafeCrack, SafeCracker},
+        eval::Eval,
+    };
+
+    fn assert_no_pending_visits(root: &Node<SafeCrack>) {
+        let mut stack = vec![&**root];
+        while let Some(node) = stack.pop() {
+            assert_eq!(node.virtual_visits, 0);
+            stack.extend(root.children_of(node).iter().map(|(_, child)| child));
+        }
+    }
+
+    #[test]
+    fn parallel_simulations_share_the_tree() {
+        const VISITS: usize = 2_000;
+        const THREADS: usize = 4;
+        let env = SafeCrack::new(vec![6]);
+        let mut rng = StdRng::seed_from_u64(864);
+        let mut root = Node::default();
+        let simulations =
+            root.parallel_simulate(&SafeCracker, &env, 0.0, THREADS, VISITS, &mut rng);
+
+        assert_eq!(simulations, VISITS);
+        assert_eq!(root.visit_count as usize, VISITS);
+        assert_eq!(root.children().len(), 10);
+        assert_no_pending_visits(&root);
+        assert_eq!(root.best_action(), Some(Some(6)));
+    }
+
+    #[test]
+    fn parallel_simulations_continue_an_existing_tree() {
+        const VISITS: usize = 1_000;
+        let env = SafeCrack::new(vec![3]);
+        let mut rng = StdRng::seed_from_u64(975);
+        let mut root = Node::default();
+        for _ in 0..VISITS {
+            root.simulate_simple(&SafeCracker, env.clone(), 0.0);
+        }
+        root.parallel_simulate(&SafeCracker, &env, 0.0, 3, VISITS, &mut rng);
+
+        assert_eq!(root.visit_count as usize, 2 * VISITS);
+        let child_visits: u32 = root.children().iter().map(|(_, c)| c.visit_count).sum();
+        assert_eq!(child_visits as usize, 2 * VISITS - 1);
+        assert_no_pending_visits(&root);
+        assert_eq!(root.best_action(), Some(Some(3)));
+    }
+
+    #[test]
+    fn shared_nodes_round_trip() {
+        let env = SafeCrack::new(vec![2]);
+        let mut root = Node::default();
+        for _ in 0..50 {
+            root.simulate_simple(&SafeCracker, env.clone(), 0.0);
+        }
+        let expected = statistics(&root, &root);
+        SharedNode::new(&root).into_tree(&mut root);
+        assert_eq!(statistics(&root, &root), expected);
+    }
+
+    /// Statistics of every node of the tree, depth first.
+    fn statistics(tree: &Node<SafeCrack>, node: &Statistics) -> Vec<(u32, Eval, usize)> {
+        let children = tree.children_of(node);
+        let mut statistics_of_tree = vec![(node.visit_count, node.evaluation, children.len())];
+        for (_, child) in children {
+            statistics_of_tree.extend(statistics(tree, child));
+        }
+        statistics_of_tree
+    }
+}
@@ -1,6 +1,10 @@
 use ordered_float::NotNan;
 
-use super::{super::env::Environment, Node};
+use super::{
+    super::{env::Environment, Fpu, SearchConfig},
+    Node,
+    Statistics,
+};
 
 /// Perform the softmax on an iterator.
 ///
@@ -21,7 +25,7 @@ pub fn softmax(
 impl<E: Environment> Node<E> {
     #[must_use]
     pub fn most_visited_count(&self) -> f32 {
-        self.children
+        self.children()
             .iter()
             .map(|(_, node)| node.visit_count)
             .max()
@@ -34,7 +38,7 @@ impl<E: Environment> Node<E> {
     ///
     /// Panics if the evaluation is NaN.
     pub fn improved_policy(&self, visitations: f32) -> impl Iterator<Item = NotNan<f32>> + '_ {
-        let p = self.children.iter().map(move |(_, node)| -> NotNan<f32> {
+        let p = self.children().iter().map(move |(_, node)| -> NotNan<f32> {
             let completed_value = if node.needs_initialization() {
                 self.evaluation
             } else {
@@ -47,6 +51,126 @@ impl<E: Environment> Node<E> {
         softmax(p)
     }
 
+    /// Get the improved policy for this node together with the actions,
+    /// in the format used by targets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the evaluation is NaN.
+    #[must_use]
+    pub fn improved_policy_target(&self, visitations: f32) -> Box<[(E::Action, NotNan<f32>)]> {
+        self.children()
+            .iter()
+            .map(|(action, _)| action.clone())
+            .zip(self.improved_policy(visitations))
+            .collect()
+    }
+
+    /// Same as [`Node::improved_policy_target`], but with forced playouts
+    /// children which were only visited because of the forcing are pruned,
+    /// see [`Node::pruned_visit_counts`], and get no probability.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the evaluation is NaN.
+    #[must_use]
+    pub fn improved_policy_target_with_config(
+        &self,
+        visitations: f32,
+        config: &SearchConfig,
+    ) -> Box<[(E::Action, NotNan<f32>)]> {
+        if config.forced_playouts.is_none() {
+            return self.improved_policy_target(visitations);
+        }
+        let pruned: Vec<_> = self
+            .children()
+            .iter()
+            .zip(self.pruned_visit_counts(config))
+            .zip(self.improved_policy(visitations))
+            .map(|(((action, child), visit_count), p)| {
+                let p = if child.visit_count > 0 && visit_count == 0 {
+                    NotNan::default()
+                } else {
+                    p
+                };
+                (action.clone(), p)
+            })
+            .collect();
+        let sum: NotNan<f32> = pruned.iter().map(|(_, p)| *p).sum();
+        pruned
+            .into_iter()
+            .map(|(action, p)| (action, p / sum))
+            .collect()
+    }
+
+    /// Visit counts of the children without the visits which were only
+    /// made because of forced playouts.
+    ///
+    /// The visits of every child except the most visited one are reduced
+    /// by up to the number of forced visits, as long as PUCT would still
+    /// have preferred the most visited child. Children which are left with
+    /// a single visit or fewer are pruned to zero.
+    #[must_use]
+    pub fn pruned_visit_counts(&self, config: &SearchConfig) -> Vec<u32> {
+        let children = self.children();
+        let mut visit_counts: Vec<_> = children
+            .iter()
+            .map(|(_, child)| child.visit_count)
+            .collect();
+        if config.forced_playouts.is_none() {
+            return visit_counts;
+        }
+        let Some(best) = (0..visit_counts.len()).max_by_key(|&i| visit_counts[i]) else {
+            return visit_counts;
+        };
+        let best_child = &children[best].1;
+        let best_puct = self.puct(config, best_child, best_child.visit_count as f32);
+
+        for (i, (_, child)) in children.iter().enumerate() {
+            if i == best || child.visit_count == 0 {
+                continue;
+            }
+            let forced = self.forced_visits(config, child);
+            #[allow(clippy::cast_sign_loss)]
+            let mut visit_count = (child.visit_count as f32 - forced).ceil().max(0.0) as u32;
+            while visit_count < child.visit_count
+                && self.puct(config, child, visit_count as f32) >= best_puct
+            {
+                visit_count += 1;
+            }
+            visit_counts[i] = if visit_count <= 1 { 0 } else { visit_count };
+        }
+        visit_counts
+    }
+
+    /// Get the distribution of visits over the children,
+    /// scaled by `visit_count^(1 / temperature)`.
+    /// Unvisited children are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the temperature is not positive.
+    #[must_use]
+    pub fn visit_policy(&self, temperature: f32) -> Vec<(E::Action, NotNan<f32>)> {
+        assert!(temperature > 0.0, "temperature should be positive");
+        let scaled: Vec<_> = self
+            .children()
+            .iter()
+            .filter(|(_, child)| child.visit_count > 0)
+            .map(|(action, child)| (action, (child.visit_count as f32).powf(temperature.recip())))
+            .collect();
+        let sum: f32 = scaled.iter().map(|(_, x)| x).sum();
+        scaled
+            .into_iter()
+            .map(|(action, x)| {
+                (
+                    action.clone(),
+                    NotNan::new(x / sum).expect("visit policy should not be NaN"),
+                )
+            })
+            .collect()
+    }
+
     /// Get index of child which maximizes the improved policy.
     /// Losing actions are pruned unless this node is a proven loss.
     ///
@@ -56,7 +180,7 @@ impl<E: Environment> Node<E> {
     #[must_use]
     pub fn select_with_improved_policy(&self) -> usize {
         self.improved_policy(self.most_visited_count())
-            .zip(self.children.iter())
+            .zip(self.children().iter())
             .enumerate()
             // Prune only losing moves to preserve optimality.
             .filter(|(_, (_, (_, child)))| self.evaluation.is_loss() || !child.evaluation.is_win())
@@ -76,39 +200,114 @@ impl<E: Environment> Node<E> {
     /// Panics if there are no children.
     #[must_use]
     pub fn select_with_puct(&self, beta: f32) -> usize {
-        let parent_visit_count = self.visit_count as f32;
-        self.children
+        self.select_with_puct_config(&SearchConfig::with_beta(beta))
+    }
+
+    /// Get index of child which maximizes PUCT with the given settings.
+    /// Losing actions are pruned unless this node is a proven loss.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no children.
+    #[must_use]
+    pub fn select_with_puct_config(&self, config: &SearchConfig) -> usize {
+        self.select_with_puct_config_of(&self.root, config)
+    }
+
+    /// Same as [`Node::select_with_puct_config`], for any node of the tree.
+    pub(super) fn select_with_puct_config_of(
+        &self,
+        node: &Statistics,
+        config: &SearchConfig,
+    ) -> usize {
+        let parent_visit_count = node.visit_count as f32;
+        let fpu = self.first_play_urgency(node, config.fpu, config.discount);
+        self.children_of(node)
             .iter()
             .enumerate()
-            .filter(|(_, (_, child))| self.evaluation.is_loss() || !child.evaluation.is_win())
+            .filter(|(_, (_, child))| node.evaluation.is_loss() || !child.evaluation.is_win())
             .max_by_key(|(_, (_, child))| {
-                let q = child.q_value();
-                let puct = upper_confidence_bound_with_predictor(
+                let q = match fpu {
+                    Some(fpu) if child.visit_count == 0 && !child.evaluation.is_known() => fpu,
+                    _ => child.q_value_with_config(config),
+                };
+                let puct = config_upper_confidence_bound_with_predictor(
+                    config,
                     parent_visit_count,
                     child.visit_count as f32,
                     child.probability.into_inner(),
                 );
-                q + puct + child.std_dev * beta
+                let uncertainty = child.std_dev * config.beta;
+                q + puct + uncertainty
             })
             .map(|(i, _)| i)
             .expect("there should always be a child to simulate")
     }
 
+    /// Get index of a visited child which has fewer visits than forced
+    /// playouts require, if forced playouts are enabled.
+    /// Losing actions are never forced unless this node is a proven loss.
+    #[must_use]
+    pub fn select_forced_playout(&self, config: &SearchConfig) -> Option<usize> {
+        self.select_forced_playout_of(&self.root, config)
+    }
+
+    /// Same as [`Node::select_forced_playout`], for any node of the tree.
+    pub(super) fn select_forced_playout_of(
+        &self,
+        node: &Statistics,
+        config: &SearchConfig,
+    ) -> Option<usize> {
+        config.forced_playouts?;
+        self.children_of(node)
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, child))| node.evaluation.is_loss() || !child.evaluation.is_win())
+            .find(|(_, (_, child))| {
+                child.visit_count > 0
+                    && (child.visit_count as f32) < node.forced_visits(config, child)
+            })
+            .map(|(i, _)| i)
+    }
+
+    /// Value of unvisited children of the node, or `None` if they keep the
+    /// value they were created with.
+    fn first_play_urgency(
+        &self,
+        node: &Statistics,
+        fpu: Fpu,
+        discount: f32,
+    ) -> Option<NotNan<f32>> {
+        match fpu {
+            Fpu::Parent => None,
+            Fpu::Reduction(reduction) => {
+                let explored: f32 = self
+                    .children_of(node)
+                    .iter()
+                    .filter(|(_, child)| child.visit_count > 0)
+                    .map(|(_, child)| child.probability.into_inner())
+                    .sum();
+                Some(node.evaluation.discounted(discount) - reduction * explored)
+            }
+        }
+    }
+
     /// Get index of child which maximizes UCT.
     /// Losing actions are pruned unless this node is a proven loss.
+    /// Known results are discounted by `discount` per ply.
     ///
     /// # Panics
     ///
     /// Panics if there are no children.
     #[must_use]
-    pub fn select_with_uct(&self, beta: f32) -> usize {
+    pub fn select_with_uct(&self, beta: f32, discount: f32) -> usize {
         let parent_visit_count = self.visit_count as f32;
-        self.children
+        self.children()
             .iter()
             .enumerate()
             .filter(|(_, (_, child))| self.evaluation.is_loss() || !child.evaluation.is_win())
             .max_by_key(|(_, (_, child))| {
-                let q = child.q_value();
+                let q = child.q_value(discount);
                 let uct = upper_confidence_bound(parent_visit_count, child.visit_count as f32);
                 q + uct + child.std_dev * beta
             })
@@ -117,6 +316,28 @@ impl<E: Environment> Node<E> {
     }
 }
 
+impl Statistics {
+    /// `k * sqrt(P(s, a) * N(s))` for a child of this node,
+    /// or zero without forced playouts.
+    fn forced_visits(&self, config: &SearchConfig, child: &Self) -> f32 {
+        config.forced_playouts.map_or(0.0, |k| {
+            k * (child.probability.into_inner() * self.visit_count as f32).sqrt()
+        })
+    }
+
+    /// PUCT of a child of this node as if it had `visit_count` visits.
+    fn puct(&self, config: &SearchConfig, child: &Self, visit_count: f32) -> NotNan<f32> {
+        child.q_value_with_config(config)
+            + config_upper_confidence_bound_with_predictor(
+                config,
+                self.visit_count as f32,
+                visit_count,
+                child.probability.into_inner(),
+            )
+            + child.std_dev * config.beta
+    }
+}
+
 #[must_use]
 pub fn sigma_select(
     q: NotNan<f32>,
@@ -137,11 +358,12 @@ pub fn sigma_improve(
     (q + std_dev * beta) * visit_count.sqrt()
 }
 
-const EXPLORATION_BASE: f32 = 500.0;
-const EXPLORATION_INIT: f32 = 4.0;
+pub const EXPLORATION_BASE: f32 = 500.0;
+pub const EXPLORATION_INIT: f32 = 4.0;
 
-fn exploration_rate(visit_count: f32) -> f32 {
-    ((1.0 + visit_count + EXPLORATION_BASE) / EXPLORATION_BASE).ln() + EXPLORATION_INIT
+fn exploration_rate(config: &SearchConfig, visit_count: f32) -> f32 {
+    ((1.0 + visit_count + config.exploration_base) / config.exploration_base).ln()
+        + config.exploration_init
 }
 
 /// U(s, a) = C(s) * P(s, a) * sqrt(N(s)) / (1 + N(s, a))
@@ -151,7 +373,24 @@ pub fn upper_confidence_bound_with_predictor(
     visit_count: f32,
     probability: f32,
 ) -> f32 {
-    exploration_rate(parent_visit_count) * probability * parent_visit_count.sqrt()
+    config_upper_confidence_bound_with_predictor(
+        &SearchConfig::default(),
+        parent_visit_count,
+        visit_count,
+        probability,
+    )
+}
+
+/// Same as [`upper_confidence_bound_with_predictor`],
+/// but with the exploration constants from `config`.
+#[must_use]
+pub fn config_upper_confidence_bound_with_predictor(
+    config: &SearchConfig,
+    parent_visit_count: f32,
+    visit_count: f32,
+    probability: f32,
+) -> f32 {
+    exploration_rate(config, parent_visit_count) * probability * parent_visit_count.sqrt()
         / (1.0 + visit_count)
 }
 
@@ -167,7 +406,11 @@ pub fn upper_confidence_bound(parent_visit_count: f32, visit_count: f32) -> f32
 mod tests {
     use ordered_float::NotNan;
 
-    use super::softmax;
+    use super::{
+        super::{Node, Statistics},
+        softmax,
+    };
+    use crate::search::{env::safecrack::SafeCrack, eval::Eval, Fpu, SearchConfig};
 
     #[test]
     fn softmax_works() {
@@ -185,4 +428,114 @@ mod tests {
             ])
             .for_each(|(a, b)| assert!((a - b).abs() < f32::EPSILON, "{a} should equal {b}"));
     }
+
+    #[test]
+    fn visit_policy_skips_unvisited() {
+        let mut node: Node<SafeCrack> =
+            Node::with_children([(Some(0), 1), (Some(1), 0), (Some(2), 3)].into_iter().map(
+                |(action, visit_count)| {
+                    (action, Statistics {
+                        visit_count,
+                        ..Default::default()
+                    })
+                },
+            ));
+        node.visit_count = 5;
+
+        let policy = node.visit_policy(1.0);
+        assert_eq!(policy.len(), 2);
+        assert_eq!(policy[0].0, Some(0));
+        assert!((policy[0].1.into_inner() - 0.25).abs() < f32::EPSILON);
+        assert_eq!(policy[1].0, Some(2));
+        assert!((policy[1].1.into_inner() - 0.75).abs() < f32::EPSILON);
+
+        // A low temperature sharpens the distribution.
+        let sharp = node.visit_policy(0.5);
+        assert!((sharp[1].1.into_inner() - 0.9).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fpu_reduction_changes_selection() {
+        // The parent is slightly winning. The first child has been visited
+        // and is worse than the parent value, the second has not been tried.
+        let mut node: Node<SafeCrack> = Node::with_children(
+            [(Some(0), 10, 0.6, -0.1), (Some(1), 0, 0.4, -0.2)]
+                .into_iter()
+                .map(|(action, visit_count, probability, value)| {
+                    (action, Statistics {
+                        evaluation: Eval::new_value(value).unwrap(),
+                        visit_count,
+                        probability: NotNan::new(probability).unwrap(),
+                        ..Default::default()
+                    })
+                }),
+        );
+        node.evaluation = Eval::new_value(0.2).unwrap();
+        node.visit_count = 11;
+        // Barely any exploration, so that the values decide.
+        let config = |fpu| SearchConfig {
+            exploration_init: 0.0,
+            fpu,
+            ..SearchConfig::default()
+        };
+
+        assert_eq!(node.select_with_puct_config(&config(Fpu::Parent)), 1);
+        assert_eq!(
+            node.select_with_puct_config(&config(Fpu::Reduction(0.0))),
+            1
+        );
+        assert_eq!(
+            node.select_with_puct_config(&config(Fpu::Reduction(0.5))),
+            0
+        );
+        // A proven child keeps its value.
+        node.children_mut()[1].1.evaluation = Eval::Loss(0);
+        assert_eq!(
+            node.select_with_puct_config(&config(Fpu::Reduction(0.5))),
+            1
+        );
+    }
+
+    #[test]
+    fn forced_visits_are_pruned_from_the_target() {
+        // The first child is clearly best, the second one only got its
+        // visits because they were forced.
+        let mut node: Node<SafeCrack> = Node::with_children(
+            [
+                (Some(0), 90, 0.5, -0.5),
+                (Some(1), 10, 0.4, 0.5),
+                (Some(2), 0, 0.1, -0.4),
+            ]
+            .into_iter()
+            .map(|(action, visit_count, probability, value)| {
+                (action, Statistics {
+                    evaluation: Eval::new_value(value).unwrap(),
+                    visit_count,
+                    probability: NotNan::new(probability).unwrap(),
+                    ..Default::default()
+                })
+            }),
+        );
+        node.evaluation = Eval::new_value(0.4).unwrap();
+        node.visit_count = 101;
+        let forced = SearchConfig {
+            exploration_init: 0.0,
+            forced_playouts: Some(2.0),
+            ..SearchConfig::default()
+        };
+
+        // `2 * sqrt(0.4 * 101)` is more than 10 visits.
+        assert_eq!(node.select_forced_playout(&forced), Some(1));
+        assert_eq!(node.select_forced_playout(&SearchConfig::default()), None);
+        assert_eq!(node.pruned_visit_counts(&forced), [90, 0, 0]);
+
+        let target = node.improved_policy_target_with_config(10.0, &forced);
+        assert_eq!(target[1].1, NotNan::default());
+        // Unvisited children are not pruned.
+        assert!(target[2].1.into_inner() > 0.0);
+        let sum: f32 = target.iter().map(|(_, p)| p.into_inner()).sum();
+        assert!((sum - 1.0).abs() < 1e-5);
+        let unpruned = node.improved_policy_target_with_config(10.0, &SearchConfig::default());
+        assert!(unpruned[1].1.into_inner() > 0.0);
+    }
 }
@@ -0,0 +1,400 @@
+//! Transposition table for [`Node::simulate_with_config`], which is used
+//! when [`SearchConfig::transpositions`] is enabled.
+//!
+//! The search still builds a tree, but every position which is reached by
+//! different sequences of actions shares its statistics: each backup through
+//! a node of the position also counts as a visit of the position in the
+//! table, and the value of the node becomes the mean value of the position
+//! over all of those visits. Visit counts of the nodes themselves stay per
+//! path, so that the visits of the children still add up for exploration
+//! and the policy target. The prediction of the agent is shared as well,
+//! so a transposition is expanded without asking the agent again.
+
+use std::collections::HashMap;
+
+use ordered_float::NotNan;
+
+use super::{
+    super::{agent::Agent, env::Environment, eval::Eval, SearchConfig},
+    mcts::{ActionPolicy, Forward, Propagated},
+    policy::softmax,
+    Node,
+    ROOT,
+};
+
+type Prediction<E> = (Vec<(<E as Environment>::Action, NotNan<f32>)>, f32, f32);
+
+/// Statistics of a position over all of its nodes.
+struct Position<E: Environment> {
+    prediction: Option<Prediction<E>>,
+    visits: u32,
+    value: f32,
+}
+
+impl<E: Environment> Default for Position<E> {
+    fn default() -> Self {
+        Self {
+            prediction: None,
+            visits: 0,
+            value: 0.0,
+        }
+    }
+}
+
+/// Statistics shared between all nodes which represent the same position,
+/// keyed by [`Environment::transposition_hash`].
+pub struct TranspositionTable<E: Environment> {
+    positions: HashMap<u64, Position<E>>,
+}
+
+impl<E: Environment> Default for TranspositionTable<E> {
+    fn default() -> Self {
+        Self {
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<E: Environment> TranspositionTable<E> {
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+    }
+
+    /// Number of backups through any node of the position.
+    #[must_use]
+    pub fn visits(&self, hash: u64) -> u32 {
+        self.positions
+            .get(&hash)
+            .map_or(0, |position| position.visits)
+    }
+
+    /// Mean value of the position over all of its visits,
+    /// or `None` if it was not visited.
+    #[must_use]
+    pub fn value(&self, hash: u64) -> Option<f32> {
+        self.positions
+            .get(&hash)
+            .filter(|position| position.visits > 0)
+            .map(|position| position.value)
+    }
+}
+
+impl<E: Environment> Node<E> {
+    /// [`Node::simulate_with_config`] with a transposition table, which this
+    /// node keeps between simulations, and which moves along to the child
+    /// with [`Node::descend`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the environment has no transposition hash,
+    /// or if the agent does not return a prediction when needed.
+    pub(super) fn simulate_with_transpositions<A: Agent<E>>(
+        &mut self,
+        agent: &A,
+        env: E,
+        config: &SearchConfig,
+    ) -> Propagated {
+        let mut table = self.transpositions.take().unwrap_or_default();
+        let mut trajectory = Vec::new();
+        let forward = self.forward_with_config(&mut trajectory, env.clone(), config);
+        let hashes = self.hashes_along(&trajectory, env);
+
+        let (propagated, leaf_value) = match forward {
+            Forward::Known(eval) => (
+                self.backward_known_eval_with_config(trajectory.iter().copied(), eval, config),
+                None,
+            ),
+            Forward::NeedsNetwork(env) => {
+                let leaf = hashes.last().expect("there should be a hash of the root");
+                let (policy, value, uncertainty) = table
+                    .positions
+                    .entry(*leaf)
+                    .or_default()
+                    .prediction
+                    .get_or_insert_with(|| {
+                        let mut actions = [Vec::new()];
+                        env.populate_actions(&mut actions[0]);
+                        let prediction = agent
+                            .policy_value_uncertainty(&[env], &actions)
+                            .next()
+                            .expect("agent should return exactly one prediction");
+                        prediction
+                    })
+                    .clone();
+                // Calculate probabilities from logits.
+                let probabilities = softmax(policy.clone().into_iter().map(|(_, p)| p));
+                let propagated = self.backward_network_eval_with_config(
+                    trajectory.iter().copied(),
+                    policy
+                        .into_iter()
+                        .zip(probabilities)
+                        .map(|((action, logit), probability)| ActionPolicy {
+                            action,
+                            logit,
+                            probability,
+                        }),
+                    value,
+                    uncertainty,
+                    config,
+                );
+                (propagated, Some(value))
+            }
+        };
+
+        self.share_statistics(&trajectory, &hashes, leaf_value, &mut table, config);
+        self.transpositions = Some(table);
+        propagated
+    }
+
+    /// Hash of every position along the trajectory, from this node to the
+    /// leaf.
+    fn hashes_along(&self, trajectory: &[usize], mut env: E) -> Vec<u64> {
+        let hash = |env: &E| {
+            env.transposition_hash()
+                .expect("transpositions need an environment with a hash")
+        };
+        let mut hashes = Vec::with_capacity(trajectory.len() + 1);
+        hashes.push(hash(&env));
+        let mut id = ROOT;
+        for &index in trajectory {
+            let (action, _) = &self.children_of(self.node(id))[index];
+            env.step(action.clone());
+            hashes.push(hash(&env));
+            id = self.child_id(id, index);
+        }
+        hashes
+    }
+
+    /// Add the values which the backup added to the nodes along the
+    /// trajectory to their positions in the table, and give the nodes the
+    /// mean value of their position.
+    fn share_statistics(
+        &mut self,
+        trajectory: &[usize],
+        hashes: &[u64],
+        leaf_value: Option<f32>,
+        table: &mut TranspositionTable<E>,
+        config: &SearchConfig,
+    ) {
+        let path = self.path(ROOT, trajectory.iter().copied());
+        let evaluations: Vec<_> = path.iter().map(|&id| self.node(id).evaluation).collect();
+
+        // Same as the backup: solved nodes pass on their result, and all
+        // others add the negated and discounted value of their child.
+        let mut samples = vec![None; evaluations.len()];
+        let mut propagated = None;
+        for (depth, evaluation) in evaluations.into_iter().enumerate().rev() {
+            if evaluation.is_known() {
+                propagated = Some(evaluation);
+                continue;
+            }
+            let sample = propagated.map_or_else(
+                || leaf_value.expect("a leaf which is not solved should have been evaluated"),
+                |child: Eval| child.negate().discounted(config.discount).into_inner(),
+            );
+            samples[depth] = Some(sample);
+            propagated =
+                Some(Eval::new_value(sample * config.discount).expect("value should not be NaN"));
+        }
+
+        for ((id, hash), sample) in path.into_iter().zip(hashes).zip(samples) {
+            if let Some(sample) = sample {
+                let position = table.positions.entry(*hash).or_default();
+                position.visits += 1;
+                position.value += (sample - position.value) / position.visits as f32;
+                self.node_mut(id).evaluation =
+                    Eval::new_value(position.value).expect("shared value should not be NaN");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::HashMap};
+
+    use fast_tak::Game;
+    use ordered_float::NotNan;
+
+    use super::TranspositionTable;
+    use crate::search::{
+        agent::{dummy::Dummy, Agent},
+        env::{Environment, Transposable},
+        eval::Eval,
+        node::Node,
+        SearchConfig,
+    };
+
+    /// Counts how many positions it is asked to evaluate,
+    /// and gives every position a value from its hash.
+    #[derive(Default)]
+    struct Counting {
+        evaluations: Cell<usize>,
+    }
+
+    impl Counting {
+        fn value<E: Environment>(env: &E) -> f32 {
+            env.transposition_hash()
+                .map_or(0.0, |hash| (hash % 11) as f32 / 10.0 - 0.5)
+        }
+    }
+
+    impl<E: Environment> Agent<E> for Counting {
+        fn policy_value_uncertainty(
+            &self,
+            env_batch: &[E],
+            actions_batch: &[Vec<E::Action>],
+        ) -> impl Iterator<Item = (Vec<(E::Action, NotNan<f32>)>, f32, f32)> {
+            self.evaluations
+                .set(self.evaluations.get() + env_batch.len());
+            let predictions: Vec<_> = Dummy
+                .policy_value_uncertainty(env_batch, actions_batch)
+                .zip(env_batch)
+                .map(|((policy, _, uncertainty), env)| (policy, Self::value(env), uncertainty))
+                .collect();
+            predictions.into_iter()
+        }
+    }
+
+    const CONFIG: SearchConfig = SearchConfig {
+        transpositions: true,
+        ..SearchConfig::with_beta(0.0)
+    };
+
+    /// White plays b2 and c2 in a different order.
+    fn transposed() -> [Game<3, 0>; 3] {
+        [
+            Game::from_ptn_moves(&["a1", "c3", "b2", "a3", "c2"]),
+            Game::from_ptn_moves(&["a1", "c3", "c2", "a3", "b2"]),
+            Game::from_ptn_moves(&["a1", "c3", "c2", "a3", "b1"]),
+        ]
+    }
+
+    #[test]
+    fn transposition_reuses_prediction() {
+        let [first, second, other] = transposed();
+        assert_eq!(first.transposition_hash(), second.transposition_hash());
+        assert_ne!(first.transposition_hash(), other.transposition_hash());
+
+        let agent = Counting::default();
+        let mut first_root = Node::default();
+        first_root.simulate_with_config(&agent, first, &CONFIG);
+        assert_eq!(agent.evaluations.get(), 1);
+        assert_eq!(first_root.transpositions.as_ref().unwrap().len(), 1);
+
+        // The transposed root is initialized without asking the agent.
+        let mut second_root = Node {
+            transpositions: first_root.transpositions.take(),
+            ..Node::default()
+        };
+        second_root.simulate_with_config(&agent, second.clone(), &CONFIG);
+        assert_eq!(agent.evaluations.get(), 1);
+        assert_eq!(second_root.children().len(), first_root.children().len());
+
+        let mut other_root = Node {
+            transpositions: second_root.transpositions.take(),
+            ..Node::default()
+        };
+        other_root.simulate_with_config(&agent, other, &CONFIG);
+        assert_eq!(agent.evaluations.get(), 2);
+        assert_eq!(other_root.transpositions.as_ref().unwrap().len(), 2);
+
+        // Without the option every node asks the agent.
+        let mut plain = Node::default();
+        plain.simulate_with_config(&agent, second, &SearchConfig::default());
+        assert_eq!(agent.evaluations.get(), 3);
+        assert!(plain.transpositions.is_none());
+    }
+
+    #[test]
+    fn transpositions_share_values_and_visits() {
+        const SIMULATIONS: u32 = 30;
+        let [first, second, _] = transposed();
+        let agent = Counting::default();
+        let mut first_root = Node::default();
+        for _ in 0..SIMULATIONS {
+            first_root.simulate_with_config(&agent, first.clone(), &CONFIG);
+        }
+        let hash = first.transposition_hash().unwrap();
+        let table = first_root.transpositions.take().unwrap();
+        assert_eq!(table.visits(hash), SIMULATIONS);
+        let Eval::Value(first_value) = first_root.evaluation else {
+            panic!("the root should not be solved");
+        };
+        assert!((table.value(hash).unwrap() - first_value.into_inner()).abs() < 1e-6);
+
+        // The first visit of the transposed root already has the value
+        // of all visits before it.
+        let mut second_root = Node {
+            transpositions: Some(table),
+            ..Node::default()
+        };
+        second_root.simulate_with_config(&agent, second.clone(), &CONFIG);
+        let table = second_root.transpositions.as_ref().unwrap();
+        assert_eq!(table.visits(hash), SIMULATIONS + 1);
+        assert_eq!(second_root.visit_count(), 1);
+        let expected = first_value.into_inner()
+            + (Counting::value(&second) - first_value.into_inner()) / (SIMULATIONS + 1) as f32;
+        assert_eq!(
+            second_root.evaluation,
+            Eval::new_value(table.value(hash).unwrap()).unwrap()
+        );
+        assert!((table.value(hash).unwrap() - expected).abs() < 1e-6);
+    }
+
+    /// Visit counts and whether every node is unsolved,
+    /// of the positions in the tree.
+    fn positions_in_tree(
+        tree: &Node<Game<3, 0>>,
+        node: &Statistics,
+        env: &Game<3, 0>,
+        positions: &mut HashMap<u64, (u32, u32, bool)>,
+    ) {
+        if node.visit_count() == 0 {
+            return;
+        }
+        let (nodes, visits, unsolved) = positions
+            .entry(env.transposition_hash().unwrap())
+            .or_insert((0, 0, true));
+        *nodes += 1;
+        *visits += node.visit_count();
+        *unsolved &= !node.evaluation.is_known();
+        for (action, child) in tree.children_of(node) {
+            let mut env = env.clone();
+            env.step(*action);
+            positions_in_tree(tree, child, &env, positions);
+        }
+    }
+
+    #[test]
+    fn table_counts_the_visits_of_every_path() {
+        let env: Game<3, 0> = Game::from_ptn_moves(&["a1", "c3"]);
+        let mut root = Node::default();
+        for _ in 0..2000 {
+            root.simulate_with_config(&Dummy, env.clone(), &CONFIG);
+        }
+        let table: &TranspositionTable<_> = root.transpositions.as_ref().unwrap();
+        let mut positions = HashMap::new();
+        positions_in_tree(&root, &root, &env, &mut positions);
+
+        // Some positions were reached by different move orders.
+        assert!(positions.values().any(|&(nodes, ..)| nodes > 1));
+        // A position is visited once for every visit of any of its nodes,
+        // unless one of them is solved, which stops counting it.
+        for (hash, (_, visits, unsolved)) in positions {
+            if unsolved {
+                assert_eq!(table.visits(hash), visits);
+            }
+        }
+    }
+}
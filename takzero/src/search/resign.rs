@@ -0,0 +1,56 @@
+/// Tracks the root values of one side,
+/// and decides when that side should resign.
+#[derive(Debug, Clone)]
+pub struct Resignation {
+    threshold: f32,
+    moves: usize,
+    below: usize,
+}
+
+impl Resignation {
+    /// Resign after `moves` moves in a row with a root value below
+    /// `threshold`.
+    #[must_use]
+    pub const fn new(threshold: f32, moves: usize) -> Self {
+        Self {
+            threshold,
+            moves,
+            below: 0,
+        }
+    }
+
+    /// Record the root value before a move by this side.
+    /// Returns whether the side should resign.
+    pub fn update(&mut self, value: f32) -> bool {
+        if value < self.threshold {
+            self.below += 1;
+        } else {
+            self.below = 0;
+        }
+        self.below >= self.moves
+    }
+
+    pub fn reset(&mut self) {
+        self.below = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resignation;
+
+    #[test]
+    fn resign_after_consecutive_moves() {
+        let mut resignation = Resignation::new(-0.9, 3);
+        assert!(!resignation.update(-0.95));
+        assert!(!resignation.update(-0.95));
+        // A single good move resets the count.
+        assert!(!resignation.update(-0.5));
+        assert!(!resignation.update(-0.95));
+        assert!(!resignation.update(-0.99));
+        assert!(resignation.update(-1.0));
+
+        resignation.reset();
+        assert!(!resignation.update(-1.0));
+    }
+}
@@ -2,14 +2,14 @@ use std::{
     collections::VecDeque,
     fmt,
     fs::OpenOptions,
-    io::{BufRead, BufReader},
+    io::{self, BufRead, BufReader, Read, Write},
     num::ParseFloatError,
     path::Path,
     str::FromStr,
 };
 
 use fast_tak::{
-    takparse::{GameResult, ParseMoveError, ParsePtnError, ParseTpsError, Ptn, Tps},
+    takparse::{Color, GameResult, ParseMoveError, ParsePtnError, ParseTpsError, Ptn, Square, Tps},
     Game,
     PlayError,
     Reserves,
@@ -19,7 +19,26 @@ use ordered_float::{FloatIsNan, NotNan};
 use rand::prelude::*;
 use thiserror::Error;
 
-use crate::search::{env::Environment, node::Node};
+use crate::search::{env::Environment, eval::Eval, node::Node};
+
+pub mod ptn;
+pub mod socket;
+
+/// Version of the format of [`Target::write_bin`], which is written before
+/// every target.
+pub const BIN_VERSION: u8 = 2;
+/// Length of the largest binary target which is read, far more than any
+/// target needs, so that a corrupt length is not mistaken for a target
+/// which is still being written.
+const BIN_MAX_LENGTH: u32 = 1 << 20;
+// Flags of the optional parts of a binary target.
+const BIN_SCORE: u8 = 1;
+const BIN_OWNERSHIP: u8 = 1 << 1;
+const BIN_RESULT: u8 = 1 << 2;
+
+fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Target<E: Environment> {
@@ -27,6 +46,15 @@ pub struct Target<E: Environment> {
     pub policy: Box<[(E::Action, NotNan<f32>)]>, // \pi'(s_t)
     pub value: f32,                              // discounted N-step value
     pub ube: f32,                                // sum of RND + discounted N-step UBE
+    /// Final flat margin, see [`final_score`], or `None` if it is unknown.
+    pub score: Option<f32>,
+    /// Final owner of each square, see [`final_ownership`],
+    /// or `None` if it is unknown.
+    pub ownership: Option<Box<[i8]>>,
+    /// Result of the game from the perspective of the player to move,
+    /// with the number of plies until it ended, or `None` if it is unknown.
+    /// It is never an [`Eval::Value`].
+    pub result: Option<Eval>,
 }
 
 pub trait Augment {
@@ -44,6 +72,12 @@ where
             env: self.env.symmetries().into_iter().nth(index).unwrap(),
             value: self.value,
             ube: self.ube,
+            score: self.score,
+            ownership: self
+                .ownership
+                .as_deref()
+                .map(|ownership| symmetric_ownership::<N>(ownership, index)),
+            result: self.result,
             policy: self
                 .policy
                 .iter()
@@ -53,6 +87,282 @@ where
     }
 }
 
+impl<const N: usize, const HALF_KOMI: i8> Target<Game<N, HALF_KOMI>>
+where
+    Reserves<N>: Default,
+{
+    /// Create all 8 symmetric variants of the target,
+    /// in the same order as [`Symmetry::symmetries`].
+    #[must_use]
+    pub fn all_symmetries(&self) -> [Self; 8] {
+        let mut envs = self.env.symmetries().into_iter();
+        std::array::from_fn(|index| Self {
+            env: envs.next().expect("there should be 8 symmetries"),
+            value: self.value,
+            ube: self.ube,
+            score: self.score,
+            ownership: self
+                .ownership
+                .as_deref()
+                .map(|ownership| symmetric_ownership::<N>(ownership, index)),
+            result: self.result,
+            policy: self
+                .policy
+                .iter()
+                .map(|(mov, p)| (Symmetry::<N>::symmetries(mov)[index], *p))
+                .collect(),
+        })
+    }
+
+    /// Write the target in a compact binary format.
+    ///
+    /// The layout is the version of the format ([`BIN_VERSION`] as `u8`),
+    /// the length of the rest of the target (`u32`), the length of the TPS
+    /// (`u16`), the TPS bytes, the value and UBE
+    /// (`f32`), the number of policy entries (`u16`), and for each non-zero
+    /// entry the index of the action among the legal actions (`u16`) and its
+    /// probability (`f32`). Then come flags (`u8`) for which of the score,
+    /// ownership, and result follow: the score (`f32`), the owner of each
+    /// square (`i8`), and the result (`u8`, 0 for a loss, 1 for a draw, and
+    /// 2 for a win) with the number of plies until the end (`u32`).
+    /// Everything is little-endian.
+    ///
+    /// Every target starts with the version, so that a target from a worker
+    /// which runs a different version is rejected instead of being misread,
+    /// and its length, so that a corrupt target can be skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the policy contains actions which are not legal.
+    pub fn write_bin<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut payload = Vec::new();
+        self.write_bin_payload(&mut payload)?;
+        writer.write_all(&[BIN_VERSION])?;
+        writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+        writer.write_all(&payload)
+    }
+
+    /// Everything of [`Target::write_bin`] after the length.
+    fn write_bin_payload(&self, writer: &mut impl Write) -> io::Result<()> {
+        let tps = Tps::from(self.env.clone()).to_string();
+        writer.write_all(&(tps.len() as u16).to_le_bytes())?;
+        writer.write_all(tps.as_bytes())?;
+        writer.write_all(&self.value.to_le_bytes())?;
+        writer.write_all(&self.ube.to_le_bytes())?;
+
+        let mut actions = Vec::new();
+        self.env.populate_actions(&mut actions);
+        let entries: Vec<_> = self
+            .policy
+            .iter()
+            .filter(|(_, p)| p.into_inner() != 0.0)
+            .map(|(action, p)| {
+                let index = actions
+                    .iter()
+                    .position(|a| a == action)
+                    .expect("policy actions should be legal");
+                (index as u16, p.into_inner())
+            })
+            .collect();
+        writer.write_all(&(entries.len() as u16).to_le_bytes())?;
+        for (index, p) in entries {
+            writer.write_all(&index.to_le_bytes())?;
+            writer.write_all(&p.to_le_bytes())?;
+        }
+
+        let result = self.result.and_then(|result| {
+            let kind = match result {
+                Eval::Loss(_) => 0,
+                Eval::Draw(_) => 1,
+                Eval::Win(_) => 2,
+                Eval::Value(_) => return None,
+            };
+            Some((kind, result.ply()?))
+        });
+        let flag = |present: bool, bit: u8| if present { bit } else { 0 };
+        let flags = flag(self.score.is_some(), BIN_SCORE)
+            | flag(self.ownership.is_some(), BIN_OWNERSHIP)
+            | flag(result.is_some(), BIN_RESULT);
+        writer.write_all(&[flags])?;
+        if let Some(score) = self.score {
+            writer.write_all(&score.to_le_bytes())?;
+        }
+        if let Some(ownership) = &self.ownership {
+            debug_assert_eq!(ownership.len(), N * N);
+            let bytes: Vec<u8> = ownership
+                .iter()
+                .map(|owner| owner.to_le_bytes()[0])
+                .collect();
+            writer.write_all(&bytes)?;
+        }
+        if let Some((kind, plies)) = result {
+            writer.write_all(&[kind])?;
+            writer.write_all(&plies.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read a target written with [`Target::write_bin`].
+    /// The policy contains every legal action in the order
+    /// of [`Environment::populate_actions`], with zeroes for missing entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns the errors of [`Target::read_bin_record`] and
+    /// [`Target::from_bin_record`].
+    pub fn read_bin<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Self::from_bin_record(&Self::read_bin_record(reader)?)
+    }
+
+    /// Read the bytes of the next target written with [`Target::write_bin`]
+    /// without parsing them, see [`Target::from_bin_record`]. A target whose
+    /// bytes turn out to be corrupt can be skipped this way, the reader is at
+    /// the start of the next target either way.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`io::ErrorKind::UnexpectedEof`] if the reader ends
+    /// in the middle of a target, and [`io::ErrorKind::InvalidData`]
+    /// if the target was written in a version of the format which is not
+    /// known or its length is not plausible. The end of the target is not
+    /// known then, so the targets after it cannot be read.
+    pub fn read_bin_record<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        let [version, length @ ..] = header;
+        if version != BIN_VERSION {
+            return Err(invalid(format!(
+                "binary target version {version} is not supported, expected {BIN_VERSION}"
+            )));
+        }
+        let length = u32::from_le_bytes(length);
+        if length > BIN_MAX_LENGTH {
+            return Err(invalid(format!(
+                "binary target length {length} is too long"
+            )));
+        }
+        let mut record = Vec::with_capacity(length as usize);
+        reader.take(u64::from(length)).read_to_end(&mut record)?;
+        if record.len() < length as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(record)
+    }
+
+    /// Parse the bytes of a target returned by [`Target::read_bin_record`].
+    ///
+    /// # Errors
+    ///
+    /// Returns [`io::ErrorKind::InvalidData`] if the bytes do not describe
+    /// a valid target.
+    pub fn from_bin_record(mut record: &[u8]) -> io::Result<Self> {
+        let target = Self::read_bin_payload(&mut record).map_err(|err| {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                invalid("binary target is longer than its length")
+            } else {
+                err
+            }
+        })?;
+        if !record.is_empty() {
+            return Err(invalid("binary target is shorter than its length"));
+        }
+        Ok(target)
+    }
+
+    /// Everything of [`Target::from_bin_record`] except checking the length.
+    fn read_bin_payload(reader: &mut &[u8]) -> io::Result<Self> {
+        fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+            let mut bytes = [0; 1];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes[0])
+        }
+        fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+            let mut bytes = [0; 2];
+            reader.read_exact(&mut bytes)?;
+            Ok(u16::from_le_bytes(bytes))
+        }
+        fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(f32::from_le_bytes(bytes))
+        }
+
+        let mut tps = vec![0; usize::from(read_u16(reader)?)];
+        reader.read_exact(&mut tps)?;
+        let tps: Tps = String::from_utf8(tps)
+            .map_err(invalid)?
+            .parse()
+            .map_err(invalid)?;
+        let env: Game<N, HALF_KOMI> = tps.into();
+        let value = read_f32(reader)?;
+        let ube = read_f32(reader)?;
+
+        let mut actions = Vec::new();
+        env.populate_actions(&mut actions);
+        let mut policy: Box<[_]> = actions
+            .into_iter()
+            .map(|action| (action, NotNan::default()))
+            .collect();
+        for _ in 0..read_u16(reader)? {
+            let index = usize::from(read_u16(reader)?);
+            let p = NotNan::new(read_f32(reader)?).map_err(invalid)?;
+            policy
+                .get_mut(index)
+                .ok_or_else(|| invalid("policy index out of range"))?
+                .1 = p;
+        }
+
+        let flags = read_u8(reader)?;
+        if flags & !(BIN_SCORE | BIN_OWNERSHIP | BIN_RESULT) != 0 {
+            return Err(invalid("unknown flags"));
+        }
+        let score = (flags & BIN_SCORE != 0)
+            .then(|| read_f32(reader))
+            .transpose()?;
+        let ownership = (flags & BIN_OWNERSHIP != 0)
+            .then(|| {
+                let mut bytes = vec![0; N * N];
+                reader.read_exact(&mut bytes)?;
+                io::Result::Ok(
+                    bytes
+                        .into_iter()
+                        .map(|owner| i8::from_le_bytes([owner]))
+                        .collect(),
+                )
+            })
+            .transpose()?;
+        let result = (flags & BIN_RESULT != 0)
+            .then(|| {
+                let kind = read_u8(reader)?;
+                let mut plies = [0; 4];
+                reader.read_exact(&mut plies)?;
+                let plies = u32::from_le_bytes(plies);
+                match kind {
+                    0 => Ok(Eval::Loss(plies)),
+                    1 => Ok(Eval::Draw(plies)),
+                    2 => Ok(Eval::Win(plies)),
+                    _ => Err(invalid("unknown result")),
+                }
+            })
+            .transpose()?;
+
+        Ok(Self {
+            env,
+            policy,
+            value,
+            ube,
+            score,
+            ownership,
+            result,
+        })
+    }
+}
+
+/// Targets are written one per line, so the output ends with a newline.
 impl<const N: usize, const HALF_KOMI: i8> fmt::Display for Target<Game<N, HALF_KOMI>>
 where
     Reserves<N>: Default,
@@ -68,7 +378,34 @@ where
             .collect::<Vec<_>>()
             .join(",");
 
-        writeln!(f, "{tps};{value};{ube};{policy}")
+        write!(f, "{tps};{value};{ube};{policy}")?;
+        // Targets without a score, ownership, or result keep the format from
+        // before they existed. Unknown fields before a known one are empty.
+        let has_ownership = self.ownership.is_some() || self.result.is_some();
+        if self.score.is_some() || has_ownership {
+            write!(f, ";")?;
+        }
+        if let Some(score) = self.score {
+            write!(f, "{score}")?;
+        }
+        if has_ownership {
+            write!(f, ";")?;
+        }
+        if let Some(ownership) = &self.ownership {
+            let ownership: String = ownership
+                .iter()
+                .map(|owner| match owner.signum() {
+                    1 => '+',
+                    -1 => '-',
+                    _ => '0',
+                })
+                .collect();
+            write!(f, "{ownership}")?;
+        }
+        if let Some(result) = self.result {
+            write!(f, ";{result}")?;
+        }
+        writeln!(f)
     }
 }
 
@@ -84,6 +421,10 @@ pub enum ParseTargetError {
     MissingPolicy,
     #[error("policy format is wrong")]
     WrongPolicyFormat,
+    #[error("ownership format is wrong")]
+    WrongOwnershipFormat,
+    #[error("result format is wrong")]
+    WrongResultFormat,
     #[error("{0}")]
     Tps(#[from] ParseTpsError),
     #[error("{0}")]
@@ -103,7 +444,7 @@ where
     type Err = ParseTargetError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        //{tps};{value};{ube};{policy}
+        //{tps};{value};{ube};{policy}[;{score}[;{ownership}[;{result}]]]
         let mut iter = s.trim().split(';');
         let tps: Tps = iter.next().ok_or(ParseTargetError::MissingTps)?.parse()?;
         let value = iter.next().ok_or(ParseTargetError::MissingValue)?.parse()?;
@@ -118,6 +459,30 @@ where
                     .and_then(|(a, p)| Ok((a.parse()?, NotNan::new(p.parse()?)?)))
             })
             .collect::<Result<_, _>>()?;
+        let score = iter
+            .next()
+            .filter(|score| !score.is_empty())
+            .map(str::parse)
+            .transpose()?;
+        let ownership = iter
+            .next()
+            .filter(|ownership| !ownership.is_empty())
+            .map(|ownership| {
+                ownership
+                    .chars()
+                    .map(|owner| match owner {
+                        '+' => Ok(1),
+                        '-' => Ok(-1),
+                        '0' => Ok(0),
+                        _ => Err(ParseTargetError::WrongOwnershipFormat),
+                    })
+                    .collect::<Result<Box<[i8]>, _>>()
+            })
+            .transpose()?;
+        if ownership.as_ref().is_some_and(|o| o.len() != N * N) {
+            return Err(ParseTargetError::WrongOwnershipFormat);
+        }
+        let result = iter.next().map(parse_result).transpose()?;
         let env: Game<N, HALF_KOMI> = tps.into();
 
         // Check that all actions that should be in the policy are in the policy,
@@ -138,11 +503,84 @@ where
             policy,
             value,
             ube,
+            score,
+            ownership,
+            result,
         })
     }
 }
 
-/// Create an improved policy target of proportional visit counts.
+/// Parse a result like `Win(3)`, as it is written by the `Display` of [`Eval`].
+fn parse_result(s: &str) -> Result<Eval, ParseTargetError> {
+    let (result, ply) = s
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or(ParseTargetError::WrongResultFormat)?;
+    let ply = ply
+        .parse()
+        .map_err(|_| ParseTargetError::WrongResultFormat)?;
+    match result {
+        "Win" => Ok(Eval::Win(ply)),
+        "Loss" => Ok(Eval::Loss(ply)),
+        "Draw" => Ok(Eval::Draw(ply)),
+        _ => Err(ParseTargetError::WrongResultFormat),
+    }
+}
+
+/// Final flat margin of a finished game from the perspective of `color`,
+/// including komi and divided by the number of squares.
+#[must_use]
+pub fn final_score<const N: usize, const HALF_KOMI: i8>(
+    terminal: &Game<N, HALF_KOMI>,
+    color: Color,
+) -> f32 {
+    let margin = f32::from(terminal.board.flat_diff()) - f32::from(HALF_KOMI) / 2.0;
+    let margin = margin / (N * N) as f32;
+    match color {
+        Color::White => margin,
+        Color::Black => -margin,
+    }
+}
+
+/// Owner of each square of a finished game from the perspective of `color`.
+/// Squares are indexed by `row * N + column`, and the owner is the color of
+/// the top piece: `1` for `color`, `-1` for the opponent, `0` if empty.
+#[must_use]
+pub fn final_ownership<const N: usize, const HALF_KOMI: i8>(
+    terminal: &Game<N, HALF_KOMI>,
+    color: Color,
+) -> Box<[i8]> {
+    let mut ownership = vec![0; N * N];
+    for (row, stacks) in terminal.board.iter().enumerate() {
+        for (column, stack) in stacks.enumerate() {
+            if let Some((_, owner)) = stack.top() {
+                ownership[row * N + column] = if owner == color { 1 } else { -1 };
+            }
+        }
+    }
+    ownership.into()
+}
+
+/// Apply the symmetry with the given index in [`Symmetry::symmetries`]
+/// to an ownership map.
+fn symmetric_ownership<const N: usize>(ownership: &[i8], index: usize) -> Box<[i8]> {
+    let mut symmetric = vec![0; N * N];
+    for (i, owner) in ownership.iter().enumerate() {
+        let square = Square::new((i % N) as u8, (i / N) as u8);
+        let square = Symmetry::<N>::symmetries(&square)[index];
+        symmetric[usize::from(square.row()) * N + usize::from(square.column())] = *owner;
+    }
+    symmetric.into()
+}
+
+/// Create a policy target of proportional visit counts, which is the
+/// policy target of selfplay and reanalyze, see [`Node::visit_policy`].
+///
+/// Only the visited children are in the target, they are normalized by
+/// their own visits, without the visit of the root itself. The children
+/// which were not visited are left out, which is the same as giving them a
+/// probability of zero, because actions which are missing from a target
+/// get zero in the policy tensor.
 ///
 /// # Panics
 ///
@@ -151,16 +589,7 @@ where
 pub fn policy_target_from_proportional_visits<E: Environment>(
     node: &Node<E>,
 ) -> Box<[(E::Action, NotNan<f32>)]> {
-    node.children
-        .iter()
-        .map(|(action, child)| {
-            (
-                action.clone(),
-                NotNan::new(child.visit_count as f32 / node.visit_count as f32)
-                    .expect("target policy should not be NaN"),
-            )
-        })
-        .collect()
+    node.visit_policy(1.0).into()
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -290,6 +719,49 @@ where
         .filter_map(|line| line.ok()?.parse::<Target<Game<N, HALF_KOMI>>>().ok()))
 }
 
+/// Open a file and parse all the targets stored with [`Target::write_bin`].
+/// A truncated target at the end of the file is ignored.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be opened or contains invalid data.
+pub fn get_binary_targets<const N: usize, const HALF_KOMI: i8>(
+    path: impl AsRef<Path>,
+) -> Result<Vec<Target<Game<N, HALF_KOMI>>>, std::io::Error>
+where
+    Reserves<N>: Default,
+{
+    let mut reader = BufReader::new(OpenOptions::new().read(true).open(path)?);
+    let mut targets = Vec::new();
+    loop {
+        match Target::read_bin(&mut reader) {
+            Ok(target) => targets.push(target),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break Ok(targets),
+            Err(err) => break Err(err),
+        }
+    }
+}
+
+/// Write targets to the writer, one per line.
+/// This is the format read by [`get_targets`].
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_targets<'a, const N: usize, const HALF_KOMI: i8>(
+    mut writer: impl Write,
+    targets: impl IntoIterator<Item = &'a Target<Game<N, HALF_KOMI>>>,
+) -> std::io::Result<()>
+where
+    Reserves<N>: Default,
+{
+    for target in targets {
+        // The `Display` implementation already terminates the line.
+        write!(writer, "{target}")?;
+    }
+    writer.flush()
+}
+
 #[cfg(test)]
 mod tests {
     use fast_tak::Game;
@@ -297,10 +769,46 @@ mod tests {
     use rand::{seq::IteratorRandom, Rng, SeedableRng};
 
     use crate::{
-        search::env::Environment,
-        target::{Replay, Target},
+        search::{
+            env::Environment,
+            eval::Eval,
+            node::{Node, Statistics},
+        },
+        target::{
+            final_ownership,
+            get_targets,
+            policy_target_from_proportional_visits,
+            write_targets,
+            Replay,
+            Target,
+            BIN_VERSION,
+        },
     };
 
+    #[test]
+    fn unvisited_children_are_left_out_of_the_policy_target() {
+        let env: Game<3, 0> = Game::default();
+        let mut actions = Vec::new();
+        env.populate_actions(&mut actions);
+        let mut node: Node<Game<3, 0>> =
+            Node::with_children(actions.iter().zip([1, 0, 3]).map(|(action, visit_count)| {
+                (*action, Statistics {
+                    visit_count,
+                    ..Statistics::default()
+                })
+            }));
+        node.visit_count = 5;
+
+        let policy = policy_target_from_proportional_visits(&node);
+        assert_eq!(policy.len(), 2);
+        assert_eq!(policy[0].0, actions[0]);
+        assert_eq!(policy[1].0, actions[2]);
+        // Normalized by the visits of the children, not of the root.
+        assert!((policy[0].1.into_inner() - 0.25).abs() < f32::EPSILON);
+        assert!((policy[1].1.into_inner() - 0.75).abs() < f32::EPSILON);
+        assert_eq!(&*policy, node.visit_policy(1.0).as_slice());
+    }
+
     #[test]
     fn target_consistency() {
         const SEED: u64 = 123;
@@ -321,6 +829,18 @@ mod tests {
                     .collect(),
                 value: rng.gen(),
                 ube: rng.gen(),
+                score: rng.gen_bool(0.5).then(|| rng.gen()),
+                ownership: rng
+                    .gen_bool(0.5)
+                    .then(|| (0..25).map(|_| rng.gen_range(-1..=1)).collect()),
+                result: rng.gen_bool(0.5).then(|| {
+                    let plies = rng.gen_range(0..100);
+                    match rng.gen_range(0..3) {
+                        0 => Eval::Win(plies),
+                        1 => Eval::Loss(plies),
+                        _ => Eval::Draw(plies),
+                    }
+                }),
             };
             let string = target.to_string();
             println!("{string}");
@@ -366,4 +886,157 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn write_and_read_targets() {
+        const SEED: u64 = 456;
+        const TARGETS: usize = 200;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        let targets: Vec<Target<Game<5, 4>>> = (0..TARGETS)
+            .map(|_| {
+                let mut env = Game::new_opening_with_random_steps(&mut rng, &mut actions, 10);
+                env.reversible_plies = 0;
+                env.populate_actions(&mut actions);
+                Target {
+                    env,
+                    policy: actions
+                        .drain(..)
+                        .map(|a| (a, NotNan::new(rng.gen()).unwrap()))
+                        .collect(),
+                    value: rng.gen(),
+                    ube: rng.gen(),
+                    score: None,
+                    ownership: None,
+                    result: None,
+                }
+            })
+            .collect();
+
+        let path = std::env::temp_dir().join("takzero-write-and-read-targets.txt");
+        write_targets(std::fs::File::create(&path).unwrap(), &targets).unwrap();
+        let recovered: Vec<_> = get_targets::<5, 4>(&path).unwrap().collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(recovered.len(), targets.len());
+        assert_eq!(recovered, targets);
+    }
+
+    #[test]
+    fn all_symmetries_keep_policy_legal() {
+        const SEED: u64 = 135;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        for _ in 0..50 {
+            let env: Game<5, 4> = Game::new_opening_with_random_steps(&mut rng, &mut actions, 15);
+            env.populate_actions(&mut actions);
+            let weights: Vec<f32> = actions.iter().map(|_| rng.gen()).collect();
+            let total: f32 = weights.iter().sum();
+            let ownership = Some(final_ownership(&env, env.to_move));
+            let target = Target {
+                env,
+                policy: actions
+                    .drain(..)
+                    .zip(weights)
+                    .map(|(a, w)| (a, NotNan::new(w / total).unwrap()))
+                    .collect(),
+                value: rng.gen(),
+                ube: rng.gen(),
+                score: None,
+                ownership,
+                result: None,
+            };
+
+            for symmetric in target.all_symmetries() {
+                let sum: f32 = symmetric.policy.iter().map(|(_, p)| p.into_inner()).sum();
+                assert!((sum - 1.0).abs() < 1e-4);
+                symmetric.env.populate_actions(&mut actions);
+                assert_eq!(actions.len(), symmetric.policy.len());
+                for (action, _) in &*symmetric.policy {
+                    assert!(actions.contains(action));
+                }
+                actions.clear();
+                assert!((symmetric.value - target.value).abs() < f32::EPSILON);
+                assert_eq!(
+                    symmetric.ownership,
+                    Some(final_ownership(&symmetric.env, symmetric.env.to_move))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        const SEED: u64 = 246;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        let targets: Vec<Target<Game<5, 4>>> = (0..200)
+            .map(|_| {
+                let mut env = Game::new_opening_with_random_steps(&mut rng, &mut actions, 20);
+                env.reversible_plies = 0;
+                env.populate_actions(&mut actions);
+                Target {
+                    policy: actions
+                        .drain(..)
+                        .map(|a| {
+                            // Keep some zeroes to exercise the sparse encoding.
+                            let p = if rng.gen_bool(0.5) { rng.gen() } else { 0.0 };
+                            (a, NotNan::new(p).unwrap())
+                        })
+                        .collect(),
+                    value: rng.gen(),
+                    ube: rng.gen(),
+                    score: rng.gen_bool(0.5).then(|| rng.gen()),
+                    ownership: rng
+                        .gen_bool(0.5)
+                        .then(|| final_ownership(&env, env.to_move)),
+                    result: rng.gen_bool(0.5).then(|| {
+                        let plies = rng.gen_range(0..100);
+                        match rng.gen_range(0..3) {
+                            0 => Eval::Win(plies),
+                            1 => Eval::Loss(plies),
+                            _ => Eval::Draw(plies),
+                        }
+                    }),
+                    env,
+                }
+            })
+            .collect();
+
+        let mut bytes = Vec::new();
+        for target in &targets {
+            target.write_bin(&mut bytes).unwrap();
+        }
+        let mut reader = bytes.as_slice();
+        for target in &targets {
+            let recovered = Target::read_bin(&mut reader).unwrap();
+            assert_eq!(&recovered, target);
+            assert_eq!(recovered.to_string(), target.to_string());
+        }
+        assert!(reader.is_empty());
+
+        // A truncated target is reported as the end of the input.
+        let mut truncated = &bytes[..bytes.len() - 1];
+        for _ in 1..targets.len() {
+            Target::<Game<5, 4>>::read_bin(&mut truncated).unwrap();
+        }
+        let err = Target::<Game<5, 4>>::read_bin(&mut truncated).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // Targets from a different version of the format are rejected.
+        let mut other_version = bytes.clone();
+        other_version[0] = BIN_VERSION + 1;
+        let err = Target::<Game<5, 4>>::read_bin(&mut other_version.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        // A corrupt target is skipped without losing the targets after it.
+        let mut corrupt = vec![BIN_VERSION, 3, 0, 0, 0, 0xff, 0xff, 0xff];
+        targets[0].write_bin(&mut corrupt).unwrap();
+        let mut reader = corrupt.as_slice();
+        let record = Target::<Game<5, 4>>::read_bin_record(&mut reader).unwrap();
+        let err = Target::<Game<5, 4>>::from_bin_record(&record).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(Target::read_bin(&mut reader).unwrap(), targets[0]);
+        assert!(reader.is_empty());
+    }
 }
@@ -0,0 +1,293 @@
+use std::fmt::Write;
+
+use fast_tak::{
+    takparse::{self, Color, Move, ParsePtnError, Ptn, Tps},
+    Game,
+    GameResult,
+    PlayError,
+    Reserves,
+};
+use ordered_float::NotNan;
+use thiserror::Error;
+
+use super::{final_ownership, final_score, Target};
+use crate::search::{env::Environment, eval::Eval};
+
+#[derive(Error, Debug)]
+pub enum PtnTargetError {
+    #[error("{0}")]
+    Ptn(#[from] ParsePtnError),
+    #[error("invalid action")]
+    Invalid(#[from] PlayError),
+    #[error("the game has no result")]
+    MissingResult,
+}
+
+/// Split PTN text containing several games into the individual games.
+/// A new game starts at the first tag after some move text.
+pub fn split_games(text: &str) -> Vec<&str> {
+    let mut games = Vec::new();
+    let mut start = 0;
+    let mut seen_moves = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if seen_moves {
+                games.push(&text[start..offset]);
+                start = offset;
+                seen_moves = false;
+            }
+        } else if !trimmed.is_empty() {
+            seen_moves = true;
+        }
+        offset += line.len();
+    }
+    if seen_moves {
+        games.push(&text[start..]);
+    }
+    games
+}
+
+enum Outcome {
+    Winner(Color),
+    Draw,
+}
+
+/// Read the outcome from the `Result` tag.
+fn result_tag(text: &str) -> Option<Outcome> {
+    let result = text
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("[Result \""))?
+        .split('"')
+        .next()?;
+    match result {
+        "R-0" | "F-0" | "1-0" => Some(Outcome::Winner(Color::White)),
+        "0-R" | "0-F" | "0-1" => Some(Outcome::Winner(Color::Black)),
+        "1/2-1/2" => Some(Outcome::Draw),
+        _ => None,
+    }
+}
+
+/// Replay a single PTN game and create a target for every position.
+///
+/// The policy target is one-hot on the move that was played, and the value
+/// target is the game result from the perspective of the player to move,
+/// multiplied by `discount` for every ply until the end of the game.
+/// The score and ownership targets are only set for games which were played
+/// to the end.
+/// The result is taken from the `Result` tag, so that games which ended
+/// by resignation or on time can be used too.
+///
+/// # Errors
+///
+/// Returns an error if the game cannot be parsed, contains an invalid move,
+/// or has no result.
+pub fn targets_from_ptn<const N: usize, const HALF_KOMI: i8>(
+    text: &str,
+    discount: f32,
+) -> Result<Vec<Target<Game<N, HALF_KOMI>>>, PtnTargetError>
+where
+    Reserves<N>: Default,
+{
+    let ptn: Ptn = text.parse()?;
+    let mut env: Game<N, HALF_KOMI> = ptn.tps().map_or_else(Game::default, Into::into);
+
+    let mut states = Vec::with_capacity(ptn.moves().len());
+    let mut actions = Vec::new();
+    for action in ptn.moves().iter().copied() {
+        if env.terminal().is_some() {
+            break;
+        }
+        states.push((env.clone(), action));
+        env.play(action)?;
+    }
+
+    let outcome = match result_tag(text) {
+        Some(outcome) => outcome,
+        None => match env.result() {
+            GameResult::Winner { color, .. } => Outcome::Winner(color),
+            GameResult::Draw { .. } => Outcome::Draw,
+            GameResult::Ongoing => return Err(PtnTargetError::MissingResult),
+        },
+    };
+
+    // Games which ended by resignation or on time have no final score.
+    let terminal = env.terminal().is_some().then_some(env);
+    let plies = states.len();
+    Ok(states
+        .into_iter()
+        .enumerate()
+        .map(|(ply, (env, played))| {
+            let remaining = (plies - ply) as u32;
+            let result = match outcome {
+                Outcome::Winner(color) if color == env.to_move => Eval::Win(remaining),
+                Outcome::Winner(_) => Eval::Loss(remaining),
+                Outcome::Draw => Eval::Draw(remaining),
+            };
+            env.populate_actions(&mut actions);
+            let policy = actions
+                .drain(..)
+                .map(|action| {
+                    let p = if action == played { 1.0 } else { 0.0 };
+                    (action, NotNan::new(p).expect("one-hot policy is not NaN"))
+                })
+                .collect();
+            let score = terminal
+                .as_ref()
+                .map(|terminal| final_score(terminal, env.to_move));
+            let ownership = terminal
+                .as_ref()
+                .map(|terminal| final_ownership(terminal, env.to_move));
+            Target {
+                env,
+                policy,
+                value: result.discounted(discount).into_inner(),
+                ube: 0.0,
+                score,
+                ownership,
+                result: Some(result),
+            }
+        })
+        .collect())
+}
+
+/// Write a game as PTN, starting from `start` and playing `moves`.
+///
+/// If no `result` is given, it is taken from the final position,
+/// and left out if the game is still ongoing.
+///
+/// # Panics
+///
+/// Panics if any of the moves are invalid.
+#[must_use]
+pub fn write_ptn<const N: usize, const HALF_KOMI: i8>(
+    start: &Game<N, HALF_KOMI>,
+    moves: &[Move],
+    result: Option<takparse::GameResult>,
+) -> String
+where
+    Reserves<N>: Default,
+{
+    let mut env = start.clone();
+    let mut move_text = String::new();
+    for (i, &action) in moves.iter().enumerate() {
+        let move_number = env.ply / 2 + 1;
+        match env.to_move {
+            Color::White => write!(move_text, "\n{move_number}. {action}"),
+            // PTN marks a missing white move with `--`.
+            Color::Black if i == 0 => write!(move_text, "\n{move_number}. -- {action}"),
+            Color::Black => write!(move_text, " {action}"),
+        }
+        .expect("writing to a string should not fail");
+        env.play(action).expect("moves should be valid");
+    }
+    let result = result.or_else(|| takparse::GameResult::try_from(env.result()).ok());
+
+    let mut ptn = format!(
+        "[Size \"{N}\"]\n[Komi \"{}\"]\n",
+        f32::from(HALF_KOMI) / 2.0
+    );
+    if *start != Game::default() {
+        writeln!(ptn, "[TPS \"{}\"]", Tps::from(start.clone()))
+            .expect("writing to a string should not fail");
+    }
+    if let Some(result) = &result {
+        writeln!(ptn, "[Result \"{result}\"]").expect("writing to a string should not fail");
+    }
+    ptn.push_str(&move_text);
+    if let Some(result) = &result {
+        write!(ptn, " {result}").expect("writing to a string should not fail");
+    }
+    ptn.push('\n');
+    ptn
+}
+
+#[cfg(test)]
+mod tests {
+    use fast_tak::{
+        takparse::{Ptn, Tps},
+        Game,
+    };
+    use rand::{seq::IteratorRandom, SeedableRng};
+
+    use super::{split_games, targets_from_ptn, write_ptn};
+    use crate::search::{env::Environment, DISCOUNT_FACTOR};
+
+    const GAME: &str = "[Size \"3\"]\n[Result \"R-0\"]\n\n1. a1 c1\n2. c2 a2\n3. c3 R-0\n";
+
+    #[test]
+    fn one_hot_targets_from_ptn() {
+        let targets = targets_from_ptn::<3, 0>(GAME, DISCOUNT_FACTOR).unwrap();
+        assert_eq!(targets.len(), 5);
+        for (ply, target) in targets.iter().enumerate() {
+            let ones = target.policy.iter().filter(|(_, p)| p.into_inner() > 0.5);
+            assert_eq!(ones.count(), 1);
+            // White made the last move and won.
+            let sign = if ply % 2 == 0 { 1.0 } else { -1.0 };
+            let expected = sign * DISCOUNT_FACTOR.powi(5 - ply as i32);
+            assert!((target.value - expected).abs() < f32::EPSILON);
+        }
+        assert_eq!(targets[0].env, Game::<3, 0>::default());
+        // The road on the last row leaves white with one more flat.
+        let score = 1.0 / 9.0;
+        assert!((targets[0].score.unwrap() - score).abs() < f32::EPSILON);
+        assert!((targets[1].score.unwrap() + score).abs() < f32::EPSILON);
+        // Three squares for white and two for black.
+        let ownership = targets[0].ownership.as_deref().unwrap();
+        assert_eq!(ownership.iter().map(|&o| i32::from(o)).sum::<i32>(), 1);
+        assert_eq!(ownership.iter().filter(|&&o| o != 0).count(), 5);
+        let opponent = targets[1].ownership.as_deref().unwrap();
+        assert!(ownership.iter().zip(opponent).all(|(a, b)| *a == -b));
+    }
+
+    #[test]
+    fn result_tag_is_used_for_resignations() {
+        let resigned = "[Size \"3\"]\n[Result \"0-R\"]\n\n1. a1 c1\n";
+        let targets = targets_from_ptn::<3, 0>(resigned, 1.0).unwrap();
+        assert_eq!(targets.len(), 2);
+        assert!((targets[0].value + 1.0).abs() < f32::EPSILON);
+        assert!((targets[1].value - 1.0).abs() < f32::EPSILON);
+        assert!(targets
+            .iter()
+            .all(|target| target.score.is_none() && target.ownership.is_none()));
+    }
+
+    #[test]
+    fn split_multiple_games() {
+        let text = format!("{GAME}\n{GAME}");
+        let games = split_games(&text);
+        assert_eq!(games.len(), 2);
+        for game in games {
+            assert_eq!(targets_from_ptn::<3, 0>(game, 1.0).unwrap().len(), 5);
+        }
+    }
+
+    #[test]
+    fn written_ptn_round_trips() {
+        const SEED: u64 = 789;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let mut actions = Vec::new();
+        for _ in 0..20 {
+            let start: Game<5, 4> = Game::new_opening(&mut rng, &mut actions);
+            let mut env = start.clone();
+            let mut moves = Vec::new();
+            while env.terminal().is_none() {
+                env.populate_actions(&mut actions);
+                let action = actions.drain(..).choose(&mut rng).unwrap();
+                moves.push(action);
+                env.step(action);
+            }
+
+            let text = write_ptn(&start, &moves, None);
+            let ptn: Ptn = text.parse().unwrap();
+            assert_eq!(ptn.moves().iter().copied().collect::<Vec<_>>(), moves);
+            let targets = targets_from_ptn::<5, 4>(&text, 1.0).unwrap();
+            assert_eq!(targets.len(), moves.len());
+            assert_eq!(
+                Tps::from(targets[0].env.clone()).to_string(),
+                Tps::from(start).to_string()
+            );
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    io::{self, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+use fast_tak::{Game, Reserves};
+
+use super::Target;
+
+/// Receives targets which workers send with a [`TargetSender`] over TCP,
+/// in the format of [`Target::write_bin`].
+///
+/// Every connection is read on its own thread, so that a slow worker
+/// does not hold up the others. A worker which disconnects, even in the
+/// middle of a target, only loses the target it was sending.
+pub struct TargetReceiver<const N: usize, const HALF_KOMI: i8> {
+    address: SocketAddr,
+    receiver: Receiver<Target<Game<N, HALF_KOMI>>>,
+}
+
+impl<const N: usize, const HALF_KOMI: i8> TargetReceiver<N, HALF_KOMI>
+where
+    Reserves<N>: Default,
+{
+    /// Start listening for workers on the address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address cannot be bound.
+    pub fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        thread::spawn(move || receive(stream, &sender));
+                    }
+                    Err(err) => log::warn!("Could not accept a worker: {err}"),
+                }
+            }
+        });
+        Ok(Self { address, receiver })
+    }
+
+    /// The address which is listened on, with the port filled in
+    /// if port 0 was given to [`TargetReceiver::bind`].
+    #[must_use]
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Targets which were received since the last call, without waiting.
+    pub fn try_iter(&self) -> impl Iterator<Item = Target<Game<N, HALF_KOMI>>> + '_ {
+        self.receiver.try_iter()
+    }
+}
+
+/// Read targets from one worker until it disconnects.
+fn receive<const N: usize, const HALF_KOMI: i8>(
+    stream: TcpStream,
+    sender: &Sender<Target<Game<N, HALF_KOMI>>>,
+) where
+    Reserves<N>: Default,
+{
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown worker".to_string(), |peer| peer.to_string());
+    log::info!("Receiving targets from {peer}");
+    let mut reader = BufReader::new(stream);
+    loop {
+        match Target::read_bin(&mut reader) {
+            Ok(target) => {
+                // The receiver was dropped, so nobody needs the targets.
+                if sender.send(target).is_err() {
+                    break;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                log::info!("{peer} disconnected");
+                break;
+            }
+            Err(err) => {
+                log::warn!("Dropping the connection to {peer}: {err}");
+                break;
+            }
+        }
+    }
+}
+
+/// Sends targets to a [`TargetReceiver`].
+///
+/// The connection is made when targets are first sent, and made again
+/// after a failure, so that the learner can be restarted without
+/// restarting the workers.
+pub struct TargetSender {
+    address: String,
+    stream: Option<TcpStream>,
+}
+
+impl TargetSender {
+    #[must_use]
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            stream: None,
+        }
+    }
+
+    /// Send the targets, connecting first if there is no connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if connecting or writing fails. Some of the targets
+    /// may have been received anyway, and the next call connects again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a policy contains actions which are not legal.
+    pub fn send<'a, const N: usize, const HALF_KOMI: i8>(
+        &mut self,
+        targets: impl IntoIterator<Item = &'a Target<Game<N, HALF_KOMI>>>,
+    ) -> io::Result<()>
+    where
+        Reserves<N>: Default,
+    {
+        let mut bytes = Vec::new();
+        for target in targets {
+            target
+                .write_bin(&mut bytes)
+                .expect("writing to a Vec should not fail");
+        }
+        let result = self.send_bytes(&bytes);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    fn send_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if self.stream.is_none() {
+            self.stream = Some(TcpStream::connect(&self.address)?);
+        }
+        let stream = self.stream.as_mut().expect("the stream was just connected");
+        stream.write_all(bytes)?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::TcpStream,
+        time::{Duration, Instant},
+    };
+
+    use fast_tak::Game;
+    use ordered_float::NotNan;
+    use rand::{Rng, SeedableRng};
+
+    use super::{TargetReceiver, TargetSender};
+    use crate::{
+        search::{env::Environment, eval::Eval},
+        target::{final_ownership, Target},
+    };
+
+    fn random_targets(count: usize, rng: &mut impl Rng) -> Vec<Target<Game<5, 4>>> {
+        let mut actions = Vec::new();
+        (0..count)
+            .map(|_| {
+                let mut env = Game::new_opening_with_random_steps(rng, &mut actions, 20);
+                env.reversible_plies = 0;
+                env.populate_actions(&mut actions);
+                let p = 1.0 / actions.len() as f32;
+                Target {
+                    policy: actions
+                        .drain(..)
+                        .map(|a| (a, NotNan::new(p).unwrap()))
+                        .collect(),
+                    value: rng.gen(),
+                    ube: rng.gen(),
+                    score: Some(rng.gen()),
+                    ownership: Some(final_ownership(&env, env.to_move)),
+                    result: Some(Eval::Draw(rng.gen_range(0..50))),
+                    env,
+                }
+            })
+            .collect()
+    }
+
+    /// Wait until `count` targets have been received or the time is up.
+    fn receive_targets(
+        receiver: &TargetReceiver<5, 4>,
+        count: usize,
+        received: &mut Vec<Target<Game<5, 4>>>,
+    ) {
+        const TIMEOUT: Duration = Duration::from_secs(10);
+        let start = Instant::now();
+        while received.len() < count && start.elapsed() < TIMEOUT {
+            received.extend(receiver.try_iter());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn targets_survive_worker_disconnects() {
+        const SEED: u64 = 357;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+        let receiver = TargetReceiver::<5, 4>::bind("127.0.0.1:0").unwrap();
+        let address = receiver.local_addr().to_string();
+        let first = random_targets(20, &mut rng);
+        let second = random_targets(10, &mut rng);
+
+        let mut sender = TargetSender::new(&address);
+        sender.send(&first).unwrap();
+        drop(sender);
+        let mut received = Vec::new();
+        receive_targets(&receiver, first.len(), &mut received);
+        assert_eq!(received, first);
+
+        // A worker which disconnects in the middle of a target
+        // does not stop the others.
+        let mut bytes = Vec::new();
+        second[0].write_bin(&mut bytes).unwrap();
+        let mut broken = TcpStream::connect(&address).unwrap();
+        broken.write_all(&bytes[..bytes.len() / 2]).unwrap();
+        drop(broken);
+
+        let mut sender = TargetSender::new(&address);
+        sender.send(&second).unwrap();
+        received.clear();
+        receive_targets(&receiver, second.len(), &mut received);
+        assert_eq!(received, second);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(receiver.try_iter().count(), 0);
+    }
+}
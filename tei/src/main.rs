@@ -104,14 +104,14 @@ fn main() {
         match get_input(&stdin, &mut line) {
             Ok(Input::IsReady) => println!("{}", Output::ReadyOk),
             Ok(Input::NewGame { size }) => {
-                if size != N {
+                if size.is_some_and(|size| size != N) {
                     log::error!("the engine is compiled only for size {N}");
                 }
-                node = Node::default();
+                node.clear();
                 env = Env::default();
             }
             Ok(Input::Position { position, moves }) => {
-                node = Node::default();
+                node.clear();
                 env = match position {
                     Position::StartPos => Env::default(),
                     Position::Tps(tps) => tps.into(),
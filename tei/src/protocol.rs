@@ -15,7 +15,7 @@ pub enum Input {
         value: String,
     },
     NewGame {
-        size: usize,
+        size: Option<usize>,
     },
     Position {
         position: Position,
@@ -50,8 +50,6 @@ pub enum ParseInputError {
     MissingName,
     #[error("missing `value` or the actual value after `option name <id>`")]
     MissingValue,
-    #[error("missing size after `newgame`")]
-    MissingSize,
     #[error("new game size parse error: {0}")]
     ParseSize(#[from] ParseIntError),
     #[error("missing second word after `position`")]
@@ -98,7 +96,7 @@ impl FromStr for Input {
                 Ok(Self::Option { name, value })
             }
             "teinewgame" => {
-                let size: usize = words.next().ok_or(ParseInputError::MissingSize)?.parse()?;
+                let size = words.next().map(str::parse).transpose()?;
                 Ok(Self::NewGame { size })
             }
             "position" => {
@@ -247,7 +245,7 @@ impl fmt::Display for Output {
                     f,
                     "info time {} nodes {nodes} nps {}",
                     time.as_millis(),
-                    1000 * nodes / time.as_millis() as usize,
+                    1000 * nodes / (time.as_millis() as usize).max(1),
                 )?;
                 if let Some(ply) = score.ply() {
                     write!(f, " score mate {ply}")?;
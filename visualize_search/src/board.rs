@@ -0,0 +1,124 @@
+use fast_tak::{
+    takparse::{Color, Piece},
+    Game,
+    Reserves,
+};
+use svg::node::element::{Circle, Group, Rectangle, Text};
+
+/// Side length of a square of the board.
+pub const SQUARE_SIZE: f32 = 24.0;
+const BOARD_COLOR: &str = "#c8a26b";
+const SQUARE_COLOR: &str = "#e6c99a";
+const WHITE_PIECE: &str = "#f4f1ea";
+const BLACK_PIECE: &str = "#2b2b2b";
+
+const fn piece_color(color: Color) -> &'static str {
+    match color {
+        Color::White => WHITE_PIECE,
+        Color::Black => BLACK_PIECE,
+    }
+}
+
+/// Side length of the board drawn by [`render_board_svg`].
+pub const fn board_size<const N: usize>() -> f32 {
+    N as f32 * SQUARE_SIZE
+}
+
+/// Draw the board with the top piece of every stack.
+/// Stacks taller than one piece show their height in the corner,
+/// and the colors of the buried pieces, from the top down,
+/// as small bars along the bottom of the square.
+///
+/// The board has its top left corner at the origin
+/// and a side length of [`board_size`].
+pub fn render_board_svg<const N: usize, const HALF_KOMI: i8>(env: &Game<N, HALF_KOMI>) -> Group
+where
+    Reserves<N>: Default,
+{
+    let mut group = Group::new().add(
+        Rectangle::new()
+            .set("width", board_size::<N>())
+            .set("height", board_size::<N>())
+            .set("fill", BOARD_COLOR),
+    );
+
+    for (row, stacks) in env.board.iter().enumerate() {
+        for (column, stack) in stacks.enumerate() {
+            // The first row is at the bottom.
+            let x = column as f32 * SQUARE_SIZE;
+            let y = (N - 1 - row) as f32 * SQUARE_SIZE;
+            group = group.add(
+                Rectangle::new()
+                    .set("x", x + 1.0)
+                    .set("y", y + 1.0)
+                    .set("width", SQUARE_SIZE - 2.0)
+                    .set("height", SQUARE_SIZE - 2.0)
+                    .set("fill", SQUARE_COLOR),
+            );
+
+            let Some((piece, color)) = stack.top() else {
+                continue;
+            };
+            group = group.add(draw_piece(piece, color, x, y));
+
+            let buried: Vec<Color> = stack.colors().reverse().into_iter().skip(1).collect();
+            if buried.is_empty() {
+                continue;
+            }
+            let bar_width = (SQUARE_SIZE - 4.0) / buried.len().max(N) as f32;
+            for (i, color) in buried.into_iter().enumerate() {
+                group = group.add(
+                    Rectangle::new()
+                        .set("x", (i as f32).mul_add(bar_width, x + 2.0))
+                        .set("y", y + SQUARE_SIZE - 4.0)
+                        .set("width", bar_width)
+                        .set("height", 2.0)
+                        .set("fill", piece_color(color))
+                        .set("stroke", "black")
+                        .set("stroke-width", 0.25),
+                );
+            }
+            group = group.add(
+                Text::new(stack.colors().into_iter().count().to_string())
+                    .set("x", x + SQUARE_SIZE - 2.0)
+                    .set("y", y + 7.0)
+                    .set("font-size", 6)
+                    .set("text-anchor", "end"),
+            );
+        }
+    }
+    group
+}
+
+fn draw_piece(piece: Piece, color: Color, x: f32, y: f32) -> Group {
+    let (center_x, center_y) = (x + SQUARE_SIZE / 2.0, y + SQUARE_SIZE / 2.0);
+    let fill = piece_color(color);
+    let shape = match piece {
+        Piece::Flat => Group::new().add(
+            Rectangle::new()
+                .set("x", center_x - SQUARE_SIZE * 0.3)
+                .set("y", center_y - SQUARE_SIZE * 0.3)
+                .set("width", SQUARE_SIZE * 0.6)
+                .set("height", SQUARE_SIZE * 0.6)
+                .set("rx", 1.5),
+        ),
+        Piece::Wall => Group::new().add(
+            Rectangle::new()
+                .set("x", center_x - SQUARE_SIZE * 0.12)
+                .set("y", center_y - SQUARE_SIZE * 0.3)
+                .set("width", SQUARE_SIZE * 0.24)
+                .set("height", SQUARE_SIZE * 0.6)
+                .set("rx", 1.0),
+        ),
+        Piece::Cap => Group::new().add(
+            Circle::new()
+                .set("cx", center_x)
+                .set("cy", center_y)
+                .set("r", SQUARE_SIZE * 0.3),
+        ),
+    };
+    shape
+        .set("fill", fill)
+        .set("stroke", "black")
+        .set("stroke-width", 0.5)
+}
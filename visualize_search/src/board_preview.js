@@ -0,0 +1,18 @@
+// <![CDATA[
+// Show the board of a node while hovering over it.
+(() => {
+  "use strict";
+  const svg = document.documentElement;
+  for (const node of svg.querySelectorAll("g.node")) {
+    const board = node.querySelector("g.board");
+    node.addEventListener("mouseover", () => {
+      // Move the node to the end, so that the board is drawn on top.
+      svg.appendChild(node);
+      board.setAttribute("visibility", "visible");
+    });
+    node.addEventListener("mouseout", () => {
+      board.setAttribute("visibility", "hidden");
+    });
+  }
+})();
+// ]]>
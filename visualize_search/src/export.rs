@@ -0,0 +1,123 @@
+use std::fmt::Write;
+
+use fast_tak::takparse::{Move, Tps};
+use takzero::search::{
+    env::Environment,
+    node::{Node, Statistics},
+};
+
+use crate::Env;
+
+/// Only visited nodes are exported, in the order of the children,
+/// so that two searches of the same position can be compared line by line.
+fn visited_children<'a>(
+    tree: &'a Node<Env>,
+    node: &Statistics,
+) -> impl Iterator<Item = &'a (Move, Statistics)> {
+    tree.children_of(node)
+        .iter()
+        .filter(|(_, child)| child.visit_count() > 0)
+}
+
+/// Escape a string for a quoted JSON or DOT string.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Serialize the visited part of the tree as nested JSON objects.
+///
+/// Every node has the action leading to it (`null` at the root), the TPS,
+/// the visit count, the value from the perspective of the player to move
+/// at the node, the prior, and its visited children.
+pub fn to_json(tree: &Node<Env>, env: &Env) -> String {
+    let mut json = String::new();
+    write_json(&mut json, tree, tree, env, None, 0);
+    json.push('\n');
+    json
+}
+
+fn write_json(
+    json: &mut String,
+    tree: &Node<Env>,
+    node: &Statistics,
+    env: &Env,
+    action: Option<Move>,
+    depth: usize,
+) {
+    let indent = "  ".repeat(depth);
+    let action = action.map_or_else(|| "null".to_string(), |a| format!("\"{a}\""));
+    write!(
+        json,
+        "{indent}{{\"action\": {action}, \"tps\": \"{}\", \"visits\": {}, \"value\": {:.6}, \
+         \"prior\": {:.6}, \"children\": [",
+        escape(&Tps::from(env.clone()).to_string()),
+        node.visit_count(),
+        f32::from(node.evaluation),
+        node.probability.into_inner(),
+    )
+    .expect("writing to a string should not fail");
+
+    let mut first = true;
+    for (action, child) in visited_children(tree, node) {
+        json.push_str(if first { "\n" } else { ",\n" });
+        first = false;
+        let mut clone = env.clone();
+        clone.step(*action);
+        write_json(json, tree, child, &clone, Some(*action), depth + 1);
+    }
+    if !first {
+        write!(json, "\n{indent}").expect("writing to a string should not fail");
+    }
+    json.push_str("]}");
+}
+
+/// Serialize the visited part of the tree as a Graphviz digraph.
+///
+/// Nodes are labelled with their visit count, value and prior,
+/// edges with the action, and the TPS is used as the tooltip.
+pub fn to_dot(tree: &Node<Env>, env: &Env) -> String {
+    let mut dot = String::from("digraph search {\n  node [shape=box, fontname=monospace];\n");
+    let mut next_id = 0;
+    write_dot(&mut dot, tree, tree, env, &mut next_id);
+    dot.push_str("}\n");
+    dot
+}
+
+/// Write the node and its sub-tree, returning the id of the node.
+fn write_dot(
+    dot: &mut String,
+    tree: &Node<Env>,
+    node: &Statistics,
+    env: &Env,
+    next_id: &mut usize,
+) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    writeln!(
+        dot,
+        "  n{id} [label=\"N={}\\nQ={:.3}\\nP={:.3}\", tooltip=\"{}\"];",
+        node.visit_count(),
+        f32::from(node.evaluation),
+        node.probability.into_inner(),
+        escape(&Tps::from(env.clone()).to_string()),
+    )
+    .expect("writing to a string should not fail");
+
+    for (action, child) in visited_children(tree, node) {
+        let mut clone = env.clone();
+        clone.step(*action);
+        let child_id = write_dot(dot, tree, child, &clone, next_id);
+        writeln!(dot, "  n{id} -> n{child_id} [label=\"{action}\"];")
+            .expect("writing to a string should not fail");
+    }
+    id
+}
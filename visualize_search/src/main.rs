@@ -1,79 +1,330 @@
-use std::f32::consts::PI;
+use std::{
+    f32::consts::PI,
+    path::{Path, PathBuf},
+};
 
-use fast_tak::takparse::Tps;
-// use rand::{rngs::StdRng, Rng, SeedableRng};
+use clap::{error::ErrorKind as ClapErrorKind, CommandFactory, Parser, ValueEnum};
+use fast_tak::takparse::{Move, Tps};
 use svg::{
-    node::element::{Circle, Line, Script},
+    node::element::{
+        Circle,
+        Definitions,
+        Group,
+        Line,
+        LinearGradient,
+        Rectangle,
+        Script,
+        Stop,
+        Text,
+    },
     Document,
 };
 use takzero::{
     network::{
-        net4_rnd::{Env, Net},
+        net4_rnd::{Env, Net, N},
         Network,
     },
-    search::{env::Environment, node::Node},
+    search::{
+        env::Environment,
+        node::{Node, Statistics},
+    },
 };
 
-const VISITS: u32 = 1000;
+mod board;
+mod export;
+
+const VIEW_BOX: (f32, f32, f32, f32) = (-400.0, -400.0, 1000.0, 1000.0);
 const ARM_LENGTH: f32 = 40.0;
 const CIRCLE_RADIUS: f32 = 6.0;
-const COLOR: &str = "#8142f5";
+/// Colors of losing, even and winning values, from the perspective
+/// of the player to move at the root.
+const LOSS_COLOR: [u8; 3] = [0xd7, 0x30, 0x27];
+const EVEN_COLOR: [u8; 3] = [0xff, 0xff, 0xff];
+const WIN_COLOR: [u8; 3] = [0x1a, 0x98, 0x50];
+/// The principal variation stands out from the value colors.
+const PV_COLOR: &str = "#2166ac";
+const PV_STROKE_WIDTH: f32 = 3.0;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Position to search from
+    #[arg(long, default_value = "x,1,x,1/x4/x4/2,x3 2 2")]
+    tps: String,
+    /// Model to search with, a randomly initialized network is used if absent
+    #[arg(long)]
+    model: Option<PathBuf>,
+    /// Number of simulations
+    #[arg(long, default_value_t = 1000)]
+    visits: u32,
+    /// Exploration bonus for uncertainty, several values create one tree each
+    #[arg(long, num_args = 1.., default_values_t = [0.0])]
+    beta: Vec<f32>,
+    /// Seed for the random network
+    #[arg(long, default_value_t = 123)]
+    seed: i64,
+    /// Format of the output
+    #[arg(long, value_enum, default_value_t = Format::Svg)]
+    format: Format,
+    /// Where to save the tree, `tree.<format>` by default.
+    /// The beta is added to the file name if there are several
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// Tree drawing with board previews
+    Svg,
+    /// Graphviz digraph
+    Dot,
+    /// Nested JSON objects, for processing or comparing searches
+    Json,
+}
+
+impl Format {
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Svg => "svg",
+            Self::Dot => "dot",
+            Self::Json => "json",
+        }
+    }
+}
 
 fn main() {
-    // let mut rng = StdRng::seed_from_u64(123);
-    // let mut actions = vec![];
-    // let game = Env::new_opening(&mut rng, &mut actions);
-    // let net = Net::new(tch::Device::Cuda(0), Some(rng.gen()));
-    let net = Net::load("directed.ot", tch::Device::Cuda(0)).unwrap();
-    let env: Env = "x,1,x,1/x4/x4/2,x3 2 2".parse::<Tps>().unwrap().into();
-
-    for beta in [0.0, 0.5, 1.0, 2.0, 3.0, 4.0] {
-        visualize_search(&net, &env, beta);
+    let args = Args::parse();
+    let env: Env = match args.tps.parse::<Tps>() {
+        Ok(tps) => tps.into(),
+        Err(err) => Args::command()
+            .error(
+                ClapErrorKind::ValueValidation,
+                format!("invalid TPS \"{}\": {err}", args.tps),
+            )
+            .exit(),
+    };
+
+    let device = tch::Device::cuda_if_available();
+    let net = match &args.model {
+        Some(model) => Net::load(model, device).expect("model should be loadable"),
+        None => Net::new(device, Some(args.seed)),
+    };
+
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("tree.{}", args.format.extension())));
+    for &beta in &args.beta {
+        let output = if args.beta.len() > 1 {
+            output_for_beta(&output, beta)
+        } else {
+            output.clone()
+        };
+        visualize_search(&net, &env, args.visits, beta, args.format, &output);
     }
 }
 
-fn visualize_search(net: &Net, env: &Env, beta: f32) {
+/// Add the beta to the file name, for example `tree_beta=0.5.svg`.
+fn output_for_beta(output: &Path, beta: f32) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = format!("{stem}_beta={beta}");
+    if let Some(extension) = output.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+fn visualize_search(net: &Net, env: &Env, visits: u32, beta: f32, format: Format, output: &Path) {
     let mut node = Node::default();
 
-    for _ in 0..VISITS {
+    for _ in 0..visits {
         node.simulate_simple(net, env.clone(), beta);
     }
+    for (action, p) in node.visit_policy(1.0) {
+        println!("beta={beta} {action}: {p:.3}");
+    }
 
-    let mut document = Document::new().set("viewBox", (-400, -400, 1000, 1000));
+    match format {
+        Format::Svg => svg::save(output, &draw_document(&node, env)),
+        Format::Dot => std::fs::write(output, export::to_dot(&node, env)),
+        Format::Json => std::fs::write(output, export::to_json(&node, env)),
+    }
+    .expect("output should be writable");
+}
+
+fn draw_document(tree: &Node<Env>, env: &Env) -> Document {
+    let mut document = Document::new().set("viewBox", VIEW_BOX);
     // .set("style", "background:black");
 
-    document = draw_tree(document, &node, env, 0.0, 0.0, 0.0, 2.0 * PI);
-    document = document.add(Script::new(include_str!("preview.js")));
+    document = draw_legend(document);
+    let pv: Vec<_> = tree.principal_variation().collect();
+    let mut highlight = Vec::new();
+    document = draw_tree(
+        document,
+        &mut highlight,
+        tree,
+        tree,
+        env,
+        Some(&pv),
+        1.0,
+        0.0,
+        0.0,
+        0.0,
+        2.0 * PI,
+    );
+    for element in highlight {
+        document = document.add(element);
+    }
+    document.add(Script::new(include_str!("board_preview.js")))
+}
 
-    svg::save(format!("tree_with_beta={beta}.svg"), &document).unwrap();
+/// Hidden drawing of the board next to a node, shown while hovering over it.
+/// It is placed below the node if there is no room above it.
+fn board_preview(env: &Env, x: f32, y: f32) -> Group {
+    let size = board::board_size::<N>();
+    let top = if y - CIRCLE_RADIUS - size < VIEW_BOX.1 {
+        y + CIRCLE_RADIUS
+    } else {
+        y - CIRCLE_RADIUS - size
+    };
+    board::render_board_svg(env)
+        .set("class", "board")
+        .set("visibility", "hidden")
+        .set("pointer-events", "none")
+        .set("transform", format!("translate({},{top})", x - size / 2.0))
 }
 
 fn opacity(visits: u32) -> f32 {
     (visits as f32 / 25.0).clamp(0.0, 1.0)
 }
 
-#[allow(clippy::suboptimal_flops)]
+/// Diverging color scale from [`LOSS_COLOR`] at -1 over [`EVEN_COLOR`] at 0
+/// to [`WIN_COLOR`] at 1.
+#[allow(clippy::cast_sign_loss)]
+fn value_color(value: f32) -> String {
+    let value = value.clamp(-1.0, 1.0);
+    let (end, t) = if value < 0.0 {
+        (LOSS_COLOR, -value)
+    } else {
+        (WIN_COLOR, value)
+    };
+    let [r, g, b] = std::array::from_fn(|i| {
+        let (even, end) = (f32::from(EVEN_COLOR[i]), f32::from(end[i]));
+        (end - even).mul_add(t, even).round() as u8
+    });
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn draw_legend(document: Document) -> Document {
+    const X: f32 = -380.0;
+    const Y: f32 = -380.0;
+    const WIDTH: f32 = 120.0;
+    const HEIGHT: f32 = 10.0;
+
+    let gradient = LinearGradient::new()
+        .set("id", "value-scale")
+        .add(
+            Stop::new()
+                .set("offset", "0%")
+                .set("stop-color", value_color(-1.0)),
+        )
+        .add(
+            Stop::new()
+                .set("offset", "50%")
+                .set("stop-color", value_color(0.0)),
+        )
+        .add(
+            Stop::new()
+                .set("offset", "100%")
+                .set("stop-color", value_color(1.0)),
+        );
+    let label = |x: f32, text: &str| {
+        Text::new(text)
+            .set("x", x)
+            .set("y", Y + HEIGHT + 12.0)
+            .set("font-size", 10)
+            .set("text-anchor", "middle")
+    };
+    document
+        .add(Definitions::new().add(gradient))
+        .add(
+            Rectangle::new()
+                .set("x", X)
+                .set("y", Y)
+                .set("width", WIDTH)
+                .set("height", HEIGHT)
+                .set("fill", "url(#value-scale)")
+                .set("stroke", "black")
+                .set("stroke-width", 0.5),
+        )
+        .add(label(X, "-1"))
+        .add(label(X + WIDTH / 2.0, "Q"))
+        .add(label(X + WIDTH, "+1"))
+}
+
+/// Label with the visit count, value and prior of a node on the
+/// principal variation.
+fn label(node: &Statistics, perspective: f32, x: f32, y: f32) -> Text {
+    Text::new(format!(
+        "N={} Q={:.2} P={:.2}",
+        node.visit_count(),
+        perspective * f32::from(node.evaluation),
+        node.probability.into_inner(),
+    ))
+    .set("x", x + CIRCLE_RADIUS + 2.0)
+    .set("y", y - CIRCLE_RADIUS)
+    .set("font-size", 8)
+    .set("fill", PV_COLOR)
+}
+
+/// Draw the node of `tree` and all of its visited children.
+/// `perspective` is 1 if the player to move at `env` is the player to move
+/// at the root, and -1 otherwise.
+/// `pv` is the rest of the principal variation if the node is on it.
+/// Its edges and labels are collected in `highlight`, so that they can be
+/// drawn on top of the rest of the tree.
+#[allow(clippy::suboptimal_flops, clippy::too_many_arguments)]
 fn draw_tree(
     mut document: Document,
-    node: &Node<Env>,
+    highlight: &mut Vec<Box<dyn svg::Node>>,
+    tree: &Node<Env>,
+    node: &Statistics,
     env: &Env,
+    pv: Option<&[Move]>,
+    perspective: f32,
     x: f32,
     y: f32,
     min_angle: f32,
     max_angle: f32,
 ) -> Document {
+    let circle = Circle::new()
+        .set("cx", x)
+        .set("cy", y)
+        .set("r", CIRCLE_RADIUS)
+        .set(
+            "fill",
+            value_color(perspective * f32::from(node.evaluation)),
+        )
+        .set("stroke", "black")
+        .set("stroke-width", 0.5)
+        .set("opacity", opacity(node.visit_count()))
+        .set("tps", Tps::from(env.clone()).to_string());
     document = document.add(
-        Circle::new()
-            .set("cx", x)
-            .set("cy", y)
-            .set("r", CIRCLE_RADIUS)
-            .set("fill", COLOR)
-            .set("opacity", opacity(node.visit_count()))
-            .set("tps", Tps::from(env.clone()).to_string()),
+        Group::new()
+            .set("class", "node")
+            .add(circle)
+            .add(board_preview(env, x, y)),
     );
+    if pv.is_some() {
+        highlight.push(Box::new(label(node, perspective, x, y)));
+    }
 
-    let angle_step = (max_angle - min_angle) / node.children.len() as f32;
-    for (i, (action, child)) in node.children.iter().enumerate() {
+    let children = tree.children_of(node);
+    let angle_step = (max_angle - min_angle) / children.len() as f32;
+    for (i, (action, child)) in children.iter().enumerate() {
         if child.visit_count() < 1 {
             continue;
         }
@@ -81,22 +332,40 @@ fn draw_tree(
         let x2 = x + ARM_LENGTH * angle.cos();
         let y2 = y + ARM_LENGTH * angle.sin();
 
-        document = document.add(
-            Line::new()
-                .set("x1", x)
-                .set("y1", y)
-                .set("x2", x2)
-                .set("y2", y2)
-                .set("stroke", COLOR)
-                .set("opacity", opacity(child.visit_count()))
-                .set("action", action.to_string()),
-        );
+        let child_pv = pv
+            .and_then(<[_]>::split_first)
+            .filter(|(first, _)| *first == action)
+            .map(|(_, rest)| rest);
+        let line = Line::new()
+            .set("x1", x)
+            .set("y1", y)
+            .set("x2", x2)
+            .set("y2", y2)
+            .set("action", action.to_string());
+        if child_pv.is_some() {
+            highlight.push(Box::new(
+                line.set("stroke", PV_COLOR)
+                    .set("stroke-width", PV_STROKE_WIDTH),
+            ));
+        } else {
+            document = document.add(
+                line.set(
+                    "stroke",
+                    value_color(-perspective * f32::from(child.evaluation)),
+                )
+                .set("opacity", opacity(child.visit_count())),
+            );
+        }
         let mut clone = env.clone();
         clone.step(*action);
         document = draw_tree(
             document,
+            highlight,
+            tree,
             child,
             &clone,
+            child_pv,
+            -perspective,
             x2,
             y2,
             angle - PI / 4.0,